@@ -9,9 +9,34 @@
 
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Minimum interval between two live previews (~30 fps); faster calls reuse the last one.
+const PREVIEW_MIN_INTERVAL: Duration = Duration::from_millis(33);
+
+/// The last live preview PNG, what it shows and when it was taken.
+type PreviewCache = Option<CachedPreview>;
+
+#[derive(Debug)]
+struct CachedPreview {
+    taken_at: Instant,
+    region: CaptureRegion,
+    max_dim: u32,
+    png: Vec<u8>,
+}
+
+impl CachedPreview {
+    /// The cached PNG, if it was taken recently for the same region and size.
+    fn reuse(&self, region: &CaptureRegion, max_dim: u32) -> Option<&[u8]> {
+        (self.region == *region
+            && self.max_dim == max_dim
+            && self.taken_at.elapsed() < PREVIEW_MIN_INTERVAL)
+            .then_some(self.png.as_slice())
+    }
+}
 
 /// Region coordinates for screen capture (sent from frontend after user selection)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CaptureRegion {
     pub x: i32,
     pub y: i32,
@@ -60,6 +85,8 @@ pub struct CaptureService {
     current_shortcut: Arc<Mutex<Option<String>>>,
     /// Whether a capture is currently in progress (overlay is shown).
     capture_active: Arc<Mutex<bool>>,
+    /// The most recent live preview and when it was taken (used for throttling).
+    last_preview: Arc<Mutex<PreviewCache>>,
 }
 
 impl CaptureService {
@@ -68,6 +95,7 @@ impl CaptureService {
         Self {
             current_shortcut: Arc::new(Mutex::new(None)),
             capture_active: Arc::new(Mutex::new(false)),
+            last_preview: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -173,6 +201,65 @@ impl CaptureService {
         // Encode as PNG
         encode_png(&pixels, region.width, region.height)
     }
+
+    /// Capture a region as PNG bytes, reusing `pixels` for the raw pixel data.
+    ///
+    /// Same result as `capture_region`, but the screen pixels are written into
+    /// the caller's buffer (cleared and resized as needed), so tight loops
+    /// such as batch capture keep a single allocation across calls. On return
+    /// `pixels` holds the RGBA data of this capture.
+    pub fn capture_region_into(
+        &self,
+        region: &CaptureRegion,
+        pixels: &mut Vec<u8>,
+    ) -> Result<Vec<u8>, CaptureError> {
+        if region.width == 0 || region.height == 0 {
+            return Err(CaptureError::InvalidRegion(
+                "截图区域的宽度和高度必须大于 0".to_string(),
+            ));
+        }
+
+        capture_screen_region_into(region, region.width, region.height, pixels)?;
+        encode_png(pixels, region.width, region.height)
+    }
+
+    /// Capture a low-resolution live preview of a region as PNG bytes.
+    ///
+    /// Called on mouse-move during rubber-band selection, so the screen is
+    /// copied with a single StretchBlt straight into a bitmap whose longest
+    /// side is at most `max_dim` pixels. Calls for the same region and
+    /// `max_dim` arriving within `PREVIEW_MIN_INTERVAL` of the previous
+    /// preview return that preview again instead of hitting the screen.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - PNG-encoded downscaled image
+    /// * `Err(CaptureError::InvalidRegion)` - zero-size region or `max_dim` of 0
+    /// * `Err(CaptureError::CaptureFailed)` - if the capture fails
+    pub fn capture_preview(&self, region: &CaptureRegion, max_dim: u32) -> Result<Vec<u8>, CaptureError> {
+        if region.width == 0 || region.height == 0 || max_dim == 0 {
+            return Err(CaptureError::InvalidRegion(
+                "预览区域的宽度、高度和最大边长必须大于 0".to_string(),
+            ));
+        }
+
+        let mut last = self.last_preview.lock().map_err(|e| {
+            CaptureError::CaptureFailed(format!("内部锁错误: {}", e))
+        })?;
+        if let Some(png) = last.as_ref().and_then(|cached| cached.reuse(region, max_dim)) {
+            return Ok(png.to_vec());
+        }
+
+        let (width, height) = preview_dimensions(region.width, region.height, max_dim);
+        let pixels = capture_screen_region_scaled(region, width, height)?;
+        let png = encode_png(&pixels, width, height)?;
+        *last = Some(CachedPreview {
+            taken_at: Instant::now(),
+            region: region.clone(),
+            max_dim,
+            png: png.clone(),
+        });
+        Ok(png)
+    }
 }
 
 impl Default for CaptureService {
@@ -219,12 +306,89 @@ pub fn validate_shortcut_format(shortcut: &str) -> bool {
     has_modifier && has_key
 }
 
+/// 快捷键可用性检测结果
+///
+/// Serialized as `{"status": "available"}`, `{"status": "conflict", "reason": "..."}`
+/// or `{"status": "invalid_format"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ShortcutTestResult {
+    /// 可以绑定
+    Available,
+    /// 已被系统或其他应用占用
+    Conflict { reason: String },
+    /// 格式无效（见 `validate_shortcut_format`）
+    InvalidFormat,
+}
+
+/// Registers global shortcuts; the command layer wraps the global-shortcut
+/// plugin, tests use a stub.
+pub trait ShortcutRegistrar {
+    fn register(&self, shortcut: &str) -> Result<(), String>;
+    fn unregister(&self, shortcut: &str) -> Result<(), String>;
+}
+
+/// Check whether `shortcut` can be bound by registering it temporarily.
+///
+/// The format is validated first; a registration that succeeds is undone
+/// immediately, a failed one is reported as `Conflict` with the
+/// registrar's error.
+pub fn test_shortcut<R: ShortcutRegistrar>(shortcut: &str, registrar: &R) -> ShortcutTestResult {
+    let shortcut = shortcut.trim();
+    if !validate_shortcut_format(shortcut) {
+        return ShortcutTestResult::InvalidFormat;
+    }
+    match registrar.register(shortcut) {
+        Ok(()) => {
+            let _ = registrar.unregister(shortcut);
+            ShortcutTestResult::Available
+        }
+        Err(reason) => ShortcutTestResult::Conflict { reason },
+    }
+}
+
+/// Scale a region so its longest side is at most `max_dim`, keeping the
+/// aspect ratio. Regions already within the limit are not enlarged.
+fn preview_dimensions(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_dim {
+        return (width, height);
+    }
+    let scale = max_dim as f64 / longest as f64;
+    let scaled = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
+/// Capture a specific screen region at its native size. Returns raw RGBA pixel data.
+fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError> {
+    capture_screen_region_scaled(region, region.width, region.height)
+}
+
+/// Capture a screen region into a `dest_width` x `dest_height` bitmap. Returns raw RGBA pixel data.
+fn capture_screen_region_scaled(
+    region: &CaptureRegion,
+    dest_width: u32,
+    dest_height: u32,
+) -> Result<Vec<u8>, CaptureError> {
+    let mut pixels = Vec::new();
+    capture_screen_region_into(region, dest_width, dest_height, &mut pixels)?;
+    Ok(pixels)
+}
+
 /// Capture a specific screen region using Win32 API.
 ///
 /// Uses GetDC(NULL) to get the screen device context, then BitBlt to copy
-/// the specified region into a memory bitmap. Returns raw BGRA pixel data.
+/// the specified region into a memory bitmap of `dest_width` x `dest_height`.
+/// When the destination is smaller than the region, StretchBlt downscales in
+/// the same copy. The raw RGBA pixel data is written into `pixels`, which is
+/// resized to fit and keeps its capacity between calls.
 #[cfg(target_os = "windows")]
-fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError> {
+fn capture_screen_region_into(
+    region: &CaptureRegion,
+    dest_width: u32,
+    dest_height: u32,
+    pixels: &mut Vec<u8>,
+) -> Result<(), CaptureError> {
     use std::ptr;
 
     // Win32 API types and functions via raw FFI
@@ -244,6 +408,7 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
         pub type WORD = u16;
 
         pub const SRCCOPY: DWORD = 0x00CC0020;
+        pub const COLORONCOLOR: INT = 3;
         pub const DIB_RGB_COLORS: UINT = 0;
         pub const BI_RGB: DWORD = 0;
 
@@ -291,6 +456,11 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
                 hdc: HDC, x: INT, y: INT, cx: INT, cy: INT,
                 hdcSrc: HDC, x1: INT, y1: INT, rop: DWORD,
             ) -> BOOL;
+            pub fn StretchBlt(
+                hdcDest: HDC, xDest: INT, yDest: INT, wDest: INT, hDest: INT,
+                hdcSrc: HDC, xSrc: INT, ySrc: INT, wSrc: INT, hSrc: INT, rop: DWORD,
+            ) -> BOOL;
+            pub fn SetStretchBltMode(hdc: HDC, mode: INT) -> INT;
             pub fn GetDIBits(
                 hdc: HDC, hbm: HBITMAP, start: UINT, cLines: UINT,
                 lpvBits: *mut c_void, lpbmi: *mut BITMAPINFO, usage: UINT,
@@ -316,11 +486,11 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
             ));
         }
 
-        // Create a compatible bitmap for the capture region
+        // Create a compatible bitmap for the (possibly downscaled) destination
         let bitmap = win32::CreateCompatibleBitmap(
             screen_dc,
-            region.width as i32,
-            region.height as i32,
+            dest_width as i32,
+            dest_height as i32,
         );
         if bitmap.is_null() {
             win32::DeleteDC(mem_dc);
@@ -334,17 +504,35 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
         let old_bitmap = win32::SelectObject(mem_dc, bitmap);
 
         // BitBlt: copy the screen region to the memory DC
-        let blt_result = win32::BitBlt(
-            mem_dc,
-            0,
-            0,
-            region.width as i32,
-            region.height as i32,
-            screen_dc,
-            region.x,
-            region.y,
-            win32::SRCCOPY,
-        );
+        // (StretchBlt with the fast COLORONCOLOR mode when downscaling)
+        let blt_result = if dest_width == region.width && dest_height == region.height {
+            win32::BitBlt(
+                mem_dc,
+                0,
+                0,
+                region.width as i32,
+                region.height as i32,
+                screen_dc,
+                region.x,
+                region.y,
+                win32::SRCCOPY,
+            )
+        } else {
+            win32::SetStretchBltMode(mem_dc, win32::COLORONCOLOR);
+            win32::StretchBlt(
+                mem_dc,
+                0,
+                0,
+                dest_width as i32,
+                dest_height as i32,
+                screen_dc,
+                region.x,
+                region.y,
+                region.width as i32,
+                region.height as i32,
+                win32::SRCCOPY,
+            )
+        };
 
         if blt_result == 0 {
             win32::SelectObject(mem_dc, old_bitmap);
@@ -360,9 +548,9 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
         let mut bmi = win32::BITMAPINFO {
             bmiHeader: win32::BITMAPINFOHEADER {
                 biSize: std::mem::size_of::<win32::BITMAPINFOHEADER>() as u32,
-                biWidth: region.width as i32,
+                biWidth: dest_width as i32,
                 // Negative height = top-down DIB (origin at top-left)
-                biHeight: -(region.height as i32),
+                biHeight: -(dest_height as i32),
                 biPlanes: 1,
                 biBitCount: 32, // BGRA
                 biCompression: win32::BI_RGB,
@@ -380,16 +568,17 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
             }],
         };
 
-        // Allocate buffer for pixel data (BGRA, 4 bytes per pixel)
-        let pixel_count = (region.width * region.height) as usize;
-        let mut pixels: Vec<u8> = vec![0u8; pixel_count * 4];
+        // Size the caller's buffer for pixel data (BGRA, 4 bytes per pixel)
+        let pixel_count = (dest_width * dest_height) as usize;
+        pixels.clear();
+        pixels.resize(pixel_count * 4, 0);
 
         // Get the bitmap bits
         let lines = win32::GetDIBits(
             mem_dc,
             bitmap,
             0,
-            region.height,
+            dest_height,
             pixels.as_mut_ptr() as *mut std::ffi::c_void,
             &mut bmi,
             win32::DIB_RGB_COLORS,
@@ -413,13 +602,18 @@ fn capture_screen_region(region: &CaptureRegion) -> Result<Vec<u8>, CaptureError
             pixels.swap(offset, offset + 2); // swap B and R
         }
 
-        Ok(pixels)
+        Ok(())
     }
 }
 
 /// Fallback screen capture for non-Windows platforms (returns an error).
 #[cfg(not(target_os = "windows"))]
-fn capture_screen_region(_region: &CaptureRegion) -> Result<Vec<u8>, CaptureError> {
+fn capture_screen_region_into(
+    _region: &CaptureRegion,
+    _dest_width: u32,
+    _dest_height: u32,
+    _pixels: &mut Vec<u8>,
+) -> Result<(), CaptureError> {
     Err(CaptureError::CaptureFailed(
         "屏幕截图仅支持 Windows 平台".to_string(),
     ))
@@ -609,6 +803,53 @@ mod tests {
         assert!(validate_shortcut_format("Ctrl+SHIFT+a"));
     }
 
+    /// Registrar stub: shortcuts in `taken` fail to register; records calls.
+    struct MockRegistrar {
+        taken: Vec<&'static str>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ShortcutRegistrar for MockRegistrar {
+        fn register(&self, shortcut: &str) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("register {}", shortcut));
+            if self.taken.contains(&shortcut) {
+                Err("HotKey already registered".to_string())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn unregister(&self, shortcut: &str) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("unregister {}", shortcut));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_shortcut_conflict_and_available() {
+        let registrar = MockRegistrar { taken: vec!["Ctrl+Shift+S"], calls: Mutex::new(Vec::new()) };
+
+        assert_eq!(
+            test_shortcut("Ctrl+Shift+S", &registrar),
+            ShortcutTestResult::Conflict { reason: "HotKey already registered".to_string() }
+        );
+        assert_eq!(test_shortcut(" Ctrl+Shift+2 ", &registrar), ShortcutTestResult::Available);
+        // The free shortcut is released again right away
+        assert_eq!(
+            *registrar.calls.lock().unwrap(),
+            vec!["register Ctrl+Shift+S", "register Ctrl+Shift+2", "unregister Ctrl+Shift+2"]
+        );
+    }
+
+    #[test]
+    fn test_shortcut_invalid_format_skips_registration() {
+        let registrar = MockRegistrar { taken: vec![], calls: Mutex::new(Vec::new()) };
+        assert_eq!(test_shortcut("Ctrl+", &registrar), ShortcutTestResult::InvalidFormat);
+        assert!(registrar.calls.lock().unwrap().is_empty());
+        let json = serde_json::to_string(&ShortcutTestResult::Conflict { reason: "x".to_string() }).unwrap();
+        assert_eq!(json, r#"{"status":"conflict","reason":"x"}"#);
+    }
+
     // ============================================================
     // CaptureService tests
     // ============================================================
@@ -779,6 +1020,104 @@ mod tests {
         }
     }
 
+    // ============================================================
+    // capture_preview tests
+    // ============================================================
+
+    #[test]
+    fn test_preview_dimensions_downscales_longest_side() {
+        assert_eq!(preview_dimensions(800, 200, 160), (160, 40));
+        assert_eq!(preview_dimensions(200, 800, 160), (40, 160));
+        // Never smaller than 1 pixel
+        assert_eq!(preview_dimensions(1000, 1, 100), (100, 1));
+    }
+
+    #[test]
+    fn test_preview_dimensions_does_not_upscale() {
+        assert_eq!(preview_dimensions(40, 30, 160), (40, 30));
+    }
+
+    #[test]
+    fn test_cached_preview_only_reused_for_same_region_and_size() {
+        let region = CaptureRegion { x: 10, y: 20, width: 300, height: 200 };
+        let cached = CachedPreview {
+            taken_at: Instant::now(),
+            region: region.clone(),
+            max_dim: 160,
+            png: vec![1, 2, 3],
+        };
+        assert_eq!(cached.reuse(&region, 160), Some(&[1u8, 2, 3][..]));
+        assert_eq!(cached.reuse(&CaptureRegion { width: 301, ..region.clone() }, 160), None);
+        assert_eq!(cached.reuse(&CaptureRegion { x: 11, ..region.clone() }, 160), None);
+        assert_eq!(cached.reuse(&region, 80), None);
+
+        let stale = CachedPreview {
+            taken_at: Instant::now() - PREVIEW_MIN_INTERVAL * 2,
+            ..cached
+        };
+        assert_eq!(stale.reuse(&region, 160), None);
+    }
+
+    #[test]
+    fn test_capture_preview_zero_size_region() {
+        let service = CaptureService::new();
+        for (width, height, max_dim) in [(0, 100, 64), (100, 0, 64), (100, 100, 0)] {
+            let region = CaptureRegion { x: 0, y: 0, width, height };
+            match service.capture_preview(&region, max_dim) {
+                Err(CaptureError::InvalidRegion(_)) => {}
+                other => panic!("Expected InvalidRegion, got: {:?}", other),
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_capture_preview_returns_downscaled_png() {
+        let service = CaptureService::new();
+        let region = CaptureRegion {
+            x: 0,
+            y: 0,
+            width: 40,
+            height: 20,
+        };
+        let png_bytes = service.capture_preview(&region, 10).expect("preview should succeed");
+        assert_eq!(&png_bytes[0..4], &[0x89, 0x50, 0x4E, 0x47]);
+
+        let img = image::load_from_memory(&png_bytes).unwrap();
+        assert_eq!(img.dimensions(), (10, 5));
+    }
+
+    #[test]
+    fn test_capture_region_into_zero_size_region() {
+        let service = CaptureService::new();
+        let mut pixels = vec![1u8, 2, 3, 4];
+        let region = CaptureRegion { x: 0, y: 0, width: 0, height: 10 };
+        match service.capture_region_into(&region, &mut pixels) {
+            Err(CaptureError::InvalidRegion(_)) => {}
+            other => panic!("Expected InvalidRegion, got: {:?}", other),
+        }
+        assert_eq!(pixels, vec![1, 2, 3, 4], "buffer should be untouched on error");
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_capture_region_into_reuses_buffer() {
+        let service = CaptureService::new();
+        let mut pixels = Vec::new();
+
+        let large = CaptureRegion { x: 0, y: 0, width: 30, height: 20 };
+        let png = service.capture_region_into(&large, &mut pixels).expect("first capture should succeed");
+        assert_eq!(pixels.len(), 30 * 20 * 4);
+        assert_eq!(image::load_from_memory(&png).unwrap().dimensions(), (30, 20));
+        let buffer_ptr = pixels.as_ptr();
+
+        let small = CaptureRegion { x: 5, y: 5, width: 10, height: 8 };
+        let png = service.capture_region_into(&small, &mut pixels).expect("second capture should succeed");
+        assert_eq!(pixels.len(), 10 * 8 * 4);
+        assert_eq!(image::load_from_memory(&png).unwrap().dimensions(), (10, 8));
+        assert_eq!(pixels.as_ptr(), buffer_ptr, "smaller capture should not reallocate");
+    }
+
     // ============================================================
     // encode_png tests
     // ============================================================
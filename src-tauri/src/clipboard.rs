@@ -2,6 +2,7 @@
 // 使用纯文本格式写入 MathML，Word 可以直接识别并转换为公式
 
 use serde::Serialize;
+use std::io::Cursor;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ClipboardError {
@@ -9,6 +10,10 @@ pub enum ClipboardError {
     OpenFailed(String),
     #[error("格式写入失败: {0}")]
     WriteFailed(String),
+    #[error("剪贴板中没有图片")]
+    NoImage,
+    #[error("读取剪贴板失败: {0}")]
+    ReadFailed(String),
 }
 
 impl Serialize for ClipboardError {
@@ -57,6 +62,52 @@ pub fn copy_latex(latex: &str) -> Result<(), ClipboardError> {
     Ok(())
 }
 
+/// 写入 CF_HTML 片段，同时写入 CF_UNICODETEXT 纯文本作为回退
+///
+/// CF_HTML 的头部（StartHTML/StartFragment 偏移）由 clipboard-win 生成，
+/// `html_fragment` 只需是 `<body>` 内部的内容。Word 粘贴时优先使用 HTML。
+pub fn copy_html(html_fragment: &str, plain_text: &str) -> Result<(), ClipboardError> {
+    let _clip = clipboard_win::Clipboard::new_attempts(10)
+        .map_err(|e| ClipboardError::OpenFailed(e.to_string()))?;
+
+    clipboard_win::raw::empty()
+        .map_err(|e| ClipboardError::WriteFailed(format!("清空剪贴板失败: {}", e)))?;
+
+    let html_format = clipboard_win::formats::Html::new()
+        .ok_or_else(|| ClipboardError::WriteFailed("无法注册 HTML Format".to_string()))?;
+    clipboard_win::raw::set_html(html_format.code(), html_fragment)
+        .map_err(|e| ClipboardError::WriteFailed(format!("写入 HTML 失败: {}", e)))?;
+
+    clipboard_win::raw::set_string_with(plain_text, clipboard_win::options::NoClear)
+        .map_err(|e| ClipboardError::WriteFailed(format!("写入纯文本失败: {}", e)))?;
+
+    Ok(())
+}
+
+/// 读取剪贴板中的图片（CF_BITMAP），转换为 PNG 字节
+///
+/// 剪贴板中没有图片时返回 `ClipboardError::NoImage`。
+pub fn read_image() -> Result<Vec<u8>, ClipboardError> {
+    let _clip = clipboard_win::Clipboard::new_attempts(10)
+        .map_err(|e| ClipboardError::OpenFailed(e.to_string()))?;
+
+    if !clipboard_win::raw::is_format_avail(clipboard_win::formats::CF_BITMAP) {
+        return Err(ClipboardError::NoImage);
+    }
+
+    // clipboard-win returns the bitmap as a complete BMP file
+    let mut bmp = Vec::new();
+    clipboard_win::raw::get_bitmap(&mut bmp)
+        .map_err(|e| ClipboardError::ReadFailed(format!("读取图片失败: {}", e)))?;
+
+    let img = image::load_from_memory_with_format(&bmp, image::ImageFormat::Bmp)
+        .map_err(|e| ClipboardError::ReadFailed(format!("解码图片失败: {}", e)))?;
+    let mut png = Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageFormat::Png)
+        .map_err(|e| ClipboardError::ReadFailed(format!("编码 PNG 失败: {}", e)))?;
+    Ok(png.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -248,4 +299,21 @@ mod tests {
             .expect("Should read unicode text");
         assert_eq!(read_text, mathml);
     }
+
+    #[test]
+    #[ignore = "Requires desktop session - clipboard access may fail in parallel tests"]
+    fn test_copy_html_writes_fragment_and_text() {
+        let fragment = "<p>x<sup>2</sup></p>";
+        let result = copy_html(fragment, "x^2");
+        assert!(result.is_ok(), "copy_html should succeed: {:?}", result.err());
+
+        let html_format = clipboard_win::formats::Html::new().expect("HTML Format");
+        let read_html: String =
+            clipboard_win::get_clipboard(html_format).expect("Should read HTML from clipboard");
+        assert_eq!(read_html, fragment);
+
+        let read_text: String = clipboard_win::get_clipboard(clipboard_win::formats::Unicode)
+            .expect("Should read unicode text");
+        assert_eq!(read_text, "x^2");
+    }
 }
@@ -4,7 +4,7 @@
 use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::reader::Reader;
 use quick_xml::writer::Writer;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
 /// OMML namespace URI
@@ -18,6 +18,8 @@ pub enum ConvertError {
     MathmlToOmml(String),
     #[error("不支持的 LaTeX 符号: {0}")]
     UnsupportedSymbol(String),
+    #[error("公式过于复杂: {0}")]
+    TooComplex(String),
 }
 
 impl Serialize for ConvertError {
@@ -29,6 +31,96 @@ impl Serialize for ConvertError {
     }
 }
 
+/// 公式显示模式
+///
+/// `Inline` renders like `$...$` (compact limits and fractions); `Block`
+/// renders like `$$...$$` so large operators and fractions are full-size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertDisplay {
+    #[default]
+    Inline,
+    Block,
+}
+
+impl From<ConvertDisplay> for latex2mathml::DisplayStyle {
+    fn from(display: ConvertDisplay) -> Self {
+        match display {
+            ConvertDisplay::Inline => latex2mathml::DisplayStyle::Inline,
+            ConvertDisplay::Block => latex2mathml::DisplayStyle::Block,
+        }
+    }
+}
+
+/// OMML 输出兼容配置
+///
+/// Word 2007 rejects some formulas whose structures lack their property
+/// element, so `Word2007` always writes one (`<m:sSupPr></m:sSupPr>` even
+/// when empty). `Word2010Plus` omits empty property elements for more
+/// compact output. Both share the same namespace placement (`xmlns:m` on
+/// `<m:oMathPara>`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OmmlProfile {
+    /// 始终写出属性元素（兼容性最好，默认）
+    #[default]
+    Word2007,
+    /// 省略空的属性元素
+    Word2010Plus,
+}
+
+/// 转换前的输入规模限制，防止病态的 OCR 输出拖慢转换、卡住界面
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ComplexityLimits {
+    /// 最大字符数
+    pub max_length: usize,
+    /// 最大花括号嵌套深度
+    pub max_depth: usize,
+}
+
+impl Default for ComplexityLimits {
+    fn default() -> Self {
+        Self {
+            max_length: 4000,
+            max_depth: 64,
+        }
+    }
+}
+
+/// Reject inputs longer than `limits.max_length` characters or with braces
+/// nested deeper than `limits.max_depth` (escaped `\{` / `\}` do not count).
+pub fn check_complexity(latex: &str, limits: &ComplexityLimits) -> Result<(), ConvertError> {
+    let length = latex.chars().count();
+    if length > limits.max_length {
+        return Err(ConvertError::TooComplex(format!(
+            "长度 {} 超过上限 {}",
+            length, limits.max_length
+        )));
+    }
+
+    let mut depth = 0usize;
+    let mut escaped = false;
+    for c in latex.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => {
+                depth += 1;
+                if depth > limits.max_depth {
+                    return Err(ConvertError::TooComplex(format!(
+                        "嵌套深度超过上限 {}",
+                        limits.max_depth
+                    )));
+                }
+            }
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 /// Attempt to extract an unsupported symbol name from the LaTeX error message.
 ///
 /// The `latex2mathml` crate returns errors for unknown commands or environments.
@@ -58,26 +150,63 @@ fn try_extract_unsupported_symbol(error: &latex2mathml::LatexError) -> Option<St
 /// LaTeX → MathML
 ///
 /// Converts a LaTeX math expression string into MathML markup using the
-/// `latex2mathml` crate with inline display style.
+/// `latex2mathml` crate, in inline or block style according to `display`
+/// (block output carries `display="block"` on the `<math>` element).
 ///
 /// # Preprocessing
 ///
 /// Before conversion, the input is preprocessed to handle commands that
 /// `latex2mathml` doesn't support:
+/// - stray alignment tabs (`&`) at the start or end of the formula are removed
 /// - `\displaystyle`, `\textstyle`, `\scriptstyle`, `\scriptscriptstyle` are removed
+/// - `\mathstrut` / `\strut` (invisible sizing struts) are removed
 /// - `\rlap{...}`, `\llap{...}` are replaced with their content
+/// - `\mathclap{...}` / `\clap{...}` are rewritten as a zero-width-space overscript
+///   (`\overset{\u{200B}}{...}`), written as a zero-width `<m:phant>`
 /// - `\quad`, `\qquad` are replaced with spaces
-/// - `array` environment is converted to `matrix`
+/// - `array` environment is converted to `matrix` (`\hline` / `\cline` rules are dropped)
+/// - `equation` / `equation*` wrappers, `\label{...}`, `\nonumber` and `\notag` are removed
+/// - `aligned` / `align*` / `split` / `eqnarray` environments are normalized to `align`
+/// - `cases` / `dcases` become a matrix opened by a lone `{` (written as a piecewise brace)
+/// - `\dfrac`, `\tfrac` and `\cfrac` (continued fractions) are rewritten as `\frac`
+/// - `\stackrel{top}{base}` is rewritten as `\overset{top}{base}`
+/// - infix `{n \choose k}` is rewritten as `\binom{n}{k}`
+/// - infix `{a \atop b}` is rewritten as a bar-less fraction (`<mfrac linethickness="0">`)
+/// - `\injlim` / `\projlim` are rewritten as upright `inj lim` / `proj lim` with any
+///   subscript placed below (`\underset{n}{\mathrm{inj\,lim}}`)
+/// - `\substack{i \\ j}` is rewritten as a one-column matrix, giving multi-line limits
+/// - `\mathring{x}` is rewritten as a ring accent (`\overset{˚}{x}`)
+/// - `\cancel` / `\bcancel` / `\xcancel` are rewritten as a diagonal-stroke overscript
+///   (`\overset{╱}{x}`), written as a struck-through `<m:borderBox>`
+/// - `\mathop{\op}` is unwrapped to `\op` (other `\mathop{...}` content becomes `\mathrm`)
+/// - `\left( ... \right)` pairs are kept (auto-sizing `<m:d>`); unpaired `\left` / `\right`
+///   and `\big`-style sizing commands are dropped, leaving the plain delimiter
+/// - `\left[ ... \right]_{a}^{b}` is grouped so the scripts attach to the whole group
 ///
 /// # Errors
 ///
+/// Returns `ConvertError::TooComplex` when the input exceeds the default
+/// `ComplexityLimits` (see `latex_to_mathml_with_limits`).
 /// Returns `ConvertError::UnsupportedSymbol` when the input contains a LaTeX
 /// command or environment that is not supported by the converter.
 /// Returns `ConvertError::LatexToMathml` for all other conversion failures
 /// (e.g. syntax errors, mismatched braces).
-pub fn latex_to_mathml(latex: &str) -> Result<String, ConvertError> {
+pub fn latex_to_mathml(latex: &str, display: ConvertDisplay) -> Result<String, ConvertError> {
+    latex_to_mathml_with_limits(latex, display, &ComplexityLimits::default())
+}
+
+/// LaTeX → MathML，使用自定义的输入规模限制
+///
+/// Same as `latex_to_mathml`, but inputs are checked against `limits`
+/// before any preprocessing.
+pub fn latex_to_mathml_with_limits(
+    latex: &str,
+    display: ConvertDisplay,
+    limits: &ComplexityLimits,
+) -> Result<String, ConvertError> {
+    check_complexity(latex, limits)?;
     let preprocessed = preprocess_latex(latex);
-    let mathml = latex2mathml::latex_to_mathml(&preprocessed, latex2mathml::DisplayStyle::Inline).map_err(|e| {
+    let mathml = latex2mathml::latex_to_mathml(&preprocessed, display.into()).map_err(|e| {
         if let Some(symbol) = try_extract_unsupported_symbol(&e) {
             ConvertError::UnsupportedSymbol(symbol)
         } else {
@@ -85,42 +214,188 @@ pub fn latex_to_mathml(latex: &str) -> Result<String, ConvertError> {
         }
     })?;
     
-    // Post-process MathML to fix msup/msub nesting issues
-    // Convert <msup><msub>base sub</msub> sup</msup> to <msubsup>base sub sup</msubsup>
-    let fixed_mathml = fix_mathml_subsup(&mathml);
-    
-    Ok(fixed_mathml)
+    // <msup><msub>base sub</msub> sup</msup> is merged into a sub-superscript
+    // structurally when the MathML is parsed (see parse_element "msup")
+    Ok(tag_eq_arrays(&strip_atop_parens(&mathml)))
 }
 
-/// Fix MathML structure: convert nested msup/msub to msubsup
-/// This fixes the issue where latex2mathml generates <msup><msub>...</msub>...</msup>
-/// instead of <msubsup>...</msubsup> for expressions like X_a^b
-fn fix_mathml_subsup(mathml: &str) -> String {
-    // Use regex to find and fix the pattern
-    // Pattern: <msup><msub>base sub</msub>sup</msup> -> <msubsup>base sub sup</msubsup>
-    
-    let re = match regex::Regex::new(
-        r"<msup>(\s*)<msub>(.*?)</msub>(\s*)(.*?)</msup>"
-    ) {
-        Ok(r) => r,
-        Err(_) => return mathml.to_string(),
+/// LaTeX → MathML（行内模式）
+///
+/// Shorthand for `latex_to_mathml(latex, ConvertDisplay::Inline)`.
+pub fn latex_to_mathml_inline(latex: &str) -> Result<String, ConvertError> {
+    latex_to_mathml(latex, ConvertDisplay::Inline)
+}
+
+/// Commands whose braced argument is set in text mode, where spaces are part
+/// of the content rather than insignificant math-mode whitespace.
+const TEXT_MODE_COMMANDS: &[&str] = &[
+    "text", "textrm", "textit", "textbf", "textsf", "texttt", "textup", "textnormal", "mbox", "hbox",
+];
+
+/// LaTeX 规范形式：去掉无意义的差异，供 `formula_hash` 使用
+///
+/// Math-mode whitespace is removed except where it separates two letters
+/// (e.g. `\alpha b`, where dropping it would change the command). A control
+/// space (`\ `) is kept, and inside a text-mode argument (`\text{...}`,
+/// `\mbox{...}`, ...) each run of whitespace collapses to one space. A script
+/// argument that is a single braced letter or digit loses its braces
+/// (`x^{2}` → `x^2`).
+pub fn canonical_latex(latex: &str) -> String {
+    let chars: Vec<char> = latex.trim().chars().collect();
+    let mut canonical = String::with_capacity(latex.len());
+    // Brace depth inside a text-mode argument (0 in math mode)
+    let mut text_depth = 0usize;
+    // A text-mode command was just seen and its argument has not opened yet
+    let mut text_pending = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            let start = i;
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            let before = chars[start - 1];
+            if text_depth > 0 || (before.is_ascii_alphabetic() && chars[i].is_ascii_alphabetic()) {
+                canonical.push(' ');
+            }
+            continue;
+        }
+        match c {
+            '\\' => {
+                // A command name, or one escaped character (`\{`, `\\`, control space)
+                let name_len = chars[i + 1..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+                let end = (i + 1 + name_len.max(1)).min(chars.len());
+                if name_len == 0 && chars.get(i + 1).is_some_and(|c| c.is_whitespace()) {
+                    canonical.push_str("\\ ");
+                } else {
+                    canonical.extend(&chars[i..end]);
+                }
+                let name: String = chars[i + 1..end].iter().collect();
+                text_pending = text_depth == 0 && TEXT_MODE_COMMANDS.contains(&name.as_str());
+                i = end;
+                continue;
+            }
+            '{' if text_depth > 0 => text_depth += 1,
+            '{' if text_pending => text_depth = 1,
+            '}' => text_depth = text_depth.saturating_sub(1),
+            _ => {}
+        }
+        text_pending = false;
+        canonical.push(c);
+        i += 1;
+    }
+    match regex::Regex::new(r"([\^_])\{([A-Za-z0-9])\}") {
+        Ok(re) => re.replace_all(&canonical, "$1$2").to_string(),
+        Err(_) => canonical,
+    }
+}
+
+/// 公式内容哈希（规范化后的 LaTeX 的 blake3 十六进制摘要）
+///
+/// Stable across runs and platforms; formulas with the same
+/// `canonical_latex` hash the same. History deduplication and merging both
+/// compare LaTeX through it.
+pub fn formula_hash(latex: &str) -> String {
+    blake3::hash(canonical_latex(latex).as_bytes()).to_hex().to_string()
+}
+
+/// 校验 LaTeX，列出所有不支持的命令与环境
+///
+/// Runs the same preprocessing as `latex_to_mathml`. An unknown environment
+/// aborts latex2mathml, so each one is recorded and its `\begin`/`\end`
+/// stripped before retrying; unknown commands do not abort and are collected
+/// from the parse-error markers left in the output. A failure that names no
+/// symbol (e.g. a missing `}`) is reported as its error message.
+pub fn validate_latex(latex: &str) -> Result<(), Vec<String>> {
+    fn record(found: &mut Vec<String>, name: String) {
+        if !found.contains(&name) {
+            found.push(name);
+        }
+    }
+
+    let mut source = preprocess_latex(latex);
+    let mut unsupported: Vec<String> = Vec::new();
+    let mathml = loop {
+        match latex2mathml::latex_to_mathml(&source, latex2mathml::DisplayStyle::Inline) {
+            Ok(mathml) => break Some(mathml),
+            Err(e) => {
+                let Some(symbol) = try_extract_unsupported_symbol(&e) else {
+                    record(&mut unsupported, ConvertError::LatexToMathml(e.to_string()).to_string());
+                    break None;
+                };
+                let stripped = if symbol.starts_with('\\') {
+                    source.replace(&symbol, "")
+                } else {
+                    source
+                        .replace(&format!(r"\begin{{{}}}", symbol), "")
+                        .replace(&format!(r"\end{{{}}}", symbol), "")
+                };
+                record(&mut unsupported, symbol);
+                if stripped == source {
+                    break None;
+                }
+                source = stripped;
+            }
+        }
     };
-    
-    // This simple regex won't handle nested cases well, so we need a more robust approach
-    // For now, let's use a simple string replacement approach
-    
-    let mut result = mathml.to_string();
-    
-    // Keep replacing until no more matches (handles nested cases)
+
+    if let (Some(mathml), Ok(re)) = (mathml, regex::Regex::new(r#"Command\(\\"([A-Za-z]+)\\"\)"#)) {
+        for caps in re.captures_iter(&mathml) {
+            record(&mut unsupported, format!("\\{}", &caps[1]));
+        }
+    }
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(unsupported)
+    }
+}
+
+/// 严格校验：在 `validate_latex` 的基础上报告首尾孤立的对齐符 `&`
+///
+/// `latex_to_mathml` silently drops such alignment tabs; strict callers that
+/// want the source itself fixed get `"&"` listed first.
+pub fn validate_latex_strict(latex: &str) -> Result<(), Vec<String>> {
+    let mut unsupported = validate_latex(latex).err().unwrap_or_default();
+    let trimmed = latex.trim();
+    if strip_stray_ampersands(trimmed) != trimmed {
+        unsupported.insert(0, "&".to_string());
+    }
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(unsupported)
+    }
+}
+
+/// Remove alignment tabs (`&`) at the very start or end of a formula.
+///
+/// An ampersand there cannot belong to an environment, so it is left over
+/// from a fragment cut out of an aligned equation. An escaped `\&` is kept.
+fn strip_stray_ampersands(latex: &str) -> &str {
+    let mut rest = latex;
     loop {
-        let new_result = re.replace_all(&result, "<msubsup>$1$2$3$4</msubsup>").to_string();
-        if new_result == result {
+        let before = rest;
+        if let Some(after) = rest.trim_start().strip_prefix('&') {
+            rest = after;
+        }
+        if let Some(front) = rest.trim_end().strip_suffix('&') {
+            let backslashes = front.chars().rev().take_while(|&c| c == '\\').count();
+            if backslashes % 2 == 0 {
+                rest = front;
+            }
+        }
+        if rest == before {
             break;
         }
-        result = new_result;
     }
-    
-    result
+    if rest.len() == latex.len() {
+        latex
+    } else {
+        rest.trim()
+    }
 }
 
 /// Preprocess LaTeX to remove/replace unsupported commands
@@ -145,6 +420,9 @@ fn preprocess_latex(latex: &str) -> String {
     result = result.trim_start_matches("$$").trim_end_matches("$$").to_string();
     result = result.trim_start_matches('$').trim_end_matches('$').to_string();
     
+    // Drop stray alignment tabs from fragments captured mid-equation: "& x = y" -> "x = y"
+    result = strip_stray_ampersands(&result).to_string();
+    
     // Fix \mathcal L -> \mathcal{L} (OCR often misses the braces)
     // Match \mathcal followed by a single letter without braces
     let mathcal_re = regex::Regex::new(r"\\mathcal\s+([A-Za-z])").ok();
@@ -176,20 +454,9 @@ fn preprocess_latex(latex: &str) -> String {
         }
     }
     
-    // Fix spaces in common function names: "l o g" -> "log", "g e n" -> "gen"
-    result = result.replace("l o g", "log");
-    result = result.replace("g e n", "gen");
-    result = result.replace("s i n", "sin");
-    result = result.replace("c o s", "cos");
-    result = result.replace("t a n", "tan");
-    result = result.replace("e x p", "exp");
-    result = result.replace("l n", "ln");
-    
-    // Fix spaced-out common words: "E n c" -> "Enc", "D e c" -> "Dec"
-    result = result.replace("E n c", "Enc");
-    result = result.replace("D e c", "Dec");
-    result = result.replace("C L S", "CLS");
-    result = result.replace("S E P", "SEP");
+    // Fix spaced-out words from OCR: "l o g" -> "log", "g e n" -> "gen", "E n c" -> "Enc"
+    // (only where they stand alone, so variable products like "a l n" survive)
+    result = collapse_spaced_words(&result);
     
     // Remove excessive \qquad (OCR often adds too many)
     let qquad_re = regex::Regex::new(r"(\\qquad\s*){3,}").ok();
@@ -225,17 +492,25 @@ fn preprocess_latex(latex: &str) -> String {
         result = result.replace(cmd, "");
     }
     
+    // Remove struts (\mathstrut, \strut): invisible, zero-width, only affect line height
+    if let Ok(re) = regex::Regex::new(r"\\(?:mathstrut|strut)\b") {
+        result = re.replace_all(&result, "").to_string();
+    }
+    
+    // Unwrap \mathop{\bigoplus} -> \bigoplus so custom big operators keep their limits
+    // (must run before \limits is stripped below)
+    result = replace_mathop(&result);
+    
     // Remove \limits and \nolimits commands (they only affect placement, not structure)
     // \prod\limits_{k=1} -> \prod_{k=1}
     result = result.replace(r"\limits", "");
     result = result.replace(r"\nolimits", "");
     
-    // Remove bracket sizing commands (they don't affect the math structure in OMML)
-    let sizing_commands = [
-        r"\Big", r"\big", r"\Bigg", r"\bigg",
-        r"\Big", r"\big", r"\Bigg", r"\bigg",
-        r"\left", r"\right",
-    ];
+    // Evaluation bars \left[ ... \right]_{a}^{b}: keep the delimiter pair as the script base
+    result = group_scripted_delimiters(&result);
+    
+    // Remove fixed bracket sizing commands (they don't affect the math structure in OMML)
+    let sizing_commands = [r"\Big", r"\big", r"\Bigg", r"\bigg"];
     for cmd in &sizing_commands {
         // Replace \Big( with just ( etc.
         result = result.replace(&format!("{}(", cmd), "(");
@@ -245,9 +520,18 @@ fn preprocess_latex(latex: &str) -> String {
         result = result.replace(&format!("{}{{", cmd), "{");
         result = result.replace(&format!("{}}}", cmd), "}");
         result = result.replace(&format!("{}|", cmd), "|");
-        result = result.replace(&format!("{}.", cmd), "");  // \left. \right. -> nothing
+        result = result.replace(&format!("{}.", cmd), "");  // \big. -> nothing
     }
     
+    // Piecewise definitions: cases -> matrix opened by a lone brace
+    for env in ["dcases", "cases"] {
+        result = result.replace(&format!(r"\begin{{{}}}", env), r"\left\{\begin{matrix}");
+        result = result.replace(&format!(r"\end{{{}}}", env), r"\end{matrix}\right.");
+    }
+    
+    // Keep \left ... \right pairs as auto-sizing delimiters; drop unpaired ones
+    result = normalize_left_right(&result);
+    
     // Replace old-style font commands with modern equivalents
     // \bf{...} -> \mathbf{...}, \it{...} -> \mathit{...}, etc.
     result = replace_font_command(&result, r"\bf", r"\mathbf");
@@ -257,7 +541,7 @@ fn preprocess_latex(latex: &str) -> String {
     result = replace_font_command(&result, r"\tt", r"\mathtt");
     result = replace_font_command(&result, r"\sf", r"\mathsf");
     
-    // Replace \operatorname{...} with \mathrm{...}
+    // Replace \operatorname{...} with {\mathrm{...}}
     // latex2mathml doesn't support \operatorname
     result = replace_operatorname(&result);
     
@@ -273,16 +557,66 @@ fn preprocess_latex(latex: &str) -> String {
     result = replace_command_with_content(&result, r"\rlap");
     result = replace_command_with_content(&result, r"\llap");
     
+    // latex2mathml has no \mathclap: mark the content with an invisible overscript
+    // so it is written as a shown, zero-width phantom
+    if let Ok(re) = regex::Regex::new(r"\\(?:mathclap|clap)\b") {
+        result = re.replace_all(&result, format!(r"\overset{{{}}}", ZERO_WIDTH_MARK).as_str()).to_string();
+    }
+    
     // Convert array environment to matrix (basic conversion)
     // \begin{array}{...} ... \end{array} -> \begin{matrix} ... \end{matrix}
     result = convert_array_to_matrix(&result);
     
+    // Drop equation wrappers (numbering has no meaning for a single formula)
+    result = strip_equation_environment(&result);
+    
+    // latex2mathml only understands `align`: map aligned/align*/split onto it
+    result = normalize_align_environments(&result);
+    // Tag each `align` so its table is read back as an equation array, not a matrix
+    result = result.replace(r"\begin{align}", &format!(r"\begin{{align}}{}", EQ_ARRAY_MARK));
+    
+    // {n \choose k} is the infix spelling of \binom{n}{k}
+    result = replace_choose(&result);
+    // {a \atop b} is a bar-less fraction without parentheses
+    result = replace_atop(&result);
+    
+    // latex2mathml has no \injlim / \projlim: spell them as upright names with the limit below
+    result = replace_named_limits(&result);
+    
+    // latex2mathml has no \substack: stack the limit rows as a one-column matrix
+    result = replace_substack(&result);
+    
+    // latex2mathml has no \mathring: spell it as a ring accent over the base
+    if let Ok(re) = regex::Regex::new(r"\\mathring\b") {
+        result = re.replace_all(&result, r"\overset{˚}").to_string();
+    }
+    
+    // latex2mathml has no \cancel: carry the strike direction as a stroke glyph overscript
+    for (cmd, stroke) in [("xcancel", STRIKE_BOTH), ("bcancel", STRIKE_DOWN), ("cancel", STRIKE_UP)] {
+        if let Ok(re) = regex::Regex::new(&format!(r"\\{}\b", cmd)) {
+            result = re.replace_all(&result, format!(r"\overset{{{}}}", stroke).as_str()).to_string();
+        }
+    }
+    
+    // latex2mathml only knows \frac: \dfrac / \tfrac / \cfrac (and \cfrac's [l]/[r]
+    // numerator alignment) become plain fractions, so continued fractions nest as <m:f>
+    if let Ok(re) = regex::Regex::new(r"\\[dtc]frac(?:\s*\[[lrc]\])?([^a-zA-Z]|$)") {
+        result = re.replace_all(&result, r"\frac$1").to_string();
+    }
+    
+    // \stackrel{!}{=} is the legacy spelling of \overset{!}{=}
+    result = result.replace(r"\stackrel", r"\overset");
+    
+    // Derivative primes: f'' -> f^{″}, f'^2 -> f^{′2}
+    result = normalize_primes(&result);
+    
     // Fix subscript-superscript order for latex2mathml
     // X_{sub}^{sup} -> {X_{sub}}^{sup} to ensure correct MathML structure
     result = fix_subsup_order(&result);
     
     // Remove empty braces that might result from preprocessing
-    result = result.replace("{}", "");
+    // (but not empty script/command arguments: x^{} and \frac{}{2} keep their slots)
+    result = remove_empty_groups(&result);
     
     // Clean up multiple spaces
     while result.contains("  ") {
@@ -292,6 +626,150 @@ fn preprocess_latex(latex: &str) -> String {
     result.trim().to_string()
 }
 
+/// Words OCR tends to spell out letter by letter, with their collapsed form
+const SPACED_WORDS: &[(&str, &str)] = &[
+    ("l o g", "log"),
+    ("g e n", "gen"),
+    ("s i n", "sin"),
+    ("c o s", "cos"),
+    ("t a n", "tan"),
+    ("e x p", "exp"),
+    ("l n", "ln"),
+    ("E n c", "Enc"),
+    ("D e c", "Dec"),
+    ("C L S", "CLS"),
+    ("S E P", "SEP"),
+];
+
+/// Collapse spelled-out words ("l o g" -> "log") that stand alone as a token
+///
+/// A run only counts when no letter touches it through spaces on either side,
+/// so a product of single-letter variables (`a l n`, `\alpha l n \beta`) is
+/// left alone. Braces, parentheses and operators are boundaries, and the word
+/// may be followed by a sizing command (`l o g \left(`).
+fn collapse_spaced_words(latex: &str) -> String {
+    let mut result = latex.to_string();
+    for &(spaced, word) in SPACED_WORDS {
+        let mut from = 0;
+        while let Some(rel) = result[from..].find(spaced) {
+            let start = from + rel;
+            let end = start + spaced.len();
+            let before = result[..start].trim_end().chars().next_back();
+            let after = result[end..].trim_start();
+            let after_ok = match after.chars().next() {
+                None => true,
+                Some('\\') => ["left", "right", "big", "Big"]
+                    .iter()
+                    .any(|cmd| after[1..].starts_with(cmd)),
+                Some(c) => !c.is_ascii_alphabetic(),
+            };
+            if !before.is_some_and(|c| c.is_ascii_alphabetic()) && after_ok {
+                result.replace_range(start..end, word);
+                from = start + word.len();
+            } else {
+                from = end;
+            }
+        }
+    }
+    result
+}
+
+/// Remove `{}` groups that don't delimit an argument
+///
+/// A `{}` right after `^`, `_`, another group's `}` or a `\command` is an
+/// argument (`x^{}`, `\frac{a}{}`, `\sqrt{}`); dropping it would shift the
+/// following tokens into that slot, so those are kept.
+fn remove_empty_groups(latex: &str) -> String {
+    let mut result = String::with_capacity(latex.len());
+    let mut rest = latex;
+
+    while let Some(pos) = rest.find("{}") {
+        let before = &rest[..pos];
+        let prev = before.trim_end();
+        let after_command = prev.ends_with(|c: char| c.is_ascii_alphabetic())
+            && prev
+                .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+                .ends_with('\\');
+        let is_argument = after_command || prev.ends_with(['^', '_', '}']);
+        result.push_str(before);
+        if is_argument {
+            result.push_str("{}");
+        }
+        rest = &rest[pos + 2..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Turn apostrophe primes into prime superscripts: `f''` -> `f^{″}`
+///
+/// latex2mathml only understands a lone `'`, so a run of apostrophes becomes
+/// the matching Unicode prime (′ ″ ‴ ⁗) in a superscript on the preceding
+/// base. A superscript right after the primes joins the same group, as in
+/// TeX: `f'^2` -> `f^{′2}`. Text-mode arguments (`\text{don't}`) and a
+/// leading apostrophe with no base are left untouched.
+fn normalize_primes(latex: &str) -> String {
+    const PRIMES: [&str; 4] = ["′", "″", "‴", "⁗"];
+
+    let mut result = String::with_capacity(latex.len());
+    let mut rest = latex;
+
+    while let Some(pos) = rest.find(['\'', '\\']) {
+        let (before, tail) = rest.split_at(pos);
+        result.push_str(before);
+
+        if let Some(command) = tail.strip_prefix('\\') {
+            // Copy the command through; text-mode commands take their argument with them
+            let name_len = command.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+            let name = &command[..name_len];
+            let mut end = 1 + name_len;
+            if name_len == 0 {
+                end += command.chars().next().map_or(0, char::len_utf8);
+            } else if name.starts_with("text") || name == "mbox" {
+                let arg = &tail[end..];
+                let lead = arg.len() - arg.trim_start().len();
+                if let Some(close) = find_matching_brace(arg, lead) {
+                    end += close + 1;
+                }
+            }
+            result.push_str(&tail[..end]);
+            rest = &tail[end..];
+            continue;
+        }
+
+        let count = tail.bytes().take_while(|&b| b == b'\'').count();
+        if result.trim_end().is_empty() {
+            result.push_str(&tail[..count]);
+            rest = &tail[count..];
+            continue;
+        }
+
+        let primes = if count <= PRIMES.len() {
+            PRIMES[count - 1].to_string()
+        } else {
+            PRIMES[0].repeat(count)
+        };
+        let mut after = &tail[count..];
+        let mut exponent = "";
+        if let Some(sup) = after.trim_start().strip_prefix('^') {
+            if let Some(len) = script_argument_len(sup) {
+                let arg = sup[..len].trim_start();
+                exponent = arg
+                    .strip_prefix('{')
+                    .and_then(|a| a.strip_suffix('}'))
+                    .unwrap_or(arg);
+                after = &sup[len..];
+            }
+        }
+        result.push_str(&format!("^{{{}{}}}", primes, exponent));
+        rest = after;
+    }
+
+    result.push_str(rest);
+    result
+}
+
 /// Fix subscript-superscript order for latex2mathml
 /// Converts X_{sub}^{sup} to {X_{sub}}^{sup} to ensure correct MathML structure
 /// This is needed because latex2mathml incorrectly nests msub inside msup for X_a^b
@@ -317,12 +795,21 @@ fn fix_subsup_order(latex: &str) -> String {
     };
     let result = re2.replace_all(&result, "$1{$2_$3}$4").to_string();
     
-    // Handle command with braces as base: \cmd{x}_{sub}^{sup} -> {\cmd{x}_{sub}}^{sup}
-    let re3 = match regex::Regex::new(r"(\\[a-zA-Z]+\{[^}]*\})(_\{[^}]*\})(\^\{[^}]*\})") {
+    // Handle single char superscript: A_{sub}^s / A_a^s -> {A_{sub}}^s / {A_a}^s
+    let re4 = match regex::Regex::new(r"(^|[^a-zA-Z\\])([A-Za-z])(_(?:\{[^}]*\}|[A-Za-z0-9]))(\^[A-Za-z0-9])") {
+        Ok(r) => r,
+        Err(_) => return result,
+    };
+    let result = re4.replace_all(&result, "$1{$2$3}$4").to_string();
+    
+    // Handle command with braces as base: \cmd{x}_{sub}^{sup} -> {{\cmd{x}}_{sub}}^{sup}
+    // The command itself is braced too: latex2mathml otherwise puts an accent
+    // over the whole script (\tilde{E}_k -> mover(msub(E, k), ~))
+    let re3 = match regex::Regex::new(r"(\\[a-zA-Z]+\{[^}]*\})(_(?:\{[^}]*\}|[A-Za-z0-9]))(\^(?:\{[^}]*\}|[A-Za-z0-9]))") {
         Ok(r) => r,
         Err(_) => return result,
     };
-    let result = re3.replace_all(&result, "{$1$2}$3").to_string();
+    let result = re3.replace_all(&result, "{{$1}$2}$3").to_string();
     
     result
 }
@@ -415,7 +902,7 @@ fn replace_mathcal(latex: &str) -> String {
     result
 }
 
-/// Replace \operatorname{...} with \mathrm{...}
+/// Replace \operatorname{...} with {\mathrm{...}}
 fn replace_operatorname(latex: &str) -> String {
     let mut result = String::new();
     let mut chars = latex.chars().peekable();
@@ -473,8 +960,9 @@ fn replace_operatorname(latex: &str) -> String {
                         }
                     }
                     
-                    // Output as \mathrm{content}
-                    result.push_str(&format!("\\mathrm{{{}}}", content));
+                    // Output as {\mathrm{content}}: the group keeps the name upright
+                    // when a subscript follows (latex2mathml drops the variant on \mathrm{X}_n)
+                    result.push_str(&format!("{{\\mathrm{{{}}}}}", content));
                 } else {
                     // No brace, just output \mathrm
                     result.push_str("\\mathrm");
@@ -512,6 +1000,273 @@ fn replace_font_command(latex: &str, old_cmd: &str, new_cmd: &str) -> String {
     result
 }
 
+/// Replace \mathop{...} (unsupported by latex2mathml)
+/// \mathop{\bigoplus} -> \bigoplus (a single operator command keeps its n-ary limits)
+/// \mathop{rank} -> \mathrm{rank}
+fn replace_mathop(latex: &str) -> String {
+    let mut result = latex.to_string();
+    let cmd = r"\mathop";
+    let mut search_from = 0;
+
+    while let Some(rel) = result[search_from..].find(cmd) {
+        let start = search_from + rel;
+        let after_cmd = start + cmd.len();
+        // Skip \mathopen etc.
+        if result[after_cmd..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            search_from = after_cmd;
+            continue;
+        }
+        let brace = after_cmd + (result[after_cmd..].len() - result[after_cmd..].trim_start().len());
+        let Some(end) = find_matching_brace(&result, brace) else {
+            search_from = after_cmd;
+            continue;
+        };
+
+        let content = result[brace + 1..end].trim();
+        let is_single_command = content.len() > 1
+            && content.starts_with('\\')
+            && content[1..].chars().all(|c| c.is_ascii_alphabetic());
+        let replacement = if is_single_command {
+            content.to_string()
+        } else {
+            format!(r"\mathrm{{{}}}", content)
+        };
+        result = format!("{}{}{}", &result[..start], replacement, &result[end + 1..]);
+        search_from = start + replacement.len();
+    }
+
+    result
+}
+
+/// Wrap `\left<open> ... \right<close>` followed by `_`/`^` scripts in braces
+/// \left[ F \right]_{a}^{b} -> {{\left[ F \right]}_{a}}^{b}
+///
+/// latex2mathml otherwise attaches the scripts to the closing delimiter only.
+fn group_scripted_delimiters(latex: &str) -> String {
+    let mut result = String::new();
+    let mut rest = latex;
+
+    while let Some(start) = find_command(rest, r"\left") {
+        result.push_str(&rest[..start]);
+        let group = &rest[start..];
+        let Some(close_end) = matching_right_end(group) else {
+            result.push_str(group);
+            return result;
+        };
+
+        let mut pos = close_end;
+        let mut sub = None;
+        let mut sup = None;
+        while let Some(marker) = group[pos..].trim_start().chars().next() {
+            let marker_pos = group.len() - group[pos..].trim_start().len();
+            let slot = match marker {
+                '_' if sub.is_none() => &mut sub,
+                '^' if sup.is_none() => &mut sup,
+                _ => break,
+            };
+            let Some(len) = script_argument_len(&group[marker_pos + 1..]) else {
+                break;
+            };
+            *slot = Some(group[marker_pos + 1..marker_pos + 1 + len].trim().to_string());
+            pos = marker_pos + 1 + len;
+        }
+
+        if sub.is_none() && sup.is_none() {
+            // No scripts: leave the pair as it is
+            result.push_str(r"\left");
+            rest = &group[r"\left".len()..];
+            continue;
+        }
+
+        let right = group[..close_end].rfind(r"\right").unwrap_or(0);
+        let inner = group_scripted_delimiters(&group[r"\left".len()..right]);
+        let fenced = format!(
+            r"\left{}\right{}",
+            inner,
+            &group[right + r"\right".len()..close_end]
+        );
+        let braced = |arg: &str| {
+            if arg.starts_with('{') {
+                arg.to_string()
+            } else {
+                format!("{{{}}}", arg)
+            }
+        };
+        let grouped = match (sub, sup) {
+            (Some(sub), Some(sup)) => format!("{{{{{}}}_{}}}^{}", fenced, braced(&sub), braced(&sup)),
+            (Some(sub), None) => format!("{{{}}}_{}", fenced, braced(&sub)),
+            (None, Some(sup)) => format!("{{{}}}^{}", fenced, braced(&sup)),
+            (None, None) => unreachable!(),
+        };
+        result.push_str(&grouped);
+        rest = &group[pos..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Keep `\left` / `\right` pairs so the OMML delimiter grows with its content
+///
+/// Delimiter spellings latex2mathml cannot size are normalized (`\lvert` -> `|`,
+/// bare `{` -> `\{`). A `\left` or `\right` without a partner, or a pair whose
+/// delimiter has no sized form, loses the sizing command and keeps its
+/// delimiter as a plain character (`.` disappears).
+fn normalize_left_right(latex: &str) -> String {
+    // (start, end, is_left, delimiter) for every \left / \right
+    let mut tokens: Vec<(usize, usize, bool, &str)> = Vec::new();
+    let mut pos = 0;
+    loop {
+        let left = find_command(&latex[pos..], r"\left").map(|p| (pos + p, true));
+        let right = find_command(&latex[pos..], r"\right").map(|p| (pos + p, false));
+        let (start, is_left) = match (left, right) {
+            (Some(l), Some(r)) => {
+                if l.0 < r.0 {
+                    l
+                } else {
+                    r
+                }
+            }
+            (Some(t), None) | (None, Some(t)) => t,
+            (None, None) => break,
+        };
+        let command_end = start + if is_left { r"\left".len() } else { r"\right".len() };
+        let after = &latex[command_end..];
+        let lead = after.len() - after.trim_start().len();
+        let delimiter_end = command_end + lead + delimiter_len(&after[lead..]).unwrap_or(0);
+        tokens.push((start, delimiter_end, is_left, &latex[command_end + lead..delimiter_end]));
+        pos = delimiter_end;
+    }
+
+    let mut sized = vec![false; tokens.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for (i, &(_, _, is_left, _)) in tokens.iter().enumerate() {
+        if is_left {
+            open.push(i);
+        } else if let Some(l) = open.pop() {
+            let both_sized =
+                sized_delimiter(tokens[l].3).is_some() && sized_delimiter(tokens[i].3).is_some();
+            sized[l] = both_sized;
+            sized[i] = both_sized;
+        }
+    }
+
+    let mut result = String::with_capacity(latex.len());
+    let mut copied = 0;
+    for (&(start, end, is_left, delimiter), &keep) in tokens.iter().zip(&sized) {
+        result.push_str(&latex[copied..start]);
+        match (keep, sized_delimiter(delimiter)) {
+            (true, Some(sized)) => {
+                result.push_str(if is_left { r"\left" } else { r"\right" });
+                result.push_str(sized);
+            }
+            _ => result.push_str(match delimiter {
+                "." => "",
+                "{" => r"\{",
+                "}" => r"\}",
+                other => other,
+            }),
+        }
+        copied = end;
+    }
+    result.push_str(&latex[copied..]);
+    result
+}
+
+/// The spelling latex2mathml accepts after `\left` / `\right` for a delimiter
+fn sized_delimiter(delimiter: &str) -> Option<&'static str> {
+    Some(match delimiter {
+        "(" => "(",
+        ")" => ")",
+        "[" => "[",
+        "]" => "]",
+        "." => ".",
+        "|" | r"\vert" | r"\lvert" | r"\rvert" => "|",
+        r"\|" | r"\Vert" | r"\lVert" | r"\rVert" => r"\|",
+        "{" | r"\{" | r"\lbrace" => r"\{",
+        "}" | r"\}" | r"\rbrace" => r"\}",
+        "<" | r"\langle" => r"\langle",
+        ">" | r"\rangle" => r"\rangle",
+        r"\lfloor" => r"\lfloor",
+        r"\rfloor" => r"\rfloor",
+        r"\lceil" => r"\lceil",
+        r"\rceil" => r"\rceil",
+        _ => return None,
+    })
+}
+
+/// Find `cmd` not followed by a letter (so `\left` does not match `\leftarrow`)
+fn find_command(s: &str, cmd: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(rel) = s[from..].find(cmd) {
+        let pos = from + rel;
+        let after = pos + cmd.len();
+        if !s[after..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Some(pos);
+        }
+        from = after;
+    }
+    None
+}
+
+/// Length of a delimiter token after `\left` / `\right` (`(`, `.`, `\{`, `\rangle`)
+fn delimiter_len(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices();
+    match chars.next()? {
+        (_, '\\') => {
+            let name = s[1..].chars().take_while(|c| c.is_ascii_alphabetic()).count();
+            if name > 0 {
+                Some(1 + name)
+            } else {
+                s[1..].chars().next().map(|c| 1 + c.len_utf8())
+            }
+        }
+        (_, c) if c.is_whitespace() => None,
+        (_, c) => Some(c.len_utf8()),
+    }
+}
+
+/// End offset (exclusive) of the `\right<close>` matching the `\left` at the start of `s`
+fn matching_right_end(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut pos = 0;
+    loop {
+        let left = find_command(&s[pos..], r"\left").map(|p| pos + p);
+        let right = find_command(&s[pos..], r"\right").map(|p| pos + p);
+        match (left, right) {
+            (Some(l), Some(r)) if l < r => {
+                depth += 1;
+                pos = l + r"\left".len();
+            }
+            (Some(l), None) => {
+                depth += 1;
+                pos = l + r"\left".len();
+            }
+            (_, Some(r)) => {
+                depth = depth.checked_sub(1)?;
+                let after = r + r"\right".len();
+                if depth == 0 {
+                    return Some(after + delimiter_len(&s[after..])?);
+                }
+                pos = after;
+            }
+            (None, None) => return None,
+        }
+    }
+}
+
+/// Length of a script argument after `_` / `^`: `{...}`, `\cmd` or a single character
+fn script_argument_len(s: &str) -> Option<usize> {
+    let lead = s.len() - s.trim_start().len();
+    let body = &s[lead..];
+    let len = match body.chars().next()? {
+        '{' => find_matching_brace(body, 0)? + 1,
+        '\\' => delimiter_len(body)?,
+        c => c.len_utf8(),
+    };
+    Some(lead + len)
+}
+
 /// Replace a command like \rlap{content} with just content
 fn replace_command_with_content(latex: &str, cmd: &str) -> String {
     let mut result = String::new();
@@ -618,41 +1373,232 @@ fn convert_array_to_matrix(latex: &str) -> String {
     // Replace \end{array} with \end{matrix}
     result = result.replace(r"\end{array}", r"\end{matrix}");
     
+    // Drop horizontal rules (\hline, \hdashline, \cline{i-j}): OMML matrices have no row borders
+    if let Ok(re) = regex::Regex::new(r"\\(?:hline|hdashline)\b|\\cline\{[^}]*\}") {
+        result = re.replace_all(&result, "").to_string();
+    }
+    // A rule after the last row leaves `\\` before the end: don't emit an empty row
+    if let Ok(re) = regex::Regex::new(r"\\\\\s*(\\end\{matrix\})") {
+        result = re.replace_all(&result, "$1").to_string();
+    }
+    
     result
 }
 
-/// Find the position of the matching closing brace
-fn find_matching_brace(s: &str, open_pos: usize) -> Option<usize> {
-    let bytes = s.as_bytes();
-    if bytes.get(open_pos) != Some(&b'{') {
-        return None;
+/// Remove `equation` / `equation*` wrappers and numbering commands
+/// \begin{equation}\begin{split}...\end{split}\end{equation} -> \begin{split}...\end{split}
+fn strip_equation_environment(latex: &str) -> String {
+    let mut result = latex.to_string();
+    for env in ["equation*", "equation"] {
+        result = result.replace(&format!(r"\begin{{{}}}", env), "");
+        result = result.replace(&format!(r"\end{{{}}}", env), "");
     }
-    
-    let mut depth = 1;
-    for (i, &b) in bytes.iter().enumerate().skip(open_pos + 1) {
-        match b {
-            b'{' => depth += 1,
-            b'}' => {
-                depth -= 1;
-                if depth == 0 {
-                    return Some(i);
+    if let Ok(re) = regex::Regex::new(r"\\label\{[^{}]*\}") {
+        result = re.replace_all(&result, "").to_string();
+    }
+    result = result.replace(r"\nonumber", "");
+    result = result.replace(r"\notag", "");
+    result
+}
+
+/// Rewrite infix `{a \choose b}` as `\binom{a}{b}`
+fn replace_choose(latex: &str) -> String {
+    replace_infix_fraction(latex, r"\choose", |num, den| format!(r"\binom{{{}}}{{{}}}", num, den))
+}
+
+/// Rewrite infix `{a \atop b}` as a bar-less fraction
+///
+/// latex2mathml has no bar-less fraction without parentheses, so `\binom{a}{b}`
+/// is emitted between `ATOP_OPEN` / `ATOP_CLOSE` markers; `strip_atop_parens`
+/// removes the markers and the parentheses from the generated MathML.
+fn replace_atop(latex: &str) -> String {
+    replace_infix_fraction(latex, r"\atop", |num, den| {
+        format!(r"{}\binom{{{}}}{{{}}}{}", ATOP_OPEN, num, den, ATOP_CLOSE)
+    })
+}
+
+/// Rewrite an infix fraction command (`\choose`, `\atop`) with `fraction(numerator, denominator)`
+///
+/// The operands run to the enclosing group's braces, or to the ends of the
+/// expression when the command is not inside a group.
+fn replace_infix_fraction(latex: &str, cmd: &str, fraction: impl Fn(&str, &str) -> String) -> String {
+    let mut result = latex.to_string();
+    while let Some(pos) = find_command(&result, cmd) {
+        let bytes = result.as_bytes();
+        let mut depth = 0usize;
+        let mut open = None;
+        for i in (0..pos).rev() {
+            match bytes[i] {
+                b'}' => depth += 1,
+                b'{' if depth == 0 => {
+                    open = Some(i);
+                    break;
                 }
+                b'{' => depth -= 1,
+                _ => {}
             }
-            _ => {}
         }
+        let (start, end) = match open {
+            Some(open) => match find_matching_brace(&result, open) {
+                Some(close) => (open + 1, close),
+                None => break,
+            },
+            None => (0, result.len()),
+        };
+        let numerator = result[start..pos].trim();
+        let denominator = result[pos + cmd.len()..end].trim();
+        let replacement = fraction(numerator, denominator);
+        result.replace_range(start..end, &replacement);
     }
-    None
+    result
 }
 
-// ---------------------------------------------------------------------------
-// MathML → OMML conversion
-// ---------------------------------------------------------------------------
-
-/// Intermediate representation of a parsed MathML tree node.
+/// Rewrite `\injlim_{n}` as `\underset{n}{\mathrm{inj\,lim}}` (and `\projlim` alike)
+///
+/// The subscript may be a braced group, a command or a single character; without
+/// one only the upright name is written.
+fn replace_named_limits(latex: &str) -> String {
+    let mut result = latex.to_string();
+    for (cmd, name) in [(r"\injlim", r"\mathrm{inj\,lim}"), (r"\projlim", r"\mathrm{proj\,lim}")] {
+        while let Some(pos) = find_command(&result, cmd) {
+            let after = pos + cmd.len();
+            let rest = &result[after..];
+            let script = rest.trim_start().strip_prefix('_').map(|s| s.trim_start());
+            let (end, replacement) = match script {
+                Some(arg) => {
+                    let arg_start = result.len() - arg.len();
+                    let arg_len = match arg.chars().next() {
+                        Some('{') => find_matching_brace(arg, 0).map(|close| close + 1),
+                        Some('\\') => Some(1 + arg[1..].find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(arg.len() - 1)),
+                        Some(c) => Some(c.len_utf8()),
+                        None => None,
+                    };
+                    match arg_len {
+                        Some(len) => {
+                            let sub = &result[arg_start..arg_start + len];
+                            let sub = sub.strip_prefix('{').and_then(|s| s.strip_suffix('}')).unwrap_or(sub);
+                            (arg_start + len, format!(r"\underset{{{}}}{{{}}}", sub, name))
+                        }
+                        None => (after, name.to_string()),
+                    }
+                }
+                None => (after, name.to_string()),
+            };
+            result.replace_range(pos..end, &replacement);
+        }
+    }
+    result
+}
+
+/// Rewrite `\substack{a \\ b}` as a one-column matrix `{\begin{matrix}a \\ b\end{matrix}}`
+///
+/// Used for multi-line limits under `\sum`, `\prod`, `\int`, ...
+fn replace_substack(latex: &str) -> String {
+    let mut result = latex.to_string();
+    while let Some(pos) = find_command(&result, r"\substack") {
+        let after = pos + r"\substack".len();
+        let open = after + result[after..].len() - result[after..].trim_start().len();
+        let Some(close) = find_matching_brace(&result, open) else {
+            break;
+        };
+        let rows = format!(r"{{\begin{{matrix}}{}\end{{matrix}}}}", &result[open + 1..close]);
+        result.replace_range(pos..=close, &rows);
+    }
+    result
+}
+
+/// Normalize alignment environments to `align`
+/// \begin{aligned} / \begin{align*} / \begin{split} -> \begin{align}
+/// \begin{eqnarray} a &=& b \end{eqnarray} -> \begin{align} a &= b \end{align}
+fn normalize_align_environments(latex: &str) -> String {
+    let mut result = latex.to_string();
+    for env in ["eqnarray*", "eqnarray"] {
+        result = rewrite_eqnarray(&result, env);
+    }
+    for env in ["aligned", "align*", "split"] {
+        result = result.replace(&format!(r"\begin{{{}}}", env), r"\begin{align}");
+        result = result.replace(&format!(r"\end{{{}}}", env), r"\end{align}");
+    }
+    result
+}
+
+/// Rewrite an `eqnarray` body (three columns, `lhs & rel & rhs`) as `align`
+/// rows (`lhs &rel rhs`) by dropping the second alignment `&` of each row.
+fn rewrite_eqnarray(latex: &str, env: &str) -> String {
+    let begin = format!(r"\begin{{{}}}", env);
+    let end = format!(r"\end{{{}}}", env);
+    let mut result = String::with_capacity(latex.len());
+    let mut rest = latex;
+
+    while let Some(start) = rest.find(&begin) {
+        let body_start = start + begin.len();
+        let Some(body_len) = rest[body_start..].find(&end) else {
+            break;
+        };
+        let body = &rest[body_start..body_start + body_len];
+        let rows: Vec<String> = body
+            .split(r"\\")
+            .map(|row| {
+                let mut seen = 0;
+                let mut escaped = false;
+                row.chars()
+                    .filter(|&c| {
+                        let is_marker = c == '&' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        if is_marker {
+                            seen += 1;
+                        }
+                        !(is_marker && seen == 2)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        result.push_str(&rest[..start]);
+        result.push_str(r"\begin{align}");
+        result.push_str(&rows.join(r"\\"));
+        result.push_str(r"\end{align}");
+        rest = &rest[body_start + body_len + end.len()..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Find the position of the matching closing brace
+fn find_matching_brace(s: &str, open_pos: usize) -> Option<usize> {
+    let bytes = s.as_bytes();
+    if bytes.get(open_pos) != Some(&b'{') {
+        return None;
+    }
+    
+    let mut depth = 1;
+    for (i, &b) in bytes.iter().enumerate().skip(open_pos + 1) {
+        match b {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// MathML → OMML conversion
+// ---------------------------------------------------------------------------
+
+/// Intermediate representation of a parsed MathML tree node.
 #[derive(Debug, Clone)]
 enum MathNode {
     /// An identifier (`<mi>`)
     Mi(String),
+    /// An upright identifier (`<mi mathvariant="normal">`, from `\mathrm` / `\operatorname`)
+    Upright(String),
     /// A number (`<mn>`)
     Mn(String),
     /// An operator (`<mo>`)
@@ -663,6 +1609,10 @@ enum MathNode {
     Mrow(Vec<MathNode>),
     /// Fraction (`<mfrac>`) with numerator and denominator
     Mfrac(Box<MathNode>, Box<MathNode>),
+    /// Fraction without a bar (`<mfrac linethickness="0">`, e.g. from `\binom`);
+    /// the last field is the display style from an enclosing `<mstyle>`
+    /// (`\dbinom` / `\tbinom`), `None` when unstyled
+    NoBarFrac(Box<MathNode>, Box<MathNode>, Option<bool>),
     /// Square root (`<msqrt>`)
     Msqrt(Vec<MathNode>),
     /// Nth root (`<mroot>`) with base and index
@@ -679,14 +1629,48 @@ enum MathNode {
     Munder(Box<MathNode>, Box<MathNode>),
     /// Under-over (`<munderover>`)
     Munderover(Box<MathNode>, Box<MathNode>, Box<MathNode>),
+    /// N-ary operator (`\sum`, `\int`, ...) with its limits and the operand
+    /// that follows it in the row; `display` comes from an enclosing
+    /// `<mstyle displaystyle>` (`None` when no style applies)
+    Nary {
+        chr: String,
+        sub: Option<Box<MathNode>>,
+        sup: Option<Box<MathNode>>,
+        body: Vec<MathNode>,
+        display: Option<bool>,
+    },
+    /// Grouping brace, parenthesis or tortoise shell over or under a base
+    /// (`\overbrace`, `\underparen`, ...), with the optional label set beyond it
+    GroupChr {
+        base: Box<MathNode>,
+        label: Option<Box<MathNode>>,
+        over: bool,
+        chr: char,
+    },
+    /// Struck-through base (`\cancel`, `\bcancel`, `<menclose>` diagonal strikes);
+    /// `up` strikes bottom-left to top-right, `down` top-left to bottom-right
+    Strike {
+        base: Box<MathNode>,
+        up: bool,
+        down: bool,
+    },
+    /// Content shown with zero advance width (`\mathclap`, `<mpadded width="0">`)
+    ZeroWidth(Box<MathNode>),
     /// Table / matrix (`<mtable>`)
     Mtable(Vec<Vec<MathNode>>),
+    /// Equation array (`<mtable data-env="align">` from `align`), rows of cells
+    EqArray(Vec<Vec<MathNode>>),
+    /// Piecewise definition (`cases`: a table fenced by `{` alone), rows of
+    /// `[value, condition]` cells
+    Cases(Vec<Vec<MathNode>>),
     /// Fenced expression (`<mfenced>`) with open, close delimiters and children
     Mfenced {
         open: String,
         close: String,
         children: Vec<MathNode>,
     },
+    /// Stretchy delimiter (`<mo stretchy="true">` from `\left` / `\right`)
+    Fence(String),
     /// Space (`<mspace>`) – mostly ignored
     Mspace,
     /// Raw text that doesn't fit other categories
@@ -697,22 +1681,219 @@ enum MathNode {
 fn is_large_operator(s: &str) -> bool {
     matches!(
         s,
-        "∫" | "∬" | "∭" | "∮" | "∑" | "∏" | "⋃" | "⋂" | "⋁" | "⋀"
+        "∫" | "∬" | "∭" | "∮" | "∑" | "∏" | "∐" | "⋃" | "⋂" | "⋁" | "⋀"
+            | "⨁" | "⨂" | "⨀" | "⨄" | "⨆"
     )
 }
 
-/// Check if a string represents a common accent character.
-fn is_accent_char(s: &str) -> bool {
+/// LaTeX accent commands: the glyph latex2mathml places over the base in
+/// `<mover>`, and the combining character Word expects in `<m:chr>`.
+const ACCENTS: &[(&str, &str, char)] = &[
+    (r"\hat", "^", '\u{0302}'),
+    (r"\widehat", "^", '\u{0302}'),
+    (r"\check", "ˇ", '\u{030C}'),
+    (r"\tilde", "~", '\u{0303}'),
+    (r"\widetilde", "~", '\u{0303}'),
+    (r"\acute", "´", '\u{0301}'),
+    (r"\grave", "`", '\u{0300}'),
+    (r"\dot", "˙", '\u{0307}'),
+    (r"\ddot", "¨", '\u{0308}'),
+    (r"\breve", "˘", '\u{0306}'),
+    (r"\bar", "¯", '\u{0305}'),
+    (r"\vec", "→", '\u{20D7}'),
+    (r"\mathring", "˚", '\u{030A}'),
+];
+
+/// OMML accent character for the text over an `<mover>` base, if it is an accent.
+///
+/// Spacing glyphs (`^`, `¯`, `→`) map through `ACCENTS`; a combining mark
+/// (e.g. from pasted MathML) is already what Word expects and is kept.
+fn accent_chr(s: &str) -> Option<char> {
+    if let Some(&(_, _, chr)) = ACCENTS.iter().find(|(_, glyph, _)| *glyph == s) {
+        return Some(chr);
+    }
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ ('\u{0300}'..='\u{036F}' | '\u{20D0}'..='\u{20FF}')), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Top brace drawn by `\overbrace`.
+const OVER_BRACE: char = '\u{23DE}';
+/// Bottom brace drawn by `\underbrace`.
+const UNDER_BRACE: char = '\u{23DF}';
+
+/// Top parenthesis drawn by `\overparen`.
+const OVER_PAREN: char = '\u{23DC}';
+/// Bottom parenthesis drawn by `\underparen`.
+const UNDER_PAREN: char = '\u{23DD}';
+/// Top tortoise shell bracket.
+const OVER_TORTOISE: char = '\u{23E0}';
+/// Bottom tortoise shell bracket.
+const UNDER_TORTOISE: char = '\u{23E1}';
+
+/// Grouping character for an `<mover>`/`<munder>` script, if it is a brace,
+/// parenthesis or tortoise shell.
+///
+/// Presentation forms (`︷`, `︵`, `︹`, ...) from pasted MathML map to the same characters.
+fn group_chr(node: &MathNode) -> Option<char> {
+    match node_text(node).trim() {
+        "\u{23DE}" | "\u{FE37}" => Some(OVER_BRACE),
+        "\u{23DF}" | "\u{FE38}" => Some(UNDER_BRACE),
+        "\u{23DC}" | "\u{FE35}" => Some(OVER_PAREN),
+        "\u{23DD}" | "\u{FE36}" => Some(UNDER_PAREN),
+        "\u{23E0}" | "\u{FE39}" => Some(OVER_TORTOISE),
+        "\u{23E1}" | "\u{FE3A}" => Some(UNDER_TORTOISE),
+        _ => None,
+    }
+}
+
+/// Stroke glyph standing in for `\cancel` (bottom-left to top-right).
+const STRIKE_UP: char = '\u{2571}';
+/// Stroke glyph standing in for `\bcancel` (top-left to bottom-right).
+const STRIKE_DOWN: char = '\u{2572}';
+/// Stroke glyph standing in for `\xcancel` (both diagonals).
+const STRIKE_BOTH: char = '\u{2573}';
+
+/// Invisible overscript standing in for `\mathclap` (zero width space).
+const ZERO_WIDTH_MARK: char = '\u{200B}';
+
+/// Private-use markers around the `\binom` standing in for `\atop`.
+const ATOP_OPEN: char = '\u{E000}';
+const ATOP_CLOSE: char = '\u{E001}';
+
+/// Drop the parentheses (and markers) of each `\binom` left by `replace_atop`,
+/// leaving the bare `<mfrac linethickness="0">`
+fn strip_atop_parens(mathml: &str) -> String {
+    if !mathml.contains(ATOP_OPEN) {
+        return mathml.to_string();
+    }
+    let open = format!(r#"<mi mathvariant="normal">{}</mi><mrow><mo stretchy="true" form="prefix">(</mo>"#, ATOP_OPEN);
+    let close = format!(r#"<mo stretchy="true" form="postfix">)</mo></mrow><mi mathvariant="normal">{}</mi>"#, ATOP_CLOSE);
+    mathml.replace(&open, "").replace(&close, "")
+}
+
+/// Private-use marker opening the first cell of each `align`, left by `preprocess_latex`.
+const EQ_ARRAY_MARK: char = '\u{E002}';
+
+/// Attribute tagging an `<mtable>` that came from `align` (or an environment
+/// normalized onto it); only these tables become `<m:eqArr>`.
+const EQ_ARRAY_ATTR: &str = r#"data-env="align""#;
+
+/// Replace the marker at the start of each `align` table with `EQ_ARRAY_ATTR`,
+/// so a left-aligned `array` or hand-written table stays a matrix
+fn tag_eq_arrays(mathml: &str) -> String {
+    if !mathml.contains(EQ_ARRAY_MARK) {
+        return mathml.to_string();
+    }
+    let marked = format!(r#"<mtable columnalign=left><mtr><mtd><mi mathvariant="normal">{}</mi>"#, EQ_ARRAY_MARK);
+    let tagged = format!("<mtable columnalign=left {}><mtr><mtd>", EQ_ARRAY_ATTR);
+    mathml.replace(&marked, &tagged)
+}
+
+/// Strike directions `(up, down)` for a stroke-glyph overscript left by `preprocess_latex`.
+fn strike_directions(node: &MathNode) -> Option<(bool, bool)> {
+    let text = node_text(node);
+    let mut chars = text.trim().chars();
+    let directions = match chars.next()? {
+        STRIKE_UP => (true, false),
+        STRIKE_DOWN => (false, true),
+        STRIKE_BOTH => (true, true),
+        _ => return None,
+    };
+    chars.next().is_none().then_some(directions)
+}
+
+/// Build the node for `base` decorated by an over (`over = true`) or under script.
+///
+/// latex2mathml nests the label inside the script (`<munder>X<munder>⏟ label</munder></munder>`),
+/// while hand-written MathML puts the brace innermost (`<munder><munder>X ⏟</munder> label</munder>`);
+/// both shapes become one `GroupChr`.
+fn scripted_node(base: MathNode, script: MathNode, over: bool) -> MathNode {
+    if let Some((up, down)) = strike_directions(&script).filter(|_| over) {
+        return MathNode::Strike { base: Box::new(base), up, down };
+    }
+    if over && node_text(&script).trim() == ZERO_WIDTH_MARK.to_string() {
+        return MathNode::ZeroWidth(Box::new(base));
+    }
+    let script = match script {
+        MathNode::Mover(brace, label) | MathNode::Munder(brace, label)
+            if group_chr(&brace).is_some() =>
+        {
+            let chr = group_chr(&brace).unwrap_or(UNDER_BRACE);
+            return MathNode::GroupChr { base: Box::new(base), label: Some(label), over, chr };
+        }
+        script => script,
+    };
+    if let Some(chr) = group_chr(&script) {
+        return MathNode::GroupChr { base: Box::new(base), label: None, over, chr };
+    }
+    match base {
+        MathNode::GroupChr { base, label: None, over: brace_over, chr } if brace_over == over => {
+            MathNode::GroupChr { base, label: Some(Box::new(script)), over, chr }
+        }
+        base if over => MathNode::Mover(Box::new(base), Box::new(script)),
+        base => MathNode::Munder(Box::new(base), Box::new(script)),
+    }
+}
+
+/// Check if a string is a relation operator (alignment point in equation arrays).
+fn is_relation_char(s: &str) -> bool {
     matches!(
         s,
-        "^" | "~" | "¯" | "˙" | "¨" | "˘" | "ˇ"
-            | "\u{0302}" | "\u{0303}" | "\u{0304}" | "\u{0307}"
-            | "\u{0308}" | "\u{030C}" | "\u{20D7}"
+        "=" | "<" | ">" | "≤" | "≥" | "≠" | "≈" | "≡" | "∼" | "≃" | "≅" | "∝"
+            | "≪" | "≫" | "≔" | "→" | "←" | "⇒" | "⇐" | "⇔" | "↦" | "∈" | "⊂" | "⊆"
     )
 }
 
+/// Check if a node is a relation, looking through decorations such as
+/// `\overset{!}{=}` or `\underset{x}{\to}` so the decorated base counts too.
+fn is_relation_node(node: &MathNode) -> bool {
+    match node {
+        MathNode::Mo(text) => is_relation_char(text.trim()),
+        MathNode::Mover(base, _)
+        | MathNode::Munder(base, _)
+        | MathNode::Munderover(base, _, _)
+        | MathNode::Msub(base, _)
+        | MathNode::Msup(base, _)
+        | MathNode::Msubsup(base, _, _) => is_relation_node(base),
+        MathNode::Mrow(children) if children.len() == 1 => is_relation_node(&children[0]),
+        _ => false,
+    }
+}
+
+/// Maximum element nesting accepted from MathML input. Real formulas stay far
+/// below this; the limit keeps the recursive parser and writer off the stack limit.
+const MAX_MATHML_DEPTH: usize = 64;
+
+/// Reject MathML nested deeper than `MAX_MATHML_DEPTH`.
+///
+/// Syntax errors are left for the real parse to report.
+fn check_nesting_depth(mathml: &str) -> Result<(), ConvertError> {
+    let mut reader = Reader::from_str(mathml);
+    let mut depth = 0usize;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(_)) => {
+                depth += 1;
+                if depth > MAX_MATHML_DEPTH {
+                    return Err(ConvertError::MathmlToOmml(format!(
+                        "MathML nesting exceeds {} levels",
+                        MAX_MATHML_DEPTH
+                    )));
+                }
+            }
+            Ok(Event::End(_)) => depth = depth.saturating_sub(1),
+            Ok(Event::Eof) | Err(_) => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
 /// Parse MathML XML string into a tree of `MathNode`.
 fn parse_mathml(mathml: &str) -> Result<Vec<MathNode>, ConvertError> {
+    check_nesting_depth(mathml)?;
     let mut reader = Reader::from_str(mathml);
     reader.config_mut().trim_text(true);
     let nodes = parse_children(&mut reader, None)?;
@@ -742,6 +1923,8 @@ fn parse_children(
                 let local = strip_ns_prefix(&tag_name);
                 match local.as_str() {
                     "mspace" => nodes.push(MathNode::Mspace),
+                    // An empty cell or row still occupies its slot in the table
+                    "mtd" | "mtr" => nodes.push(MathNode::Mrow(Vec::new())),
                     _ => {
                         // Self-closing element – try to extract text from attributes
                         // (rare, but handle gracefully)
@@ -795,23 +1978,68 @@ fn parse_element(
     match local_name {
         "math" => {
             let children = parse_children(reader, Some(local_name))?;
+            Ok(MathNode::Mrow(attach_nary_bodies(children)?))
+        }
+        "semantics" => {
+            // <semantics> = presentation markup + annotations; keep only the former
+            let mut children: Vec<MathNode> = parse_children(reader, Some(local_name))?
+                .into_iter()
+                .filter(|child| !matches!(child, MathNode::Text(t) if t.is_empty()))
+                .collect();
+            if children.len() == 1 {
+                return Ok(children.remove(0));
+            }
             Ok(MathNode::Mrow(children))
         }
-        "mrow" | "semantics" | "annotation" | "annotation-xml" => {
-            let children = parse_children(reader, Some(local_name))?;
+        "annotation" | "annotation-xml" => {
+            // Source text (e.g. LaTeX) is not math content: see extract_latex_annotation
+            let _children = parse_children(reader, Some(local_name))?;
+            Ok(MathNode::Text(String::new()))
+        }
+        "mrow" => {
+            let mut children = attach_nary_bodies(merge_upright(parse_children(reader, Some(local_name))?))?;
+            // \mathrm{Var} arrives as <mrow> of single letters: expose the merged name
+            // so the parent row can see it as a function name
+            if children.len() == 1 && matches!(children[0], MathNode::Upright(_)) {
+                return Ok(children.remove(0));
+            }
+            // \left( ... \right) arrives as <mrow> bracketed by stretchy operators
+            if children.len() >= 2
+                && matches!(children.first(), Some(MathNode::Fence(_)))
+                && matches!(children.last(), Some(MathNode::Fence(_)))
+            {
+                let Some(MathNode::Fence(close)) = children.pop() else { unreachable!() };
+                let MathNode::Fence(open) = children.remove(0) else { unreachable!() };
+                return Ok(fenced_node(open, close, children));
+            }
             Ok(MathNode::Mrow(children))
         }
         "mi" => {
+            let upright = get_attr(start, "mathvariant").as_deref() == Some("normal");
             let text = read_text_content(reader, local_name)?;
-            Ok(MathNode::Mi(text))
+            // \limsup / \liminf arrive as one <mi>lim sup</mi>: an upright name with a thin space
+            let spaced_name = text.contains(' ')
+                && text.chars().all(|c| c.is_ascii_alphabetic() || c == ' ');
+            Ok(if spaced_name {
+                MathNode::Upright(text.split_whitespace().collect::<Vec<_>>().join("\u{2009}"))
+            } else if upright {
+                MathNode::Upright(text)
+            } else {
+                MathNode::Mi(text)
+            })
         }
         "mn" => {
             let text = read_text_content(reader, local_name)?;
             Ok(MathNode::Mn(text))
         }
         "mo" => {
+            let stretchy = get_attr(start, "stretchy").as_deref() == Some("true");
             let text = read_text_content(reader, local_name)?;
-            Ok(MathNode::Mo(text))
+            Ok(if stretchy {
+                MathNode::Fence(text)
+            } else {
+                MathNode::Mo(text)
+            })
         }
         "mtext" => {
             let text = read_text_content(reader, local_name)?;
@@ -820,11 +2048,20 @@ fn parse_element(
         "mfrac" => {
             let children = parse_children(reader, Some(local_name))?;
             let (num, den) = take_two(children, local_name)?;
-            Ok(MathNode::Mfrac(Box::new(num), Box::new(den)))
+            let no_bar = get_attr(start, "linethickness").is_some_and(|t| {
+                t.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+                    .parse::<f64>()
+                    .is_ok_and(|v| v == 0.0)
+            });
+            Ok(if no_bar {
+                MathNode::NoBarFrac(Box::new(num), Box::new(den), None)
+            } else {
+                MathNode::Mfrac(Box::new(num), Box::new(den))
+            })
         }
         "msqrt" => {
             let children = parse_children(reader, Some(local_name))?;
-            Ok(MathNode::Msqrt(children))
+            Ok(MathNode::Msqrt(attach_nary_bodies(children)?))
         }
         "mroot" => {
             let children = parse_children(reader, Some(local_name))?;
@@ -837,7 +2074,11 @@ fn parse_element(
             
             // Check if base is an msub - if so, convert to msubsup
             // This fixes the issue where latex2mathml generates nested msup/msub
-            // instead of msubsup for X_a^b
+            // instead of msubsup for {X_a}^b (the base may be wrapped in an mrow)
+            let base = match base {
+                MathNode::Mrow(mut children) if children.len() == 1 => children.remove(0),
+                other => other,
+            };
             if let MathNode::Msub(inner_base, sub) = base {
                 Ok(MathNode::Msubsup(inner_base, sub, Box::new(sup)))
             } else {
@@ -861,12 +2102,24 @@ fn parse_element(
         "mover" => {
             let children = parse_children(reader, Some(local_name))?;
             let (base, over) = take_two(children, local_name)?;
-            Ok(MathNode::Mover(Box::new(base), Box::new(over)))
+            // \overset{n}{\underset{i}{\sum}}: one operator with both limits
+            match base {
+                MathNode::Munder(op, under) if is_large_operator(&node_text(&op)) => {
+                    Ok(MathNode::Munderover(op, under, Box::new(over)))
+                }
+                base => Ok(scripted_node(base, over, true)),
+            }
         }
         "munder" => {
             let children = parse_children(reader, Some(local_name))?;
             let (base, under) = take_two(children, local_name)?;
-            Ok(MathNode::Munder(Box::new(base), Box::new(under)))
+            // \underset{i}{\overset{n}{\sum}}: one operator with both limits
+            match base {
+                MathNode::Mover(op, over) if is_large_operator(&node_text(&op)) => {
+                    Ok(MathNode::Munderover(op, Box::new(under), over))
+                }
+                base => Ok(scripted_node(base, under, false)),
+            }
         }
         "munderover" => {
             let children = parse_children(reader, Some(local_name))?;
@@ -878,6 +2131,8 @@ fn parse_element(
             ))
         }
         "mtable" => {
+            // Keyed on the source environment, not the alignment: any table may be left-aligned
+            let is_eq_array = get_attr(start, "data-env").as_deref() == Some("align");
             let children = parse_children(reader, Some(local_name))?;
             let mut rows: Vec<Vec<MathNode>> = Vec::new();
             for child in children {
@@ -886,7 +2141,11 @@ fn parse_element(
                     other => rows.push(vec![other]),
                 }
             }
-            Ok(MathNode::Mtable(rows))
+            Ok(if is_eq_array {
+                MathNode::EqArray(rows)
+            } else {
+                MathNode::Mtable(rows)
+            })
         }
         "mtr" | "mlabeledtr" => {
             let children = parse_children(reader, Some(local_name))?;
@@ -894,7 +2153,7 @@ fn parse_element(
             Ok(MathNode::Mrow(children))
         }
         "mtd" => {
-            let children = parse_children(reader, Some(local_name))?;
+            let children = attach_nary_bodies(parse_children(reader, Some(local_name))?)?;
             Ok(if children.len() == 1 {
                 children.into_iter().next().unwrap()
             } else {
@@ -905,19 +2164,39 @@ fn parse_element(
             let open = get_attr(start, "open").unwrap_or_else(|| "(".to_string());
             let close = get_attr(start, "close").unwrap_or_else(|| ")".to_string());
             let children = parse_children(reader, Some(local_name))?;
-            Ok(MathNode::Mfenced {
-                open,
-                close,
-                children,
-            })
+            Ok(fenced_node(open, close, children))
         }
         "mspace" => {
             let _children = parse_children(reader, Some(local_name))?;
             Ok(MathNode::Mspace)
         }
-        "mpadded" | "mstyle" | "mphantom" | "menclose" | "merror" => {
+        "mstyle" => {
+            let mut children = attach_nary_bodies(parse_children(reader, Some(local_name))?)?;
+            if let Some(display) = mstyle_display(start) {
+                for child in &mut children {
+                    apply_display_style(child, display);
+                }
+            }
+            Ok(MathNode::Mrow(children))
+        }
+        "menclose" => {
+            let notation = get_attr(start, "notation").unwrap_or_default();
+            let has = |name: &str| notation.split_whitespace().any(|n| n == name);
+            let (up, down) = (has("updiagonalstrike"), has("downdiagonalstrike"));
+            let children = attach_nary_bodies(parse_children(reader, Some(local_name))?)?;
+            Ok(if up || down {
+                MathNode::Strike { base: Box::new(MathNode::Mrow(children)), up, down }
+            } else {
+                MathNode::Mrow(children)
+            })
+        }
+        "mpadded" if get_attr(start, "width").is_some_and(|w| is_zero_length(&w)) => {
+            let children = attach_nary_bodies(parse_children(reader, Some(local_name))?)?;
+            Ok(MathNode::ZeroWidth(Box::new(MathNode::Mrow(children))))
+        }
+        "mpadded" | "mphantom" | "merror" => {
             // Pass-through containers: just process children
-            let children = parse_children(reader, Some(local_name))?;
+            let children = attach_nary_bodies(parse_children(reader, Some(local_name))?)?;
             Ok(MathNode::Mrow(children))
         }
         _ => {
@@ -934,6 +2213,273 @@ fn parse_element(
     }
 }
 
+/// Gather the operand of each large operator in a row into its `Nary` body.
+///
+/// latex2mathml leaves the operand of `\sum_{i=1}^n a_i` as loose siblings
+/// after the `<munderover>`; Word needs it inside the `<m:e>` to place the
+/// limits and spacing. The body runs up to the next binary operator, relation
+/// or comma outside brackets, or to the end of the row; a nested operator
+/// takes its own body and stays inside the outer one.
+///
+/// Each nested operator adds a level to the tree, so operators nested more
+/// than `MAX_MATHML_DEPTH` deep (counting those already grouped in inner
+/// rows) are rejected like over-deep MathML.
+fn attach_nary_bodies(children: Vec<MathNode>) -> Result<Vec<MathNode>, ConvertError> {
+    collect_nary_row(&mut children.into_iter().peekable(), 0)
+}
+
+/// Collect one row; `nesting` is the number of operators whose body it is.
+fn collect_nary_row(
+    nodes: &mut std::iter::Peekable<std::vec::IntoIter<MathNode>>,
+    nesting: usize,
+) -> Result<Vec<MathNode>, ConvertError> {
+    let mut row = Vec::new();
+    let mut depth = 0usize;
+    while let Some(next) = nodes.peek() {
+        let step = bracket_step(next);
+        if nesting > 0 && depth == 0 && (step < 0 || ends_nary_body(next)) {
+            break;
+        }
+        depth = depth.saturating_add_signed(step);
+        let Some(node) = nodes.next() else { break };
+        if nesting + nary_nesting(&node) > MAX_MATHML_DEPTH {
+            return Err(nary_too_deep());
+        }
+        match nary_head(node) {
+            Ok(mut nary) => {
+                if nesting + 1 > MAX_MATHML_DEPTH {
+                    return Err(nary_too_deep());
+                }
+                if let MathNode::Nary { body, .. } = &mut nary {
+                    *body = collect_nary_row(nodes, nesting + 1)?;
+                }
+                row.push(nary);
+            }
+            Err(node) => row.push(node),
+        }
+    }
+    Ok(row)
+}
+
+fn nary_too_deep() -> ConvertError {
+    ConvertError::MathmlToOmml(format!(
+        "N-ary operators nested deeper than {} levels",
+        MAX_MATHML_DEPTH
+    ))
+}
+
+/// The most `Nary` nodes on any path down from `node`, itself included.
+fn nary_nesting(node: &MathNode) -> usize {
+    fn deepest<'a>(nodes: impl IntoIterator<Item = &'a MathNode>) -> usize {
+        nodes.into_iter().map(nary_nesting).max().unwrap_or(0)
+    }
+    match node {
+        MathNode::Nary { sub, sup, body, .. } => {
+            1 + deepest(sub.iter().chain(sup.iter()).map(|b| b.as_ref()).chain(body))
+        }
+        MathNode::Mrow(children)
+        | MathNode::Msqrt(children)
+        | MathNode::Mfenced { children, .. } => deepest(children),
+        MathNode::Mtable(rows) | MathNode::EqArray(rows) | MathNode::Cases(rows) => deepest(rows.iter().flatten()),
+        MathNode::Mfrac(a, b)
+        | MathNode::NoBarFrac(a, b, _)
+        | MathNode::Mroot(a, b)
+        | MathNode::Msup(a, b)
+        | MathNode::Msub(a, b)
+        | MathNode::Mover(a, b)
+        | MathNode::Munder(a, b) => deepest([a.as_ref(), b.as_ref()]),
+        MathNode::Msubsup(a, b, c) | MathNode::Munderover(a, b, c) => deepest([a.as_ref(), b.as_ref(), c.as_ref()]),
+        MathNode::GroupChr { base, label, .. } => deepest(std::iter::once(base.as_ref()).chain(label.as_deref())),
+        MathNode::Strike { base, .. } | MathNode::ZeroWidth(base) => nary_nesting(base),
+        MathNode::Mi(_)
+        | MathNode::Upright(_)
+        | MathNode::Mn(_)
+        | MathNode::Mo(_)
+        | MathNode::Mtext(_)
+        | MathNode::Fence(_)
+        | MathNode::Mspace
+        | MathNode::Text(_) => 0,
+    }
+}
+
+/// Turn a large operator with limits into an `Nary` with an empty body, or hand the node back.
+///
+/// Limits given as scripts (`<msub>` etc., how latex2mathml writes `\int`)
+/// keep their side position (`subSup`).
+fn nary_head(node: MathNode) -> Result<MathNode, MathNode> {
+    let is_nary = match &node {
+        MathNode::Munderover(op, _, _)
+        | MathNode::Munder(op, _)
+        | MathNode::Mover(op, _)
+        | MathNode::Msubsup(op, _, _)
+        | MathNode::Msub(op, _)
+        | MathNode::Msup(op, _) => is_large_operator(&node_text(op)),
+        _ => false,
+    };
+    if !is_nary {
+        return Err(node);
+    }
+    let (op, sub, sup, display) = match node {
+        MathNode::Munderover(op, under, over) => (op, Some(under), Some(over), None),
+        MathNode::Munder(op, under) => (op, Some(under), None, None),
+        MathNode::Mover(op, over) => (op, None, Some(over), None),
+        MathNode::Msubsup(op, sub, sup) => (op, Some(sub), Some(sup), Some(false)),
+        MathNode::Msub(op, sub) => (op, Some(sub), None, Some(false)),
+        MathNode::Msup(op, sup) => (op, None, Some(sup), Some(false)),
+        node => return Err(node),
+    };
+    Ok(MathNode::Nary { chr: node_text(&op), sub, sup, body: Vec::new(), display })
+}
+
+/// Whether an `<mstyle>` switches to display (`true`) or inline (`false`) style.
+///
+/// `displaystyle` wins; without it a raised `scriptlevel` means script
+/// (inline) size. Returns `None` when the style leaves this unchanged.
+fn mstyle_display(start: &BytesStart) -> Option<bool> {
+    if let Some(display) = get_attr(start, "displaystyle") {
+        return match display.trim() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+    }
+    let level = get_attr(start, "scriptlevel")?;
+    let level = level.trim().trim_start_matches('+').parse::<i32>().ok()?;
+    (level > 0).then_some(false)
+}
+
+/// Set the display style of every N-ary operator under `node` that no inner
+/// `<mstyle>` has already styled.
+fn apply_display_style(node: &mut MathNode, style: bool) {
+    match node {
+        MathNode::Nary { sub, sup, body, display, .. } => {
+            display.get_or_insert(style);
+            for child in sub.iter_mut().chain(sup.iter_mut()) {
+                apply_display_style(child, style);
+            }
+            for child in body {
+                apply_display_style(child, style);
+            }
+        }
+        MathNode::Mrow(children)
+        | MathNode::Msqrt(children)
+        | MathNode::Mfenced { children, .. } => {
+            for child in children {
+                apply_display_style(child, style);
+            }
+        }
+        MathNode::Mtable(rows) | MathNode::EqArray(rows) | MathNode::Cases(rows) => {
+            for child in rows.iter_mut().flatten() {
+                apply_display_style(child, style);
+            }
+        }
+        MathNode::NoBarFrac(a, b, display) => {
+            display.get_or_insert(style);
+            apply_display_style(a, style);
+            apply_display_style(b, style);
+        }
+        MathNode::Mfrac(a, b)
+        | MathNode::Mroot(a, b)
+        | MathNode::Msup(a, b)
+        | MathNode::Msub(a, b)
+        | MathNode::Mover(a, b)
+        | MathNode::Munder(a, b) => {
+            apply_display_style(a, style);
+            apply_display_style(b, style);
+        }
+        MathNode::Msubsup(a, b, c) | MathNode::Munderover(a, b, c) => {
+            apply_display_style(a, style);
+            apply_display_style(b, style);
+            apply_display_style(c, style);
+        }
+        MathNode::GroupChr { base, label, .. } => {
+            apply_display_style(base, style);
+            if let Some(label) = label {
+                apply_display_style(label, style);
+            }
+        }
+        MathNode::Strike { base, .. } | MathNode::ZeroWidth(base) => apply_display_style(base, style),
+        MathNode::Mi(_)
+        | MathNode::Upright(_)
+        | MathNode::Mn(_)
+        | MathNode::Mo(_)
+        | MathNode::Mtext(_)
+        | MathNode::Fence(_)
+        | MathNode::Mspace
+        | MathNode::Text(_) => {}
+    }
+}
+
+/// Bracket depth change for a flat `<mo>` bracket: +1 opens, -1 closes.
+fn bracket_step(node: &MathNode) -> isize {
+    match node {
+        MathNode::Mo(text) => match text.trim() {
+            "(" | "[" | "{" | "⟨" => 1,
+            ")" | "]" | "}" | "⟩" => -1,
+            _ => 0,
+        },
+        MathNode::Fence(_) => -1,
+        _ => 0,
+    }
+}
+
+/// Check if a node ends an N-ary operand: a binary operator, relation or separator.
+fn ends_nary_body(node: &MathNode) -> bool {
+    matches!(node, MathNode::Mo(text) if matches!(text.trim(), "+" | "-" | "−" | "±" | "∓" | "," | ";"))
+        || is_relation_node(node)
+}
+
+/// Build a fenced node; a lone table opened by `{` with no closing
+/// delimiter is a `cases` environment.
+fn fenced_node(open: String, close: String, mut children: Vec<MathNode>) -> MathNode {
+    if open == "{" && close.is_empty() && children.len() == 1 {
+        // Only take the child out once we know it is a table; anything
+        // else stays inside the fence.
+        if let MathNode::Mtable(_) = &children[0] {
+            if let MathNode::Mtable(rows) = children.remove(0) {
+                return MathNode::Cases(rows);
+            }
+        }
+    }
+    MathNode::Mfenced { open, close, children }
+}
+
+/// Merge the upright identifiers of one `\operatorname` / `\mathrm` group
+/// (`V`, `a`, `r` → `Var`).
+///
+/// Such a group arrives as an `<mrow>` holding nothing but upright letters
+/// and spaces; any other row is returned unchanged, so neighbouring upright
+/// symbols (`\Gamma\Delta`) or separate groups stay apart. A space between
+/// two upright parts stays inside the name as a thin space
+/// (`\operatorname{lim\,sup}` → `lim sup`).
+fn merge_upright(children: Vec<MathNode>) -> Vec<MathNode> {
+    let is_group = children.iter().any(|c| matches!(c, MathNode::Upright(_)))
+        && children.iter().all(|c| matches!(c, MathNode::Upright(_) | MathNode::Mspace));
+    if !is_group {
+        return children;
+    }
+    let mut merged: Vec<MathNode> = Vec::with_capacity(children.len());
+    for child in children {
+        if let MathNode::Upright(text) = &child {
+            let spaced = merged.len() >= 2
+                && matches!(merged.last(), Some(MathNode::Mspace))
+                && matches!(merged[merged.len() - 2], MathNode::Upright(_));
+            if spaced {
+                merged.pop();
+            }
+            if let Some(MathNode::Upright(prev)) = merged.last_mut() {
+                if spaced {
+                    prev.push('\u{2009}');
+                }
+                prev.push_str(text);
+                continue;
+            }
+        }
+        merged.push(child);
+    }
+    merged
+}
+
 /// Read text content of a leaf element until its closing tag.
 fn read_text_content(
     reader: &mut Reader<&[u8]>,
@@ -944,7 +2490,13 @@ fn read_text_content(
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Text(ref e)) => {
-                text.push_str(&e.unescape().unwrap_or_default());
+                // latex2mathml writes angle brackets as HTML entities (&lang; / &rang;)
+                let unescaped = e.unescape_with(|entity| match entity {
+                    "lang" => Some("⟨"),
+                    "rang" => Some("⟩"),
+                    _ => None,
+                });
+                text.push_str(&unescaped.unwrap_or_default());
             }
             Ok(Event::End(ref e)) => {
                 let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
@@ -980,18 +2532,29 @@ fn read_text_content(
 fn node_text(node: &MathNode) -> String {
     match node {
         MathNode::Mi(t)
+        | MathNode::Upright(t)
         | MathNode::Mn(t)
         | MathNode::Mo(t)
         | MathNode::Mtext(t)
+        | MathNode::Fence(t)
         | MathNode::Text(t) => t.clone(),
         MathNode::Mrow(children) => children.iter().map(node_text).collect::<String>(),
         _ => String::new(),
     }
 }
 
+/// Whether a MathML length such as `0`, `0em` or `+0.0pt` is zero.
+fn is_zero_length(length: &str) -> bool {
+    let number = length.trim().trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+    number.parse::<f64>().is_ok_and(|n| n == 0.0)
+}
+
 /// Get an attribute value from a `BytesStart` element.
+///
+/// Uses the lenient HTML attribute parser because latex2mathml emits some
+/// attributes unquoted (e.g. `<mtable columnalign=left>`).
 fn get_attr(start: &BytesStart, name: &str) -> Option<String> {
-    for attr in start.attributes().flatten() {
+    for attr in start.html_attributes().flatten() {
         let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
         if key == name || key.ends_with(&format!(":{}", name)) {
             return Some(String::from_utf8_lossy(&attr.value).to_string());
@@ -1071,12 +2634,28 @@ fn write_m_val_prop(
         .map_err(|e| ConvertError::MathmlToOmml(format!("Write error: {}", e)))
 }
 
+/// Math-style value for plain (upright, non-italic) runs: `\text{…}`,
+/// `\mathrm{…}`, function names and other `mathvariant="normal"` identifiers.
+const STY_PLAIN: &str = "p";
+
 /// Write an `<m:r><m:t>text</m:t></m:r>` run element.
-fn write_run(writer: &mut Writer<Cursor<Vec<u8>>>, text: &str) -> Result<(), ConvertError> {
+///
+/// When `sty` is given, the run carries `<m:rPr><m:sty m:val="…"/></m:rPr>`
+/// so Word does not fall back to its default italic math style.
+fn write_run(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    text: &str,
+    sty: Option<&str>,
+) -> Result<(), ConvertError> {
     if text.is_empty() {
         return Ok(());
     }
     write_m_start(writer, "r")?;
+    if let Some(sty) = sty {
+        write_m_start(writer, "rPr")?;
+        write_m_val_prop(writer, "sty", sty)?;
+        write_m_end(writer, "rPr")?;
+    }
     write_m_start(writer, "t")?;
     writer
         .write_event(Event::Text(BytesText::new(text)))
@@ -1086,15 +2665,110 @@ fn write_run(writer: &mut Writer<Cursor<Vec<u8>>>, text: &str) -> Result<(), Con
     Ok(())
 }
 
+/// Number of leading nodes forming a function argument: a fenced group, or a
+/// balanced `(`…`)` / `[`…`]` run of operators.
+fn function_argument_len(nodes: &[MathNode]) -> Option<usize> {
+    let (open, close) = match nodes.first()? {
+        MathNode::Mfenced { .. } => return Some(1),
+        MathNode::Mo(open) if open == "(" => ("(", ")"),
+        MathNode::Mo(open) if open == "[" => ("[", "]"),
+        _ => return None,
+    };
+    let mut depth = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        if let MathNode::Mo(text) = node {
+            if text == open {
+                depth += 1;
+            } else if text == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Node count and text of a digit-grouped number starting at `nodes[0]`.
+///
+/// latex2mathml splits `1,000.5` into `<mn>1</mn><mo>,</mo><mn>000.5</mn>`
+/// (and `1\,000` around an `<mspace>`). A leading group of 1–3 digits followed
+/// by separator + three-digit groups is one number; the last group may carry
+/// the decimal part. Anything else (`f(1, 2)`, `12,5`) stays separate tokens.
+fn grouped_number(nodes: &[MathNode]) -> Option<(usize, String)> {
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let MathNode::Mn(first) = nodes.first()? else {
+        return None;
+    };
+    if first.is_empty() || first.len() > 3 || !is_digits(first) {
+        return None;
+    }
+    let mut text = first.clone();
+    let mut len = 1;
+    while let [sep, MathNode::Mn(group), ..] = &nodes[len..] {
+        let sep = match sep {
+            MathNode::Mo(sep) if sep == "," => ",",
+            MathNode::Mspace => "\u{2009}",
+            _ => break,
+        };
+        let (digits, fraction) = group.split_once('.').unwrap_or((group, ""));
+        if digits.len() != 3 || !is_digits(digits) || !is_digits(fraction) {
+            break;
+        }
+        text.push_str(sep);
+        text.push_str(group);
+        len += 2;
+        if group.contains('.') {
+            break;
+        }
+    }
+    (len > 1).then_some((len, text))
+}
+
+/// Write a sequence of sibling nodes.
+///
+/// An upright multi-letter name directly followed by its parenthesised
+/// argument (`\operatorname{Var}(X)`) becomes an OMML function application
+/// (`m:func`), so Word keeps the argument attached with function spacing.
+/// A digit-grouped number (`1,000.5`) is written as a single run.
+fn write_nodes(writer: &mut Writer<Cursor<Vec<u8>>>, nodes: &[MathNode]) -> Result<(), ConvertError> {
+    let mut i = 0;
+    while i < nodes.len() {
+        if let Some((len, number)) = grouped_number(&nodes[i..]) {
+            write_run(writer, &number, None)?;
+            i += len;
+            continue;
+        }
+        if let MathNode::Upright(name) = &nodes[i] {
+            if name.chars().count() > 1 {
+                if let Some(arg_len) = function_argument_len(&nodes[i + 1..]) {
+                    write_m_start(writer, "func")?;
+                    write_m_start(writer, "funcPr")?;
+                    write_m_end(writer, "funcPr")?;
+                    write_m_start(writer, "fName")?;
+                    write_run(writer, name, Some(STY_PLAIN))?;
+                    write_m_end(writer, "fName")?;
+                    write_element_wrapper(writer, &nodes[i + 1..i + 1 + arg_len])?;
+                    write_m_end(writer, "func")?;
+                    i += 1 + arg_len;
+                    continue;
+                }
+            }
+        }
+        write_node(writer, &nodes[i])?;
+        i += 1;
+    }
+    Ok(())
+}
+
 /// Write a list of MathNode children wrapped in `<m:e>`.
 fn write_element_wrapper(
     writer: &mut Writer<Cursor<Vec<u8>>>,
     nodes: &[MathNode],
 ) -> Result<(), ConvertError> {
     write_m_start(writer, "e")?;
-    for node in nodes {
-        write_node(writer, node)?;
-    }
+    write_nodes(writer, nodes)?;
     write_m_end(writer, "e")?;
     Ok(())
 }
@@ -1110,39 +2784,211 @@ fn write_single_element(
     Ok(())
 }
 
-/// Write a MathNode tree to the OMML writer.
-fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(), ConvertError> {
-    match node {
-        MathNode::Mi(text) | MathNode::Mn(text) | MathNode::Mtext(text) => {
-            write_run(writer, text)?;
-        }
-        MathNode::Mo(text) => {
-            write_run(writer, text)?;
+/// Open an operator-emulating box (`<m:box>` with `opEmu`) and its `<m:e>`.
+///
+/// Word spaces a limit construct as an ordinary symbol; inside such a box it
+/// is spaced like the operator at its base, so a decorated `=` stays a relation.
+fn write_operator_box_start(writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<(), ConvertError> {
+    write_m_start(writer, "box")?;
+    write_m_start(writer, "boxPr")?;
+    write_m_val_prop(writer, "opEmu", "1")?;
+    write_m_end(writer, "boxPr")?;
+    write_m_start(writer, "e")
+}
+
+/// Close a box opened by `write_operator_box_start`.
+fn write_operator_box_end(writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<(), ConvertError> {
+    write_m_end(writer, "e")?;
+    write_m_end(writer, "box")
+}
+
+/// Write an N-ary operator (`<m:nary>`) with its limits and operand body.
+///
+/// `lim_loc` is `undOvr` (limits above and below, display style) or `subSup`
+/// (limits as scripts, inline style). A missing limit is hidden rather than left as an empty placeholder box.
+fn write_nary(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    chr: &str,
+    lim_loc: &str,
+    sub: Option<&MathNode>,
+    sup: Option<&MathNode>,
+    body: &[MathNode],
+) -> Result<(), ConvertError> {
+    write_m_start(writer, "nary")?;
+    write_m_start(writer, "naryPr")?;
+    write_m_val_prop(writer, "chr", chr)?;
+    write_m_val_prop(writer, "limLoc", lim_loc)?;
+    if sub.is_none() {
+        write_m_val_prop(writer, "subHide", "1")?;
+    }
+    if sup.is_none() {
+        write_m_val_prop(writer, "supHide", "1")?;
+    }
+    write_m_end(writer, "naryPr")?;
+    write_m_start(writer, "sub")?;
+    if let Some(sub) = sub {
+        write_node(writer, sub)?;
+    }
+    write_m_end(writer, "sub")?;
+    write_m_start(writer, "sup")?;
+    if let Some(sup) = sup {
+        write_node(writer, sup)?;
+    }
+    write_m_end(writer, "sup")?;
+    write_m_start(writer, "e")?;
+    for node in body {
+        write_node(writer, node)?;
+    }
+    write_m_end(writer, "e")?;
+    write_m_end(writer, "nary")?;
+    Ok(())
+}
+
+/// Write a grouping brace (`<m:groupChr>`), with its label as a limit beyond the brace.
+///
+/// Word positions an over brace at the top of the base, hanging down onto it,
+/// and an under brace the other way round; the label sits in a `limUpp`/`limLow`
+/// around the whole group so it lines up with the brace tip.
+fn write_group_chr(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    base: &MathNode,
+    label: Option<&MathNode>,
+    over: bool,
+    chr: char,
+) -> Result<(), ConvertError> {
+    let lim = if over { "limUpp" } else { "limLow" };
+    if label.is_some() {
+        write_m_start(writer, lim)?;
+        write_m_start(writer, &format!("{}Pr", lim))?;
+        write_m_end(writer, &format!("{}Pr", lim))?;
+        write_m_start(writer, "e")?;
+    }
+    write_m_start(writer, "groupChr")?;
+    write_m_start(writer, "groupChrPr")?;
+    write_m_val_prop(writer, "chr", chr.encode_utf8(&mut [0; 4]))?;
+    write_m_val_prop(writer, "pos", if over { "top" } else { "bot" })?;
+    write_m_val_prop(writer, "vertJc", if over { "bot" } else { "top" })?;
+    write_m_end(writer, "groupChrPr")?;
+    write_single_element(writer, base)?;
+    write_m_end(writer, "groupChr")?;
+    if let Some(label) = label {
+        write_m_end(writer, "e")?;
+        write_m_start(writer, "lim")?;
+        write_node(writer, label)?;
+        write_m_end(writer, "lim")?;
+        write_m_end(writer, lim)?;
+    }
+    Ok(())
+}
+
+/// Write a struck-through base as a `<m:borderBox>` with every border hidden
+/// and only the requested diagonal strikes drawn.
+fn write_strike(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    base: &MathNode,
+    up: bool,
+    down: bool,
+) -> Result<(), ConvertError> {
+    write_m_start(writer, "borderBox")?;
+    write_m_start(writer, "borderBoxPr")?;
+    for side in ["hideTop", "hideBot", "hideLeft", "hideRight"] {
+        write_m_val_prop(writer, side, "1")?;
+    }
+    if up {
+        write_m_val_prop(writer, "strikeBLTR", "1")?;
+    }
+    if down {
+        write_m_val_prop(writer, "strikeTLBR", "1")?;
+    }
+    write_m_end(writer, "borderBoxPr")?;
+    write_single_element(writer, base)?;
+    write_m_end(writer, "borderBox")?;
+    Ok(())
+}
+
+/// Write content that is shown but takes no horizontal space, as a
+/// `<m:phant>` with `zeroWid` set.
+fn write_zero_width(writer: &mut Writer<Cursor<Vec<u8>>>, base: &MathNode) -> Result<(), ConvertError> {
+    write_m_start(writer, "phant")?;
+    write_m_start(writer, "phantPr")?;
+    write_m_val_prop(writer, "show", "1")?;
+    write_m_val_prop(writer, "zeroWid", "1")?;
+    write_m_end(writer, "phantPr")?;
+    write_single_element(writer, base)?;
+    write_m_end(writer, "phant")?;
+    Ok(())
+}
+
+/// Write one equation-array row, emitting `&` alignment markers.
+///
+/// Cells split by explicit `&` are joined with markers. A row without any `&`
+/// is aligned on its first relation, where decorated relations such as
+/// `\overset{!}{=}` count as relations so the base `=` stays on the column.
+fn write_eq_array_row(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    cells: &[MathNode],
+) -> Result<(), ConvertError> {
+    if cells.len() > 1 {
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                write_run(writer, "&", None)?;
+            }
+            write_node(writer, cell)?;
+        }
+        return Ok(());
+    }
+
+    let children: &[MathNode] = match cells.first() {
+        Some(MathNode::Mrow(children)) => children,
+        Some(_) => cells,
+        None => return Ok(()),
+    };
+    let align_at = children.iter().position(is_relation_node);
+    for (i, child) in children.iter().enumerate() {
+        if align_at == Some(i) {
+            write_run(writer, "&", None)?;
         }
-        MathNode::Text(text) => {
+        write_node(writer, child)?;
+    }
+    Ok(())
+}
+
+/// Write a MathNode tree to the OMML writer.
+fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(), ConvertError> {
+    match node {
+        MathNode::Mi(text) | MathNode::Mn(text) | MathNode::Mo(text) => {
+            write_run(writer, text, None)?;
+        }
+        MathNode::Mtext(text) | MathNode::Upright(text) => {
+            write_run(writer, text, Some(STY_PLAIN))?;
+        }
+        MathNode::Text(text) | MathNode::Fence(text) => {
             if !text.is_empty() {
-                write_run(writer, text)?;
+                write_run(writer, text, None)?;
             }
         }
         MathNode::Mrow(children) => {
-            for child in children {
-                write_node(writer, child)?;
-            }
+            write_nodes(writer, children)?;
         }
-        MathNode::Mfrac(num, den) => {
+        MathNode::Mfrac(num, den) | MathNode::NoBarFrac(num, den, _) => {
+            let fraction_type = if matches!(node, MathNode::NoBarFrac(..)) { "noBar" } else { "bar" };
+            // \tbinom: text-style arguments are written one size smaller
+            let small = matches!(node, MathNode::NoBarFrac(_, _, Some(false)));
             write_m_start(writer, "f")?;
-            // fPr (fraction properties) – use default bar fraction
+            // fPr (fraction properties) – bar fraction, or stacked without a bar
             write_m_start(writer, "fPr")?;
-            write_m_val_prop(writer, "type", "bar")?;
+            write_m_val_prop(writer, "type", fraction_type)?;
             write_m_end(writer, "fPr")?;
-            // numerator
-            write_m_start(writer, "num")?;
-            write_node(writer, num)?;
-            write_m_end(writer, "num")?;
-            // denominator
-            write_m_start(writer, "den")?;
-            write_node(writer, den)?;
-            write_m_end(writer, "den")?;
+            for (tag, part) in [("num", num), ("den", den)] {
+                write_m_start(writer, tag)?;
+                if small {
+                    write_m_start(writer, "argPr")?;
+                    write_m_val_prop(writer, "argSz", "-1")?;
+                    write_m_end(writer, "argPr")?;
+                }
+                write_node(writer, part)?;
+                write_m_end(writer, tag)?;
+            }
             write_m_end(writer, "f")?;
         }
         MathNode::Msqrt(children) => {
@@ -1205,16 +3051,25 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
         }
         MathNode::Mover(base, over) => {
             let over_text = node_text(over);
-            if is_accent_char(&over_text) {
+            let base_text = node_text(base);
+            if is_large_operator(&base_text) {
+                // N-ary operator with upper limit only (\overset{n}{\sum})
+                write_nary(writer, &base_text, "undOvr", None, Some(over), &[])?;
+            } else if let Some(chr) = accent_chr(&over_text) {
                 // Accent
                 write_m_start(writer, "acc")?;
                 write_m_start(writer, "accPr")?;
-                write_m_val_prop(writer, "chr", &over_text)?;
+                write_m_val_prop(writer, "chr", chr.encode_utf8(&mut [0; 4]))?;
                 write_m_end(writer, "accPr")?;
                 write_single_element(writer, base)?;
                 write_m_end(writer, "acc")?;
             } else {
-                // Upper limit
+                // Upper limit; a decorated relation (\overset{!}{=}) is boxed as an
+                // operator so it keeps relation spacing
+                let relation = is_relation_node(base);
+                if relation {
+                    write_operator_box_start(writer)?;
+                }
                 write_m_start(writer, "limUpp")?;
                 write_m_start(writer, "limUppPr")?;
                 write_m_end(writer, "limUppPr")?;
@@ -1223,28 +3078,22 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
                 write_node(writer, over)?;
                 write_m_end(writer, "lim")?;
                 write_m_end(writer, "limUpp")?;
+                if relation {
+                    write_operator_box_end(writer)?;
+                }
             }
         }
         MathNode::Munder(base, under) => {
             let base_text = node_text(base);
             if is_large_operator(&base_text) {
                 // N-ary operator with lower limit only
-                write_m_start(writer, "nary")?;
-                write_m_start(writer, "naryPr")?;
-                write_m_val_prop(writer, "chr", &base_text)?;
-                write_m_val_prop(writer, "limLoc", "undOvr")?;
-                write_m_val_prop(writer, "supHide", "1")?;
-                write_m_end(writer, "naryPr")?;
-                write_m_start(writer, "sub")?;
-                write_node(writer, under)?;
-                write_m_end(writer, "sub")?;
-                write_m_start(writer, "sup")?;
-                write_m_end(writer, "sup")?;
-                write_m_start(writer, "e")?;
-                write_m_end(writer, "e")?;
-                write_m_end(writer, "nary")?;
+                write_nary(writer, &base_text, "undOvr", Some(under), None, &[])?;
             } else {
-                // Lower limit
+                // Lower limit (boxed like the upper limit for a decorated relation)
+                let relation = is_relation_node(base);
+                if relation {
+                    write_operator_box_start(writer)?;
+                }
                 write_m_start(writer, "limLow")?;
                 write_m_start(writer, "limLowPr")?;
                 write_m_end(writer, "limLowPr")?;
@@ -1253,29 +3102,35 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
                 write_node(writer, under)?;
                 write_m_end(writer, "lim")?;
                 write_m_end(writer, "limLow")?;
+                if relation {
+                    write_operator_box_end(writer)?;
+                }
             }
         }
+        MathNode::Nary { chr, sub, sup, body, display } => {
+            let lim_loc = if *display == Some(false) { "subSup" } else { "undOvr" };
+            write_nary(writer, chr, lim_loc, sub.as_deref(), sup.as_deref(), body)?;
+        }
+        MathNode::GroupChr { base, label, over, chr } => {
+            write_group_chr(writer, base, label.as_deref(), *over, *chr)?;
+        }
+        MathNode::Strike { base, up, down } => {
+            write_strike(writer, base, *up, *down)?;
+        }
+        MathNode::ZeroWidth(base) => {
+            write_zero_width(writer, base)?;
+        }
         MathNode::Munderover(base, under, over) => {
             let base_text = node_text(base);
             if is_large_operator(&base_text) {
-                // N-ary operator (sum, integral, etc.)
-                write_m_start(writer, "nary")?;
-                write_m_start(writer, "naryPr")?;
-                write_m_val_prop(writer, "chr", &base_text)?;
-                write_m_val_prop(writer, "limLoc", "undOvr")?;
-                write_m_end(writer, "naryPr")?;
-                write_m_start(writer, "sub")?;
-                write_node(writer, under)?;
-                write_m_end(writer, "sub")?;
-                write_m_start(writer, "sup")?;
-                write_node(writer, over)?;
-                write_m_end(writer, "sup")?;
-                // Empty element body – the operand typically follows in the parent
-                write_m_start(writer, "e")?;
-                write_m_end(writer, "e")?;
-                write_m_end(writer, "nary")?;
+                // N-ary operator (sum, integral, etc.) outside a row, so without operand
+                write_nary(writer, &base_text, "undOvr", Some(under), Some(over), &[])?;
             } else {
-                // Nested limits: limLow wrapping limUpp
+                // Nested limits: limLow wrapping limUpp (boxed for a decorated relation)
+                let relation = is_relation_node(base);
+                if relation {
+                    write_operator_box_start(writer)?;
+                }
                 write_m_start(writer, "limLow")?;
                 write_m_start(writer, "limLowPr")?;
                 write_m_end(writer, "limLowPr")?;
@@ -1294,9 +3149,14 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
                 write_node(writer, under)?;
                 write_m_end(writer, "lim")?;
                 write_m_end(writer, "limLow")?;
+                if relation {
+                    write_operator_box_end(writer)?;
+                }
             }
         }
         MathNode::Mtable(rows) => {
+            // Every row must have the same number of cells; short rows are padded
+            let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
             write_m_start(writer, "m")?;
             // mPr – matrix properties
             write_m_start(writer, "mPr")?;
@@ -1306,9 +3166,57 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
                 for cell in row {
                     write_single_element(writer, cell)?;
                 }
+                for _ in row.len()..columns {
+                    write_element_wrapper(writer, &[])?;
+                }
+                write_m_end(writer, "mr")?;
+            }
+            write_m_end(writer, "m")?;
+        }
+        MathNode::EqArray(rows) => {
+            write_m_start(writer, "eqArr")?;
+            write_m_start(writer, "eqArrPr")?;
+            write_m_end(writer, "eqArrPr")?;
+            for row in rows {
+                write_m_start(writer, "e")?;
+                write_eq_array_row(writer, row)?;
+                write_m_end(writer, "e")?;
+            }
+            write_m_end(writer, "eqArr")?;
+        }
+        MathNode::Cases(rows) => {
+            let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(2);
+            write_m_start(writer, "d")?;
+            write_m_start(writer, "dPr")?;
+            write_m_val_prop(writer, "begChr", "{")?;
+            write_m_val_prop(writer, "endChr", "")?;
+            write_m_end(writer, "dPr")?;
+            write_m_start(writer, "e")?;
+            // Left-aligned columns: value, then condition
+            write_m_start(writer, "m")?;
+            write_m_start(writer, "mPr")?;
+            write_m_start(writer, "mcs")?;
+            write_m_start(writer, "mc")?;
+            write_m_start(writer, "mcPr")?;
+            write_m_val_prop(writer, "count", &columns.to_string())?;
+            write_m_val_prop(writer, "mcJc", "left")?;
+            write_m_end(writer, "mcPr")?;
+            write_m_end(writer, "mc")?;
+            write_m_end(writer, "mcs")?;
+            write_m_end(writer, "mPr")?;
+            for row in rows {
+                write_m_start(writer, "mr")?;
+                for cell in row {
+                    write_single_element(writer, cell)?;
+                }
+                for _ in row.len()..columns {
+                    write_element_wrapper(writer, &[])?;
+                }
                 write_m_end(writer, "mr")?;
             }
             write_m_end(writer, "m")?;
+            write_m_end(writer, "e")?;
+            write_m_end(writer, "d")?;
         }
         MathNode::Mfenced {
             open,
@@ -1325,7 +3233,7 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
         }
         MathNode::Mspace => {
             // Emit a thin space run
-            write_run(writer, "\u{2009}")?;
+            write_run(writer, "\u{2009}", None)?;
         }
     }
     Ok(())
@@ -1336,39 +3244,52 @@ fn write_node(writer: &mut Writer<Cursor<Vec<u8>>>, node: &MathNode) -> Result<(
 /// Converts a MathML XML string into OMML (Office Math Markup Language) XML.
 /// The conversion parses the MathML into an intermediate tree representation,
 /// then serializes it as OMML wrapped in `<m:oMathPara><m:oMath>...</m:oMath></m:oMathPara>`.
+/// The output follows `OmmlProfile::Word2007` (see `mathml_to_omml_with_profile`).
 ///
 /// # Errors
 ///
 /// Returns `ConvertError::MathmlToOmml` if the MathML is malformed or contains
 /// elements that cannot be converted.
 pub fn mathml_to_omml(mathml: &str) -> Result<String, ConvertError> {
-    // Parse MathML into intermediate tree
     let nodes = parse_mathml(mathml)?;
+    write_omml_document(&nodes, true)
+}
+
+/// MathML → 行内 OMML（不带 `<m:oMathPara>`）
+///
+/// Same as `mathml_to_omml`, but the root is `<m:oMath xmlns:m="...">`, so
+/// the formula flows inline with the text of a Word paragraph instead of
+/// being placed on its own line.
+pub fn mathml_to_omath(mathml: &str) -> Result<String, ConvertError> {
+    let nodes = parse_mathml(mathml)?;
+    write_omml_document(&nodes, false)
+}
 
-    // Write OMML
+/// Serialize parsed nodes as an OMML document: `<m:oMath>` inside an
+/// `<m:oMathPara>` when `paragraph` is set, a bare `<m:oMath>` otherwise.
+/// The `xmlns:m` declaration goes on the root element.
+fn write_omml_document(nodes: &[MathNode], paragraph: bool) -> Result<String, ConvertError> {
     let mut writer = Writer::new(Cursor::new(Vec::new()));
 
-    // <m:oMathPara xmlns:m="...">
-    let mut para_start = BytesStart::new("m:oMathPara");
-    para_start.push_attribute(("xmlns:m", OMML_NS));
+    let root = if paragraph { "m:oMathPara" } else { "m:oMath" };
+    let mut root_start = BytesStart::new(root);
+    root_start.push_attribute(("xmlns:m", OMML_NS));
     writer
-        .write_event(Event::Start(para_start))
+        .write_event(Event::Start(root_start))
         .map_err(|e| ConvertError::MathmlToOmml(format!("Write error: {}", e)))?;
+    if paragraph {
+        write_m_start(&mut writer, "oMath")?;
+    }
 
-    // <m:oMath>
-    write_m_start(&mut writer, "oMath")?;
-
-    // Write all nodes
-    for node in &nodes {
+    for node in nodes {
         write_node(&mut writer, node)?;
     }
 
-    // </m:oMath>
-    write_m_end(&mut writer, "oMath")?;
-
-    // </m:oMathPara>
+    if paragraph {
+        write_m_end(&mut writer, "oMath")?;
+    }
     writer
-        .write_event(Event::End(BytesEnd::new("m:oMathPara")))
+        .write_event(Event::End(BytesEnd::new(root)))
         .map_err(|e| ConvertError::MathmlToOmml(format!("Write error: {}", e)))?;
 
     let result = writer.into_inner().into_inner();
@@ -1376,15 +3297,89 @@ pub fn mathml_to_omml(mathml: &str) -> Result<String, ConvertError> {
         .map_err(|e| ConvertError::MathmlToOmml(format!("UTF-8 error: {}", e)))
 }
 
+/// MathML → OMML，按目标 Word 版本调整序列化细节
+///
+/// Same as `mathml_to_omml`; with `OmmlProfile::Word2010Plus`, property
+/// elements that carry no properties (`<m:fPr></m:fPr>`) are left out.
+pub fn mathml_to_omml_with_profile(mathml: &str, profile: OmmlProfile) -> Result<String, ConvertError> {
+    let omml = mathml_to_omml(mathml)?;
+    Ok(match profile {
+        OmmlProfile::Word2007 => omml,
+        OmmlProfile::Word2010Plus => strip_empty_properties(&omml),
+    })
+}
+
+/// Remove empty `<m:xxxPr></m:xxxPr>` elements.
+fn strip_empty_properties(omml: &str) -> String {
+    match regex::Regex::new(r"<m:([A-Za-z]+Pr)></m:([A-Za-z]+Pr)>") {
+        Ok(re) => re
+            .replace_all(omml, |caps: &regex::Captures| {
+                if caps[1] == caps[2] {
+                    String::new()
+                } else {
+                    caps[0].to_string()
+                }
+            })
+            .into_owned(),
+        Err(_) => omml.to_string(),
+    }
+}
+
+/// 提取 MathML 中的 LaTeX 注释
+///
+/// Returns the trimmed text of the first
+/// `<annotation encoding="application/x-tex">` (also `TeX` /
+/// `application/x-latex`) inside a `<semantics>` element, or `None` if there
+/// is none. `mathml_to_omml` drops annotations from the formula itself.
+pub fn extract_latex_annotation(mathml: &str) -> Option<String> {
+    let mut reader = Reader::from_str(mathml);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                let tag_name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                if strip_ns_prefix(&tag_name) != "annotation" {
+                    buf.clear();
+                    continue;
+                }
+                let is_tex = matches!(
+                    get_attr(e, "encoding").as_deref(),
+                    Some("application/x-tex" | "TeX" | "application/x-latex")
+                );
+                if is_tex {
+                    let text = read_text_content(&mut reader, "annotation").ok()?;
+                    let text = text.trim();
+                    if !text.is_empty() {
+                        return Some(text.to_string());
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
 /// LaTeX → OMML（组合调用）
 ///
 /// Converts a LaTeX math expression to OMML by first converting to MathML,
 /// then converting the MathML to OMML.
-pub fn latex_to_omml(latex: &str) -> Result<String, ConvertError> {
-    let mathml = latex_to_mathml(latex)?;
+pub fn latex_to_omml(latex: &str, display: ConvertDisplay) -> Result<String, ConvertError> {
+    let mathml = latex_to_mathml(latex, display)?;
     mathml_to_omml(&mathml)
 }
 
+/// LaTeX → 行内 OMML（只有 `<m:oMath>`，不带 `<m:oMathPara>`）
+///
+/// Converts in `ConvertDisplay::Inline` mode like `latex_to_omml`,
+/// but writes the result with `mathml_to_omath`, for embedding the formula
+/// in a Word paragraph that also contains text.
+pub fn latex_to_omml_inline(latex: &str) -> Result<String, ConvertError> {
+    let mathml = latex_to_mathml(latex, ConvertDisplay::Inline)?;
+    mathml_to_omath(&mathml)
+}
+
 /// 格式化 OMML 为可读 XML
 ///
 /// Parses the input OMML XML string and re-serializes it with proper indentation
@@ -1434,7 +3429,7 @@ mod tests {
 
     #[test]
     fn test_simple_variable() {
-        let result = latex_to_mathml("x").unwrap();
+        let result = latex_to_mathml_inline("x").unwrap();
         assert!(result.contains("<math"), "Output should contain <math tag");
         assert!(result.contains("</math>"), "Output should be closed with </math>");
         assert!(result.contains("x"), "Output should contain the variable 'x'");
@@ -1442,7 +3437,7 @@ mod tests {
 
     #[test]
     fn test_superscript_and_subscript() {
-        let result = latex_to_mathml("x_i^2").unwrap();
+        let result = latex_to_mathml_inline("x_i^2").unwrap();
         assert!(result.contains("<math"), "Should produce valid MathML");
         let has_script_tag = result.contains("<msub")
             || result.contains("<msup")
@@ -1452,19 +3447,43 @@ mod tests {
 
     #[test]
     fn test_fraction() {
-        let result = latex_to_mathml(r"\frac{a}{b}").unwrap();
+        let result = latex_to_mathml_inline(r"\frac{a}{b}").unwrap();
         assert!(result.contains("<mfrac"), "Should contain <mfrac> for fractions");
     }
 
+    #[test]
+    fn test_display_style_attribute() {
+        let block = latex_to_mathml(r"\sum_{i=1}^{n} i", ConvertDisplay::Block).unwrap();
+        assert!(block.contains(r#"display="block""#), "block mode should be marked: {}", block);
+
+        let inline = latex_to_mathml(r"\sum_{i=1}^{n} i", ConvertDisplay::Inline).unwrap();
+        assert!(inline.contains(r#"display="inline""#), "inline mode should be marked: {}", inline);
+        assert_eq!(inline, latex_to_mathml_inline(r"\sum_{i=1}^{n} i").unwrap());
+    }
+
+    #[test]
+    fn test_block_display_converts_to_omml() {
+        let omml = latex_to_omml(r"\frac{a}{b}", ConvertDisplay::Block).unwrap();
+        assert!(omml.starts_with("<m:oMathPara"), "got: {}", omml);
+        assert!(omml.contains("<m:f>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_convert_display_deserializes_lowercase() {
+        let display: ConvertDisplay = serde_json::from_str(r#""block""#).unwrap();
+        assert_eq!(display, ConvertDisplay::Block);
+        assert_eq!(ConvertDisplay::default(), ConvertDisplay::Inline);
+    }
+
     #[test]
     fn test_square_root() {
-        let result = latex_to_mathml(r"\sqrt{x}").unwrap();
+        let result = latex_to_mathml_inline(r"\sqrt{x}").unwrap();
         assert!(result.contains("<msqrt"), "Should contain <msqrt> for square roots");
     }
 
     #[test]
     fn test_integral() {
-        let result = latex_to_mathml(r"\int_0^\infty f(x) dx").unwrap();
+        let result = latex_to_mathml_inline(r"\int_0^\infty f(x) dx").unwrap();
         assert!(result.contains("<math"), "Should produce valid MathML");
         assert!(
             result.contains("∫") || result.contains("&#x222B;") || result.contains("int"),
@@ -1474,7 +3493,7 @@ mod tests {
 
     #[test]
     fn test_summation() {
-        let result = latex_to_mathml(r"\sum_{i=0}^{n} i").unwrap();
+        let result = latex_to_mathml_inline(r"\sum_{i=0}^{n} i").unwrap();
         assert!(result.contains("<math"), "Should produce valid MathML");
         assert!(
             result.contains("∑") || result.contains("&#x2211;") || result.contains("sum"),
@@ -1484,7 +3503,7 @@ mod tests {
 
     #[test]
     fn test_matrix() {
-        let result = latex_to_mathml(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap();
+        let result = latex_to_mathml_inline(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap();
         assert!(result.contains("<math"), "Should produce valid MathML");
         assert!(
             result.contains("<mtable") || result.contains("<mtr"),
@@ -1494,7 +3513,7 @@ mod tests {
 
     #[test]
     fn test_greek_letters() {
-        let result = latex_to_mathml(r"\alpha + \beta = \gamma").unwrap();
+        let result = latex_to_mathml_inline(r"\alpha + \beta = \gamma").unwrap();
         assert!(result.contains("<math"), "Should produce valid MathML");
         assert!(
             result.contains("α") || result.contains("&#x03B1;") || result.contains("alpha"),
@@ -1511,7 +3530,7 @@ mod tests {
             r"\sqrt{a^2 + b^2}",
         ];
         for formula in formulas {
-            let result = latex_to_mathml(formula).unwrap();
+            let result = latex_to_mathml_inline(formula).unwrap();
             assert!(
                 result.starts_with("<math"),
                 "MathML output for '{}' should start with <math",
@@ -1527,7 +3546,7 @@ mod tests {
 
     #[test]
     fn test_unknown_environment_returns_unsupported_symbol() {
-        let result = latex_to_mathml(r"\begin{tikzpicture} \end{tikzpicture}");
+        let result = latex_to_mathml_inline(r"\begin{tikzpicture} \end{tikzpicture}");
         assert!(result.is_err(), "Unknown environment should produce an error");
         match result.unwrap_err() {
             ConvertError::UnsupportedSymbol(sym) => {
@@ -1549,7 +3568,7 @@ mod tests {
 
     #[test]
     fn test_empty_input() {
-        let result = latex_to_mathml("");
+        let result = latex_to_mathml_inline("");
         if let Ok(mathml) = &result {
             assert!(mathml.contains("<math"), "Even empty input should produce <math wrapper");
         }
@@ -1558,14 +3577,14 @@ mod tests {
     #[test]
     fn test_complex_formula() {
         let latex = r"\int_0^1 \frac{\sqrt{x^2 + 1}}{\sum_{k=0}^{n} \alpha_k} dx";
-        let result = latex_to_mathml(latex).unwrap();
+        let result = latex_to_mathml_inline(latex).unwrap();
         assert!(result.contains("<math"), "Complex formula should produce valid MathML");
         assert!(result.contains("</math>"), "Complex formula should be well-formed");
     }
 
     #[test]
     fn test_error_is_descriptive() {
-        let result = latex_to_mathml(r"\frac{a}");
+        let result = latex_to_mathml_inline(r"\frac{a}");
         if let Err(e) = result {
             let msg = e.to_string();
             assert!(!msg.is_empty(), "Error message should not be empty");
@@ -1620,7 +3639,7 @@ mod tests {
 
     #[test]
     fn test_mathml_to_omml_simple_variable() {
-        let mathml = latex_to_mathml("x").unwrap();
+        let mathml = latex_to_mathml_inline("x").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:r>"), "Should contain a run element");
@@ -1631,7 +3650,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_fraction() {
         // Requirement 6.6: 分式
-        let mathml = latex_to_mathml(r"\frac{a}{b}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\frac{a}{b}").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:f>"), "Should contain fraction element <m:f>");
@@ -1644,7 +3663,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_square_root() {
         // Requirement 6.6: 根号
-        let mathml = latex_to_mathml(r"\sqrt{x}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\sqrt{x}").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:rad>"), "Should contain radical element <m:rad>");
@@ -1657,7 +3676,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_superscript() {
         // Requirement 6.6: 上标
-        let mathml = latex_to_mathml("x^2").unwrap();
+        let mathml = latex_to_mathml_inline("x^2").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(
@@ -1672,7 +3691,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_subscript() {
         // Requirement 6.6: 下标
-        let mathml = latex_to_mathml("x_i").unwrap();
+        let mathml = latex_to_mathml_inline("x_i").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(
@@ -1685,7 +3704,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_sub_superscript() {
         // Requirement 6.6: 上下标
-        let mathml = latex_to_mathml("x_i^2").unwrap();
+        let mathml = latex_to_mathml_inline("x_i^2").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Could be sSubSup or nested sSub/sSup depending on MathML structure
@@ -1698,7 +3717,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_greek_letters() {
         // Requirement 6.6: 希腊字母
-        let mathml = latex_to_mathml(r"\alpha + \beta").unwrap();
+        let mathml = latex_to_mathml_inline(r"\alpha + \beta").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Greek letters should appear as Unicode in the output
@@ -1716,7 +3735,7 @@ mod tests {
     fn test_mathml_to_omml_matrix() {
         // Requirement 6.6: 矩阵
         let mathml =
-            latex_to_mathml(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap();
+            latex_to_mathml_inline(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Matrix should produce <m:m> with <m:mr> rows
@@ -1729,10 +3748,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_matrix_empty_cell_keeps_column_count() {
+        let omml = latex_to_omml(r"\begin{matrix} & b \\ c & d+e \end{matrix}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        let rows: Vec<&str> = omml.split("<m:mr>").skip(1).collect();
+        assert_eq!(rows.len(), 2, "got: {}", omml);
+        for row in rows {
+            assert_eq!(row.matches("<m:e>").count(), 2, "got: {}", omml);
+        }
+        assert!(omml.contains("<m:mr><m:e></m:e><m:e><m:r><m:t>b</m:t></m:r></m:e></m:mr>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_matrix_self_closing_and_missing_cells_are_padded() {
+        let mathml = "<math><mtable><mtr><mtd/><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd></mtr></mtable></math>";
+        let omml = mathml_to_omml(mathml).unwrap();
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains(concat!(
+                "<m:mr><m:e></m:e><m:e><m:r><m:t>b</m:t></m:r></m:e></m:mr>",
+                "<m:mr><m:e><m:r><m:t>c</m:t></m:r></m:e><m:e></m:e></m:mr>"
+            )),
+            "got: {}",
+            omml
+        );
+    }
+
     #[test]
     fn test_mathml_to_omml_summation() {
         // Requirement 6.6: 求和
-        let mathml = latex_to_mathml(r"\sum_{i=0}^{n} i").unwrap();
+        let mathml = latex_to_mathml_inline(r"\sum_{i=0}^{n} i").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Summation should produce nary or sub/sup elements
@@ -1747,7 +3793,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_integral() {
         // Requirement 6.6: 积分
-        let mathml = latex_to_mathml(r"\int_0^1 f(x) dx").unwrap();
+        let mathml = latex_to_mathml_inline(r"\int_0^1 f(x) dx").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Should contain the integral symbol somewhere
@@ -1760,7 +3806,7 @@ mod tests {
     #[test]
     fn test_latex_to_omml_composition() {
         // Requirement 6.1, 6.4: latex_to_omml should compose latex_to_mathml and mathml_to_omml
-        let omml = latex_to_omml(r"\frac{1}{2}").unwrap();
+        let omml = latex_to_omml(r"\frac{1}{2}", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:f>"), "Should contain fraction");
         assert!(omml.contains("1"), "Should contain numerator '1'");
@@ -1770,13 +3816,13 @@ mod tests {
     #[test]
     fn test_latex_to_omml_complex_formula() {
         // Requirement 6.6: complex formula combining multiple features
-        let omml = latex_to_omml(r"e^{i\pi} + 1 = 0").unwrap();
+        let omml = latex_to_omml(r"e^{i\pi} + 1 = 0", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
     }
 
     #[test]
     fn test_latex_to_omml_euler_identity() {
-        let omml = latex_to_omml(r"\sqrt{a^2 + b^2}").unwrap();
+        let omml = latex_to_omml(r"\sqrt{a^2 + b^2}", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:rad>"), "Should contain radical");
         assert!(omml.contains("<m:sSup>"), "Should contain superscript");
@@ -1785,7 +3831,7 @@ mod tests {
     #[test]
     fn test_mathml_to_omml_preserves_text_content() {
         // Verify that text content is preserved through the conversion
-        let mathml = latex_to_mathml("abc").unwrap();
+        let mathml = latex_to_mathml_inline("abc").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("a"), "Should preserve 'a'");
@@ -1795,7 +3841,7 @@ mod tests {
 
     #[test]
     fn test_mathml_to_omml_nested_fractions() {
-        let mathml = latex_to_mathml(r"\frac{\frac{a}{b}}{c}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\frac{\frac{a}{b}}{c}").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         // Should have nested fractions
@@ -1836,7 +3882,7 @@ mod tests {
 
     #[test]
     fn test_mathml_to_omml_nth_root() {
-        let mathml = latex_to_mathml(r"\sqrt[3]{x}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\sqrt[3]{x}").unwrap();
         let omml = mathml_to_omml(&mathml).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:rad>"), "Should contain radical element");
@@ -1909,7 +3955,7 @@ mod tests {
     #[test]
     fn test_pretty_print_omml_basic() {
         // Generate OMML from a simple formula, then pretty-print it
-        let omml = latex_to_omml("x").unwrap();
+        let omml = latex_to_omml("x", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         // The pretty output should contain newlines (indentation)
@@ -1925,7 +3971,7 @@ mod tests {
     #[test]
     fn test_pretty_print_omml_preserves_structure() {
         // Requirement 6.3: pretty_print_omml should preserve the XML DOM structure
-        let omml = latex_to_omml(r"\frac{a}{b}").unwrap();
+        let omml = latex_to_omml(r"\frac{a}{b}", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         // Parse both and compare structural events
@@ -1941,7 +3987,7 @@ mod tests {
     #[test]
     fn test_pretty_print_omml_preserves_attributes() {
         // Ensure attributes (like xmlns:m, m:val) are preserved
-        let omml = latex_to_omml(r"\sqrt{x}").unwrap();
+        let omml = latex_to_omml(r"\sqrt{x}", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         assert!(
@@ -1961,7 +4007,7 @@ mod tests {
 
     #[test]
     fn test_pretty_print_omml_preserves_text_content() {
-        let omml = latex_to_omml(r"\alpha + \beta").unwrap();
+        let omml = latex_to_omml(r"\alpha + \beta", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         // Text content should be preserved
@@ -1977,7 +4023,7 @@ mod tests {
 
     #[test]
     fn test_pretty_print_omml_indentation() {
-        let omml = latex_to_omml("x").unwrap();
+        let omml = latex_to_omml("x", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         // Check that indentation uses spaces
@@ -1999,7 +4045,7 @@ mod tests {
     #[test]
     fn test_pretty_print_omml_complex_formula() {
         // Test with a complex formula that exercises many OMML elements
-        let omml = latex_to_omml(r"\int_0^1 \frac{\sqrt{x^2 + 1}}{\sum_{k=0}^{n} \alpha_k} dx").unwrap();
+        let omml = latex_to_omml(r"\int_0^1 \frac{\sqrt{x^2 + 1}}{\sum_{k=0}^{n} \alpha_k} dx", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
 
         // Should be valid XML
@@ -2043,7 +4089,7 @@ mod tests {
     #[test]
     fn test_pretty_print_omml_idempotent() {
         // Pretty-printing an already pretty-printed string should produce the same result
-        let omml = latex_to_omml(r"\frac{a}{b}").unwrap();
+        let omml = latex_to_omml(r"\frac{a}{b}", ConvertDisplay::Inline).unwrap();
         let pretty1 = pretty_print_omml(&omml).unwrap();
         let pretty2 = pretty_print_omml(&pretty1).unwrap();
         assert_eq!(
@@ -2054,7 +4100,7 @@ mod tests {
 
     #[test]
     fn test_pretty_print_omml_matrix() {
-        let omml = latex_to_omml(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap();
+        let omml = latex_to_omml(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}", ConvertDisplay::Inline).unwrap();
         let pretty = pretty_print_omml(&omml).unwrap();
         assert_valid_omml(&pretty);
 
@@ -2072,13 +4118,13 @@ mod tests {
     #[test]
     fn test_task34_superscript_subscript_combined() {
         // 测试上下标组合: x^2_i
-        let mathml = latex_to_mathml("x^2_i").unwrap();
+        let mathml = latex_to_mathml_inline("x^2_i").unwrap();
         assert!(mathml.contains("<math"), "Should produce valid MathML");
         let has_script = mathml.contains("<msubsup") 
             || (mathml.contains("<msub") && mathml.contains("<msup"));
         assert!(has_script, "Should contain sub/superscript elements");
         
-        let omml = latex_to_omml("x^2_i").unwrap();
+        let omml = latex_to_omml("x^2_i", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
         let has_omml_script = omml.contains("<m:sSubSup>")
             || (omml.contains("<m:sSub>") && omml.contains("<m:sSup>"));
@@ -2091,12 +4137,12 @@ mod tests {
     #[test]
     fn test_task34_fraction_ab() {
         // 测试分式: \frac{a}{b}
-        let mathml = latex_to_mathml(r"\frac{a}{b}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\frac{a}{b}").unwrap();
         assert!(mathml.contains("<mfrac"), "MathML should contain <mfrac>");
         assert!(mathml.contains("a"), "Should contain numerator 'a'");
         assert!(mathml.contains("b"), "Should contain denominator 'b'");
         
-        let omml = latex_to_omml(r"\frac{a}{b}").unwrap();
+        let omml = latex_to_omml(r"\frac{a}{b}", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:f>"), "OMML should contain fraction <m:f>");
         assert!(omml.contains("<m:num>"), "OMML should contain <m:num>");
@@ -2106,236 +4152,1520 @@ mod tests {
     #[test]
     fn test_task34_square_root_x() {
         // 测试根号: \sqrt{x}
-        let mathml = latex_to_mathml(r"\sqrt{x}").unwrap();
+        let mathml = latex_to_mathml_inline(r"\sqrt{x}").unwrap();
         assert!(mathml.contains("<msqrt"), "MathML should contain <msqrt>");
         assert!(mathml.contains("x"), "Should contain radicand 'x'");
         
-        let omml = latex_to_omml(r"\sqrt{x}").unwrap();
+        let omml = latex_to_omml(r"\sqrt{x}", ConvertDisplay::Inline).unwrap();
         assert_valid_omml(&omml);
         assert!(omml.contains("<m:rad>"), "OMML should contain radical <m:rad>");
         assert!(omml.contains("degHide"), "Square root should hide degree");
     }
 
     #[test]
-    fn test_task34_integral_bounds() {
-        // 测试积分: \int_0^1
-        let mathml = latex_to_mathml(r"\int_0^1 f(x) dx").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        assert!(
-            mathml.contains("∫") || mathml.contains("int"),
-            "Should contain integral symbol"
-        );
-        
-        let omml = latex_to_omml(r"\int_0^1 f(x) dx").unwrap();
+    fn test_task34_integral_bounds() {
+        // 测试积分: \int_0^1
+        let mathml = latex_to_mathml_inline(r"\int_0^1 f(x) dx").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(
+            mathml.contains("∫") || mathml.contains("int"),
+            "Should contain integral symbol"
+        );
+        
+        let omml = latex_to_omml(r"\int_0^1 f(x) dx", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains("∫") || omml.contains("<m:nary>"),
+            "OMML should contain integral"
+        );
+        assert!(omml.contains("0"), "Should contain lower bound '0'");
+        assert!(omml.contains("1"), "Should contain upper bound '1'");
+    }
+
+    #[test]
+    fn test_task34_summation_bounds() {
+        // 测试求和: \sum_{i=1}^n
+        let mathml = latex_to_mathml_inline(r"\sum_{i=1}^{n} a_i").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(
+            mathml.contains("∑") || mathml.contains("sum"),
+            "Should contain summation symbol"
+        );
+        
+        let omml = latex_to_omml(r"\sum_{i=1}^{n} a_i", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains("∑") || omml.contains("<m:nary>"),
+            "OMML should contain summation"
+        );
+    }
+
+    #[test]
+    fn test_task34_matrix_basic() {
+        // 测试矩阵: \begin{matrix}...\end{matrix}
+        let mathml = latex_to_mathml_inline(r"\begin{matrix} a & b \\ c & d \end{matrix}").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(
+            mathml.contains("<mtable") || mathml.contains("<mtr"),
+            "MathML should contain matrix elements"
+        );
+        
+        let omml = latex_to_omml(r"\begin{matrix} a & b \\ c & d \end{matrix}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        let has_matrix = omml.contains("<m:m>") || omml.contains("<m:mr>");
+        assert!(has_matrix, "OMML should contain matrix elements");
+        assert!(omml.contains("a"), "Should contain element 'a'");
+        assert!(omml.contains("d"), "Should contain element 'd'");
+    }
+
+    #[test]
+    fn test_task34_greek_alpha_beta_gamma() {
+        // 测试希腊字母: \alpha, \beta, \gamma
+        let mathml = latex_to_mathml_inline(r"\alpha + \beta + \gamma").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(
+            mathml.contains("α") || mathml.contains("alpha"),
+            "Should contain alpha"
+        );
+        assert!(
+            mathml.contains("β") || mathml.contains("beta"),
+            "Should contain beta"
+        );
+        assert!(
+            mathml.contains("γ") || mathml.contains("gamma"),
+            "Should contain gamma"
+        );
+        
+        let omml = latex_to_omml(r"\alpha + \beta + \gamma", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(omml.contains("α"), "OMML should contain alpha symbol");
+        assert!(omml.contains("β"), "OMML should contain beta symbol");
+        assert!(omml.contains("γ"), "OMML should contain gamma symbol");
+    }
+
+    #[test]
+    fn test_task34_fallback_unsupported_symbol() {
+        // 测试转换失败的回退行为: 不支持的符号应返回描述性错误
+        let result = latex_to_mathml_inline(r"\begin{tikzpicture}\end{tikzpicture}");
+        assert!(result.is_err(), "Unsupported environment should fail");
+        
+        match result.unwrap_err() {
+            ConvertError::UnsupportedSymbol(sym) => {
+                assert!(
+                    sym.contains("tikzpicture"),
+                    "Error should mention the unsupported symbol: {}",
+                    sym
+                );
+            }
+            ConvertError::LatexToMathml(msg) => {
+                assert!(
+                    !msg.is_empty(),
+                    "Error message should be descriptive"
+                );
+            }
+            _ => panic!("Unexpected error type"),
+        }
+    }
+
+    #[test]
+    fn test_task34_fallback_malformed_latex() {
+        // 测试转换失败的回退行为: 格式错误的 LaTeX
+        let result = latex_to_mathml_inline(r"\frac{a}");
+        // Should return an error for incomplete fraction
+        if let Err(e) = result {
+            let msg = e.to_string();
+            assert!(!msg.is_empty(), "Error message should not be empty");
+        }
+    }
+
+    #[test]
+    fn test_task34_fallback_latex_to_omml_chain() {
+        // 测试 latex_to_omml 组合调用的错误传播
+        let result = latex_to_omml(r"\begin{unknownenv}\end{unknownenv}", ConvertDisplay::Inline);
+        assert!(result.is_err(), "Unknown environment should fail in full chain");
+        
+        let err = result.unwrap_err();
+        let msg = err.to_string();
+        assert!(!msg.is_empty(), "Error should be descriptive");
+    }
+
+    #[test]
+    fn test_task34_fallback_empty_input() {
+        // 测试空输入的处理
+        let mathml_result = latex_to_mathml_inline("");
+        // Empty input should either succeed with minimal output or fail gracefully
+        match mathml_result {
+            Ok(mathml) => {
+                assert!(mathml.contains("<math"), "Even empty input should produce <math wrapper");
+            }
+            Err(e) => {
+                let msg = e.to_string();
+                assert!(!msg.is_empty(), "Error should be descriptive");
+            }
+        }
+    }
+
+    #[test]
+    fn test_task34_combined_formula() {
+        // 测试组合公式: 包含多种元素
+        let latex = r"\int_0^1 \frac{\sqrt{x^2 + 1}}{\sum_{k=0}^{n} \alpha_k} dx";
+        let mathml = latex_to_mathml_inline(latex).unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(mathml.contains("</math>"), "Should be well-formed");
+        
+        let omml = latex_to_omml(latex, ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        // Should contain various elements
+        assert!(omml.contains("<m:f>") || omml.contains("<m:rad>"), 
+            "Should contain fraction or radical");
+    }
+
+    #[test]
+    fn test_task34_pmatrix_with_delimiters() {
+        // 测试带括号的矩阵
+        let mathml = latex_to_mathml_inline(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        
+        let omml = latex_to_omml(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        // pmatrix should have delimiters
+        let has_delim_or_matrix = omml.contains("<m:d>") || omml.contains("<m:m>");
+        assert!(has_delim_or_matrix, "Should contain delimiter or matrix element");
+    }
+
+    #[test]
+    fn test_task34_bmatrix() {
+        // 测试方括号矩阵
+        let mathml = latex_to_mathml_inline(r"\begin{bmatrix} a & b \\ c & d \end{bmatrix}").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        
+        let omml = latex_to_omml(r"\begin{bmatrix} a & b \\ c & d \end{bmatrix}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+    }
+
+    #[test]
+    fn test_task34_nth_root() {
+        // 测试 n 次根号
+        let mathml = latex_to_mathml_inline(r"\sqrt[3]{x}").unwrap();
+        assert!(mathml.contains("<mroot") || mathml.contains("<msqrt"), 
+            "Should contain root element");
+        
+        let omml = latex_to_omml(r"\sqrt[3]{x}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(omml.contains("<m:rad>"), "Should contain radical");
+        assert!(omml.contains("<m:deg>"), "Should contain degree for nth root");
+        assert!(omml.contains("3"), "Should contain root index '3'");
+    }
+
+    #[test]
+    fn test_task34_product_symbol() {
+        // 测试连乘符号
+        let mathml = latex_to_mathml_inline(r"\prod_{i=1}^{n} x_i").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        assert!(
+            mathml.contains("∏") || mathml.contains("prod"),
+            "Should contain product symbol"
+        );
+        
+        let omml = latex_to_omml(r"\prod_{i=1}^{n} x_i", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+    }
+
+    #[test]
+    fn test_task34_more_greek_letters() {
+        // 测试更多希腊字母
+        let mathml = latex_to_mathml_inline(r"\delta + \epsilon + \theta + \lambda + \pi + \sigma + \omega").unwrap();
+        assert!(mathml.contains("<math"), "Should produce valid MathML");
+        
+        let omml = latex_to_omml(r"\delta + \epsilon + \theta + \lambda + \pi + \sigma + \omega", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        // Check for some Greek letters in Unicode
+        assert!(omml.contains("δ") || omml.contains("delta"), "Should contain delta");
+        assert!(omml.contains("π") || omml.contains("pi"), "Should contain pi");
+    }
+
+    // =====================================================================
+    // Alignment environment tests
+    // =====================================================================
+
+    /// The `&` alignment marker immediately followed by `\overset{!}{=}`
+    /// (an operator box around a `limUpp` whose base is the `=` relation).
+    const ALIGNED_DECORATED_EQ: &str = "<m:r><m:t>&amp;</m:t></m:r><m:box><m:boxPr><m:opEmu m:val=\"1\"/></m:boxPr><m:e><m:limUpp><m:limUppPr></m:limUppPr><m:e><m:r><m:t>=</m:t></m:r></m:e><m:lim><m:r><m:t>!</m:t></m:r></m:lim></m:limUpp></m:e></m:box>";
+
+    /// Count the `<m:e>` rows directly inside the first `<m:eqArr>`.
+    fn eq_array_row_count(omml: &str) -> usize {
+        let mut reader = Reader::from_str(omml);
+        let mut depth_in_arr: Option<usize> = None;
+        let mut rows = 0;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) => {
+                    let name = e.name();
+                    match depth_in_arr {
+                        None if name.as_ref() == b"m:eqArr" => depth_in_arr = Some(0),
+                        Some(depth) => {
+                            if depth == 0 && name.as_ref() == b"m:e" {
+                                rows += 1;
+                            }
+                            depth_in_arr = Some(depth + 1);
+                        }
+                        None => {}
+                    }
+                }
+                Ok(Event::End(_)) => match depth_in_arr {
+                    Some(0) => break,
+                    Some(depth) => depth_in_arr = Some(depth - 1),
+                    None => {}
+                },
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_aligned_decorated_relation_keeps_alignment_on_base() {
+        let omml = latex_to_omml(r"\begin{aligned} a &= b \\ c &\overset{!}{=} d \end{aligned}", ConvertDisplay::Inline)
+            .expect("aligned should convert");
+        assert_valid_omml(&omml);
+
+        assert!(omml.contains("<m:eqArr>"), "aligned should map to an equation array: {}", omml);
+        assert_eq!(eq_array_row_count(&omml), 2, "eqArr should contain two rows: {}", omml);
+        assert!(
+            omml.contains("<m:r><m:t>a</m:t></m:r><m:r><m:t>&amp;</m:t></m:r><m:r><m:t>=</m:t></m:r>"),
+            "first row should align on '=': {}",
+            omml
+        );
+        assert!(
+            omml.contains(&format!("<m:r><m:t>c</m:t></m:r>{}", ALIGNED_DECORATED_EQ)),
+            "second row should align on the decorated '=': {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_split_inside_equation_is_two_row_equation_array() {
+        let omml = latex_to_omml(r"\begin{equation}\begin{split} a &= b \\ &= c \end{split}\end{equation}", ConvertDisplay::Inline)
+            .expect("split inside equation should convert");
+        assert_valid_omml(&omml);
+
+        assert!(omml.contains("<m:eqArr>"), "split should map to an equation array: {}", omml);
+        assert_eq!(eq_array_row_count(&omml), 2, "eqArr should contain two rows: {}", omml);
+        assert!(
+            omml.contains("<m:e><m:r><m:t>&amp;</m:t></m:r><m:r><m:t>=</m:t></m:r><m:r><m:t>c</m:t></m:r></m:e>"),
+            "continuation row should start at the alignment column: {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_equation_wrapper_and_label_are_stripped() {
+        let mathml = latex_to_mathml_inline(r"\begin{equation*} x = 1 \label{eq:x} \end{equation*}")
+            .expect("equation* should convert");
+        assert!(!mathml.contains("label"), "label should be dropped: {}", mathml);
+        assert!(mathml.contains("<mn>1</mn>"));
+    }
+
+    #[test]
+    fn test_align_row_without_ampersand_aligns_on_decorated_relation() {
+        let omml = latex_to_omml(r"\begin{align*} f(x) \stackrel{!}{=} 0 \\ y = 1 \end{align*}", ConvertDisplay::Inline)
+            .expect("align* should convert");
+        assert_valid_omml(&omml);
+
+        assert!(
+            omml.contains(&format!("<m:r><m:t>)</m:t></m:r>{}", ALIGNED_DECORATED_EQ)),
+            "alignment marker should precede the decorated '=': {}",
+            omml
+        );
+        assert!(
+            omml.contains("<m:r><m:t>y</m:t></m:r><m:r><m:t>&amp;</m:t></m:r><m:r><m:t>=</m:t></m:r>"),
+            "plain row should align on '=': {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_eqnarray_is_equation_array_aligned_on_relation() {
+        let omml = latex_to_omml(r"\begin{eqnarray} a &=& b + 1 \\ c &\leq& d \nonumber \end{eqnarray}", ConvertDisplay::Inline)
+            .expect("eqnarray should convert");
+        assert_valid_omml(&omml);
+
+        assert!(omml.contains("<m:eqArr>"), "eqnarray should map to an equation array: {}", omml);
+        assert_eq!(eq_array_row_count(&omml), 2, "eqArr should contain two rows: {}", omml);
+        assert_eq!(omml.matches("<m:t>&amp;</m:t>").count(), 2, "one alignment marker per row: {}", omml);
+        assert!(
+            omml.contains("<m:r><m:t>a</m:t></m:r><m:r><m:t>&amp;</m:t></m:r><m:r><m:t>=</m:t></m:r>"),
+            "first row should align on '=': {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_rewrite_eqnarray_keeps_escaped_ampersand() {
+        assert_eq!(
+            normalize_align_environments(r"\begin{eqnarray*} a &=& b \& c \\ d &<& e \end{eqnarray*}"),
+            r"\begin{align} a &= b \& c \\ d &< e \end{align}"
+        );
+    }
+
+    #[test]
+    fn test_single_row_align_round_trips() {
+        let omml = latex_to_omml(r"\begin{align} a &= b \end{align}", ConvertDisplay::Inline).expect("single-row align should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains("<m:eqArr>"), "got: {}", omml);
+        assert_eq!(eq_array_row_count(&omml), 1, "got: {}", omml);
+    }
+
+    // =====================================================================
+    // \operatorname tests
+    // =====================================================================
+
+    #[test]
+    fn test_operatorname_is_upright_function_with_adjacent_argument() {
+        let omml = latex_to_omml(r"\operatorname{Var}(X)", ConvertDisplay::Inline).expect("operatorname should convert");
+        assert_valid_omml(&omml);
+
+        // "Var" is one upright run used as the function name, and "(X)" is
+        // its argument with no detached runs in between
+        assert!(
+            omml.contains(concat!(
+                "<m:func><m:funcPr></m:funcPr>",
+                r#"<m:fName><m:r><m:rPr><m:sty m:val="p"/></m:rPr><m:t>Var</m:t></m:r></m:fName>"#,
+                "<m:e><m:r><m:t>(</m:t></m:r><m:r><m:t>X</m:t></m:r><m:r><m:t>)</m:t></m:r></m:e>",
+                "</m:func>"
+            )),
+            "got: {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_operatorname_without_argument_is_upright_run() {
+        let omml = latex_to_omml(r"\operatorname{rank} A", ConvertDisplay::Inline).expect("operatorname should convert");
+        assert!(omml.contains(r#"<m:r><m:rPr><m:sty m:val="p"/></m:rPr><m:t>rank</m:t></m:r><m:r><m:t>A</m:t></m:r>"#), "got: {}", omml);
+        assert!(!omml.contains("<m:func>"));
+    }
+
+    #[test]
+    fn test_adjacent_upright_symbols_and_groups_stay_apart() {
+        let omml = latex_to_omml(r"\Gamma\Delta x", ConvertDisplay::Inline).expect("greek should convert");
+        assert!(omml.contains(r#"<m:t>Γ</m:t></m:r><m:r><m:rPr><m:sty m:val="p"/></m:rPr><m:t>Δ</m:t>"#), "got: {}", omml);
+        assert!(!omml.contains("ΓΔ"), "got: {}", omml);
+
+        let omml = latex_to_omml(r"\operatorname{Var}\mathrm{ab} x", ConvertDisplay::Inline).expect("groups should convert");
+        assert!(omml.contains("<m:t>Var</m:t>"), "got: {}", omml);
+        assert!(omml.contains("<m:t>ab</m:t>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_text_and_mathrm_are_plain_style_runs() {
+        let omml = latex_to_omml(r"\text{abc}", ConvertDisplay::Inline).expect("text should convert");
+        assert!(omml.contains(r#"<m:r><m:rPr><m:sty m:val="p"/></m:rPr><m:t>abc</m:t></m:r>"#), "got: {}", omml);
+
+        let omml = latex_to_omml(r"\mathrm{d}x", ConvertDisplay::Inline).expect("mathrm should convert");
+        assert!(omml.contains(r#"<m:r><m:rPr><m:sty m:val="p"/></m:rPr><m:t>d</m:t></m:r>"#), "got: {}", omml);
+        // Plain identifiers keep Word's default italic math style
+        assert!(omml.contains("<m:r><m:t>x</m:t></m:r>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_limsup_is_upright_spaced_name_with_under_limit() {
+        let omml = latex_to_omml(r"\limsup_{n} a_n", ConvertDisplay::Inline).expect("limsup should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains("<m:limLow><m:limLowPr></m:limLowPr><m:e><m:r><m:rPr><m:sty m:val=\"p\"/></m:rPr><m:t>lim\u{2009}sup</m:t></m:r></m:e><m:lim><m:r><m:t>n</m:t></m:r></m:lim></m:limLow>"),
+            "got: {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_injlim_projlim_are_named_limit_operators() {
+        let omml = latex_to_omml(r"\injlim_{i \in I} A_i", ConvertDisplay::Inline).expect("injlim should convert");
+        assert!(!omml.contains("PARSE ERROR"), "got: {}", omml);
+        assert!(omml.contains("<m:limLow>"), "got: {}", omml);
+        assert!(omml.contains("<m:t>inj\u{2009}lim</m:t>"), "got: {}", omml);
+
+        let omml = latex_to_omml(r"\projlim A", ConvertDisplay::Inline).expect("projlim should convert");
+        assert!(omml.contains("<m:sty m:val=\"p\"/></m:rPr><m:t>proj\u{2009}lim</m:t>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_operatorname_keeps_thin_space_and_upright_with_subscript() {
+        let omml = latex_to_omml(r"\operatorname{lim\,sup}_n a", ConvertDisplay::Inline).expect("operatorname should convert");
+        assert!(
+            omml.contains("<m:e><m:r><m:rPr><m:sty m:val=\"p\"/></m:rPr><m:t>lim\u{2009}sup</m:t></m:r></m:e><m:sub>"),
+            "got: {}",
+            omml
+        );
+    }
+
+    // =====================================================================
+    // Custom operator (\mathop) tests
+    // =====================================================================
+
+    #[test]
+    fn test_mathop_sum_with_limits_is_nary_under_over() {
+        let omml = latex_to_omml(r"\mathop{\sum}\limits_{i=1}^{n} x", ConvertDisplay::Inline).expect("mathop should convert");
+        assert_valid_omml(&omml);
+
+        assert!(!omml.contains("PARSE ERROR"), "\\mathop should be understood: {}", omml);
+        assert!(omml.contains("<m:nary>"), "should be an n-ary operator: {}", omml);
+        assert!(omml.contains(r#"<m:chr m:val="∑"/><m:limLoc m:val="undOvr"/>"#), "limits should be under/over: {}", omml);
+        assert!(omml.contains("<m:sub><m:r><m:t>i</m:t></m:r><m:r><m:t>=</m:t></m:r><m:r><m:t>1</m:t></m:r></m:sub>"));
+        assert!(omml.contains("<m:sup><m:r><m:t>n</m:t></m:r></m:sup>"));
+    }
+
+    #[test]
+    fn test_mathop_bigoplus_with_lower_limit_is_nary() {
+        let omml = latex_to_omml(r"\mathop{\bigoplus}\limits_{i} A_i", ConvertDisplay::Inline).expect("mathop should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:chr m:val="⨁"/><m:limLoc m:val="undOvr"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_mathop_text_becomes_upright() {
+        assert_eq!(replace_mathop(r"\mathop{rank} A"), r"\mathrm{rank} A");
+        assert_eq!(replace_mathop(r"\mathopen{(} x"), r"\mathopen{(} x");
+    }
+
+    // =====================================================================
+    // Script argument brace tests
+    // =====================================================================
+
+    #[test]
+    fn test_fraction_in_superscript_keeps_its_braces() {
+        for latex in [r"x^{\frac{1}{2}}", r"x^{{\frac{1}{2}}}", r"x^{{{\frac{1}{2}}}}"] {
+            let omml = latex_to_omml(latex, ConvertDisplay::Inline).expect("fraction exponent should convert");
+            assert_valid_omml(&omml);
+            assert!(
+                omml.contains("<m:sup><m:f><m:fPr><m:type m:val=\"bar\"/></m:fPr><m:num><m:r><m:t>1</m:t></m:r></m:num><m:den><m:r><m:t>2</m:t></m:r></m:den></m:f></m:sup>"),
+                "{} should be x with a fractional superscript: {}",
+                latex,
+                omml
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_argument_groups_are_kept() {
+        assert_eq!(remove_empty_groups(r"x^{} + \frac{}{2} + \sqrt{}"), r"x^{} + \frac{}{2} + \sqrt{}");
+        assert_eq!(remove_empty_groups(r"a {} b"), r"a  b");
+        assert_eq!(preprocess_latex(r"x^{{}} y"), r"x^{} y");
+    }
+
+    // =====================================================================
+    // Strut tests
+    // =====================================================================
+
+    #[test]
+    fn test_mathstrut_produces_no_visible_run() {
+        let omml = latex_to_omml(r"x\mathstrut + \sqrt{\mathstrut a} \strut", ConvertDisplay::Inline).expect("struts should convert");
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("PARSE ERROR"), "got: {}", omml);
+        let runs: Vec<&str> = omml.split("<m:t>").skip(1).map(|t| t.split("</m:t>").next().unwrap()).collect();
+        assert_eq!(runs, vec!["x", "+", "a"]);
+    }
+
+    #[test]
+    fn test_strut_prefix_of_longer_command_is_kept() {
+        assert!(preprocess_latex(r"\struts").contains(r"\struts"));
+        assert_eq!(preprocess_latex(r"\alpha\strut b"), r"\alpha b");
+    }
+
+    // =====================================================================
+    // <semantics> annotation tests
+    // =====================================================================
+
+    const ANNOTATED_MATHML: &str = r#"<math xmlns="http://www.w3.org/1998/Math/MathML"><semantics><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow><annotation encoding="application/x-tex">x^{2}</annotation></semantics></math>"#;
+
+    #[test]
+    fn test_annotation_text_does_not_leak_into_omml() {
+        let omml = mathml_to_omml(ANNOTATED_MATHML).expect("annotated MathML should convert");
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("x^{2}"), "annotation text leaked into runs: {}", omml);
+        assert_eq!(omml.matches("<m:r>").count(), 2, "only x and 2 should be runs: {}", omml);
+        assert!(omml.contains("<m:sSup>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_annotation_xml_is_skipped() {
+        let mathml = r#"<math><semantics><mi>y</mi><annotation-xml encoding="MathML-Content"><ci>y</ci></annotation-xml></semantics></math>"#;
+        let omml = mathml_to_omml(mathml).expect("annotated MathML should convert");
+        assert_eq!(omml.matches("<m:r>").count(), 1, "got: {}", omml);
+    }
+
+    #[test]
+    fn test_extract_latex_annotation() {
+        assert_eq!(extract_latex_annotation(ANNOTATED_MATHML).as_deref(), Some("x^{2}"));
+        assert_eq!(extract_latex_annotation(r#"<math><mi>x</mi></math>"#), None);
+        assert_eq!(
+            extract_latex_annotation(r#"<math><semantics><mi>x</mi><annotation encoding="text/plain">x</annotation></semantics></math>"#),
+            None
+        );
+    }
+
+    // =====================================================================
+    // Array with \hline tests
+    // =====================================================================
+
+    #[test]
+    fn test_array_with_hline_converts_to_matrix() {
+        let omml = latex_to_omml(r"\begin{array}{cc|c} 1 & 2 & 3 \\ \hline 4 & 5 & 6 \end{array}", ConvertDisplay::Inline)
+            .expect("array with \\hline should convert");
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("PARSE ERROR"), "\\hline should not leak into the output: {}", omml);
+        assert_eq!(omml.matches("<m:mr>").count(), 2, "got: {}", omml);
+    }
+
+    #[test]
+    fn test_array_rules_around_rows_do_not_add_empty_rows() {
+        let preprocessed = convert_array_to_matrix(
+            r"\begin{array}{cc} \hline 1 & 2 \\ \cline{1-2} 3 & 4 \\ \hline \end{array}",
+        );
+        assert!(!preprocessed.contains("line"), "got: {}", preprocessed);
+
+        let omml = latex_to_omml(r"\begin{array}{cc} \hline 1 & 2 \\ \hline 3 & 4 \\ \hline \end{array}", ConvertDisplay::Inline)
+            .expect("array should convert");
+        assert_eq!(omml.matches("<m:mr>").count(), 2, "got: {}", omml);
+    }
+
+    #[test]
+    fn test_left_aligned_array_stays_matrix() {
+        let omml = latex_to_omml(r"\begin{array}{ll} a & b \\ c & d \end{array}", ConvertDisplay::Inline)
+            .expect("array should convert");
+        assert!(omml.contains("<m:m>"), "got: {}", omml);
+        assert!(!omml.contains("<m:eqArr>"), "got: {}", omml);
+
+        let omml = mathml_to_omml(
+            "<math><mtable columnalign=\"left\"><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr></mtable></math>",
+        )
+        .expect("left-aligned table should convert");
+        assert!(omml.contains("<m:m>"), "got: {}", omml);
+        assert!(!omml.contains("<m:eqArr>"), "got: {}", omml);
+    }
+
+    // =====================================================================
+    // \cfrac / \dfrac tests
+    // =====================================================================
+
+    #[test]
+    fn test_three_level_cfrac_gives_nested_fractions() {
+        let omml = latex_to_omml(r"\cfrac{1}{1+\cfrac{1}{1+\cfrac{1}{1+\cdots}}}", ConvertDisplay::Inline)
+            .expect("continued fraction should convert");
+        assert_eq!(omml.matches("<m:f>").count(), 3, "{}", omml);
+        // Each fraction sits in the denominator of the one before
+        assert!(omml.contains("<m:den><m:r><m:t>1</m:t></m:r><m:r><m:t>+</m:t></m:r><m:f>"), "{}", omml);
+        assert!(!omml.contains("PARSE ERROR"), "{}", omml);
+    }
+
+    #[test]
+    fn test_dfrac_tfrac_and_aligned_cfrac_are_plain_fractions() {
+        for latex in [r"\dfrac{a}{b}", r"\tfrac{a}{b}", r"\cfrac[l]{a}{b}", r"\dfrac12"] {
+            let omml = latex_to_omml(latex, ConvertDisplay::Inline).expect("fraction should convert");
+            assert_eq!(omml.matches("<m:f>").count(), 1, "{}: {}", latex, omml);
+        }
+    }
+
+    #[test]
+    fn test_deep_cfrac_converts_within_limits() {
+        let depth = 30;
+        let latex = format!("{}1{}", r"\cfrac{1}{1+".repeat(depth), "}".repeat(depth));
+        let omml = latex_to_omml(&latex, ConvertDisplay::Inline).expect("30-level continued fraction should convert");
+        assert_eq!(omml.matches("<m:f>").count(), depth);
+    }
+
+    // =====================================================================
+    // Complexity guard tests
+    // =====================================================================
+
+    #[test]
+    fn test_over_long_input_is_rejected_quickly() {
+        let latex = "x + ".repeat(50_000);
+        let started = std::time::Instant::now();
+        let result = latex_to_omml(&latex, ConvertDisplay::Inline);
+        assert!(matches!(result, Err(ConvertError::TooComplex(ref msg)) if msg.contains("4000")), "got: {:?}", result);
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let latex = format!("{}x{}", "{".repeat(100), "}".repeat(100));
+        assert!(matches!(latex_to_mathml_inline(&latex), Err(ConvertError::TooComplex(_))));
+        // Escaped braces are delimiters, not groups
+        let escaped = format!("{}x{}", r"\{".repeat(100), r"\}".repeat(100));
+        assert!(check_complexity(&escaped, &ComplexityLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn test_normal_input_passes_and_limits_are_configurable() {
+        assert!(latex_to_mathml_inline(r"\frac{a}{b} + \sqrt{x^2 + 1}").is_ok());
+        let tight = ComplexityLimits { max_length: 5, max_depth: 1 };
+        assert!(matches!(
+            latex_to_mathml_with_limits("a + b + c", ConvertDisplay::Inline, &tight),
+            Err(ConvertError::TooComplex(_))
+        ));
+        assert!(matches!(check_complexity("{{a}}", &tight), Err(ConvertError::TooComplex(_))));
+        assert!(check_complexity("{a}", &tight).is_ok());
+    }
+
+    // =====================================================================
+    // Inline (unwrapped) OMML tests
+    // =====================================================================
+
+    #[test]
+    fn test_latex_to_omml_inline_has_no_paragraph_wrapper() {
+        let omath = latex_to_omml_inline(r"\frac{a}{b} + x^2").expect("should convert");
+        assert!(omath.starts_with(&format!(r#"<m:oMath xmlns:m="{}">"#, OMML_NS)), "got: {}", omath);
+        assert!(omath.ends_with("</m:oMath>"), "got: {}", omath);
+        assert!(!omath.contains("<m:oMathPara"), "got: {}", omath);
+        // Same formula body as the paragraph form
+        let para = latex_to_omml(r"\frac{a}{b} + x^2", ConvertDisplay::Inline).unwrap();
+        let body = |omml: &str| omml[omml.find("<m:f>").unwrap()..omml.rfind("</m:oMath>").unwrap()].to_string();
+        assert_eq!(body(&omath), body(&para));
+    }
+
+    // =====================================================================
+    // Formula hash tests
+    // =====================================================================
+
+    #[test]
+    fn test_canonical_latex_ignores_insignificant_differences() {
+        assert_eq!(canonical_latex(" x ^ { 2 } + 1 "), "x^2+1");
+        assert_eq!(canonical_latex(r"\alpha   b"), r"\alpha b");
+        assert_ne!(canonical_latex(r"\alpha b"), canonical_latex(r"\alphab"));
+        // Multi-character script groups keep their braces
+        assert_eq!(canonical_latex("x^{10}"), "x^{10}");
+        assert_eq!(canonical_latex(r"a_{\beta}"), r"a_{\beta}");
+    }
+
+    #[test]
+    fn test_canonical_latex_keeps_significant_spaces() {
+        // A control space is not the same as a command
+        assert_eq!(canonical_latex(r"a\ b"), r"a\ b");
+        assert_eq!(canonical_latex(r"a \  b"), r"a\ b");
+        assert_ne!(canonical_latex(r"a\ b"), canonical_latex(r"a\b"));
+        // `\\` is a line break, not a control space
+        assert_eq!(canonical_latex(r"a \\ b"), r"a\\b");
+        // Text-mode spaces are content; runs collapse to one
+        assert_eq!(canonical_latex(r"x + \text{ for  all } y"), r"x+\text{ for all }y");
+        assert_ne!(canonical_latex(r"\text{a b}"), canonical_latex(r"\text{ab}"));
+        assert_eq!(canonical_latex(r"\mbox {a {b c}} d"), r"\mbox{a {b c}}d");
+        // Math-mode groups still drop their spaces
+        assert_eq!(canonical_latex(r"\mathrm{ d } x"), r"\mathrm{d}x");
+        assert_eq!(canonical_latex(r"\{ a \}"), r"\{a\}");
+    }
+
+    #[test]
+    fn test_formula_hash_matches_equivalent_spellings() {
+        assert_eq!(formula_hash("x^2"), formula_hash("x^{2}"));
+        assert_eq!(formula_hash("a_{i} + b"), formula_hash(" a_i+b "));
+        assert_ne!(formula_hash("x^2"), formula_hash("x^3"));
+        assert_ne!(formula_hash("x^{10}"), formula_hash("x^10"));
+        // blake3 hex digest: 32 bytes
+        let hash = formula_hash("x^2");
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    // =====================================================================
+    // Throughput guard tests
+    // =====================================================================
+
+    /// 有代表性的公式语料，覆盖转换热路径上的主要结构
+    const THROUGHPUT_CORPUS: &[&str] = &[
+        r"E = mc^2",
+        r"x = \frac{-b \pm \sqrt{b^2 - 4ac}}{2a}",
+        r"\sum_{i=1}^{n} i = \frac{n(n+1)}{2}",
+        r"\int_{0}^{\infty} e^{-x^2} \, dx = \frac{\sqrt{\pi}}{2}",
+        r"\lim_{x \to 0} \frac{\sin x}{x} = 1",
+        r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+        r"\left( \frac{a}{b} \right)^{2}",
+        r"\hat{x} + \bar{y} + \vec{v} + \dot{z}",
+        r"f(x) = \begin{cases} 1 & x > 0 \\ 0 & x \le 0 \end{cases}",
+        r"\prod_{k=1}^{n} \left(1 + \frac{1}{k}\right)",
+        r"\mathbb{R}^n \to \mathbb{C}",
+        r"\alpha + \beta \cdot \gamma \neq \delta",
+        r"\overbrace{a + b}^{n} + \underbrace{c + d}_{m}",
+        r"\sqrt[3]{x^3 + y^3}",
+        r"\nabla \cdot \mathbf{E} = \frac{\rho}{\varepsilon_0}",
+        r"\binom{n}{k} = \frac{n!}{k!(n-k)!}",
+    ];
+
+    #[test]
+    fn test_conversion_throughput_stays_under_bound() {
+        // Generous bound (debug build, shared CI machines): this only catches
+        // order-of-magnitude regressions in the conversion hot path.
+        const PASSES: usize = 10;
+        const BOUND: std::time::Duration = std::time::Duration::from_secs(30);
+
+        let started = std::time::Instant::now();
+        for _ in 0..PASSES {
+            for latex in THROUGHPUT_CORPUS {
+                latex_to_omml(latex, ConvertDisplay::Block)
+                    .unwrap_or_else(|e| panic!("{} should convert: {}", latex, e));
+            }
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed < BOUND,
+            "converting {} formulas took {:?} (bound {:?})",
+            PASSES * THROUGHPUT_CORPUS.len(),
+            elapsed,
+            BOUND
+        );
+    }
+
+    // =====================================================================
+    // \underset / \overset on n-ary operator tests
+    // =====================================================================
+
+    #[test]
+    fn test_underset_sum_is_nary_with_lower_limit() {
+        let omml = latex_to_omml(r"\underset{i=1}{\sum} x_i", ConvertDisplay::Inline).expect("underset should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains(r#"<m:chr m:val="∑"/><m:limLoc m:val="undOvr"/><m:supHide m:val="1"/>"#),
+            "should be an n-ary sum without upper limit: {}",
+            omml
+        );
+        assert!(omml.contains("<m:sub><m:r><m:t>i</m:t></m:r><m:r><m:t>=</m:t></m:r><m:r><m:t>1</m:t></m:r></m:sub>"));
+    }
+
+    #[test]
+    fn test_overset_sum_is_nary_with_upper_limit() {
+        let omml = latex_to_omml(r"\overset{n}{\sum} x", ConvertDisplay::Inline).expect("overset should convert");
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("<m:limUpp>"), "sum should not be a plain upper limit: {}", omml);
+        assert!(omml.contains(r#"<m:subHide m:val="1"/>"#), "got: {}", omml);
+        assert!(omml.contains("<m:sup><m:r><m:t>n</m:t></m:r></m:sup>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_overset_relation_keeps_relation_spacing() {
+        let plain = latex_to_omml("a = b", ConvertDisplay::Inline).unwrap();
+        let decorated = latex_to_omml(r"a \overset{!}{=} b", ConvertDisplay::Inline).expect("overset should convert");
+        assert_valid_omml(&decorated);
+        let boxed = concat!(
+            r#"<m:box><m:boxPr><m:opEmu m:val="1"/></m:boxPr><m:e><m:limUpp><m:limUppPr></m:limUppPr>"#,
+            "<m:e><m:r><m:t>=</m:t></m:r></m:e><m:lim><m:r><m:t>!</m:t></m:r></m:lim></m:limUpp></m:e></m:box>"
+        );
+        assert!(decorated.contains(boxed), "decorated = should be an operator box: {}", decorated);
+        // Apart from the decoration, the runs around the = are the same as in `a = b`
+        assert_eq!(decorated.replace(boxed, "<m:r><m:t>=</m:t></m:r>"), plain);
+
+        let omml = latex_to_omml(r"x \underset{n \to \infty}{\to} 0", ConvertDisplay::Inline).unwrap();
+        assert!(omml.contains(r#"<m:opEmu m:val="1"/></m:boxPr><m:e><m:limLow>"#), "got: {}", omml);
+        // A non-relation base is not boxed
+        let omml = latex_to_omml(r"\overset{n}{x}", ConvertDisplay::Inline).unwrap();
+        assert!(!omml.contains("<m:box>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_nested_underset_overset_sum_is_single_nary() {
+        let omml = latex_to_omml(r"\underset{i=1}{\overset{n}{\sum}} x", ConvertDisplay::Inline).expect("nested limits should convert");
+        assert_valid_omml(&omml);
+        assert_eq!(omml.matches("<m:nary>").count(), 1, "got: {}", omml);
+        assert!(!omml.contains("<m:limLow>"), "got: {}", omml);
+        assert!(omml.contains("<m:sup><m:r><m:t>n</m:t></m:r></m:sup>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_substack_gives_two_row_product_subscript() {
+        let omml = latex_to_omml(r"\prod_{\substack{i\\j}} x", ConvertDisplay::Inline).expect("substack should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:chr m:val="∏"/>"#), "should be an n-ary product: {}", omml);
+        assert!(
+            omml.contains("<m:sub><m:m><m:mPr></m:mPr><m:mr><m:e><m:r><m:t>i</m:t></m:r></m:e></m:mr><m:mr><m:e><m:r><m:t>j</m:t></m:r></m:e></m:mr></m:m></m:sub>"),
+            "subscript should have two rows: {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_integral_with_script_limits_is_nary_sub_sup() {
+        let omml = latex_to_omml(r"\iint_{\substack{x>0\\y>0}} f", ConvertDisplay::Inline).expect("integral should convert");
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("<m:sSub>"), "got: {}", omml);
+        assert!(omml.contains(r#"<m:chr m:val="∬"/><m:limLoc m:val="subSup"/>"#), "got: {}", omml);
+        assert_eq!(omml.matches("<m:mr>").count(), 2, "got: {}", omml);
+        assert!(omml.contains("<m:e><m:r><m:t>f</m:t></m:r></m:e></m:nary>"), "got: {}", omml);
+
+        let omml = latex_to_omml(r"\int_a^b f", ConvertDisplay::Inline).unwrap();
+        assert!(omml.contains("<m:sub><m:r><m:t>a</m:t></m:r></m:sub><m:sup><m:r><m:t>b</m:t></m:r></m:sup>"), "got: {}", omml);
+    }
+
+    // =====================================================================
+    // Evaluation bar (\left ... \right with scripts) tests
+    // =====================================================================
+
+    #[test]
+    fn test_evaluation_bar_keeps_brackets_and_limits() {
+        let omml = latex_to_omml(r"\left[ x^2 \right]_{a}^{b}", ConvertDisplay::Inline).expect("evaluation bar should convert");
+        assert_valid_omml(&omml);
+
+        assert!(
+            omml.contains(r#"<m:sSubSup><m:sSubSupPr></m:sSubSupPr><m:e><m:d><m:dPr><m:begChr m:val="["/><m:endChr m:val="]"/></m:dPr>"#),
+            "the bracketed group should be the script base: {}",
+            omml
+        );
+        assert!(omml.contains("<m:sub><m:r><m:t>a</m:t></m:r></m:sub>"), "got: {}", omml);
+        assert!(omml.contains("<m:sup><m:r><m:t>b</m:t></m:r></m:sup>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_evaluation_bar_with_empty_opening_delimiter() {
+        let omml = latex_to_omml(r"\left. F(x) \right|_{0}^{1}", ConvertDisplay::Inline).expect("evaluation bar should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:begChr m:val=""/><m:endChr m:val="|"/>"#), "got: {}", omml);
+        assert!(omml.contains("<m:sSubSup>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_group_scripted_delimiters_leaves_plain_pairs() {
+        assert_eq!(group_scripted_delimiters(r"\left( x \right) + y"), r"\left( x \right) + y");
+        assert_eq!(
+            group_scripted_delimiters(r"\left[ x \right]^2_1"),
+            r"{{\left[ x \right]}_{1}}^{2}"
+        );
+    }
+
+    // ================================================================
+    // Auto-sizing delimiter (\left / \right) tests
+    // ================================================================
+
+    #[test]
+    fn test_left_right_parens_wrap_fraction_in_delimiter() {
+        let omml = latex_to_omml(r"\left(\frac{a}{b}\right)", ConvertDisplay::Inline).expect("sized parens should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains(r#"<m:d><m:dPr><m:begChr m:val="("/><m:endChr m:val=")"/></m:dPr><m:e><m:f>"#),
+            "the fraction should sit inside a growing delimiter: {}",
+            omml
+        );
+        assert!(!omml.contains("<m:t>(</m:t>"), "no literal parenthesis runs: {}", omml);
+    }
+
+    #[test]
+    fn test_left_right_empty_delimiters() {
+        let omml = latex_to_omml(r"\left. \frac{df}{dx} \right|", ConvertDisplay::Inline).expect("one-sided bar should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:begChr m:val=""/><m:endChr m:val="|"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_left_right_angle_brackets() {
+        let omml = latex_to_omml(r"\left\langle u, v \right\rangle", ConvertDisplay::Inline).expect("angle brackets should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:begChr m:val="⟨"/><m:endChr m:val="⟩"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_unbalanced_left_right_fall_back_to_plain_delimiters() {
+        assert_eq!(normalize_left_right(r"\left( x"), "( x");
+        assert_eq!(normalize_left_right(r"x \right)"), "x )");
+        assert_eq!(normalize_left_right(r"\left. x"), " x");
+        assert_eq!(normalize_left_right(r"\left\{ a \right\} \right)"), r"\left\{ a \right\} )");
+        assert_eq!(normalize_left_right(r"\left/ x \right/"), "/ x /");
+        assert_eq!(normalize_left_right(r"\left\lvert x \right\rvert"), r"\left| x \right|");
+        assert_eq!(normalize_left_right(r"\leftarrow \rightarrow"), r"\leftarrow \rightarrow");
+
+        for latex in [r"\left( x", r"x \right)", r"\left[ \left( x \right]", r"\right) \left("] {
+            let omml = latex_to_omml(latex, ConvertDisplay::Inline).expect("unbalanced delimiters should still convert");
+            assert_valid_omml(&omml);
+        }
+    }
+
+    // ================================================================
+    // Spaced-out word tests
+    // ================================================================
+
+    #[test]
+    fn test_spaced_words_collapse_when_standalone() {
+        assert_eq!(collapse_spaced_words("l o g(P(y))"), "log(P(y))");
+        assert_eq!(collapse_spaced_words(r"-\sum_{t=1}^{T}l o g(x)"), r"-\sum_{t=1}^{T}log(x)");
+        assert_eq!(collapse_spaced_words(r"{\mathcal L}_{g e n}"), r"{\mathcal L}_{gen}");
+        assert_eq!(collapse_spaced_words(r"= l n \left( x \right)"), r"= ln \left( x \right)");
+        assert_eq!(collapse_spaced_words("E n c(x) + D e c(z)"), "Enc(x) + Dec(z)");
+    }
+
+    #[test]
+    fn test_spaced_words_leave_variable_products_alone() {
+        assert_eq!(collapse_spaced_words(r"\alpha l n \beta"), r"\alpha l n \beta");
+        assert_eq!(collapse_spaced_words("a l n"), "a l n");
+        assert_eq!(collapse_spaced_words("x s i n y"), "x s i n y");
+        assert_eq!(collapse_spaced_words("l o g s"), "l o g s");
+        assert_eq!(collapse_spaced_words(r"-\sum_{t=1}^{T}l o g x"), r"-\sum_{t=1}^{T}l o g x");
+        assert_eq!(collapse_spaced_words(r"\lambda l o g"), r"\lambda l o g");
+    }
+
+    #[test]
+    fn test_preprocess_keeps_spaced_variables() {
+        assert_eq!(preprocess_latex(r"\alpha l n \beta"), r"\alpha l n \beta");
+        assert_eq!(preprocess_latex(r"l o g(x)"), "log(x)");
+    }
+
+    // ================================================================
+    // Grouped number tests
+    // ================================================================
+
+    #[test]
+    fn test_thousands_separated_number_is_one_run() {
+        let omml = latex_to_omml("x = 1,000.5", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(omml.contains("<m:r><m:t>1,000.5</m:t></m:r></m:oMath>"), "got: {}", omml);
+        let omml = latex_to_omml("12,345,678", ConvertDisplay::Inline).unwrap();
+        assert!(omml.contains("<m:oMath><m:r><m:t>12,345,678</m:t></m:r></m:oMath>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_thin_space_grouped_number_is_one_run() {
+        let omml = latex_to_omml(r"1\,000", ConvertDisplay::Inline).unwrap();
+        assert!(omml.contains("<m:oMath><m:r><m:t>1\u{2009}000</m:t></m:r></m:oMath>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_comma_separated_arguments_stay_separate() {
+        let omml = latex_to_omml("f(1, 2) + g(12,5) + h(1234,567)", ConvertDisplay::Inline).unwrap();
+        assert!(omml.contains("<m:r><m:t>1</m:t></m:r><m:r><m:t>,</m:t></m:r><m:r><m:t>2</m:t></m:r>"), "got: {}", omml);
+        assert!(omml.contains("<m:r><m:t>12</m:t></m:r><m:r><m:t>,</m:t></m:r><m:r><m:t>5</m:t></m:r>"), "got: {}", omml);
+        assert!(omml.contains("<m:r><m:t>1234</m:t></m:r><m:r><m:t>,</m:t></m:r>"), "got: {}", omml);
+    }
+
+    // ================================================================
+    // Validation tests
+    // ================================================================
+
+    #[test]
+    fn test_validate_latex_accepts_supported_input() {
+        assert_eq!(validate_latex(r"\frac{a}{b} + \sqrt{x}"), Ok(()));
+        assert_eq!(validate_latex(r"\begin{cases} 1 & x>0 \\ 0 & x \end{cases}"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_latex_reports_every_unknown_environment() {
+        let unsupported = validate_latex(r"\begin{gather} a \end{gather} + \begin{multline} b \end{multline}")
+            .expect_err("unknown environments should be reported");
+        assert_eq!(unsupported, vec!["gather".to_string(), "multline".to_string()]);
+    }
+
+    #[test]
+    fn test_validate_latex_reports_unknown_commands_once() {
+        let unsupported = validate_latex(r"\gather{x} + \foo{y} + \foo{z} + \begin{gather} a \end{gather}")
+            .expect_err("unknown commands should be reported");
+        assert_eq!(unsupported, vec!["gather".to_string(), r"\gather".to_string(), r"\foo".to_string()]);
+    }
+
+    #[test]
+    fn test_validate_latex_reports_syntax_errors() {
+        let unsupported = validate_latex(r"\frac{a}{").expect_err("unbalanced braces should fail");
+        assert_eq!(unsupported.len(), 1);
+        assert!(unsupported[0].starts_with("LaTeX 转 MathML 失败"), "got: {:?}", unsupported);
+    }
+
+    #[test]
+    fn test_leading_ampersand_is_stripped() {
+        let omml = latex_to_omml("& x = y", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert!(!omml.contains("PARSE ERROR"), "got: {}", omml);
+        assert!(omml.contains("<m:oMath><m:r><m:t>x</m:t></m:r>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_strip_stray_ampersands() {
+        assert_eq!(strip_stray_ampersands("&& x = y &"), "x = y");
+        assert_eq!(strip_stray_ampersands(r"a \&"), r"a \&");
+        assert_eq!(strip_stray_ampersands(r"a \\ &"), r"a \\");
+        assert_eq!(strip_stray_ampersands(r"\begin{align} a &= b \end{align}"), r"\begin{align} a &= b \end{align}");
+    }
+
+    #[test]
+    fn test_validate_latex_strict_reports_stray_ampersand() {
+        assert_eq!(validate_latex_strict("x = y"), Ok(()));
+        assert_eq!(validate_latex_strict("& x = y"), Err(vec!["&".to_string()]));
+        assert_eq!(validate_latex("& x = y"), Ok(()));
+    }
+
+    // ================================================================
+    // Prime notation tests
+    // ================================================================
+
+    #[test]
+    fn test_normalize_primes() {
+        assert_eq!(normalize_primes("f'"), "f^{′}");
+        assert_eq!(normalize_primes("f''(x)"), "f^{″}(x)");
+        assert_eq!(normalize_primes("y''' + y'"), "y^{‴} + y^{′}");
+        assert_eq!(normalize_primes("f'^2"), "f^{′2}");
+        assert_eq!(normalize_primes("f''^{n+1}"), "f^{″n+1}");
+        assert_eq!(normalize_primes(r"f'^\alpha"), r"f^{′\alpha}");
+    }
+
+    #[test]
+    fn test_normalize_primes_leaves_text_and_bare_apostrophes() {
+        assert_eq!(normalize_primes(r"\text{don't} f'"), r"\text{don't} f^{′}");
+        assert_eq!(normalize_primes("'x"), "'x");
+        assert_eq!(normalize_primes(r"a \, b"), r"a \, b");
+    }
+
+    #[test]
+    fn test_double_prime_is_superscript_on_base() {
+        let omml = latex_to_omml("f''(x)", ConvertDisplay::Inline).expect("f'' should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains("<m:sSup>"), "got: {}", omml);
+        assert!(omml.contains("<m:e><m:r><m:t>f</m:t></m:r></m:e>"), "got: {}", omml);
+        assert!(omml.contains("<m:t>″</m:t></m:r></m:sup>"), "got: {}", omml);
+        assert!(!omml.contains('\''), "no stray apostrophe should remain: {}", omml);
+    }
+
+    #[test]
+    fn test_prime_with_exponent_shares_one_superscript() {
+        let omml = latex_to_omml("f'^2", ConvertDisplay::Inline).expect("f'^2 should convert");
+        assert_valid_omml(&omml);
+        assert_eq!(omml.matches("<m:sSup>").count(), 1, "got: {}", omml);
+        assert!(omml.contains("<m:t>′</m:t>"), "got: {}", omml);
+        assert!(omml.contains("<m:t>2</m:t>"), "got: {}", omml);
+    }
+
+    // ================================================================
+    // Piecewise (cases) tests
+    // ================================================================
+
+    #[test]
+    fn test_cases_is_brace_fenced_two_column_matrix() {
+        let omml = latex_to_omml(r"f(x) = \begin{cases} 1 & x>0 \\ -1 & \text{otherwise} \end{cases}", ConvertDisplay::Inline)
+            .expect("cases should convert");
+        assert_valid_omml(&omml);
+
+        assert!(
+            omml.contains(r#"<m:d><m:dPr><m:begChr m:val="{"/><m:endChr m:val=""/></m:dPr><m:e><m:m>"#),
+            "cases should be a matrix opened by a lone brace: {}",
+            omml
+        );
+        assert!(
+            omml.contains(r#"<m:mcPr><m:count m:val="2"/><m:mcJc m:val="left"/></m:mcPr>"#),
+            "both columns should be left-aligned: {}",
+            omml
+        );
+        assert_eq!(omml.matches("<m:mr>").count(), 2, "got: {}", omml);
+        assert!(omml.contains("<m:e><m:r><m:t>1</m:t></m:r></m:e>"), "first value: {}", omml);
+        assert!(omml.contains("<m:t>&gt;</m:t>"), "first condition: {}", omml);
+        assert!(omml.contains("<m:t>otherwise</m:t>"), "second condition: {}", omml);
+    }
+
+    #[test]
+    fn test_cases_row_without_condition_is_padded() {
+        let omml = latex_to_omml(r"\begin{cases} a & b \\ c \end{cases}", ConvertDisplay::Inline).expect("cases should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains("<m:mr><m:e><m:r><m:t>c</m:t></m:r></m:e><m:e></m:e></m:mr>"),
+            "short row should get an empty condition cell: {}",
+            omml
+        );
+    }
+
+    #[test]
+    fn test_lone_brace_fence_around_non_table_stays_delimiter() {
+        let omml = mathml_to_omml(
+            r#"<math><mrow><mo stretchy="true" form="prefix">{</mo><mi>x</mi><mo stretchy="true" form="postfix"></mo></mrow></math>"#,
+        )
+        .expect("one-sided brace should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:begChr m:val="{"/>"#), "got: {}", omml);
+        assert!(!omml.contains("<m:m>"), "got: {}", omml);
+        assert!(omml.contains("<m:t>x</m:t>"), "fenced content should be kept: {}", omml);
+    }
+
+    #[test]
+    fn test_mfenced_brace_table_is_cases() {
+        let omml = mathml_to_omml(
+            r#"<math><mfenced open="{" close=""><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mi>x</mi></mtd></mtr></mtable></mfenced></math>"#,
+        )
+        .expect("fenced table should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:mcJc m:val="left"/>"#), "got: {}", omml);
+    }
+
+    // ================================================================
+    // Binomial coefficient tests
+    // ================================================================
+
+    /// `(n over k)`: a no-bar fraction inside a parenthesis delimiter.
+    const BINOM_N_K: &str = r#"<m:d><m:dPr><m:begChr m:val="("/><m:endChr m:val=")"/></m:dPr><m:e><m:f><m:fPr><m:type m:val="noBar"/></m:fPr><m:num><m:r><m:t>n</m:t></m:r></m:num><m:den><m:r><m:t>k</m:t></m:r></m:den></m:f></m:e></m:d>"#;
+
+    #[test]
+    fn test_binom_is_no_bar_fraction_in_parentheses() {
+        let omml = latex_to_omml(r"\binom{n}{k}", ConvertDisplay::Inline).expect("binom should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(BINOM_N_K), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_dbinom_and_tbinom_are_binomials_with_size_hint() {
+        let omml = latex_to_omml(r"\dbinom{n}{k}", ConvertDisplay::Inline).expect("dbinom should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(BINOM_N_K), "got: {}", omml);
+
+        let omml = latex_to_omml(r"\tbinom{n}{k}", ConvertDisplay::Inline).expect("tbinom should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:type m:val="noBar"/>"#), "got: {}", omml);
+        assert!(omml.contains(r#"<m:num><m:argPr><m:argSz m:val="-1"/></m:argPr><m:r><m:t>n</m:t></m:r></m:num>"#), "got: {}", omml);
+        assert!(omml.contains(r#"<m:den><m:argPr><m:argSz m:val="-1"/></m:argPr><m:r><m:t>k</m:t></m:r></m:den>"#), "got: {}", omml);
+        assert!(omml.contains(r#"<m:begChr m:val="("/><m:endChr m:val=")"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_choose_matches_binom() {
+        let omml = latex_to_omml(r"{n \choose k}", ConvertDisplay::Inline).expect("choose should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(BINOM_N_K), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_atop_is_no_bar_fraction_without_parentheses() {
+        let mathml = latex_to_mathml_inline(r"{a \atop b}").expect("atop should convert");
+        assert!(mathml.contains(r#"<mfrac linethickness="0"><mi>a</mi><mi>b</mi></mfrac>"#), "got: {}", mathml);
+        assert!(!mathml.contains(ATOP_OPEN) && !mathml.contains('('), "got: {}", mathml);
+
+        let omml = latex_to_omml(r"{a \atop b}", ConvertDisplay::Inline).expect("atop should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains(r#"<m:f><m:fPr><m:type m:val="noBar"/></m:fPr><m:num><m:r><m:t>a</m:t></m:r></m:num><m:den><m:r><m:t>b</m:t></m:r></m:den></m:f>"#),
+            "got: {}",
+            omml
+        );
+        assert!(!omml.contains("<m:d>"), "atop should not be parenthesized: {}", omml);
+    }
+
+    #[test]
+    fn test_atop_inside_binom_keeps_outer_parentheses() {
+        let omml = latex_to_omml(r"x^{n \atop k} + \binom{{a \atop b}}{c}", ConvertDisplay::Inline).expect("should convert");
+        assert_valid_omml(&omml);
+        assert_eq!(omml.matches("<m:d>").count(), 1, "only the binom is parenthesized: {}", omml);
+        assert_eq!(omml.matches(r#"<m:type m:val="noBar"/>"#).count(), 3, "got: {}", omml);
+    }
+
+    #[test]
+    fn test_replace_choose_uses_enclosing_group() {
+        assert_eq!(replace_choose(r"x + {n+1 \choose 2} y"), r"x + {\binom{n+1}{2}} y");
+        assert_eq!(replace_choose(r"a \choose b"), r"\binom{a}{b}");
+        assert_eq!(replace_choose(r"\chooser"), r"\chooser");
+    }
+
+    #[test]
+    fn test_plain_fraction_keeps_bar() {
+        let omml = mathml_to_omml(r#"<math><mfrac linethickness="1px"><mi>a</mi><mi>b</mi></mfrac></math>"#)
+            .expect("fraction should convert");
+        assert!(omml.contains(r#"<m:type m:val="bar"/>"#), "got: {}", omml);
+
+        let omml = mathml_to_omml(r#"<math><mfrac linethickness="0pt"><mi>a</mi><mi>b</mi></mfrac></math>"#)
+            .expect("fraction should convert");
+        assert!(omml.contains(r#"<m:type m:val="noBar"/>"#), "got: {}", omml);
+    }
+
+    // ================================================================
+    // Accent tests
+    // ================================================================
+
+    #[test]
+    fn test_each_accent_command_maps_to_combining_chr() {
+        for &(command, _, chr) in ACCENTS {
+            let latex = format!("{}{{x}}", command);
+            let omml = latex_to_omml(&latex, ConvertDisplay::Inline).expect("accent should convert");
+            assert_valid_omml(&omml);
+            let expected = format!(
+                r#"<m:acc><m:accPr><m:chr m:val="{}"/></m:accPr><m:e><m:r><m:t>x</m:t></m:r></m:e></m:acc>"#,
+                chr
+            );
+            assert!(omml.contains(&expected), "{} should be an accent with chr U+{:04X}: {}", command, chr as u32, omml);
+        }
+    }
+
+    #[test]
+    fn test_accent_base_with_sub_and_superscript() {
+        for latex in [r"\tilde{E}_{k}^{s}", r"\tilde{E}_k^s"] {
+            let omml = latex_to_omml(latex, ConvertDisplay::Inline).expect("accented subsup should convert");
+            // One sSubSup whose base is the accented E alone
+            assert!(
+                omml.contains("<m:sSubSup><m:sSubSupPr></m:sSubSupPr><m:e><m:acc><m:accPr><m:chr m:val=\"\u{303}\"/></m:accPr><m:e><m:r><m:t>E</m:t></m:r></m:e></m:acc></m:e>"),
+                "{} should be sSubSup over the accented E: {}", latex, omml
+            );
+            assert!(omml.contains("<m:sub><m:r><m:t>k</m:t></m:r></m:sub><m:sup><m:r><m:t>s</m:t></m:r></m:sup>"), "{}", omml);
+            assert!(!omml.contains("<m:sSup>") && !omml.contains("<m:sSub>"), "{}", omml);
+        }
+    }
+
+    #[test]
+    fn test_nested_accents() {
+        let omml = latex_to_omml(r"\hat{\vec{x}}", ConvertDisplay::Inline).expect("nested accents should convert");
+        assert_valid_omml(&omml);
+        assert!(
+            omml.contains("<m:chr m:val=\"\u{0302}\"/></m:accPr><m:e><m:acc><m:accPr><m:chr m:val=\"\u{20D7}\"/>"),
+            "got: {}",
+            omml
+        );
+        assert!(!omml.contains("<m:limUpp>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_accent_chr_keeps_combining_marks_and_rejects_limits() {
+        assert_eq!(accent_chr("\u{0304}"), Some('\u{0304}'));
+        assert_eq!(accent_chr("¯"), Some('\u{0305}'));
+        assert_eq!(accent_chr("n"), None);
+        assert_eq!(accent_chr("!"), None);
+    }
+
+    // ================================================================
+    // Grouping brace tests
+    // ================================================================
+
+    #[test]
+    fn test_underbrace_with_label() {
+        let omml = latex_to_omml(r"\underbrace{a+b+c}_{n\text{ terms}}", ConvertDisplay::Inline).expect("underbrace should convert");
         assert_valid_omml(&omml);
         assert!(
-            omml.contains("∫") || omml.contains("<m:nary>"),
-            "OMML should contain integral"
+            omml.contains(r#"<m:limLow><m:limLowPr></m:limLowPr><m:e><m:groupChr><m:groupChrPr><m:chr m:val="⏟"/><m:pos m:val="bot"/>"#),
+            "got: {}",
+            omml
         );
-        assert!(omml.contains("0"), "Should contain lower bound '0'");
-        assert!(omml.contains("1"), "Should contain upper bound '1'");
+        // The label is the limit below the brace, not a second brace
+        assert!(omml.contains("<m:lim><m:r><m:t>n</m:t></m:r><m:r><m:rPr><m:sty m:val=\"p\"/></m:rPr><m:t>terms</m:t></m:r></m:lim>"), "got: {}", omml);
+        assert_eq!(omml.matches("⏟").count(), 1, "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_summation_bounds() {
-        // 测试求和: \sum_{i=1}^n
-        let mathml = latex_to_mathml(r"\sum_{i=1}^{n} a_i").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        assert!(
-            mathml.contains("∑") || mathml.contains("sum"),
-            "Should contain summation symbol"
-        );
-        
-        let omml = latex_to_omml(r"\sum_{i=1}^{n} a_i").unwrap();
+    fn test_overbrace_with_label() {
+        let omml = latex_to_omml(r"\overbrace{x+y}^{k}", ConvertDisplay::Inline).expect("overbrace should convert");
         assert_valid_omml(&omml);
         assert!(
-            omml.contains("∑") || omml.contains("<m:nary>"),
-            "OMML should contain summation"
+            omml.contains(r#"<m:limUpp><m:limUppPr></m:limUppPr><m:e><m:groupChr><m:groupChrPr><m:chr m:val="⏞"/><m:pos m:val="top"/><m:vertJc m:val="bot"/>"#),
+            "got: {}",
+            omml
         );
+        assert!(omml.contains("<m:lim><m:r><m:t>k</m:t></m:r></m:lim></m:limUpp>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_matrix_basic() {
-        // 测试矩阵: \begin{matrix}...\end{matrix}
-        let mathml = latex_to_mathml(r"\begin{matrix} a & b \\ c & d \end{matrix}").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        assert!(
-            mathml.contains("<mtable") || mathml.contains("<mtr"),
-            "MathML should contain matrix elements"
-        );
-        
-        let omml = latex_to_omml(r"\begin{matrix} a & b \\ c & d \end{matrix}").unwrap();
+    fn test_brace_without_label() {
+        let omml = latex_to_omml(r"\underbrace{x}", ConvertDisplay::Inline).expect("underbrace should convert");
         assert_valid_omml(&omml);
-        let has_matrix = omml.contains("<m:m>") || omml.contains("<m:mr>");
-        assert!(has_matrix, "OMML should contain matrix elements");
-        assert!(omml.contains("a"), "Should contain element 'a'");
-        assert!(omml.contains("d"), "Should contain element 'd'");
+        assert!(omml.contains(r#"<m:groupChr><m:groupChrPr><m:chr m:val="⏟"/>"#), "got: {}", omml);
+        assert!(!omml.contains("<m:limLow>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_greek_alpha_beta_gamma() {
-        // 测试希腊字母: \alpha, \beta, \gamma
-        let mathml = latex_to_mathml(r"\alpha + \beta + \gamma").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
+    fn test_overparen_is_top_group_chr() {
+        let omml = latex_to_omml(r"\overparen{abc}", ConvertDisplay::Inline).expect("overparen should convert");
+        assert_valid_omml(&omml);
         assert!(
-            mathml.contains("α") || mathml.contains("alpha"),
-            "Should contain alpha"
+            omml.contains(concat!(
+                r#"<m:groupChr><m:groupChrPr><m:chr m:val="⏜"/><m:pos m:val="top"/><m:vertJc m:val="bot"/></m:groupChrPr>"#,
+                "<m:e><m:r><m:t>a</m:t></m:r><m:r><m:t>b</m:t></m:r><m:r><m:t>c</m:t></m:r></m:e></m:groupChr>"
+            )),
+            "got: {}",
+            omml
         );
+        assert!(!omml.contains("<m:limUpp>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_underparen_with_label() {
+        let omml = latex_to_omml(r"\underparen{xy}_{n}", ConvertDisplay::Inline).expect("underparen should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:chr m:val="⏝"/><m:pos m:val="bot"/>"#), "got: {}", omml);
+        assert!(omml.contains("<m:lim><m:r><m:t>n</m:t></m:r></m:lim></m:limLow>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_tortoise_shell_mathml_becomes_group_chr() {
+        let mathml = "<math><mover><mi>x</mi><mo>\u{FE39}</mo></mover></math>";
+        let nodes = parse_mathml(mathml).unwrap();
+        let [MathNode::Mrow(children)] = nodes.as_slice() else {
+            panic!("got: {:?}", nodes);
+        };
+        let [MathNode::GroupChr { label: None, over: true, chr, .. }] = children.as_slice() else {
+            panic!("got: {:?}", children);
+        };
+        assert_eq!(*chr, OVER_TORTOISE);
+    }
+
+    #[test]
+    fn test_cancel_is_struck_border_box() {
+        let omml = latex_to_omml(r"\cancel{x}", ConvertDisplay::Inline).expect("cancel should convert");
+        assert_valid_omml(&omml);
         assert!(
-            mathml.contains("β") || mathml.contains("beta"),
-            "Should contain beta"
+            omml.contains(r#"<m:hideRight m:val="1"/><m:strikeBLTR m:val="1"/></m:borderBoxPr><m:e><m:r><m:t>x</m:t></m:r></m:e></m:borderBox>"#),
+            "got: {}",
+            omml
         );
+        assert!(!omml.contains("strikeTLBR"), "got: {}", omml);
+        assert!(!omml.contains('\u{2571}'), "stroke glyph should not leak: {}", omml);
+    }
+
+    #[test]
+    fn test_omml_profiles_differ_only_in_empty_properties() {
+        let mathml = latex_to_mathml_inline(r"x^{2} + \frac{a}{b} + \sqrt{y}").unwrap();
+        let word2007 = mathml_to_omml_with_profile(&mathml, OmmlProfile::Word2007).unwrap();
+        let word2010 = mathml_to_omml_with_profile(&mathml, OmmlProfile::Word2010Plus).unwrap();
+        assert_valid_omml(&word2007);
+        assert_valid_omml(&word2010);
+
+        assert_eq!(word2007, mathml_to_omml(&mathml).unwrap(), "Word2007 is the default output");
+        assert!(word2007.contains("<m:sSupPr></m:sSupPr>"), "got: {}", word2007);
+        assert!(!word2010.contains("<m:sSupPr>"), "got: {}", word2010);
+        // Non-empty properties are kept
+        assert!(word2010.contains(r#"<m:radPr><m:degHide m:val="1"/></m:radPr>"#), "got: {}", word2010);
+        assert_eq!(strip_empty_properties(&word2007), word2010);
+    }
+
+    #[test]
+    fn test_mathclap_is_shown_zero_width_phantom() {
+        let omml = latex_to_omml(r"\sum_{\mathclap{1 \le i \le n}} a_i + \clap{b}", ConvertDisplay::Inline).expect("mathclap should convert");
+        assert_valid_omml(&omml);
+        assert_eq!(omml.matches("<m:phant>").count(), 2, "got: {}", omml);
         assert!(
-            mathml.contains("γ") || mathml.contains("gamma"),
-            "Should contain gamma"
+            omml.contains(r#"<m:phantPr><m:show m:val="1"/><m:zeroWid m:val="1"/></m:phantPr><m:e><m:r><m:t>b</m:t></m:r></m:e></m:phant>"#),
+            "got: {}",
+            omml
         );
-        
-        let omml = latex_to_omml(r"\alpha + \beta + \gamma").unwrap();
-        assert_valid_omml(&omml);
-        assert!(omml.contains("α"), "OMML should contain alpha symbol");
-        assert!(omml.contains("β"), "OMML should contain beta symbol");
-        assert!(omml.contains("γ"), "OMML should contain gamma symbol");
+        assert!(omml.contains("<m:t>n</m:t>"), "content is still emitted: {}", omml);
+        assert!(!omml.contains('\u{200B}'), "marker should not leak: {}", omml);
     }
 
     #[test]
-    fn test_task34_fallback_unsupported_symbol() {
-        // 测试转换失败的回退行为: 不支持的符号应返回描述性错误
-        let result = latex_to_mathml(r"\begin{tikzpicture}\end{tikzpicture}");
-        assert!(result.is_err(), "Unsupported environment should fail");
-        
-        match result.unwrap_err() {
-            ConvertError::UnsupportedSymbol(sym) => {
-                assert!(
-                    sym.contains("tikzpicture"),
-                    "Error should mention the unsupported symbol: {}",
-                    sym
-                );
-            }
-            ConvertError::LatexToMathml(msg) => {
-                assert!(
-                    !msg.is_empty(),
-                    "Error message should be descriptive"
-                );
-            }
-            _ => panic!("Unexpected error type"),
-        }
+    fn test_zero_width_mpadded_becomes_phantom() {
+        let mathml = r#"<math><mpadded width="0em"><mi>x</mi></mpadded><mpadded width="2em"><mi>y</mi></mpadded></math>"#;
+        let omml = mathml_to_omml(mathml).unwrap();
+        assert_eq!(omml.matches("<m:zeroWid").count(), 1, "got: {}", omml);
+        assert!(omml.contains("<m:t>y</m:t>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_fallback_malformed_latex() {
-        // 测试转换失败的回退行为: 格式错误的 LaTeX
-        let result = latex_to_mathml(r"\frac{a}");
-        // Should return an error for incomplete fraction
-        if let Err(e) = result {
-            let msg = e.to_string();
-            assert!(!msg.is_empty(), "Error message should not be empty");
-        }
+    fn test_bcancel_and_xcancel_strike_directions() {
+        let omml = latex_to_omml(r"\bcancel{a} + \xcancel{b}", ConvertDisplay::Inline).unwrap();
+        assert_valid_omml(&omml);
+        assert_eq!(omml.matches("<m:borderBox>").count(), 2, "got: {}", omml);
+        assert_eq!(omml.matches("strikeTLBR").count(), 2, "got: {}", omml);
+        assert_eq!(omml.matches("strikeBLTR").count(), 1, "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_fallback_latex_to_omml_chain() {
-        // 测试 latex_to_omml 组合调用的错误传播
-        let result = latex_to_omml(r"\begin{unknownenv}\end{unknownenv}");
-        assert!(result.is_err(), "Unknown environment should fail in full chain");
-        
-        let err = result.unwrap_err();
-        let msg = err.to_string();
-        assert!(!msg.is_empty(), "Error should be descriptive");
+    fn test_menclose_diagonal_strike_becomes_strike() {
+        let mathml = r#"<math><menclose notation="updiagonalstrike"><mi>x</mi></menclose><menclose notation="box"><mi>y</mi></menclose></math>"#;
+        let omml = mathml_to_omml(mathml).unwrap();
+        assert_eq!(omml.matches("<m:borderBox>").count(), 1, "got: {}", omml);
+        assert!(omml.contains("<m:strikeBLTR"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_fallback_empty_input() {
-        // 测试空输入的处理
-        let mathml_result = latex_to_mathml("");
-        // Empty input should either succeed with minimal output or fail gracefully
-        match mathml_result {
-            Ok(mathml) => {
-                assert!(mathml.contains("<math"), "Even empty input should produce <math wrapper");
-            }
-            Err(e) => {
-                let msg = e.to_string();
-                assert!(!msg.is_empty(), "Error should be descriptive");
-            }
-        }
+    fn test_brace_innermost_mathml_becomes_group_chr() {
+        let mathml = "<math><munder><munder><mi>x</mi><mo>\u{FE38}</mo></munder><mi>n</mi></munder></math>";
+        let nodes = parse_mathml(mathml).unwrap();
+        let [MathNode::Mrow(children)] = nodes.as_slice() else {
+            panic!("got: {:?}", nodes);
+        };
+        let [MathNode::GroupChr { label: Some(label), over: false, chr, .. }] = children.as_slice() else {
+            panic!("got: {:?}", children);
+        };
+        assert_eq!(*chr, UNDER_BRACE);
+        assert_eq!(node_text(label), "n");
     }
 
+    // ================================================================
+    // N-ary operand tests
+    // ================================================================
+
     #[test]
-    fn test_task34_combined_formula() {
-        // 测试组合公式: 包含多种元素
-        let latex = r"\int_0^1 \frac{\sqrt{x^2 + 1}}{\sum_{k=0}^{n} \alpha_k} dx";
-        let mathml = latex_to_mathml(latex).unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        assert!(mathml.contains("</math>"), "Should be well-formed");
-        
-        let omml = latex_to_omml(latex).unwrap();
+    fn test_sum_operand_inside_nary_body() {
+        let omml = latex_to_omml(r"\sum_{i=1}^n a_i", ConvertDisplay::Inline).expect("sum should convert");
         assert_valid_omml(&omml);
-        // Should contain various elements
-        assert!(omml.contains("<m:f>") || omml.contains("<m:rad>"), 
-            "Should contain fraction or radical");
+        assert!(
+            omml.contains("<m:e><m:sSub><m:sSubPr></m:sSubPr><m:e><m:r><m:t>a</m:t></m:r></m:e><m:sub><m:r><m:t>i</m:t></m:r></m:sub></m:sSub></m:e></m:nary>"),
+            "a_i should be the nary body: {}",
+            omml
+        );
+        assert!(!omml.contains("<m:e></m:e></m:nary>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_pmatrix_with_delimiters() {
-        // 测试带括号的矩阵
-        let mathml = latex_to_mathml(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        
-        let omml = latex_to_omml(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}").unwrap();
+    fn test_nary_body_stops_at_binary_operator_and_relation() {
+        let omml = latex_to_omml(r"\sum_{i} a_i + b = c", ConvertDisplay::Inline).expect("sum should convert");
         assert_valid_omml(&omml);
-        // pmatrix should have delimiters
-        let has_delim_or_matrix = omml.contains("<m:d>") || omml.contains("<m:m>");
-        assert!(has_delim_or_matrix, "Should contain delimiter or matrix element");
+        assert!(omml.contains("</m:sSub></m:e></m:nary><m:r><m:t>+</m:t></m:r>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_bmatrix() {
-        // 测试方括号矩阵
-        let mathml = latex_to_mathml(r"\begin{bmatrix} a & b \\ c & d \end{bmatrix}").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        
-        let omml = latex_to_omml(r"\begin{bmatrix} a & b \\ c & d \end{bmatrix}").unwrap();
+    fn test_nary_body_keeps_bracketed_operand() {
+        let omml = latex_to_omml(r"\sum_{i} (a_i + b_i) + c", ConvertDisplay::Inline).expect("sum should convert");
         assert_valid_omml(&omml);
+        assert!(
+            omml.contains("<m:r><m:t>)</m:t></m:r></m:e></m:nary><m:r><m:t>+</m:t></m:r><m:r><m:t>c</m:t></m:r>"),
+            "got: {}",
+            omml
+        );
     }
 
     #[test]
-    fn test_task34_nth_root() {
-        // 测试 n 次根号
-        let mathml = latex_to_mathml(r"\sqrt[3]{x}").unwrap();
-        assert!(mathml.contains("<mroot") || mathml.contains("<msqrt"), 
-            "Should contain root element");
-        
-        let omml = latex_to_omml(r"\sqrt[3]{x}").unwrap();
+    fn test_nested_nary_stays_in_outer_body() {
+        let omml = latex_to_omml(r"\sum_{j} \sum_{k} c_{jk}", ConvertDisplay::Inline).expect("double sum should convert");
         assert_valid_omml(&omml);
-        assert!(omml.contains("<m:rad>"), "Should contain radical");
-        assert!(omml.contains("<m:deg>"), "Should contain degree for nth root");
-        assert!(omml.contains("3"), "Should contain root index '3'");
+        assert!(omml.contains("<m:e><m:nary>"), "inner sum should be the outer body: {}", omml);
+        assert!(omml.ends_with("</m:e></m:nary></m:e></m:nary></m:oMath></m:oMathPara>"), "got: {}", omml);
     }
 
     #[test]
-    fn test_task34_product_symbol() {
-        // 测试连乘符号
-        let mathml = latex_to_mathml(r"\prod_{i=1}^{n} x_i").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        assert!(
-            mathml.contains("∏") || mathml.contains("prod"),
-            "Should contain product symbol"
-        );
-        
-        let omml = latex_to_omml(r"\prod_{i=1}^{n} x_i").unwrap();
+    fn test_nary_body_stops_at_closing_fence() {
+        let omml = latex_to_omml(r"\left( \sum_{i} a_i \right)", ConvertDisplay::Inline).expect("fenced sum should convert");
         assert_valid_omml(&omml);
+        assert!(omml.contains("</m:nary></m:e></m:d>"), "got: {}", omml);
+    }
+
+    // ================================================================
+    // mstyle display tests
+    // ================================================================
+
+    /// A summation with limits and operand wrapped in `<mstyle {attrs}>`.
+    fn styled_sum(attrs: &str) -> String {
+        format!(
+            "<math><mstyle {}><munderover><mo>∑</mo><mi>i</mi><mi>n</mi></munderover><mi>x</mi></mstyle></math>",
+            attrs
+        )
     }
 
     #[test]
-    fn test_task34_more_greek_letters() {
-        // 测试更多希腊字母
-        let mathml = latex_to_mathml(r"\delta + \epsilon + \theta + \lambda + \pi + \sigma + \omega").unwrap();
-        assert!(mathml.contains("<math"), "Should produce valid MathML");
-        
-        let omml = latex_to_omml(r"\delta + \epsilon + \theta + \lambda + \pi + \sigma + \omega").unwrap();
+    fn test_mstyle_displaystyle_true_keeps_limits_under_over() {
+        let omml = mathml_to_omml(&styled_sum(r#"displaystyle="true""#)).expect("mstyle should convert");
         assert_valid_omml(&omml);
-        // Check for some Greek letters in Unicode
-        assert!(omml.contains("δ") || omml.contains("delta"), "Should contain delta");
-        assert!(omml.contains("π") || omml.contains("pi"), "Should contain pi");
+        assert!(omml.contains(r#"<m:limLoc m:val="undOvr"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_mstyle_displaystyle_false_puts_limits_as_scripts() {
+        let omml = mathml_to_omml(&styled_sum(r#"displaystyle="false""#)).expect("mstyle should convert");
+        assert_valid_omml(&omml);
+        assert!(omml.contains(r#"<m:limLoc m:val="subSup"/>"#), "got: {}", omml);
+        assert!(omml.contains("<m:e><m:r><m:t>x</m:t></m:r></m:e></m:nary>"), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_mstyle_scriptlevel_implies_inline_limits() {
+        let omml = mathml_to_omml(&styled_sum(r#"scriptlevel="+1""#)).expect("mstyle should convert");
+        assert!(omml.contains(r#"<m:limLoc m:val="subSup"/>"#), "got: {}", omml);
+        let omml = mathml_to_omml(&styled_sum(r#"scriptlevel="1" displaystyle="true""#)).expect("mstyle should convert");
+        assert!(omml.contains(r#"<m:limLoc m:val="undOvr"/>"#), "got: {}", omml);
+    }
+
+    #[test]
+    fn test_inner_mstyle_overrides_outer() {
+        let mathml = format!(
+            r#"<math><mstyle displaystyle="true">{}</mstyle></math>"#,
+            styled_sum(r#"displaystyle="false""#)
+                .trim_start_matches("<math>")
+                .trim_end_matches("</math>")
+        );
+        let omml = mathml_to_omml(&mathml).expect("nested mstyle should convert");
+        assert!(omml.contains(r#"<m:limLoc m:val="subSup"/>"#), "got: {}", omml);
+        assert!(!omml.contains(r#"<m:limLoc m:val="undOvr"/>"#), "got: {}", omml);
     }
 }
 
@@ -2354,25 +5684,80 @@ mod subsup_tests {
         assert_eq!(fix_subsup_order(r"A_{k_2}^{s2t}"), r"{A_{k_2}}^{s2t}");
     }
     
+    /// Parse the MathML for `latex` and return its single top-level node.
+    fn single_node(latex: &str) -> MathNode {
+        let mathml = latex_to_mathml_inline(latex).unwrap();
+        let mut nodes = parse_mathml(&mathml).unwrap();
+        assert_eq!(nodes.len(), 1, "expected one top-level node for {}: {:?}", latex, nodes);
+        match nodes.remove(0) {
+            MathNode::Mrow(mut children) if children.len() == 1 => children.remove(0),
+            node => node,
+        }
+    }
+
+    #[test]
+    fn test_fix_subsup_order_unbraced_scripts() {
+        assert_eq!(fix_subsup_order(r"X_a^b"), r"{X_a}^b");
+        assert_eq!(fix_subsup_order(r"X_{a}^b"), r"{X_{a}}^b");
+        assert_eq!(fix_subsup_order(r"\alpha_a^b"), r"\alpha_a^b");
+    }
+
     #[test]
     fn test_fix_subsup_mathml() {
         let latex = r"A_{k_2}^{s2t}";
-        let mathml = latex_to_mathml(latex).unwrap();
-        println!("LaTeX: {}", latex);
-        println!("MathML: {}", mathml);
-        
-        // After fix, the MathML should have msubsup instead of nested msup/msub
-        assert!(mathml.contains("<msubsup>"), "Should have msubsup (combined sub+sup)");
+        let node = single_node(latex);
+
+        // msup(msub(A, k_2), s2t) is merged into one msubsup on A
+        let MathNode::Msubsup(base, sub, sup) = node else {
+            panic!("Should have msubsup (combined sub+sup): {:?}", node);
+        };
+        assert!(matches!(*base, MathNode::Mi(ref x) if x == "A"), "base: {:?}", base);
         // Should still have msub for the nested k_2
-        assert!(mathml.contains("<msub>"), "Should have msub for nested subscript");
-        // Should NOT have msup at the top level (it's been converted to msubsup)
-        assert!(!mathml.contains("<msup>"), "Should not have separate msup");
+        assert!(matches!(*sub, MathNode::Msub(..)), "Should have msub for nested subscript: {:?}", sub);
+        assert!(matches!(*sup, MathNode::Mrow(_)), "sup: {:?}", sup);
+    }
+
+    #[test]
+    fn test_simple_subsup_tree() {
+        let node = single_node(r"X_a^b");
+        let MathNode::Msubsup(base, sub, sup) = node else {
+            panic!("X_a^b should be a single msubsup: {:?}", node);
+        };
+        assert!(matches!(*base, MathNode::Mi(ref x) if x == "X"), "base: {:?}", base);
+        assert!(matches!(*sub, MathNode::Mi(ref x) if x == "a"), "sub: {:?}", sub);
+        assert!(matches!(*sup, MathNode::Mi(ref x) if x == "b"), "sup: {:?}", sup);
+    }
+
+    #[test]
+    fn test_nested_subscript_subsup_tree() {
+        let node = single_node(r"X_{a_b}^c");
+        let MathNode::Msubsup(base, sub, sup) = node else {
+            panic!("X_{{a_b}}^c should be a single msubsup: {:?}", node);
+        };
+        assert!(matches!(*base, MathNode::Mi(ref x) if x == "X"), "base: {:?}", base);
+        let MathNode::Msub(inner_base, inner_sub) = *sub else {
+            panic!("the subscript should be a_b: {:?}", sub);
+        };
+        assert!(matches!(*inner_base, MathNode::Mi(ref x) if x == "a"));
+        assert!(matches!(*inner_sub, MathNode::Mi(ref x) if x == "b"));
+        assert!(matches!(*sup, MathNode::Mi(ref x) if x == "c"), "sup: {:?}", sup);
+    }
+
+    #[test]
+    fn test_mrow_wrapped_msub_base_merges() {
+        let nodes = parse_mathml("<math><msup><mrow><msub><mi>x</mi><mi>i</mi></msub></mrow><mn>2</mn></msup></math>")
+            .unwrap();
+        assert!(
+            matches!(nodes.as_slice(), [MathNode::Mrow(children)] if matches!(children.as_slice(), [MathNode::Msubsup(..)])),
+            "got: {:?}",
+            nodes
+        );
     }
     
     #[test]
     fn test_tilde_subsup() {
         let latex = r"\tilde{E}_{k_2}^{s2t}";
-        let mathml = latex_to_mathml(latex).unwrap();
+        let mathml = latex_to_mathml_inline(latex).unwrap();
         println!("LaTeX: {}", latex);
         println!("MathML: {}", mathml);
         // Should produce valid MathML
@@ -2391,7 +5776,7 @@ mod debug_tests {
     #[test]
     fn test_debug_subsup_omml() {
         let latex = r"A_{k_2}^{s2t}";
-        let mathml = latex_to_mathml(latex).unwrap();
+        let mathml = latex_to_mathml_inline(latex).unwrap();
         println!("=== LaTeX ===\n{}", latex);
         println!("\n=== MathML ===\n{}", mathml);
         
@@ -2418,7 +5803,7 @@ mod complex_formula_tests {
         let latex = r"\(\begin{array}{c}{{{\mathcal L}_{g e n}(y_{t})=-\sum_{t=1}^{T}l o g(P(y_{t}|y<t,D,S))}}\\ {{{\mathcal L}={\mathcal L}_{g e n}(y_{t})+{\mathcal L}_{e}}}\end{array}\)";
         println!("Input LaTeX: {}", latex);
         
-        match latex_to_mathml(latex) {
+        match latex_to_mathml_inline(latex) {
             Ok(mathml) => {
                 println!("MathML output: {}", mathml);
             }
@@ -2438,7 +5823,7 @@ mod mathml_format_tests {
     fn test_print_mathml_format() {
         // Test simple fraction
         let latex = r"\frac{1}{2}";
-        let mathml = latex_to_mathml(latex).unwrap();
+        let mathml = latex_to_mathml_inline(latex).unwrap();
         println!("\n=== Simple Fraction ===");
         println!("LaTeX: {}", latex);
         println!("MathML:\n{}", mathml);
@@ -2451,7 +5836,7 @@ mod mathml_format_tests {
     #[test]
     fn test_print_complex_mathml() {
         let latex = r"\sum_{i=1}^{n} x_i";
-        let mathml = latex_to_mathml(latex).unwrap();
+        let mathml = latex_to_mathml_inline(latex).unwrap();
         println!("\n=== Summation ===");
         println!("LaTeX: {}", latex);
         println!("MathML:\n{}", mathml);
@@ -2638,7 +6023,7 @@ mod property_tests {
         /// 3. Be parseable by an XML parser
         #[test]
         fn prop_latex_to_mathml_produces_valid_xml(latex in valid_latex_expr()) {
-            let result = latex_to_mathml(&latex);
+            let result = latex_to_mathml_inline(&latex);
 
             // The conversion should succeed for valid LaTeX
             prop_assert!(result.is_ok(), "latex_to_mathml failed for '{}': {:?}", latex, result.err());
@@ -2677,7 +6062,7 @@ mod property_tests {
         /// 3. Be parseable by an XML parser
         #[test]
         fn prop_latex_to_omml_produces_valid_xml(latex in valid_latex_expr()) {
-            let result = latex_to_omml(&latex);
+            let result = latex_to_omml(&latex, ConvertDisplay::Inline);
 
             // The conversion should succeed for valid LaTeX
             prop_assert!(result.is_ok(), "latex_to_omml failed for '{}': {:?}", latex, result.err());
@@ -2727,7 +6112,7 @@ mod property_tests {
         #[test]
         fn prop_latex_conversion_pipeline_produces_valid_xml(latex in valid_latex_expr()) {
             // Step 1: LaTeX → MathML
-            let mathml_result = latex_to_mathml(&latex);
+            let mathml_result = latex_to_mathml_inline(&latex);
             prop_assert!(mathml_result.is_ok(), "LaTeX to MathML failed for '{}'", latex);
             let mathml = mathml_result.unwrap();
 
@@ -2757,7 +6142,7 @@ mod property_tests {
         #[test]
         fn prop_omml_pretty_print_preserves_structure(latex in valid_latex_expr()) {
             // Generate valid OMML from LaTeX
-            let omml_result = latex_to_omml(&latex);
+            let omml_result = latex_to_omml(&latex, ConvertDisplay::Inline);
             prop_assert!(omml_result.is_ok(), "latex_to_omml failed for '{}': {:?}", latex, omml_result.err());
             let original_omml = omml_result.unwrap();
 
@@ -2912,7 +6297,7 @@ mod property_tests {
         /// the error message should contain the name of the unsupported environment.
         #[test]
         fn prop_unsupported_environment_error_contains_env_name((latex, env_name) in latex_with_unsupported_environment()) {
-            let result = latex_to_mathml(&latex);
+            let result = latex_to_mathml_inline(&latex);
 
             // The conversion should fail for unsupported environments
             prop_assert!(
@@ -2943,7 +6328,7 @@ mod property_tests {
         fn prop_unsupported_environment_with_content_error_contains_env_name(
             (latex, env_name) in latex_with_unsupported_environment_and_content()
         ) {
-            let result = latex_to_mathml(&latex);
+            let result = latex_to_mathml_inline(&latex);
 
             // The conversion should fail for unsupported environments
             prop_assert!(
@@ -2965,4 +6350,171 @@ mod property_tests {
             );
         }
     }
-}
\ No newline at end of file
+}
+
+// ===========================================================================
+// Fuzz Tests for the MathML parser (pasted / external MathML)
+// ===========================================================================
+// mathml_to_omml must never panic on arbitrary input: malformed MathML either
+// returns an error or produces well-formed OMML.
+// ===========================================================================
+
+#[cfg(test)]
+mod fuzz_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The fuzz invariant: no panic, and any `Ok` output is well-formed OMML.
+    fn check_mathml(input: &str) {
+        let result = std::panic::catch_unwind(|| mathml_to_omml(input))
+            .unwrap_or_else(|_| panic!("mathml_to_omml panicked on: {:?}", input));
+        if let Ok(omml) = result {
+            assert!(omml.starts_with("<m:oMathPara"), "unexpected OMML root: {}", omml);
+            let mut reader = Reader::from_str(&omml);
+            let mut depth = 0i32;
+            loop {
+                match reader.read_event() {
+                    Ok(Event::Start(_)) => depth += 1,
+                    Ok(Event::End(_)) => depth -= 1,
+                    Ok(Event::Eof) => break,
+                    Err(e) => panic!("OMML for {:?} is not valid XML: {} ({})", input, e, omml),
+                    _ => {}
+                }
+            }
+            assert_eq!(depth, 0, "OMML for {:?} is unbalanced: {}", input, omml);
+        }
+    }
+
+    const TAGS: &[&str] = &[
+        "math", "mrow", "mi", "mn", "mo", "mtext", "mfrac", "msqrt", "mroot", "msup", "msub",
+        "msubsup", "mover", "munder", "munderover", "mtable", "mtr", "mtd", "mfenced", "mspace",
+        "mstyle", "semantics", "annotation", "unknown",
+    ];
+
+    /// Arbitrary token soup: open/close/empty tags, text, entities and odd markup.
+    fn token() -> impl Strategy<Value = String> {
+        let tag = proptest::sample::select(TAGS);
+        prop_oneof![
+            tag.clone().prop_map(|t| format!("<{}>", t)),
+            tag.clone().prop_map(|t| format!("</{}>", t)),
+            tag.prop_map(|t| format!("<{}/>", t)),
+            Just("x".to_string()),
+            Just("∑".to_string()),
+            Just("&amp;".to_string()),
+            Just("&bogus;".to_string()),
+            Just("<".to_string()),
+            Just(">".to_string()),
+            Just(r#"<mfenced open="[" close="]">"#.to_string()),
+            Just("<mtable columnalign=left>".to_string()),
+            Just("<mi mathvariant=normal>".to_string()),
+            Just("<!-- c -->".to_string()),
+            Just("<![CDATA[x]]>".to_string()),
+            Just("<?pi x?>".to_string()),
+        ]
+    }
+
+    /// Well-formed trees with arbitrary tags and arities (e.g. `<mfrac>` with
+    /// zero or three children), which stress the positional element handlers.
+    fn tree() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            Just("<mi>x</mi>".to_string()),
+            Just("<mn>1</mn>".to_string()),
+            Just("<mo>=</mo>".to_string()),
+            Just("<mo>(</mo>".to_string()),
+            Just("<mi mathvariant=\"normal\">d</mi>".to_string()),
+            Just("<mspace/>".to_string()),
+        ];
+        leaf.prop_recursive(6, 64, 4, |inner| {
+            (proptest::sample::select(TAGS), prop::collection::vec(inner, 0..4))
+                .prop_map(|(tag, children)| format!("<{0}>{1}</{0}>", tag, children.concat()))
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(256))]
+
+        #[test]
+        fn fuzz_mathml_token_soup(tokens in prop::collection::vec(token(), 0..40)) {
+            check_mathml(&tokens.concat());
+        }
+
+        #[test]
+        fn fuzz_mathml_well_formed_trees(body in tree()) {
+            check_mathml(&format!(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">{}</math>"#, body));
+        }
+
+        #[test]
+        fn fuzz_mathml_arbitrary_text(input in ".{0,200}") {
+            check_mathml(&input);
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Seed corpus: malformed inputs that previously risked panics
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn seed_unclosed_tags() {
+        for input in [
+            "<math>",
+            "<math><mfrac><mi>a</mi>",
+            "<math><msubsup><mi>x</mi><mn>1</mn>",
+            "<math><mtable><mtr><mtd><mi>a</mi>",
+            "<math><mi>x",
+            "<mfenced open=\"[\"",
+        ] {
+            check_mathml(input);
+        }
+    }
+
+    #[test]
+    fn seed_mismatched_and_stray_tags() {
+        for input in [
+            "</mrow></math>",
+            "<math><mi>x</mo></math>",
+            "<math><mrow></mi></mrow></math>",
+            "<math><msub/><mfrac/></math>",
+            "<<>>",
+            "<math><mover><mo>=</mo></mover></math>",
+            "<math><munderover><mo>∑</mo></munderover></math>",
+            "<math><mtable columnalign=left><mtr></mtr></mtable></math>",
+            "<math><mi>&unknown;</mi></math>",
+        ] {
+            check_mathml(input);
+        }
+    }
+
+    #[test]
+    fn seed_deep_nesting_is_rejected_without_overflow() {
+        let depth = 10_000;
+        let input = format!("{}<mi>x</mi>{}", "<mrow>".repeat(depth), "</mrow>".repeat(depth));
+        check_mathml(&input);
+        assert!(mathml_to_omml(&input).is_err(), "absurd nesting should be rejected");
+
+        let depth = MAX_MATHML_DEPTH - 1;
+        let input = format!("{}<mi>x</mi>{}", "<mrow>".repeat(depth), "</mrow>".repeat(depth));
+        assert!(mathml_to_omml(&input).is_ok(), "nesting within the limit should convert");
+    }
+
+    #[test]
+    fn seed_chained_nary_operators_are_rejected_without_overflow() {
+        // Each operator takes the next as its body: one tree level per operator
+        let latex = format!("{}x", r"\sum_i ".repeat(570));
+        assert!(check_complexity(&latex, &ComplexityLimits::default()).is_ok());
+        assert!(latex_to_omml(&latex, ConvertDisplay::Block).is_err());
+
+        let sum = "<munder><mo>∑</mo><mi>i</mi></munder>";
+        let input = format!("<math>{}<mi>x</mi></math>", sum.repeat(10_000));
+        check_mathml(&input);
+        assert!(mathml_to_omml(&input).is_err(), "10k chained operators should be rejected");
+
+        // Operators grouped in inner rows count towards the same limit
+        let level = sum.repeat(MAX_MATHML_DEPTH / 2);
+        let input = format!("<math>{}<mrow>{}<mi>x</mi></mrow></math>", level, level.repeat(2));
+        assert!(mathml_to_omml(&input).is_err(), "nesting across rows should add up");
+
+        let input = format!("<math>{}<mi>x</mi></math>", sum.repeat(MAX_MATHML_DEPTH));
+        assert!(mathml_to_omml(&input).is_ok(), "nesting within the limit should convert");
+        assert!(latex_to_omml(r"\sum_i \sum_j \prod_k a_{ijk}", ConvertDisplay::Block).is_ok());
+    }
+}
@@ -2,7 +2,10 @@
 // 负责生成 .tex 和 .docx 文件
 
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{Cursor, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use zip::write::SimpleFileOptions;
 use zip::ZipWriter;
 
@@ -12,16 +15,123 @@ use crate::history::HistoryRecord;
 pub struct TexExportOptions {
     /// 是否添加时间注释分隔
     pub add_time_comments: bool,
+    /// 是否按内容自动选择 amsmath 环境（多行公式用 `align*`/`gather*`）
+    #[serde(default)]
+    pub smart_environments: bool,
+    /// 单行公式的定界符风格
+    #[serde(default)]
+    pub delimiter: TexDelimiter,
 }
 
 impl Default for TexExportOptions {
     fn default() -> Self {
         Self {
             add_time_comments: false,
+            smart_environments: false,
+            delimiter: TexDelimiter::DoubleDollar,
         }
     }
 }
 
+/// .tex 数学公式定界符风格
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TexDelimiter {
+    /// `$$...$$`（默认）
+    #[default]
+    DoubleDollar,
+    /// `\[...\]`
+    BracketDisplay,
+    /// `\begin{equation}...\end{equation}`（带编号）
+    EquationEnv,
+}
+
+impl TexDelimiter {
+    /// 用该风格的定界符包裹一条公式
+    fn wrap(self, latex: &str) -> String {
+        match self {
+            TexDelimiter::DoubleDollar => format!("$${}$$", latex),
+            TexDelimiter::BracketDisplay => format!("\\[{}\\]", latex),
+            TexDelimiter::EquationEnv => format!("\\begin{{equation}}\n{}\n\\end{{equation}}", latex.trim()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocxExportOptions {
+    /// 是否在公式后附加隐藏的 LaTeX 源码（供屏幕阅读器和回转使用）
+    pub include_latex_annotation: bool,
+    /// 是否写入带 `<w:mathPr>` 的 `word/settings.xml`，声明 Cambria Math 为公式字体
+    #[serde(default)]
+    pub include_math_settings: bool,
+    /// 是否以行内公式（只有 `<m:oMath>`）写入，而不是独占一行的 `<m:oMathPara>`
+    #[serde(default)]
+    pub inline_math: bool,
+}
+
+/// Markdown 数学公式定界符风格
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkdownMathDelimiter {
+    /// `$$...$$`（默认，多数渲染器支持）
+    #[default]
+    Dollars,
+    /// `\[...\]`
+    Brackets,
+    /// GitHub 风格的 ```` ```math ```` 代码块
+    MathFence,
+}
+
+impl MarkdownMathDelimiter {
+    /// 用该风格的定界符包裹一条公式
+    fn wrap(self, latex: &str) -> String {
+        match self {
+            MarkdownMathDelimiter::Dollars => format!("$${}$$", latex),
+            MarkdownMathDelimiter::Brackets => format!("\\[{}\\]", latex),
+            MarkdownMathDelimiter::MathFence => format!("```math\n{}\n```", latex),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkdownExportOptions {
+    /// 公式定界符风格
+    pub delimiter: MarkdownMathDelimiter,
+    /// 是否在每条公式前写入 `## 时间戳` 标题及识别置信度
+    pub include_heading: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HtmlExportOptions {
+    /// 是否在每个 `<math>` 上保留 `data-latex` 源码属性，便于之后回转编辑
+    pub include_latex_attribute: bool,
+}
+
+/// 按标签分组导出的文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagExportFormat {
+    /// 每个标签一个 `.tex`（同 `export_tex`）
+    #[default]
+    Tex,
+    /// 每个标签一个 `.md`（同 `export_markdown`）
+    Markdown,
+}
+
+/// 按标签分组导出的选项
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagExportFilter {
+    pub format: TagExportFormat,
+    /// 只导出这些标签；`None` 表示全部标签以及无标签的记录
+    pub tags: Option<Vec<String>>,
+}
+
+/// 无标签记录所在文件的名称（不含扩展名）
+pub const UNTAGGED_FILE_STEM: &str = "untagged";
+
 #[derive(Debug, thiserror::Error)]
 pub enum ExportError {
     #[error("导出失败: {0}")]
@@ -39,47 +149,634 @@ impl Serialize for ExportError {
     }
 }
 
+/// 单条公式导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SingleExportFormat {
+    /// LaTeX 源码文本
+    Latex,
+    /// MathML (`<math>...</math>`)
+    Mathml,
+    /// OMML 片段 (`<m:oMathPara>...</m:oMathPara>`)
+    Omml,
+    /// 内嵌 MathML 的 SVG
+    Svg,
+    /// 记录保存的缩略图（统一输出为 PNG）
+    Png,
+}
+
+/// SVG / PNG 预览的配色方案
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    /// 白底黑字（默认）
+    #[default]
+    Light,
+    /// 深色背景、浅色字形
+    Dark,
+    /// 透明背景、黑色字形
+    Transparent,
+}
+
+impl ColorScheme {
+    /// 字形颜色
+    fn foreground(self) -> [u8; 3] {
+        match self {
+            ColorScheme::Light | ColorScheme::Transparent => [0x00, 0x00, 0x00],
+            ColorScheme::Dark => [0xE6, 0xE6, 0xE6],
+        }
+    }
+
+    /// 背景颜色；`None` 表示透明
+    fn background(self) -> Option<[u8; 3]> {
+        match self {
+            ColorScheme::Light => Some([0xFF, 0xFF, 0xFF]),
+            ColorScheme::Dark => Some([0x1E, 0x1E, 0x1E]),
+            ColorScheme::Transparent => None,
+        }
+    }
+}
+
+/// `#rrggbb` for an RGB triple.
+fn css_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// 单条 PNG 导出中保存 LaTeX 的 iTXt 关键字
+pub const PNG_LATEX_KEYWORD: &str = "latex";
+
+/// SVG 画布默认尺寸（记录没有缩略图时使用）
+const DEFAULT_SVG_SIZE: (u32, u32) = (640, 160);
+
+/// 单条记录的导出结果
+///
+/// Serialized as `{"status": "succeeded", "id": 1}` or
+/// `{"status": "failed", "id": 2, "error": "..."}` so the UI can list which
+/// formulas did not convert.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ExportRecordStatus {
+    Succeeded { id: Option<i64> },
+    Failed { id: Option<i64>, error: String },
+}
+
+/// .docx 文件字节与逐条转换状态
+pub type DocxWithReport = (Vec<u8>, Vec<ExportRecordStatus>);
+
+/// 导出进度（已处理 / 总记录数），作为 `export-progress` 事件的负载
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// 可取消导出的结果
+///
+/// Serialized as `{"status": "completed", "output": ...}` or
+/// `{"status": "cancelled", "done": 3, "total": 10}`; a cancelled export
+/// produces no file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum StreamedExport<T> {
+    Completed { output: T },
+    Cancelled { done: usize, total: usize },
+}
+
+/// 导出取消标志
+///
+/// One per export, shared between the running export and the command that
+/// cancels it; the export checks it before each record.
+#[derive(Debug, Default)]
+pub struct ExportCancellation(AtomicBool);
+
+impl ExportCancellation {
+    /// Ask the running export to stop before its next record.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// 可取消导出的登记表：导出令牌 → 该导出的取消标志
+///
+/// `start` hands out a fresh token for each export, so cancelling one
+/// export never stops another that runs at the same time (or a later one).
+#[derive(Debug, Default)]
+pub struct ExportRegistry {
+    next_token: AtomicU64,
+    running: Mutex<HashMap<u64, Arc<ExportCancellation>>>,
+}
+
+impl ExportRegistry {
+    /// Register a new export and return its token.
+    pub fn start(&self) -> u64 {
+        let token = self.next_token.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(token, Arc::default());
+        }
+        token
+    }
+
+    /// The cancellation flag of export `token`, if it is registered.
+    pub fn flag(&self, token: u64) -> Option<Arc<ExportCancellation>> {
+        self.running.lock().ok()?.get(&token).cloned()
+    }
+
+    /// Cancel export `token`; returns `false` when no such export is registered.
+    pub fn cancel(&self, token: u64) -> bool {
+        match self.flag(token) {
+            Some(flag) => {
+                flag.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget export `token` once it has finished.
+    pub fn finish(&self, token: u64) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(&token);
+        }
+    }
+}
+
+/// Per-record progress hook: reports after each record and tells the export
+/// whether to continue with the next one.
+struct ProgressTracker<'a> {
+    cancel: &'a ExportCancellation,
+    on_progress: &'a mut dyn FnMut(ExportProgress),
+    done: usize,
+    total: usize,
+}
+
+impl ProgressTracker<'_> {
+    /// `false` when cancellation was requested and the export should stop.
+    fn proceed(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+
+    fn record_done(&mut self) {
+        self.done += 1;
+        (self.on_progress)(ExportProgress { done: self.done, total: self.total });
+    }
+
+    fn cancelled<T>(&self) -> StreamedExport<T> {
+        StreamedExport::Cancelled { done: self.done, total: self.total }
+    }
+}
+
 /// Returns the effective LaTeX string for a record.
 /// Uses `edited_latex` if available, otherwise falls back to `original_latex`.
-fn effective_latex(record: &HistoryRecord) -> &str {
+pub(crate) fn effective_latex(record: &HistoryRecord) -> &str {
     record
         .edited_latex
         .as_deref()
         .unwrap_or(&record.original_latex)
 }
 
+/// Returns the record's note, if it has a non-blank one.
+fn record_note(record: &HistoryRecord) -> Option<&str> {
+    record.note.as_deref().map(str::trim).filter(|note| !note.is_empty())
+}
+
 /// 导出为 .tex 文件
 ///
 /// Records are sorted by `created_at` ascending (oldest first, chronological order).
-/// Each formula is wrapped in `$$...$$` display math mode.
+/// Each formula is wrapped according to `options.delimiter` (`$$...$$` by
+/// default). When `options.smart_environments` is true, formulas with
+/// top-level `\\` line breaks go into `align*` if they also have top-level `&`
+/// alignment points and into `gather*` otherwise; single-line formulas keep
+/// `options.delimiter`.
 /// When `options.add_time_comments` is true, a comment line `% [timestamp]` is
 /// inserted before each formula.
+/// A record's note, if any, is written as an escaped prose line just above
+/// its formula.
 /// Formulas are separated by blank lines.
 pub fn export_tex(
     records: &[HistoryRecord],
     options: &TexExportOptions,
 ) -> Result<Vec<u8>, ExportError> {
+    match export_tex_streamed(records, options, &ExportCancellation::default(), |_| {})? {
+        StreamedExport::Completed { output } => Ok(output),
+        StreamedExport::Cancelled { .. } => Err(ExportError::ExportFailed("导出已取消".to_string())),
+    }
+}
+
+/// 导出 .tex 文件，逐条报告进度并支持中途取消
+///
+/// Same output as `export_tex`. `on_progress` is called after each record;
+/// `cancel` is checked before each record, and a cancelled export returns
+/// `StreamedExport::Cancelled` with the number of records already processed.
+pub fn export_tex_streamed(
+    records: &[HistoryRecord],
+    options: &TexExportOptions,
+    cancel: &ExportCancellation,
+    mut on_progress: impl FnMut(ExportProgress),
+) -> Result<StreamedExport<Vec<u8>>, ExportError> {
     // Sort records by created_at ascending (oldest first)
     let mut sorted: Vec<&HistoryRecord> = records.iter().collect();
     sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at));
 
     let mut parts: Vec<String> = Vec::with_capacity(sorted.len());
+    let mut tracker = ProgressTracker { cancel, on_progress: &mut on_progress, done: 0, total: sorted.len() };
 
     for record in &sorted {
+        if !tracker.proceed() {
+            return Ok(tracker.cancelled());
+        }
         let mut block = String::new();
 
         if options.add_time_comments {
             block.push_str(&format!("% [{}]\n", record.created_at));
         }
 
+        if let Some(note) = record_note(record) {
+            block.push_str(&tex_escape(note));
+            block.push('\n');
+        }
+
         let latex = effective_latex(record);
-        block.push_str(&format!("$${}$$", latex));
+        match tex_environment(latex).filter(|_| options.smart_environments) {
+            Some(env) => block.push_str(&format!(
+                "\\begin{{{env}}}\n{}\n\\end{{{env}}}",
+                latex.trim()
+            )),
+            None => block.push_str(&options.delimiter.wrap(latex)),
+        }
 
         parts.push(block);
+        tracker.record_done();
     }
 
     let content = parts.join("\n\n");
-    Ok(content.into_bytes())
+    Ok(StreamedExport::Completed { output: content.into_bytes() })
+}
+
+/// 导出为 Markdown 文件
+///
+/// Records are sorted by `created_at` ascending like `export_tex`. Each
+/// formula is wrapped with `options.delimiter`; a record's note, if any, is
+/// written as a prose paragraph just above its formula. With
+/// `options.include_heading`, each block starts with a `## <created_at>`
+/// heading followed by a confidence line. Blocks are separated by blank lines.
+pub fn export_markdown(
+    records: &[HistoryRecord],
+    options: &MarkdownExportOptions,
+) -> Result<Vec<u8>, ExportError> {
+    let mut sorted: Vec<&HistoryRecord> = records.iter().collect();
+    sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let blocks: Vec<String> = sorted
+        .iter()
+        .map(|record| {
+            let mut parts = Vec::new();
+            if options.include_heading {
+                parts.push(format!("## {}", record.created_at));
+                parts.push(format!("*置信度 {:.0}%*", record.confidence * 100.0));
+            }
+            if let Some(note) = record_note(record) {
+                parts.push(note.to_string());
+            }
+            parts.push(options.delimiter.wrap(effective_latex(record)));
+            parts.join("\n\n")
+        })
+        .collect();
+
+    Ok(blocks.join("\n\n").into_bytes())
+}
+
+/// 按标签分组导出为 ZIP，每个标签一个文件
+///
+/// `tags` maps record IDs to their tags (see `history::get_tags`). A record
+/// with several tags appears in each of their files; records without tags go
+/// to `untagged.tex` / `untagged.md`, which is only written when
+/// `filter.tags` is `None`. File contents are the same as `export_tex` /
+/// `export_markdown` with default options. Characters that are not allowed
+/// in file names are replaced with `_`; tags whose file names would then
+/// collide (`a/b` and `a:b`, or names differing only in case) get a
+/// numeric suffix (`a_b_2`) so each tag keeps its own file.
+pub fn export_by_tag_zip(
+    records: &[HistoryRecord],
+    tags: &HashMap<i64, Vec<String>>,
+    filter: &TagExportFilter,
+) -> Result<Vec<u8>, ExportError> {
+    let mut groups: BTreeMap<&str, Vec<HistoryRecord>> = BTreeMap::new();
+    let mut untagged = Vec::new();
+    for record in records {
+        let record_tags = record.id.and_then(|id| tags.get(&id)).filter(|t| !t.is_empty());
+        match (record_tags, &filter.tags) {
+            (Some(record_tags), wanted) => {
+                for tag in record_tags {
+                    if wanted.as_ref().is_none_or(|wanted| wanted.contains(tag)) {
+                        groups.entry(tag.as_str()).or_default().push(record.clone());
+                    }
+                }
+            }
+            (None, None) => untagged.push(record.clone()),
+            (None, Some(_)) => {}
+        }
+    }
+
+    // File names are compared case-insensitively, as on Windows and macOS
+    let mut used = HashSet::new();
+    let mut files = Vec::new();
+    if !untagged.is_empty() {
+        used.insert(UNTAGGED_FILE_STEM.to_lowercase());
+        files.push((UNTAGGED_FILE_STEM.to_string(), untagged));
+    }
+    for (tag, group) in groups {
+        let base = tag_file_stem(tag);
+        let mut stem = base.clone();
+        let mut suffix = 1;
+        while !used.insert(stem.to_lowercase()) {
+            suffix += 1;
+            stem = format!("{}_{}", base, suffix);
+        }
+        files.push((stem, group));
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    for (stem, group) in &files {
+        let (name, content) = match filter.format {
+            TagExportFormat::Tex => (
+                format!("{}.tex", stem),
+                export_tex(group, &TexExportOptions::default())?,
+            ),
+            TagExportFormat::Markdown => (
+                format!("{}.md", stem),
+                export_markdown(group, &MarkdownExportOptions::default())?,
+            ),
+        };
+        zip.start_file(name, options)
+            .map_err(|e| ExportError::ExportFailed(format!("ZIP error: {}", e)))?;
+        zip.write_all(&content)
+            .map_err(|e| ExportError::ExportFailed(format!("Write error: {}", e)))?;
+    }
+
+    let result = zip
+        .finish()
+        .map_err(|e| ExportError::ExportFailed(format!("ZIP finish error: {}", e)))?;
+    Ok(result.into_inner())
+}
+
+/// File name for a tag: path separators and other reserved characters become `_`.
+fn tag_file_stem(tag: &str) -> String {
+    tag.trim()
+        .chars()
+        .map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { '_' } else { c })
+        .collect()
+}
+
+/// 导出单条公式为指定格式
+///
+/// LaTeX, MathML and OMML come from the effective LaTeX via the `convert`
+/// module. SVG wraps the MathML in a `<foreignObject>`, sized like the
+/// record's thumbnail when it has one. PNG returns the stored thumbnail
+/// (re-encoded to PNG if it was stored as JPEG) with the effective LaTeX in
+/// an `iTXt` "latex" chunk and the record's engine version in a `tEXt`
+/// "engine_version" chunk (omitted when empty), and fails with
+/// `ExportFailed` when the record was saved without a thumbnail.
+pub fn export_one(record: &HistoryRecord, format: SingleExportFormat) -> Result<Vec<u8>, ExportError> {
+    export_one_with_scheme(record, format, ColorScheme::default())
+}
+
+/// 按配色方案导出单条公式
+///
+/// Like `export_one`; `color_scheme` sets the glyph and background colors
+/// of SVG and PNG output. The SVG gets a background `<rect>` (none for
+/// `Transparent`) and its content is drawn in the scheme's foreground color.
+/// The thumbnail is recolored by ink darkness, except for `Light`, which keeps
+/// the stored pixels as is. Text formats ignore the scheme.
+pub fn export_one_with_scheme(
+    record: &HistoryRecord,
+    format: SingleExportFormat,
+    color_scheme: ColorScheme,
+) -> Result<Vec<u8>, ExportError> {
+    let latex = effective_latex(record);
+    let convert_err = |e: crate::convert::ConvertError| ExportError::ConvertFailed(e.to_string());
+
+    match format {
+        SingleExportFormat::Latex => Ok(latex.as_bytes().to_vec()),
+        SingleExportFormat::Mathml => Ok(crate::convert::latex_to_mathml_inline(latex).map_err(convert_err)?.into_bytes()),
+        SingleExportFormat::Omml => Ok(crate::convert::latex_to_omml(latex, crate::convert::ConvertDisplay::Inline).map_err(convert_err)?.into_bytes()),
+        SingleExportFormat::Svg => {
+            let mathml = crate::convert::latex_to_mathml_inline(latex).map_err(convert_err)?;
+            let (width, height) = record
+                .thumbnail
+                .as_deref()
+                .and_then(|png| image::load_from_memory(png).ok())
+                .map(|img| (img.width(), img.height()))
+                .unwrap_or(DEFAULT_SVG_SIZE);
+            let background = color_scheme
+                .background()
+                .map(|bg| format!(r#"<rect width="100%" height="100%" fill="{}"/>"#, css_color(bg)))
+                .unwrap_or_default();
+            let foreground = css_color(color_scheme.foreground());
+            let svg = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}"><title>{title}</title>{background}<g fill="{fg}" color="{fg}"><foreignObject x="0" y="0" width="{w}" height="{h}">{mathml}</foreignObject></g></svg>"#,
+                w = width,
+                h = height,
+                title = xml_escape(latex),
+                background = background,
+                fg = foreground,
+                mathml = mathml
+            );
+            Ok(svg.into_bytes())
+        }
+        SingleExportFormat::Png => {
+            let thumbnail = record
+                .thumbnail
+                .as_deref()
+                .ok_or_else(|| ExportError::ExportFailed("记录没有保存缩略图".to_string()))?;
+            let png = if color_scheme == ColorScheme::Light {
+                thumbnail_as_png(thumbnail)?
+            } else {
+                crate::preprocess::recolor(thumbnail, color_scheme.foreground(), color_scheme.background())
+                    .map_err(|e| ExportError::ExportFailed(e.to_string()))?
+            };
+            let mut png = crate::preprocess::insert_png_itxt_chunk(&png, PNG_LATEX_KEYWORD, latex)
+                .map_err(|e| ExportError::ExportFailed(e.to_string()))?;
+            if !record.engine_version.is_empty() {
+                png = crate::preprocess::stamp_engine_version(&png, &record.engine_version)
+                    .map_err(|e| ExportError::ExportFailed(e.to_string()))?;
+            }
+            Ok(png)
+        }
+    }
+}
+
+/// 将缩略图统一为 PNG
+///
+/// `history::reencode_thumbnail` 可能已把缩略图转存为 JPEG，PNG 块注入只接受
+/// PNG 数据，因此非 PNG 的缩略图先重新编码。
+fn thumbnail_as_png(thumbnail: &[u8]) -> Result<Vec<u8>, ExportError> {
+    if image::guess_format(thumbnail).ok() == Some(image::ImageFormat::Png) {
+        return Ok(thumbnail.to_vec());
+    }
+    crate::preprocess::reencode(thumbnail, crate::preprocess::OutputFormat::Png)
+        .map_err(|e| ExportError::ExportFailed(e.to_string()))
+}
+
+/// 拼图导出选项
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PngSheetOptions {
+    /// 公式之间及四周的留白（像素）
+    pub padding: u32,
+    /// 配色方案（同时决定拼图背景）
+    pub color_scheme: ColorScheme,
+}
+
+impl Default for PngSheetOptions {
+    fn default() -> Self {
+        Self {
+            padding: 16,
+            color_scheme: ColorScheme::Light,
+        }
+    }
+}
+
+/// 无法渲染的公式在拼图中的占位框尺寸
+const SHEET_PLACEHOLDER_SIZE: (u32, u32) = (240, 48);
+
+/// 占位框的边框颜色
+const SHEET_PLACEHOLDER_COLOR: [u8; 3] = [0xC0, 0xC0, 0xC0];
+
+/// 将多条公式纵向拼接为一张 PNG
+///
+/// Records are sorted by `created_at` ascending like `export_tex`. Each one
+/// is rendered as `export_one_with_scheme(.., Png, ..)` would, centered
+/// horizontally and separated by `options.padding` pixels, which also frames
+/// the sheet. A record saved without a thumbnail gets a gray outlined
+/// placeholder box instead, so positions still match the selection; a stored
+/// thumbnail that cannot be decoded fails the export with `ExportFailed`.
+pub fn export_png_sheet(records: &[HistoryRecord], options: &PngSheetOptions) -> Result<Vec<u8>, ExportError> {
+    if records.is_empty() {
+        return Err(ExportError::ExportFailed("没有可导出的记录".to_string()));
+    }
+    let mut sorted: Vec<&HistoryRecord> = records.iter().collect();
+    sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let tiles = sorted
+        .iter()
+        .map(|record| {
+            if record.thumbnail.is_none() {
+                return Ok(sheet_placeholder());
+            }
+            let png = export_one_with_scheme(record, SingleExportFormat::Png, options.color_scheme)?;
+            let img = image::load_from_memory(&png)
+                .map_err(|e| ExportError::ExportFailed(format!("缩略图解码失败: {}", e)))?;
+            Ok(img.to_rgba8())
+        })
+        .collect::<Result<Vec<image::RgbaImage>, ExportError>>()?;
+
+    let padding = options.padding;
+    let width = tiles.iter().map(|t| t.width()).max().unwrap_or(0) + 2 * padding;
+    let height = tiles.iter().map(|t| t.height() + padding).sum::<u32>() + padding;
+    let background = match options.color_scheme.background() {
+        Some([r, g, b]) => image::Rgba([r, g, b, 255]),
+        None => image::Rgba([0, 0, 0, 0]),
+    };
+    let mut sheet = image::RgbaImage::from_pixel(width, height, background);
+
+    let mut y = padding;
+    for tile in &tiles {
+        let x = (width - tile.width()) / 2;
+        image::imageops::overlay(&mut sheet, tile, x as i64, y as i64);
+        y += tile.height() + padding;
+    }
+
+    let mut buf = Cursor::new(Vec::new());
+    sheet
+        .write_to(&mut buf, image::ImageFormat::Png)
+        .map_err(|e| ExportError::ExportFailed(format!("PNG 编码失败: {}", e)))?;
+    Ok(buf.into_inner())
+}
+
+/// Transparent box with a gray outline standing in for an unrenderable formula.
+fn sheet_placeholder() -> image::RgbaImage {
+    let (w, h) = SHEET_PLACEHOLDER_SIZE;
+    let [r, g, b] = SHEET_PLACEHOLDER_COLOR;
+    image::RgbaImage::from_fn(w, h, |x, y| {
+        if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+            image::Rgba([r, g, b, 255])
+        } else {
+            image::Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// 导出为 HTML 文件
+///
+/// Records are sorted by `created_at` ascending like `export_tex`. Each
+/// record becomes an `<article>` holding its note, if any, as a paragraph
+/// followed by the formula as display MathML. With
+/// `options.include_latex_attribute` every `<math>` element also carries its
+/// source as an escaped `data-latex` attribute. Formulas that fail to convert
+/// fall back to the escaped LaTeX in a `<code class="转换失败">` element, as
+/// `export_docx` falls back to plain text.
+pub fn export_html(records: &[HistoryRecord], options: &HtmlExportOptions) -> Result<Vec<u8>, ExportError> {
+    let mut sorted: Vec<&HistoryRecord> = records.iter().collect();
+    sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>FormulaSnap</title>\n</head>\n<body>\n",
+    );
+    for record in sorted {
+        html.push_str("<article>\n");
+        if let Some(note) = record_note(record) {
+            html.push_str(&format!("<p>{}</p>\n", xml_escape(note)));
+        }
+        let latex = effective_latex(record);
+        match crate::convert::latex_to_mathml(latex, crate::convert::ConvertDisplay::Block) {
+            Ok(mathml) if options.include_latex_attribute => {
+                let attribute = format!("<math data-latex=\"{}\"", xml_escape(latex));
+                html.push_str(&mathml.replacen("<math", &attribute, 1));
+            }
+            Ok(mathml) => html.push_str(&mathml),
+            Err(_) => html.push_str(&format!("<code class=\"转换失败\">{}</code>", xml_escape(latex))),
+        }
+        html.push_str("\n</article>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    Ok(html.into_bytes())
+}
+
+/// 导出为 Jupyter Notebook (.ipynb) 文件
+///
+/// Produces a minimal nbformat 4.4 notebook with one markdown cell per record
+/// (in input order), each holding the effective LaTeX as `$$...$$` display
+/// math. The notebook has no code cells, so no kernel is required to open it.
+pub fn export_ipynb(records: &[HistoryRecord]) -> Result<Vec<u8>, ExportError> {
+    let cells: Vec<serde_json::Value> = records
+        .iter()
+        .map(|record| {
+            serde_json::json!({
+                "cell_type": "markdown",
+                "metadata": {},
+                "source": match record_note(record) {
+                    Some(note) => format!("{}\n\n$${}$$", note, effective_latex(record)),
+                    None => format!("$${}$$", effective_latex(record)),
+                },
+            })
+        })
+        .collect();
+
+    let notebook = serde_json::json!({
+        "cells": cells,
+        "metadata": {},
+        "nbformat": 4,
+        "nbformat_minor": 4,
+    });
+
+    serde_json::to_vec_pretty(&notebook)
+        .map_err(|e| ExportError::ExportFailed(format!("JSON 序列化失败: {}", e)))
 }
 
 /// 导出为 .docx 文件
@@ -93,7 +790,64 @@ pub fn export_tex(
 /// - `_rels/.rels`
 /// - `word/_rels/document.xml.rels`
 /// - `word/document.xml`
+/// - `word/settings.xml` (only with `DocxExportOptions::include_math_settings`)
 pub fn export_docx(records: &[HistoryRecord]) -> Result<Vec<u8>, ExportError> {
+    export_docx_with_report(records).map(|(docx, _)| docx)
+}
+
+/// 导出为 .docx 文件并返回逐条转换状态
+///
+/// Same output as `export_docx`, plus one `ExportRecordStatus` per input
+/// record (in input order) telling whether its LaTeX converted to OMML or
+/// fell back to the "转换失败" text, with the conversion error.
+pub fn export_docx_with_report(
+    records: &[HistoryRecord],
+) -> Result<(Vec<u8>, Vec<ExportRecordStatus>), ExportError> {
+    export_docx_with_options(records, &DocxExportOptions::default())
+}
+
+/// 按选项导出 .docx 文件并返回逐条转换状态
+///
+/// When `options.include_latex_annotation` is true, every converted formula
+/// paragraph also carries its source LaTeX as a hidden (`<w:vanish/>`) run
+/// next to the `<m:oMathPara>`, so the equation keeps its source without
+/// changing what is displayed or printed.
+///
+/// When `options.inline_math` is true, formulas are written as inline
+/// `<m:oMath>` elements rather than `<m:oMathPara>` blocks, so Word lays
+/// them out inline with the paragraph instead of on their own line.
+///
+/// When `options.include_math_settings` is true, the package also gets a
+/// `word/settings.xml` whose `<m:mathPr>` declares Cambria Math, so Word
+/// versions that otherwise fall back to a text font still render the
+/// equations with the math font.
+pub fn export_docx_with_options(
+    records: &[HistoryRecord],
+    docx_options: &DocxExportOptions,
+) -> Result<(Vec<u8>, Vec<ExportRecordStatus>), ExportError> {
+    match export_docx_streamed(records, docx_options, &ExportCancellation::default(), |_| {})? {
+        StreamedExport::Completed { output } => Ok(output),
+        StreamedExport::Cancelled { .. } => Err(ExportError::ExportFailed("导出已取消".to_string())),
+    }
+}
+
+/// 按选项导出 .docx 文件，逐条报告进度并支持中途取消
+///
+/// Same output as `export_docx_with_options`. `on_progress` is called after
+/// each record is converted; `cancel` is checked before each record, and a
+/// cancelled export returns `StreamedExport::Cancelled` without building the
+/// package.
+pub fn export_docx_streamed(
+    records: &[HistoryRecord],
+    docx_options: &DocxExportOptions,
+    cancel: &ExportCancellation,
+    mut on_progress: impl FnMut(ExportProgress),
+) -> Result<StreamedExport<DocxWithReport>, ExportError> {
+    let mut tracker = ProgressTracker { cancel, on_progress: &mut on_progress, done: 0, total: records.len() };
+    let Some((document_xml, statuses)) = build_document_xml(records, docx_options, &mut tracker) else {
+        return Ok(tracker.cancelled());
+    };
+
     let buf = Cursor::new(Vec::new());
     let mut zip = ZipWriter::new(buf);
     let options = SimpleFileOptions::default()
@@ -102,7 +856,12 @@ pub fn export_docx(records: &[HistoryRecord]) -> Result<Vec<u8>, ExportError> {
     // 1. [Content_Types].xml
     zip.start_file("[Content_Types].xml", options)
         .map_err(|e| ExportError::ExportFailed(format!("ZIP error: {}", e)))?;
-    zip.write_all(CONTENT_TYPES_XML.as_bytes())
+    let content_types = if docx_options.include_math_settings {
+        CONTENT_TYPES_WITH_SETTINGS_XML
+    } else {
+        CONTENT_TYPES_XML
+    };
+    zip.write_all(content_types.as_bytes())
         .map_err(|e| ExportError::ExportFailed(format!("Write error: {}", e)))?;
 
     // 2. _rels/.rels
@@ -114,22 +873,34 @@ pub fn export_docx(records: &[HistoryRecord]) -> Result<Vec<u8>, ExportError> {
     // 3. word/_rels/document.xml.rels
     zip.start_file("word/_rels/document.xml.rels", options)
         .map_err(|e| ExportError::ExportFailed(format!("ZIP error: {}", e)))?;
-    zip.write_all(DOCUMENT_RELS_XML.as_bytes())
+    let document_rels = if docx_options.include_math_settings {
+        DOCUMENT_RELS_WITH_SETTINGS_XML
+    } else {
+        DOCUMENT_RELS_XML
+    };
+    zip.write_all(document_rels.as_bytes())
         .map_err(|e| ExportError::ExportFailed(format!("Write error: {}", e)))?;
 
     // 4. word/document.xml – main content
     zip.start_file("word/document.xml", options)
         .map_err(|e| ExportError::ExportFailed(format!("ZIP error: {}", e)))?;
 
-    let document_xml = build_document_xml(records);
     zip.write_all(document_xml.as_bytes())
         .map_err(|e| ExportError::ExportFailed(format!("Write error: {}", e)))?;
 
+    // 5. word/settings.xml – default math font
+    if docx_options.include_math_settings {
+        zip.start_file("word/settings.xml", options)
+            .map_err(|e| ExportError::ExportFailed(format!("ZIP error: {}", e)))?;
+        zip.write_all(SETTINGS_XML.as_bytes())
+            .map_err(|e| ExportError::ExportFailed(format!("Write error: {}", e)))?;
+    }
+
     let result = zip
         .finish()
         .map_err(|e| ExportError::ExportFailed(format!("ZIP finish error: {}", e)))?;
 
-    Ok(result.into_inner())
+    Ok(StreamedExport::Completed { output: (result.into_inner(), statuses) })
 }
 
 // ---------------------------------------------------------------------------
@@ -143,6 +914,14 @@ const CONTENT_TYPES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalo
   <Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
 </Types>"#;
 
+const CONTENT_TYPES_WITH_SETTINGS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="xml" ContentType="application/xml"/>
+  <Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
+  <Override PartName="/word/settings.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.settings+xml"/>
+</Types>"#;
+
 const RELS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
 <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
   <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
@@ -152,6 +931,28 @@ const DOCUMENT_RELS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalo
 <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
 </Relationships>"#;
 
+const DOCUMENT_RELS_WITH_SETTINGS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/settings" Target="settings.xml"/>
+</Relationships>"#;
+
+const SETTINGS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:settings xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:m="http://schemas.openxmlformats.org/officeDocument/2006/math">
+  <m:mathPr>
+    <m:mathFont m:val="Cambria Math"/>
+    <m:brkBin m:val="before"/>
+    <m:brkBinSub m:val="--"/>
+    <m:smallFrac m:val="0"/>
+    <m:dispDef/>
+    <m:lMargin m:val="0"/>
+    <m:rMargin m:val="0"/>
+    <m:defJc m:val="centerGroup"/>
+    <m:wrapIndent m:val="1440"/>
+    <m:intLim m:val="subSup"/>
+    <m:naryLim m:val="undOvr"/>
+  </m:mathPr>
+</w:settings>"#;
+
 // ---------------------------------------------------------------------------
 // Document XML builder
 // ---------------------------------------------------------------------------
@@ -160,33 +961,65 @@ const DOCUMENT_RELS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalo
 ///
 /// For each record:
 /// - Try to convert the effective LaTeX to OMML via `crate::convert::latex_to_omml`.
-/// - On success: wrap the OMML in `<w:p><m:oMathPara>…</m:oMathPara></w:p>`.
+/// - On success: wrap the OMML in `<w:p><m:oMathPara>…</m:oMathPara></w:p>`
+///   (`<w:p><m:oMath>…</m:oMath></w:p>` with `DocxExportOptions::inline_math`).
 /// - On failure: insert a plain-text paragraph with the LaTeX and a "转换失败" annotation.
-fn build_document_xml(records: &[HistoryRecord]) -> String {
+///
+/// Returns the XML together with the per-record conversion statuses, or
+/// `None` when `tracker` reports cancellation before all records are done.
+fn build_document_xml(
+    records: &[HistoryRecord],
+    options: &DocxExportOptions,
+    tracker: &mut ProgressTracker,
+) -> Option<(String, Vec<ExportRecordStatus>)> {
     let mut paragraphs = String::new();
+    let mut statuses = Vec::with_capacity(records.len());
 
     for record in records {
+        if !tracker.proceed() {
+            return None;
+        }
         let latex = effective_latex(record);
 
-        match crate::convert::latex_to_omml(latex) {
+        if let Some(note) = record_note(record) {
+            paragraphs.push_str(r#"<w:p><w:r><w:t xml:space="preserve">"#);
+            paragraphs.push_str(&xml_escape(note));
+            paragraphs.push_str("</w:t></w:r></w:p>");
+        }
+
+        let converted = if options.inline_math {
+            crate::convert::latex_to_omml_inline(latex)
+        } else {
+            crate::convert::latex_to_omml(latex, crate::convert::ConvertDisplay::Inline)
+        };
+        match converted {
             Ok(omml) => {
-                // The OMML from latex_to_omml already contains <m:oMathPara> wrapper.
+                // The OMML already has its <m:oMathPara> (or inline <m:oMath>) root.
                 // We wrap it in a <w:p> paragraph.
                 paragraphs.push_str("<w:p>");
                 paragraphs.push_str(&omml);
+                if options.include_latex_annotation {
+                    paragraphs.push_str(&latex_annotation_run(latex));
+                }
                 paragraphs.push_str("</w:p>");
+                statuses.push(ExportRecordStatus::Succeeded { id: record.id });
             }
-            Err(_) => {
+            Err(e) => {
                 // Conversion failed – insert plain text with "转换失败" annotation
                 paragraphs.push_str("<w:p><w:r><w:t>");
                 paragraphs.push_str(&xml_escape(latex));
                 paragraphs.push_str(" (转换失败)");
                 paragraphs.push_str("</w:t></w:r></w:p>");
+                statuses.push(ExportRecordStatus::Failed {
+                    id: record.id,
+                    error: e.to_string(),
+                });
             }
         }
+        tracker.record_done();
     }
 
-    format!(
+    let xml = format!(
         r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
 <w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:m="http://schemas.openxmlformats.org/officeDocument/2006/math">{}</w:document>"#,
         if paragraphs.is_empty() {
@@ -194,9 +1027,112 @@ fn build_document_xml(records: &[HistoryRecord]) -> String {
         } else {
             format!("<w:body>{}</w:body>", paragraphs)
         }
+    );
+    Some((xml, statuses))
+}
+
+/// Hidden run carrying the source LaTeX of a formula paragraph.
+fn latex_annotation_run(latex: &str) -> String {
+    format!(
+        r#"<w:r><w:rPr><w:vanish/></w:rPr><w:t xml:space="preserve">LaTeX: {}</w:t></w:r>"#,
+        xml_escape(latex)
     )
 }
 
+/// 构建可粘贴到 Word 的 HTML 片段（剪贴板 CF_HTML 使用）
+///
+/// Each record becomes one `<p>` in input order. Successful conversions embed
+/// the OMML inside a `<!--[if gte msEquation 12]>` conditional comment — the
+/// same form Word itself writes — followed by a LaTeX fallback for other
+/// HTML consumers. Failed conversions fall back to plain text annotated with
+/// "转换失败", mirroring `export_docx`.
+pub fn build_docx_fragment_html(records: &[HistoryRecord]) -> String {
+    let mut html = String::new();
+
+    for record in records {
+        let latex = effective_latex(record);
+
+        if let Some(note) = record_note(record) {
+            html.push_str("<p>");
+            html.push_str(&xml_escape(note));
+            html.push_str("</p>");
+        }
+
+        match crate::convert::latex_to_omml(latex, crate::convert::ConvertDisplay::Inline) {
+            Ok(omml) => {
+                html.push_str("<p><!--[if gte msEquation 12]>");
+                html.push_str(&omml);
+                html.push_str("<![endif]--><![if !msEquation]>");
+                html.push_str(&xml_escape(latex));
+                html.push_str("<![endif]></p>");
+            }
+            Err(_) => {
+                html.push_str("<p>");
+                html.push_str(&xml_escape(latex));
+                html.push_str(" (转换失败)</p>");
+            }
+        }
+    }
+
+    html
+}
+
+/// amsmath environment for a multi-line formula: `align*` when it has
+/// top-level `&`, `gather*` otherwise. `None` when there is no top-level
+/// `\\`. Breaks and `&` inside braces or nested `\begin...\end` blocks
+/// (matrices, `cases`, `aligned`) do not count.
+fn tex_environment(latex: &str) -> Option<&'static str> {
+    let bytes = latex.as_bytes();
+    let (mut braces, mut envs) = (0usize, 0usize);
+    let (mut has_break, mut has_align) = (false, false);
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                let rest = &latex[i..];
+                if rest.starts_with("\\begin{") {
+                    envs += 1;
+                } else if rest.starts_with("\\end{") {
+                    envs = envs.saturating_sub(1);
+                } else if rest.starts_with("\\\\") && braces == 0 && envs == 0 {
+                    has_break = true;
+                }
+                // Skip the escaped character so `\{`, `\&` and `\\` are not re-read.
+                i += 2;
+                continue;
+            }
+            b'{' => braces += 1,
+            b'}' => braces = braces.saturating_sub(1),
+            b'&' if braces == 0 && envs == 0 => has_align = true,
+            _ => {}
+        }
+        i += 1;
+    }
+    match (has_break, has_align) {
+        (false, _) => None,
+        (true, true) => Some("align*"),
+        (true, false) => Some("gather*"),
+    }
+}
+
+/// Escape LaTeX special characters so prose is typeset literally.
+fn tex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str(r"\textbackslash{}"),
+            '~' => escaped.push_str(r"\textasciitilde{}"),
+            '^' => escaped.push_str(r"\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 /// Escape special XML characters in text content.
 fn xml_escape(s: &str) -> String {
     s.replace('&', "&amp;")
@@ -229,6 +1165,8 @@ mod tests {
             engine_version: "pix2tex-v1".to_string(),
             thumbnail: None,
             is_favorite: false,
+            note: None,
+            needs_review: false,
         }
     }
 
@@ -237,6 +1175,7 @@ mod tests {
         let records = vec![make_record("2025-01-01T00:00:00Z", r"E = mc^2", None)];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -250,6 +1189,7 @@ mod tests {
         let records = vec![make_record("2025-01-01T00:00:00Z", r"E = mc^2", None)];
         let options = TexExportOptions {
             add_time_comments: true,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -268,6 +1208,7 @@ mod tests {
         ];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -286,6 +1227,7 @@ mod tests {
         ];
         let options = TexExportOptions {
             add_time_comments: true,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -304,6 +1246,7 @@ mod tests {
         )];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -318,6 +1261,7 @@ mod tests {
         let records = vec![make_record("2025-01-01T00:00:00Z", r"\sum_{i=1}^n i", None)];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -331,6 +1275,7 @@ mod tests {
         let records: Vec<HistoryRecord> = vec![];
         let options = TexExportOptions {
             add_time_comments: true,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -344,6 +1289,7 @@ mod tests {
         let records = vec![make_record("2025-01-01T00:00:00Z", r"\frac{a}{b}", None)];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -360,6 +1306,7 @@ mod tests {
         ];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -382,6 +1329,7 @@ mod tests {
         ];
         let options = TexExportOptions {
             add_time_comments: false,
+            ..Default::default()
         };
 
         let result = export_tex(&records, &options).expect("export should succeed");
@@ -391,6 +1339,161 @@ mod tests {
         assert_eq!(content, expected);
     }
 
+    #[test]
+    fn test_export_tex_smart_environments_wraps_multiline_in_align() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"a &= b \\ c &= d", None)];
+        let options = TexExportOptions {
+            smart_environments: true,
+            ..Default::default()
+        };
+
+        let content = String::from_utf8(export_tex(&records, &options).expect("export should succeed"))
+            .expect("should be valid UTF-8");
+
+        assert_eq!(content, "\\begin{align*}\na &= b \\\\ c &= d\n\\end{align*}");
+    }
+
+    #[test]
+    fn test_export_tex_smart_environments_gathers_unaligned_lines() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x = 1 \\ y = 2", None)];
+        let options = TexExportOptions {
+            smart_environments: true,
+            ..Default::default()
+        };
+
+        let content = String::from_utf8(export_tex(&records, &options).expect("export should succeed"))
+            .expect("should be valid UTF-8");
+
+        assert!(content.starts_with("\\begin{gather*}\n"));
+        assert!(content.ends_with("\n\\end{gather*}"));
+    }
+
+    #[test]
+    fn test_export_tex_smart_environments_keeps_single_line_in_dollars() {
+        let records = vec![
+            make_record("2025-01-01T00:00:00Z", r"E = mc^2", None),
+            make_record("2025-01-02T00:00:00Z", r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}", None),
+        ];
+        let options = TexExportOptions {
+            smart_environments: true,
+            ..Default::default()
+        };
+
+        let content = String::from_utf8(export_tex(&records, &options).expect("export should succeed"))
+            .expect("should be valid UTF-8");
+
+        assert_eq!(
+            content,
+            "$$E = mc^2$$\n\n$$\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}$$"
+        );
+    }
+
+    #[test]
+    fn test_export_tex_delimiter_variants() {
+        let records = vec![
+            make_record("2025-01-02T00:00:00Z", r"a + b", None),
+            make_record("2025-01-01T00:00:00Z", r" E = mc^2 ", None),
+        ];
+        let export_with = |delimiter| {
+            let options = TexExportOptions { delimiter, ..Default::default() };
+            String::from_utf8(export_tex(&records, &options).expect("export should succeed")).unwrap()
+        };
+
+        assert_eq!(export_with(TexDelimiter::DoubleDollar), "$$ E = mc^2 $$\n\n$$a + b$$");
+        assert_eq!(export_with(TexDelimiter::BracketDisplay), "\\[ E = mc^2 \\]\n\n\\[a + b\\]");
+        assert_eq!(
+            export_with(TexDelimiter::EquationEnv),
+            "\\begin{equation}\nE = mc^2\n\\end{equation}\n\n\\begin{equation}\na + b\n\\end{equation}"
+        );
+        // The default stays byte-identical to the old hard-coded `$$` output
+        assert_eq!(
+            export_tex(&records, &TexExportOptions::default()).unwrap(),
+            export_with(TexDelimiter::DoubleDollar).into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_tex_delimiter_deserializes_snake_case_with_default() {
+        let options: TexExportOptions =
+            serde_json::from_str(r#"{"add_time_comments":false,"delimiter":"equation_env"}"#).unwrap();
+        assert_eq!(options.delimiter, TexDelimiter::EquationEnv);
+        let options: TexExportOptions = serde_json::from_str(r#"{"add_time_comments":false}"#).unwrap();
+        assert_eq!(options.delimiter, TexDelimiter::DoubleDollar);
+    }
+
+    #[test]
+    fn test_export_tex_streamed_reports_progress_and_matches_export_tex() {
+        let records: Vec<HistoryRecord> = (1..=4)
+            .map(|i| make_record(&format!("2025-01-0{}T00:00:00Z", i), &format!("x^{}", i), None))
+            .collect();
+        let options = TexExportOptions::default();
+        let mut events = Vec::new();
+
+        let result = export_tex_streamed(&records, &options, &ExportCancellation::default(), |p| events.push(p))
+            .expect("export should succeed");
+
+        assert_eq!(
+            result,
+            StreamedExport::Completed { output: export_tex(&records, &options).unwrap() }
+        );
+        let expected: Vec<ExportProgress> = (1..=4).map(|done| ExportProgress { done, total: 4 }).collect();
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn test_export_streamed_cancellation_stops_processing() {
+        let records: Vec<HistoryRecord> = (1..=5)
+            .map(|i| make_record(&format!("2025-01-0{}T00:00:00Z", i), &format!("x^{}", i), None))
+            .collect();
+
+        // The emitter cancels after the second record, like a UI cancel button would
+        let cancel = ExportCancellation::default();
+        let mut events = Vec::new();
+        let result = export_tex_streamed(&records, &TexExportOptions::default(), &cancel, |p| {
+            events.push(p);
+            if p.done == 2 {
+                cancel.cancel();
+            }
+        })
+        .expect("cancellation is not an error");
+        assert_eq!(result, StreamedExport::Cancelled { done: 2, total: 5 });
+        assert_eq!(events.len(), 2);
+
+        let cancel = ExportCancellation::default();
+        let mut events = Vec::new();
+        let result = export_docx_streamed(&records, &DocxExportOptions::default(), &cancel, |p| {
+            events.push(p);
+            if p.done == 3 {
+                cancel.cancel();
+            }
+        })
+        .expect("cancellation is not an error");
+        assert_eq!(result, StreamedExport::Cancelled { done: 3, total: 5 });
+        assert_eq!(events.last(), Some(&ExportProgress { done: 3, total: 5 }));
+
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(json, r#"{"status":"cancelled","done":3,"total":5}"#);
+    }
+
+    #[test]
+    fn test_export_registry_cancels_only_the_given_export() {
+        let registry = ExportRegistry::default();
+        let first = registry.start();
+        let second = registry.start();
+        assert_ne!(first, second);
+
+        assert!(registry.cancel(first));
+        assert!(registry.flag(first).unwrap().is_cancelled());
+        assert!(!registry.flag(second).unwrap().is_cancelled(), "other exports keep running");
+
+        // A finished export's token no longer cancels anything, and is never reused
+        registry.finish(first);
+        assert!(registry.flag(first).is_none());
+        assert!(!registry.cancel(first));
+        let third = registry.start();
+        assert!(third != first && !registry.flag(third).unwrap().is_cancelled());
+    }
+
     #[test]
     fn test_effective_latex_prefers_edited() {
         let record = make_record("2025-01-01T00:00:00Z", "original", Some("edited"));
@@ -403,6 +1506,424 @@ mod tests {
         assert_eq!(effective_latex(&record), "original");
     }
 
+    // -----------------------------------------------------------------------
+    // Single formula export tests
+    // -----------------------------------------------------------------------
+
+    /// Helper: a real 8x4 PNG to stand in for a thumbnail.
+    fn png_thumbnail() -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(8, 4, image::Rgba([0, 0, 0, 255]));
+        let mut buf = Cursor::new(Vec::new());
+        img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_export_png_sheet_stacks_formulas_with_padding() {
+        let thumbnail = |w: u32, h: u32| {
+            let img = image::RgbaImage::from_pixel(w, h, image::Rgba([0, 0, 0, 255]));
+            let mut buf = Cursor::new(Vec::new());
+            img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+            buf.into_inner()
+        };
+        let mut records: Vec<HistoryRecord> = [(100, 30), (60, 40), (80, 20)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| {
+                let mut record = make_record(&format!("2025-01-0{}T00:00:00Z", i + 1), "x", None);
+                record.thumbnail = Some(thumbnail(w, h));
+                record
+            })
+            .collect();
+        // No thumbnail: rendered as a placeholder box
+        records.push(make_record("2025-01-04T00:00:00Z", "y", None));
+        let options = PngSheetOptions { padding: 10, ..Default::default() };
+
+        let sheet = image::load_from_memory(&export_png_sheet(&records, &options).unwrap()).unwrap();
+
+        let (pw, ph) = SHEET_PLACEHOLDER_SIZE;
+        assert_eq!(sheet.width(), pw.max(100) + 20);
+        assert_eq!(sheet.height(), 30 + 40 + 20 + ph + 5 * 10);
+        assert!(sheet.height() > 40, "sheet should be taller than any single formula");
+        let sheet = sheet.to_rgba8();
+        // White padding above the first formula, which is centered
+        assert_eq!(sheet.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        let first_x = (sheet.width() - 100) / 2;
+        assert_eq!(sheet.get_pixel(first_x, 10).0, [0, 0, 0, 255]);
+        assert_eq!(sheet.get_pixel(first_x, 9).0, [255, 255, 255, 255]);
+        // Placeholder outline at the bottom
+        let placeholder_y = 10 + 30 + 10 + 40 + 10 + 20 + 10;
+        assert_eq!(sheet.get_pixel(sheet.width() / 2, placeholder_y).0, [0xC0, 0xC0, 0xC0, 255]);
+
+        assert!(matches!(export_png_sheet(&[], &options), Err(ExportError::ExportFailed(_))));
+    }
+
+    #[test]
+    fn test_export_png_sheet_decodes_jpeg_thumbnails() {
+        let mut record = make_record("2025-01-01T00:00:00Z", "x", None);
+        record.thumbnail = Some(
+            crate::preprocess::reencode(&png_thumbnail(), crate::preprocess::OutputFormat::Jpeg { quality: 90 })
+                .unwrap(),
+        );
+        let options = PngSheetOptions { padding: 10, ..Default::default() };
+
+        let sheet = image::load_from_memory(&export_png_sheet(&[record.clone()], &options).unwrap()).unwrap();
+        assert_eq!((sheet.width(), sheet.height()), (8 + 20, 4 + 20), "no placeholder for a JPEG thumbnail");
+        let [r, g, b, _] = sheet.to_rgba8().get_pixel(12, 11).0;
+        assert!(r < 32 && g < 32 && b < 32, "formula pixels should be dark, got {:?}", (r, g, b));
+
+        // A stored thumbnail that is not an image is an error, not a placeholder
+        record.thumbnail = Some(b"not an image".to_vec());
+        assert!(matches!(export_png_sheet(&[record], &options), Err(ExportError::ExportFailed(_))));
+    }
+
+    #[test]
+    fn test_export_one_text_formats() {
+        let record = make_record("2025-01-01T00:00:00Z", r"x^2", Some(r"\frac{a}{b}"));
+
+        let latex = export_one(&record, SingleExportFormat::Latex).unwrap();
+        assert_eq!(latex, br"\frac{a}{b}".to_vec());
+
+        let mathml = String::from_utf8(export_one(&record, SingleExportFormat::Mathml).unwrap()).unwrap();
+        assert!(mathml.starts_with("<math"), "got: {}", mathml);
+        assert!(mathml.contains("<mfrac>"));
+
+        let omml = String::from_utf8(export_one(&record, SingleExportFormat::Omml).unwrap()).unwrap();
+        assert!(omml.starts_with("<m:oMathPara"), "got: {}", omml);
+        assert!(omml.contains("<m:f>"));
+    }
+
+    #[test]
+    fn test_export_one_svg_embeds_mathml_at_thumbnail_size() {
+        let mut record = make_record("2025-01-01T00:00:00Z", r"x^2", None);
+        record.thumbnail = Some(png_thumbnail());
+
+        let svg = String::from_utf8(export_one(&record, SingleExportFormat::Svg).unwrap()).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""), "got: {}", svg);
+        assert!(svg.contains(r#"width="8" height="4""#), "got: {}", svg);
+        assert!(svg.contains("<foreignObject") && svg.contains("<msup>"), "got: {}", svg);
+        assert!(svg.contains("<title>x^2</title>"));
+    }
+
+    #[test]
+    fn test_export_one_png_returns_thumbnail() {
+        let mut record = make_record("2025-01-01T00:00:00Z", r"x^2", None);
+        record.thumbnail = Some(png_thumbnail());
+        let png = export_one(&record, SingleExportFormat::Png).unwrap();
+        assert_eq!(&png[0..4], &[0x89, 0x50, 0x4E, 0x47]);
+
+        record.thumbnail = None;
+        assert!(matches!(
+            export_one(&record, SingleExportFormat::Png),
+            Err(ExportError::ExportFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_one_png_converts_jpeg_thumbnail() {
+        let mut record = make_record("2025-01-01T00:00:00Z", r"x^2", None);
+        let jpeg = crate::preprocess::reencode(&png_thumbnail(), crate::preprocess::OutputFormat::Jpeg { quality: 90 })
+            .unwrap();
+        record.thumbnail = Some(jpeg);
+
+        let png = export_one(&record, SingleExportFormat::Png).expect("JPEG thumbnail should export");
+        assert_eq!(image::guess_format(&png).unwrap(), image::ImageFormat::Png);
+        let needle = [PNG_LATEX_KEYWORD.as_bytes(), &[0, 0, 0, 0, 0], br"x^2"].concat();
+        assert!(png.windows(needle.len()).any(|w| w == needle.as_slice()));
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (8, 4));
+    }
+
+    #[test]
+    fn test_export_one_uses_edited_latex_in_every_format() {
+        let mut record = make_record("2025-01-01T00:00:00Z", r"\alpha", Some(r"\beta"));
+        record.thumbnail = Some(png_thumbnail());
+        for format in [
+            SingleExportFormat::Latex,
+            SingleExportFormat::Mathml,
+            SingleExportFormat::Omml,
+            SingleExportFormat::Svg,
+            SingleExportFormat::Png,
+        ] {
+            let bytes = export_one(&record, format).unwrap();
+            let text = String::from_utf8_lossy(&bytes);
+            assert!(text.contains('β') || text.contains(r"\beta"), "{:?} should use the edit", format);
+            assert!(!text.contains('α') && !text.contains(r"\alpha"), "{:?} used the original", format);
+        }
+
+        // The PNG carries the LaTeX it stands for as metadata
+        let png = export_one(&record, SingleExportFormat::Png).unwrap();
+        let needle = [PNG_LATEX_KEYWORD.as_bytes(), &[0, 0, 0, 0, 0], br"\beta"].concat();
+        assert!(png.windows(needle.len()).any(|w| w == needle.as_slice()));
+        // ... and the engine that recognized it
+        let needle = [crate::preprocess::ENGINE_VERSION_KEYWORD.as_bytes(), b"\0pix2tex-v1"].concat();
+        assert!(png.windows(needle.len()).any(|w| w == needle.as_slice()));
+        assert!(image::load_from_memory(&png).is_ok());
+
+        record.engine_version.clear();
+        let png = export_one(&record, SingleExportFormat::Png).unwrap();
+        let keyword = [crate::preprocess::ENGINE_VERSION_KEYWORD.as_bytes(), b"\0"].concat();
+        assert!(!png.windows(keyword.len()).any(|w| w == keyword.as_slice()));
+    }
+
+    #[test]
+    fn test_export_one_svg_dark_scheme_uses_light_glyphs() {
+        let record = make_record("2025-01-01T00:00:00Z", r"x^2", None);
+        let svg = |scheme| String::from_utf8(export_one_with_scheme(&record, SingleExportFormat::Svg, scheme).unwrap()).unwrap();
+
+        let dark = svg(ColorScheme::Dark);
+        assert!(dark.contains(r##"<rect width="100%" height="100%" fill="#1e1e1e"/>"##), "got: {}", dark);
+        assert!(dark.contains(r##"<g fill="#e6e6e6" color="#e6e6e6">"##), "got: {}", dark);
+
+        let transparent = svg(ColorScheme::Transparent);
+        assert!(!transparent.contains("<rect"), "got: {}", transparent);
+        assert!(transparent.contains(r##"fill="#000000""##));
+
+        assert!(svg(ColorScheme::Light).contains(r##"fill="#ffffff""##));
+    }
+
+    #[test]
+    fn test_export_one_png_dark_scheme_recolors_thumbnail() {
+        // Black ink on the left half, white paper on the right
+        let img = image::RgbaImage::from_fn(8, 4, |x, _| {
+            if x < 4 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) }
+        });
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let mut record = make_record("2025-01-01T00:00:00Z", r"x^2", None);
+        record.thumbnail = Some(png.into_inner());
+
+        let dark = export_one_with_scheme(&record, SingleExportFormat::Png, ColorScheme::Dark).unwrap();
+        let dark = image::load_from_memory(&dark).unwrap().to_rgba8();
+        assert_eq!(dark.get_pixel(0, 0).0, [0xE6, 0xE6, 0xE6, 255], "glyphs should be light");
+        assert_eq!(dark.get_pixel(7, 0).0, [0x1E, 0x1E, 0x1E, 255], "background should be dark");
+
+        let clear = export_one_with_scheme(&record, SingleExportFormat::Png, ColorScheme::Transparent).unwrap();
+        let clear = image::load_from_memory(&clear).unwrap().to_rgba8();
+        assert_eq!(clear.get_pixel(0, 0).0[3], 255);
+        assert_eq!(clear.get_pixel(7, 0).0[3], 0, "paper should become transparent");
+
+        let light = export_one_with_scheme(&record, SingleExportFormat::Png, ColorScheme::Light).unwrap();
+        assert_eq!(
+            image::load_from_memory(&light).unwrap().to_rgba8(),
+            img,
+            "light scheme keeps the thumbnail's pixels"
+        );
+    }
+
+    #[test]
+    fn test_export_one_conversion_failure() {
+        let record = make_record("2025-01-01T00:00:00Z", r"\invalidcommandthatwillfail{{{", None);
+        assert!(matches!(
+            export_one(&record, SingleExportFormat::Omml),
+            Err(ExportError::ConvertFailed(_))
+        ));
+    }
+
+    // -----------------------------------------------------------------------
+    // Markdown export tests
+    // -----------------------------------------------------------------------
+
+    fn export_markdown_string(records: &[HistoryRecord], delimiter: MarkdownMathDelimiter) -> String {
+        let options = MarkdownExportOptions { delimiter, ..Default::default() };
+        let bytes = export_markdown(records, &options).expect("export should succeed");
+        String::from_utf8(bytes).expect("should be valid UTF-8")
+    }
+
+    #[test]
+    fn test_export_markdown_delimiter_styles() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x^2", None)];
+        assert_eq!(export_markdown_string(&records, MarkdownMathDelimiter::Dollars), r"$$x^2$$");
+        assert_eq!(export_markdown_string(&records, MarkdownMathDelimiter::Brackets), r"\[x^2\]");
+        assert_eq!(export_markdown_string(&records, MarkdownMathDelimiter::MathFence), "```math\nx^2\n```");
+    }
+
+    #[test]
+    fn test_export_markdown_sorted_with_notes() {
+        let records = vec![
+            make_record("2025-01-02T00:00:00Z", r"\alpha", Some(r"\beta")),
+            noted_record("2025-01-01T00:00:00Z", r"E = mc^2", "Mass-energy"),
+        ];
+        assert_eq!(
+            export_markdown_string(&records, MarkdownMathDelimiter::default()),
+            "Mass-energy\n\n$$E = mc^2$$\n\n$$\\beta$$"
+        );
+        assert_eq!(export_markdown_string(&[], MarkdownMathDelimiter::default()), "");
+    }
+
+    #[test]
+    fn test_export_markdown_headings_follow_chronological_order() {
+        let records = vec![
+            make_record("2025-01-02T00:00:00Z", r"\beta", None),
+            noted_record("2025-01-01T00:00:00Z", r"\alpha", "First"),
+        ];
+        let options = MarkdownExportOptions { include_heading: true, ..Default::default() };
+        let output = String::from_utf8(export_markdown(&records, &options).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "## 2025-01-01T00:00:00Z\n\n*置信度 95%*\n\nFirst\n\n$$\\alpha$$\n\n\
+             ## 2025-01-02T00:00:00Z\n\n*置信度 95%*\n\n$$\\beta$$"
+        );
+
+        // Headings are off by default
+        let plain = export_markdown_string(&records, MarkdownMathDelimiter::default());
+        assert!(!plain.contains("##"), "got: {}", plain);
+    }
+
+    #[test]
+    fn test_markdown_delimiter_deserializes_snake_case() {
+        let options: MarkdownExportOptions = serde_json::from_str(r#"{"delimiter":"math_fence"}"#).unwrap();
+        assert_eq!(options.delimiter, MarkdownMathDelimiter::MathFence);
+        let options: MarkdownExportOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options.delimiter, MarkdownMathDelimiter::Dollars);
+    }
+
+    // -----------------------------------------------------------------------
+    // HTML export tests
+    // -----------------------------------------------------------------------
+
+    fn export_html_string(records: &[HistoryRecord], include_latex_attribute: bool) -> String {
+        let bytes = export_html(records, &HtmlExportOptions { include_latex_attribute }).expect("export should succeed");
+        String::from_utf8(bytes).expect("should be valid UTF-8")
+    }
+
+    #[test]
+    fn test_export_html_math_carries_escaped_data_latex() {
+        let records = vec![
+            make_record("2025-01-02T00:00:00Z", r"a < b", None),
+            noted_record("2025-01-01T00:00:00Z", r#"\begin{aligned} x &= \text{"y"} \end{aligned}"#, "Note & more"),
+        ];
+        let html = export_html_string(&records, true);
+
+        assert_eq!(html.matches("<math ").count(), 2, "{}", html);
+        assert_eq!(html.matches("<math data-latex=\"").count(), 2, "{}", html);
+        assert!(html.contains(r#"<math data-latex="\begin{aligned} x &amp;= \text{&quot;y&quot;} \end{aligned}" xmlns="#), "{}", html);
+        assert!(html.contains(r#"<math data-latex="a &lt; b" xmlns="#), "{}", html);
+        assert!(html.contains("<p>Note &amp; more</p>"));
+        // Sorted by created_at: the aligned formula comes first
+        assert!(html.find("aligned").unwrap() < html.find("a &lt; b").unwrap());
+    }
+
+    #[test]
+    fn test_export_html_without_attribute_and_fallback() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x^2", None)];
+        let html = export_html_string(&records, false);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<article>\n<math xmlns="), "{}", html);
+        assert!(html.contains("</math>\n</article>"), "{}", html);
+        assert!(!html.contains("data-latex"));
+
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"\frac{a}{b", None)];
+        let html = export_html_string(&records, true);
+        assert!(html.contains(r#"<code class="转换失败">\frac{a}{b</code>"#), "{}", html);
+        assert!(!html.contains("<math"), "{}", html);
+    }
+
+    // -----------------------------------------------------------------------
+    // Tag zip export tests
+    // -----------------------------------------------------------------------
+
+    fn tagged_records() -> (Vec<HistoryRecord>, HashMap<i64, Vec<String>>) {
+        let mut records = vec![
+            make_record("2025-01-01T00:00:00Z", "a^2", None),
+            make_record("2025-01-02T00:00:00Z", "b^2", None),
+            make_record("2025-01-03T00:00:00Z", "c^2", None),
+            make_record("2025-01-04T00:00:00Z", "d^2", None),
+        ];
+        for (i, record) in records.iter_mut().enumerate() {
+            record.id = Some(i as i64 + 1);
+        }
+        let tags = HashMap::from([
+            (1, vec!["algebra".to_string()]),
+            (2, vec!["physics".to_string()]),
+            (3, vec!["algebra".to_string(), "physics".to_string()]),
+        ]);
+        (records, tags)
+    }
+
+    #[test]
+    fn test_export_by_tag_zip_one_file_per_tag() {
+        let (records, tags) = tagged_records();
+        let zip = export_by_tag_zip(&records, &tags, &TagExportFilter::default()).expect("export should succeed");
+
+        let mut names = zip_file_names(&zip);
+        names.sort();
+        assert_eq!(names, vec!["algebra.tex", "physics.tex", "untagged.tex"]);
+        assert_eq!(read_zip_entry(&zip, "algebra.tex").unwrap(), "$$a^2$$\n\n$$c^2$$");
+        assert_eq!(read_zip_entry(&zip, "physics.tex").unwrap(), "$$b^2$$\n\n$$c^2$$");
+        assert_eq!(read_zip_entry(&zip, "untagged.tex").unwrap(), "$$d^2$$");
+    }
+
+    #[test]
+    fn test_export_by_tag_zip_filter_and_markdown() {
+        let (records, tags) = tagged_records();
+        let filter = TagExportFilter {
+            format: TagExportFormat::Markdown,
+            tags: Some(vec!["physics".to_string()]),
+        };
+        let zip = export_by_tag_zip(&records, &tags, &filter).expect("export should succeed");
+        assert_eq!(zip_file_names(&zip), vec!["physics.md"]);
+        assert_eq!(read_zip_entry(&zip, "physics.md").unwrap(), "$$b^2$$\n\n$$c^2$$");
+    }
+
+    #[test]
+    fn test_tag_file_stem_replaces_reserved_characters() {
+        assert_eq!(tag_file_stem(" a/b:c "), "a_b_c");
+        assert_eq!(tag_file_stem("线性代数"), "线性代数");
+    }
+
+    #[test]
+    fn test_export_by_tag_zip_keeps_colliding_tags_apart() {
+        let (records, _) = tagged_records();
+        let tags = HashMap::from([
+            (1, vec!["a/b".to_string()]),
+            (2, vec!["a:b".to_string()]),
+            (3, vec!["A_B".to_string(), "Untagged".to_string()]),
+        ]);
+        let zip = export_by_tag_zip(&records, &tags, &TagExportFilter::default()).expect("export should succeed");
+
+        let mut names = zip_file_names(&zip);
+        names.sort();
+        assert_eq!(names, vec!["A_B.tex", "Untagged_2.tex", "a_b_2.tex", "a_b_3.tex", "untagged.tex"]);
+        assert_eq!(read_zip_entry(&zip, "A_B.tex").unwrap(), "$$c^2$$");
+        assert_eq!(read_zip_entry(&zip, "a_b_2.tex").unwrap(), "$$a^2$$");
+        assert_eq!(read_zip_entry(&zip, "a_b_3.tex").unwrap(), "$$b^2$$");
+        assert_eq!(read_zip_entry(&zip, "untagged.tex").unwrap(), "$$d^2$$");
+    }
+
+    // -----------------------------------------------------------------------
+    // .ipynb export tests
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn test_export_ipynb_has_one_markdown_cell_per_record() {
+        let records = vec![
+            make_record("2025-01-01T00:00:00Z", r"x^2", None),
+            make_record("2025-01-02T00:00:00Z", r"\alpha", Some(r"\frac{\alpha}{2}")),
+        ];
+
+        let bytes = export_ipynb(&records).expect("export should succeed");
+        let notebook: serde_json::Value = serde_json::from_slice(&bytes).expect("output should be JSON");
+
+        assert_eq!(notebook["nbformat"], 4);
+        assert!(notebook["metadata"].is_object());
+        let cells = notebook["cells"].as_array().expect("cells should be an array");
+        assert_eq!(cells.len(), 2);
+        for cell in cells {
+            assert_eq!(cell["cell_type"], "markdown");
+            assert!(cell["metadata"].is_object());
+        }
+        assert_eq!(cells[0]["source"], r"$$x^2$$");
+        assert_eq!(cells[1]["source"], r"$$\frac{\alpha}{2}$$");
+    }
+
+    #[test]
+    fn test_export_ipynb_empty_records() {
+        let bytes = export_ipynb(&[]).expect("export should succeed");
+        let notebook: serde_json::Value = serde_json::from_slice(&bytes).expect("output should be JSON");
+        assert_eq!(notebook["cells"], serde_json::json!([]));
+    }
+
     // -----------------------------------------------------------------------
     // .docx export tests
     // -----------------------------------------------------------------------
@@ -592,6 +2113,122 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_export_docx_with_report_statuses_align_with_records() {
+        let mut records = vec![
+            make_record("2025-01-01T00:00:00Z", r"x^2", None),                          // should succeed
+            make_record("2025-01-02T00:00:00Z", r"\invalidcommandthatwillfail{{{", None), // should fail
+            make_record("2025-01-03T00:00:00Z", r"\alpha", None),                         // should succeed
+        ];
+        for (i, record) in records.iter_mut().enumerate() {
+            record.id = Some(i as i64 + 10);
+        }
+
+        let (docx, statuses) = export_docx_with_report(&records).expect("export should succeed");
+        assert_eq!(statuses.len(), records.len());
+        assert_eq!(statuses[0], ExportRecordStatus::Succeeded { id: Some(10) });
+        match &statuses[1] {
+            ExportRecordStatus::Failed { id, error } => {
+                assert_eq!(*id, Some(11));
+                assert!(!error.is_empty(), "failure should carry the conversion error");
+            }
+            other => panic!("expected Failed, got: {:?}", other),
+        }
+        assert_eq!(statuses[2], ExportRecordStatus::Succeeded { id: Some(12) });
+
+        // The document itself is identical to export_docx
+        let doc_xml = read_zip_entry(&docx, "word/document.xml").expect("document.xml should exist");
+        assert_eq!(doc_xml.matches("<w:p>").count(), 3);
+        assert_eq!(doc_xml.matches("转换失败").count(), 1);
+    }
+
+    #[test]
+    fn test_export_docx_with_report_empty_records() {
+        let (_, statuses) = export_docx_with_report(&[]).expect("export should succeed");
+        assert!(statuses.is_empty());
+    }
+
+    #[test]
+    fn test_export_record_status_serialization() {
+        let json = serde_json::to_string(&ExportRecordStatus::Failed {
+            id: Some(3),
+            error: "boom".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"status":"failed","id":3,"error":"boom"}"#);
+        let json = serde_json::to_string(&ExportRecordStatus::Succeeded { id: None }).unwrap();
+        assert_eq!(json, r#"{"status":"succeeded","id":null}"#);
+    }
+
+    #[test]
+    fn test_export_docx_latex_annotation_contains_source() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"\frac{1}{2} x^2", None)];
+        let options = DocxExportOptions {
+            include_latex_annotation: true,
+            ..Default::default()
+        };
+
+        let (docx, _) = export_docx_with_options(&records, &options).expect("export should succeed");
+        let doc_xml = read_zip_entry(&docx, "word/document.xml").expect("document.xml should exist");
+
+        assert!(doc_xml.contains("<m:oMathPara"), "formula should still be OMML");
+        assert!(
+            doc_xml.contains(r#"<w:rPr><w:vanish/></w:rPr><w:t xml:space="preserve">LaTeX: \frac{1}{2} x^2</w:t>"#),
+            "annotation should carry the source LaTeX: {}",
+            doc_xml
+        );
+    }
+
+    #[test]
+    fn test_export_docx_omits_latex_annotation_by_default() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x^2", None)];
+        let docx = export_docx(&records).expect("export should succeed");
+        let doc_xml = read_zip_entry(&docx, "word/document.xml").expect("document.xml should exist");
+        assert!(!doc_xml.contains("<w:vanish/>"));
+    }
+
+    #[test]
+    fn test_export_docx_math_settings_declares_cambria_math() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x^2", None)];
+        let options = DocxExportOptions {
+            include_math_settings: true,
+            ..Default::default()
+        };
+
+        let (docx, _) = export_docx_with_options(&records, &options).expect("export should succeed");
+        let settings = read_zip_entry(&docx, "word/settings.xml").expect("settings.xml should exist");
+        assert!(settings.contains(r#"<m:mathFont m:val="Cambria Math"/>"#), "got: {}", settings);
+
+        let rels = read_zip_entry(&docx, "word/_rels/document.xml.rels").unwrap();
+        assert!(rels.contains(r#"Target="settings.xml""#), "got: {}", rels);
+        let content_types = read_zip_entry(&docx, "[Content_Types].xml").unwrap();
+        assert!(content_types.contains(r#"PartName="/word/settings.xml""#));
+
+        let docx = export_docx(&records).expect("export should succeed");
+        assert!(!zip_file_names(&docx).contains(&"word/settings.xml".to_string()));
+    }
+
+    #[test]
+    fn test_export_docx_inline_math_uses_bare_omath() {
+        let records = vec![make_record("2025-01-01T00:00:00Z", r"x^2", None)];
+        let options = DocxExportOptions {
+            inline_math: true,
+            ..Default::default()
+        };
+
+        let (docx, statuses) = export_docx_with_options(&records, &options).expect("export should succeed");
+        let doc_xml = read_zip_entry(&docx, "word/document.xml").expect("document.xml should exist");
+        assert!(doc_xml.contains("<w:p><m:oMath xmlns:m="), "got: {}", doc_xml);
+        assert!(!doc_xml.contains("<m:oMathPara"), "got: {}", doc_xml);
+        assert!(matches!(statuses[0], ExportRecordStatus::Succeeded { .. }));
+    }
+
+    #[test]
+    fn test_export_docx_options_default_math_settings_off() {
+        let options: DocxExportOptions = serde_json::from_str(r#"{"include_latex_annotation":true}"#).unwrap();
+        assert!(!options.include_math_settings);
+    }
+
     #[test]
     fn test_xml_escape() {
         assert_eq!(xml_escape("a < b & c > d"), "a &lt; b &amp; c &gt; d");
@@ -600,6 +2237,124 @@ mod tests {
         assert_eq!(xml_escape("plain text"), "plain text");
     }
 
+    // -----------------------------------------------------------------------
+    // Clipboard docx fragment tests
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn test_docx_fragment_contains_one_math_block_per_record() {
+        let records = vec![
+            make_record("2025-01-01T00:00:00Z", r"x^2", None),
+            make_record("2025-01-02T00:00:00Z", r"\frac{a}{b}", None),
+            make_record("2025-01-03T00:00:00Z", r"\alpha + \beta", None),
+        ];
+        let html = build_docx_fragment_html(&records);
+
+        assert_eq!(html.matches("<p>").count(), 3);
+        assert_eq!(html.matches("<m:oMathPara").count(), 3);
+        assert_eq!(html.matches("<!--[if gte msEquation 12]>").count(), 3);
+    }
+
+    #[test]
+    fn test_docx_fragment_preserves_record_order() {
+        let records = vec![
+            make_record("2025-06-01T00:00:00Z", r"\gamma", None),
+            make_record("2025-01-01T00:00:00Z", r"\alpha", None),
+        ];
+        let html = build_docx_fragment_html(&records);
+
+        let gamma = html.find("γ").expect("gamma should be present");
+        let alpha = html.find("α").expect("alpha should be present");
+        assert!(gamma < alpha, "fragment should follow the given order, not created_at");
+    }
+
+    #[test]
+    fn test_docx_fragment_failed_conversion_falls_back_to_text() {
+        let records = vec![
+            make_record("2025-01-01T00:00:00Z", r"x^2", None),
+            make_record("2025-01-02T00:00:00Z", r"\invalidcommandthatwillfail{{{", None),
+        ];
+        let html = build_docx_fragment_html(&records);
+
+        assert_eq!(html.matches("<p>").count(), 2);
+        assert_eq!(html.matches("<m:oMathPara").count(), 1);
+        assert!(html.contains("转换失败"));
+    }
+
+    #[test]
+    fn test_docx_fragment_empty_records() {
+        assert_eq!(build_docx_fragment_html(&[]), "");
+    }
+
+    // -----------------------------------------------------------------------
+    // Note tests
+    // -----------------------------------------------------------------------
+
+    fn noted_record(created_at: &str, latex: &str, note: &str) -> HistoryRecord {
+        HistoryRecord {
+            note: Some(note.to_string()),
+            ..make_record(created_at, latex, None)
+        }
+    }
+
+    #[test]
+    fn test_export_tex_note_precedes_formula() {
+        let records = vec![
+            noted_record("2025-01-01T00:00:00Z", r"a^2 + b^2 = c^2", "勾股定理 100% & more"),
+            make_record("2025-01-02T00:00:00Z", r"E = mc^2", None),
+        ];
+        let options = TexExportOptions {
+            add_time_comments: true,
+            ..Default::default()
+        };
+
+        let content = String::from_utf8(export_tex(&records, &options).expect("export should succeed"))
+            .expect("should be valid UTF-8");
+
+        assert_eq!(
+            content,
+            "% [2025-01-01T00:00:00Z]\n勾股定理 100\\% \\& more\n$$a^2 + b^2 = c^2$$\n\n% [2025-01-02T00:00:00Z]\n$$E = mc^2$$"
+        );
+    }
+
+    #[test]
+    fn test_export_tex_blank_note_is_skipped() {
+        let records = vec![noted_record("2025-01-01T00:00:00Z", r"x", "   ")];
+        let options = TexExportOptions {
+            add_time_comments: false,
+            ..Default::default()
+        };
+
+        let content = String::from_utf8(export_tex(&records, &options).expect("export should succeed"))
+            .expect("should be valid UTF-8");
+        assert_eq!(content, "$$x$$");
+    }
+
+    #[test]
+    fn test_export_docx_note_paragraph_precedes_formula() {
+        let records = vec![
+            make_record("2025-01-01T00:00:00Z", r"x^2", None),
+            noted_record("2025-01-02T00:00:00Z", r"\alpha", "Note <1>"),
+        ];
+
+        let data = export_docx(&records).expect("export should succeed");
+        let xml = read_zip_entry(&data, "word/document.xml").expect("document.xml should exist");
+
+        let note = xml.find("Note &lt;1&gt;").expect("escaped note should be present");
+        let first = xml.find("<m:t>x</m:t>").expect("first formula should be present");
+        let alpha = xml.find("α").expect("second formula should be present");
+        assert!(first < note && note < alpha, "note should sit directly before its formula");
+    }
+
+    #[test]
+    fn test_export_ipynb_note_precedes_formula() {
+        let records = vec![noted_record("2025-01-01T00:00:00Z", r"x^2", "Quadratic term")];
+
+        let bytes = export_ipynb(&records).expect("export should succeed");
+        let notebook: serde_json::Value = serde_json::from_slice(&bytes).expect("output should be JSON");
+        assert_eq!(notebook["cells"][0]["source"], "Quadratic term\n\n$$x^2$$");
+    }
+
     // -----------------------------------------------------------------------
     // Property-Based Tests (proptest)
     // -----------------------------------------------------------------------
@@ -644,6 +2399,8 @@ mod tests {
                 engine_version: "pix2tex-v1".to_string(),
                 thumbnail: None,
                 is_favorite: false,
+                note: None,
+                needs_review: false,
             },
         )
     }
@@ -665,7 +2422,7 @@ mod tests {
             records in proptest::collection::vec(arb_history_record(), 1..10),
             add_time_comments in proptest::bool::ANY,
         ) {
-            let options = TexExportOptions { add_time_comments };
+            let options = TexExportOptions { add_time_comments, ..Default::default() };
             let result = export_tex(&records, &options).expect("export should succeed");
             let content = String::from_utf8(result).expect("should be valid UTF-8");
 
@@ -10,10 +10,16 @@ pub mod ocr;
 pub mod preprocess;
 
 use capture::CaptureRegion;
+use convert::ConvertDisplay;
 use history::HistoryRecord;
-use ocr::OcrResult;
-use export::TexExportOptions;
-use tauri::Manager;
+use ocr::{OcrResult, RecognitionMode, RegionRecognition, SymbolRule, TempImageOptions};
+use preprocess::PreprocessOptions;
+use export::{
+    ColorScheme, DocxExportOptions, ExportRecordStatus, ExportRegistry, HtmlExportOptions,
+    MarkdownExportOptions, PngSheetOptions, SingleExportFormat, StreamedExport, TagExportFilter,
+    TexExportOptions,
+};
+use tauri::{Emitter, Manager};
 
 // ============================================================
 // Tauri Commands
@@ -32,6 +38,43 @@ async fn capture_screen_region(region: CaptureRegion) -> Result<Vec<u8>, String>
     service.capture_region(&region).map_err(|e| e.to_string())
 }
 
+/// Capture a small live preview of the region being selected.
+/// Called on mouse-move by the CaptureOverlay; throttled by the shared CaptureService.
+#[tauri::command]
+async fn capture_preview(
+    region: CaptureRegion,
+    max_dim: u32,
+    service: tauri::State<'_, capture::CaptureService>,
+) -> Result<Vec<u8>, String> {
+    service.capture_preview(&region, max_dim).map_err(|e| e.to_string())
+}
+
+/// 检测快捷键是否可用：临时注册后立即注销
+#[tauri::command]
+async fn test_shortcut(shortcut: String, app_handle: tauri::AppHandle) -> capture::ShortcutTestResult {
+    capture::test_shortcut(&shortcut, &PluginShortcutRegistrar(&app_handle))
+}
+
+/// `ShortcutRegistrar` backed by the global-shortcut plugin.
+struct PluginShortcutRegistrar<'a>(&'a tauri::AppHandle);
+
+impl capture::ShortcutRegistrar for PluginShortcutRegistrar<'_> {
+    fn register(&self, shortcut: &str) -> Result<(), String> {
+        use tauri_plugin_global_shortcut::GlobalShortcutExt;
+        let shortcuts = self.0.global_shortcut();
+        // Already bound by this app: registering again would only report our own binding
+        if shortcuts.is_registered(shortcut) {
+            return Err("已被本应用注册".to_string());
+        }
+        shortcuts.register(shortcut).map_err(|e| e.to_string())
+    }
+
+    fn unregister(&self, shortcut: &str) -> Result<(), String> {
+        use tauri_plugin_global_shortcut::GlobalShortcutExt;
+        self.0.global_shortcut().unregister(shortcut).map_err(|e| e.to_string())
+    }
+}
+
 /// Cancel the current capture operation (called when user presses Escape).
 #[tauri::command]
 async fn cancel_capture() -> Result<(), String> {
@@ -43,45 +86,150 @@ async fn cancel_capture() -> Result<(), String> {
 /// 
 /// 优先使用打包的 ocr_engine.exe（PyInstaller 打包），
 /// 回退到 Python 脚本调用。
+/// `image_options` 控制临时图片的格式与 JPEG 质量，缺省为 PNG。
+/// 传入 `symbol_rules` 时按该符号字典修正常见混淆（如 `a x b` → `a \times b`）。
+/// 传入 `preprocess_options` 时先按该参数预处理图片，再写入临时文件交给引擎。
+#[tauri::command]
+async fn recognize_formula(
+    image: Vec<u8>,
+    image_options: Option<TempImageOptions>,
+    symbol_rules: Option<Vec<SymbolRule>>,
+    preprocess_options: Option<PreprocessOptions>,
+    app_handle: tauri::AppHandle,
+) -> Result<OcrResult, String> {
+    let image_options = image_options.unwrap_or_default();
+    let mut result = match preprocess_options {
+        Some(options) => ocr::recognize_image_with(&image, &options, RecognitionMode::Single, |prepared| {
+            run_ocr_engine(prepared, &image_options, &app_handle).map_err(ocr::OcrError::InferenceFailed)
+        })
+        .map_err(|e| e.to_string())?,
+        None => run_ocr_engine(&image, &image_options, &app_handle)?,
+    };
+    if let Some(rules) = symbol_rules {
+        result.latex = ocr::correct_symbols(&result.latex, &rules);
+    }
+    Ok(result)
+}
+
+/// 返回默认符号字典，供前端展示和编辑
+#[tauri::command]
+async fn default_symbol_rules() -> Vec<SymbolRule> {
+    ocr::default_symbol_rules()
+}
+
+/// 截图、预处理并识别指定区域，一次调用同时返回截图 PNG 与识别结果
+///
+/// 截图失败时不会启动 OCR 引擎。
 #[tauri::command]
-async fn recognize_formula(image: Vec<u8>, app_handle: tauri::AppHandle) -> Result<OcrResult, String> {
+async fn recognize_region(
+    region: CaptureRegion,
+    preprocess_options: Option<PreprocessOptions>,
+    recognition_mode: Option<RecognitionMode>,
+    app_handle: tauri::AppHandle,
+) -> Result<RegionRecognition, String> {
+    let service = capture::CaptureService::new();
+    ocr::recognize_region_with(
+        || service.capture_region(&region),
+        &preprocess_options.unwrap_or_default(),
+        recognition_mode.unwrap_or_default(),
+        |image| {
+            run_ocr_engine(image, &TempImageOptions::default(), &app_handle)
+                .map_err(ocr::OcrError::InferenceFailed)
+        },
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// 识别剪贴板中已有的公式图片（如从 PDF 复制的截图），同时返回图片 PNG 与识别结果
+///
+/// 剪贴板中没有图片时返回明确的错误，不会启动 OCR 引擎。
+#[tauri::command]
+async fn recognize_clipboard_image(
+    preprocess_options: Option<PreprocessOptions>,
+    recognition_mode: Option<RecognitionMode>,
+    app_handle: tauri::AppHandle,
+) -> Result<RegionRecognition, String> {
+    ocr::recognize_clipboard_with(
+        clipboard::read_image,
+        &preprocess_options.unwrap_or_default(),
+        recognition_mode.unwrap_or_default(),
+        |image| {
+            run_ocr_engine(image, &TempImageOptions::default(), &app_handle)
+                .map_err(ocr::OcrError::InferenceFailed)
+        },
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// 预处理、识别并转换一张图片，一次返回 LaTeX、MathML、OMML 与置信度
+///
+/// 某种格式转换失败时只在该格式上报告错误，其余结果照常返回。
+#[tauri::command]
+async fn recognize_and_convert(
+    image: Vec<u8>,
+    preprocess_options: Option<PreprocessOptions>,
+    display: Option<ConvertDisplay>,
+    app_handle: tauri::AppHandle,
+) -> Result<ocr::ConvertedRecognition, String> {
+    ocr::recognize_and_convert_with(
+        &image,
+        &preprocess_options.unwrap_or_default(),
+        display.unwrap_or_default(),
+        |image| {
+            run_ocr_engine(image, &TempImageOptions::default(), &app_handle)
+                .map_err(ocr::OcrError::InferenceFailed)
+        },
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// 将图片写入临时文件并调用外部 OCR 引擎
+fn run_ocr_engine(
+    image: &[u8],
+    image_options: &TempImageOptions,
+    app_handle: &tauri::AppHandle,
+) -> Result<OcrResult, String> {
+    run_ocr_engine_with_timeout(image, image_options, app_handle, None)
+}
+
+/// 与 `run_ocr_engine` 相同，但引擎进程超过 `timeout` 时被终止
+fn run_ocr_engine_with_timeout(
+    image: &[u8],
+    image_options: &TempImageOptions,
+    app_handle: &tauri::AppHandle,
+    timeout: Option<std::time::Duration>,
+) -> Result<OcrResult, String> {
     use std::process::Command;
-    use std::io::Write;
 
-    // 将图片写入临时文件
-    let temp_dir = std::env::temp_dir();
-    let temp_path = temp_dir.join("formulasnap_ocr_input.png");
-    
-    {
-        let mut file = std::fs::File::create(&temp_path)
-            .map_err(|e| format!("无法创建临时文件: {}", e))?;
-        file.write_all(&image)
-            .map_err(|e| format!("无法写入临时文件: {}", e))?;
-    }
+    // 将图片写入临时文件（引擎无法读取的格式会回退为 PNG）
+    let temp_path = ocr::write_temp_image(
+        image,
+        &std::env::temp_dir(),
+        image_options,
+        ocr::ENGINE_SUPPORTED_FORMATS,
+    )
+    .map_err(|e| e.to_string())?;
 
     // 获取 OCR 引擎路径
-    let (ocr_cmd, ocr_args) = get_ocr_command(&app_handle, &temp_path)?;
+    let (ocr_cmd, ocr_args) = get_ocr_command(app_handle, &temp_path)?;
 
     // 调用 OCR 引擎（Windows 上隐藏控制台窗口）
+    let mut command = Command::new(&ocr_cmd);
+    command.args(&ocr_args);
     #[cfg(windows)]
-    let output = {
+    {
         use std::os::windows::process::CommandExt;
         const CREATE_NO_WINDOW: u32 = 0x08000000;
-        Command::new(&ocr_cmd)
-            .args(&ocr_args)
-            .creation_flags(CREATE_NO_WINDOW)
-            .output()
-            .map_err(|e| format!("无法启动 OCR 引擎: {}", e))?
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let output = match timeout {
+        Some(timeout) => ocr::run_engine_process(&mut command, timeout).map_err(|e| e.to_string()),
+        None => command.output().map_err(|e| format!("无法启动 OCR 引擎: {}", e)),
     };
-    
-    #[cfg(not(windows))]
-    let output = Command::new(&ocr_cmd)
-        .args(&ocr_args)
-        .output()
-        .map_err(|e| format!("无法启动 OCR 引擎: {}", e))?;
 
     // 清理临时文件
     let _ = std::fs::remove_file(&temp_path);
+    let output = output?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -106,7 +254,22 @@ async fn recognize_formula(image: Vec<u8>, app_handle: tauri::AppHandle) -> Resu
         .and_then(|v| v.as_f64())
         .unwrap_or(0.9);
 
-    Ok(OcrResult { latex, confidence })
+    Ok(OcrResult { latex, confidence }.calibrated(ocr::ENGINE_VERSION))
+}
+
+/// 识别以 base64 data URL（如 `data:image/png;base64,...`）传入的图片
+///
+/// Web 前端直接传递 data URL，这里解码后复用 `recognize_formula`。
+#[tauri::command]
+async fn recognize_data_url(
+    data_url: String,
+    image_options: Option<TempImageOptions>,
+    symbol_rules: Option<Vec<SymbolRule>>,
+    preprocess_options: Option<PreprocessOptions>,
+    app_handle: tauri::AppHandle,
+) -> Result<OcrResult, String> {
+    let image = ocr::decode_data_url(&data_url).map_err(|e| e.to_string())?;
+    recognize_formula(image, image_options, symbol_rules, preprocess_options, app_handle).await
 }
 
 /// 获取 OCR 命令和参数
@@ -194,10 +357,20 @@ fn get_python_path() -> String {
     "python".to_string()
 }
 
+/// LaTeX → OMML，供粘贴到 Word
+///
+/// `display` selects inline (default) or block/display style; `profile`
+/// selects the target Word version's serialization quirks.
 #[tauri::command]
-async fn convert_to_omml(latex: String) -> Result<String, String> {
+async fn convert_to_omml(
+    latex: String,
+    display: Option<ConvertDisplay>,
+    profile: Option<convert::OmmlProfile>,
+) -> Result<String, String> {
     eprintln!("[convert_to_omml] Input LaTeX length: {}", latex.len());
-    match convert::latex_to_omml(&latex) {
+    let omml = convert::latex_to_mathml(&latex, display.unwrap_or_default())
+        .and_then(|mathml| convert::mathml_to_omml_with_profile(&mathml, profile.unwrap_or_default()));
+    match omml {
         Ok(omml) => {
             eprintln!("[convert_to_omml] Success! OMML length: {}", omml.len());
             Ok(omml)
@@ -212,7 +385,7 @@ async fn convert_to_omml(latex: String) -> Result<String, String> {
 #[tauri::command]
 async fn convert_to_mathml(latex: String) -> Result<String, String> {
     eprintln!("[convert_to_mathml] Input LaTeX: {}", latex);
-    match convert::latex_to_mathml(&latex) {
+    match convert::latex_to_mathml_inline(&latex) {
         Ok(mathml) => {
             eprintln!("[convert_to_mathml] Success! MathML length: {}", mathml.len());
             Ok(mathml)
@@ -224,6 +397,18 @@ async fn convert_to_mathml(latex: String) -> Result<String, String> {
     }
 }
 
+/// List the unsupported commands / environments in a formula (empty when it converts).
+/// `strict` also reports stray leading/trailing `&` that conversion would drop.
+#[tauri::command]
+async fn validate_formula(latex: String, strict: Option<bool>) -> Result<Vec<String>, String> {
+    let result = if strict.unwrap_or(false) {
+        convert::validate_latex_strict(&latex)
+    } else {
+        convert::validate_latex(&latex)
+    };
+    Ok(result.err().unwrap_or_default())
+}
+
 #[tauri::command]
 async fn copy_formula_to_clipboard(
     latex: String,
@@ -243,14 +428,235 @@ async fn copy_latex_to_clipboard(latex: String) -> Result<(), String> {
     clipboard::copy_latex(&latex).map_err(|e| e.to_string())
 }
 
+/// 将多条历史公式按 ids 顺序复制为 Word 可粘贴的公式段落（CF_HTML + 纯文本回退）
+#[tauri::command]
+async fn copy_selection_as_docx(ids: Vec<i64>) -> Result<(), String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    let html = export::build_docx_fragment_html(&records);
+    let text = records
+        .iter()
+        .map(export::effective_latex)
+        .collect::<Vec<_>>()
+        .join("\n");
+    clipboard::copy_html(&html, &text).map_err(|e| e.to_string())
+}
+
+/// Report resolution, content density and contrast so the UI can warn before OCR.
+#[tauri::command]
+async fn assess_image(image: Vec<u8>) -> Result<preprocess::ImageQuality, String> {
+    preprocess::assess_image(&image).map_err(|e| e.to_string())
+}
+
+/// 按给定参数预处理图片（裁剪、反色、对比度增强、缩放），返回 PNG
+#[tauri::command]
+async fn preprocess_image(image: Vec<u8>, options: PreprocessOptions) -> Result<Vec<u8>, String> {
+    preprocess::preprocess(&image, &options).map_err(|e| e.to_string())
+}
+
+/// Return the cropped/enhanced PNG that OCR would receive, for preview before recognizing.
+#[tauri::command]
+async fn preview_preprocessed(image: Vec<u8>, options: Option<PreprocessOptions>) -> Result<Vec<u8>, String> {
+    preprocess_image(image, options.unwrap_or_default()).await
+}
+
+/// 批量预处理并识别多张图片，最多 `workers` 个 OCR 引擎进程同时运行（缺省为 1）
+///
+/// 结果与输入按顺序一一对应，单张失败（包括超过 `ocr::INFERENCE_TIMEOUT`
+/// 被终止的引擎进程）不影响其他图片；已识别过的图片直接取自识别缓存。整批在阻塞线程池上运行。
 #[tauri::command]
-async fn save_history(record: HistoryRecord) -> Result<i64, String> {
-    history::save(&record).map_err(|e| e.to_string())
+async fn recognize_batch(
+    images: Vec<Vec<u8>>,
+    preprocess_options: Option<PreprocessOptions>,
+    workers: Option<usize>,
+    cache: tauri::State<'_, ocr::RecognitionCache>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<Result<OcrResult, String>>, String> {
+    let cache = cache.inner().clone();
+    let results = tauri::async_runtime::spawn_blocking(move || {
+        ocr::recognize_batch_with(
+            &images,
+            &preprocess_options.unwrap_or_default(),
+            workers.unwrap_or(1),
+            ocr::INFERENCE_TIMEOUT,
+            &cache,
+            move |image, timeout| {
+                run_ocr_engine_with_timeout(image, &TempImageOptions::default(), &app_handle, Some(timeout))
+                    .map_err(ocr::OcrError::InferenceFailed)
+            },
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(results
+        .into_iter()
+        .map(|result| result.map_err(|e| e.to_string()))
+        .collect())
 }
 
+/// 报告各预处理步骤对图片的实际影响，帮助用户调整选项
 #[tauri::command]
-async fn search_history(query: String) -> Result<Vec<HistoryRecord>, String> {
-    history::search(&query).map_err(|e| e.to_string())
+async fn preprocess_trace(image: Vec<u8>, options: Option<PreprocessOptions>) -> Result<preprocess::PreprocessTrace, String> {
+    preprocess::preprocess_trace(&image, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+/// Preprocess a batch of imported images in one call.
+/// Results are aligned with the input; a bad image only fails its own slot.
+#[tauri::command]
+async fn preprocess_many(
+    images: Vec<Vec<u8>>,
+    options: Option<PreprocessOptions>,
+) -> Vec<Result<Vec<u8>, String>> {
+    preprocess::preprocess_many(images, &options.unwrap_or_default())
+}
+
+/// Save a recognized formula. Its confidence was already calibrated when it
+/// was recognized (see `OcrResult::calibrated`), so it is stored as given.
+/// With `review_threshold`, records below it are flagged for review.
+#[tauri::command]
+async fn save_history(record: HistoryRecord, review_threshold: Option<f64>) -> Result<i64, String> {
+    match review_threshold {
+        Some(threshold) => history::save_with_review(&record, threshold),
+        None => history::save(&record),
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_needs_review() -> Result<Vec<HistoryRecord>, String> {
+    history::list_needs_review().map_err(|e| e.to_string())
+}
+
+/// Set or clear a record's review flag (cleared once the user has checked it).
+#[tauri::command]
+async fn set_needs_review(id: i64, needs_review: bool) -> Result<(), String> {
+    history::set_needs_review(id, needs_review).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn search_history(
+    query: String,
+    options: Option<history::SearchOptions>,
+) -> Result<Vec<HistoryRecord>, String> {
+    history::search_with_options(&query, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn search_history_paged(query: String, limit: i64, offset: i64) -> Result<Vec<HistoryRecord>, String> {
+    history::search_paged(&query, limit, offset).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn query_history(filter: Option<history::HistoryFilter>) -> Result<Vec<HistoryRecord>, String> {
+    history::query(&filter.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn count_history(query: String) -> Result<i64, String> {
+    history::count(&query).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn suggest_history(prefix: String, limit: i64) -> Result<Vec<String>, String> {
+    history::suggest(&prefix, limit).map_err(|e| e.to_string())
+}
+
+/// Re-encode a stored thumbnail (e.g. PNG → JPEG) to shrink the database.
+#[tauri::command]
+async fn reencode_thumbnail(id: i64, format: preprocess::OutputFormat) -> Result<(), String> {
+    history::reencode_thumbnail(id, format).map_err(|e| e.to_string())
+}
+
+/// 用新的预处理参数重新识别历史记录的缩略图，更新 `edited_latex` 与置信度
+#[tauri::command]
+async fn reprocess_record(
+    id: i64,
+    preprocess_options: Option<PreprocessOptions>,
+    recognition_mode: Option<RecognitionMode>,
+    app_handle: tauri::AppHandle,
+) -> Result<HistoryRecord, String> {
+    history::reprocess_record(
+        id,
+        &preprocess_options.unwrap_or_default(),
+        recognition_mode.unwrap_or_default(),
+        |image| {
+            run_ocr_engine(image, &TempImageOptions::default(), &app_handle)
+                .map_err(ocr::OcrError::InferenceFailed)
+        },
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn check_history_db() -> Result<(), String> {
+    history::check_integrity().map_err(|e| e.to_string())
+}
+
+/// 启动时历史数据库的恢复提示（原文件损坏，已备份并重建）
+///
+/// Set during setup, before the window can listen for events, so the
+/// frontend fetches it with `take_db_recovery_notice` once it has loaded.
+#[derive(Default)]
+struct DbRecoveryNotice(std::sync::Mutex<Option<String>>);
+
+/// Return the startup database recovery message, if any, and clear it.
+#[tauri::command]
+async fn take_db_recovery_notice(notice: tauri::State<'_, DbRecoveryNotice>) -> Result<Option<String>, String> {
+    let mut notice = notice.0.lock().map_err(|e| e.to_string())?;
+    Ok(notice.take())
+}
+
+/// Compact the database and export every record as a backup ZIP.
+#[tauri::command]
+async fn export_backup() -> Result<Vec<u8>, String> {
+    history::export_backup().map_err(|e| e.to_string())
+}
+
+/// Remove OCR temp files left behind by crashed recognitions; returns how many were removed.
+/// Files younger than `max_age_secs` (default one hour) are kept.
+#[tauri::command]
+async fn cleanup_temp_files(max_age_secs: Option<u64>) -> Result<usize, String> {
+    let max_age = max_age_secs.map_or(ocr::DEFAULT_STALE_TEMP_AGE, std::time::Duration::from_secs);
+    ocr::cleanup_temp_files(&std::env::temp_dir(), max_age).map_err(|e| e.to_string())
+}
+
+/// Restore records from a backup ZIP; returns how many were added.
+#[tauri::command]
+async fn import_backup(bytes: Vec<u8>) -> Result<usize, String> {
+    history::import_backup(&bytes).map_err(|e| e.to_string())
+}
+
+/// Remove records with identical LaTeX, keeping one per group; returns how many were removed.
+#[tauri::command]
+async fn dedup_history(keep: history::DedupKeep) -> Result<usize, String> {
+    history::dedup_history(keep).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn confidence_histogram(buckets: usize) -> Result<Vec<u64>, String> {
+    history::confidence_histogram(buckets).map_err(|e| e.to_string())
+}
+
+/// Save the editor's LaTeX as the record's `edited_latex`; the original recognition is kept.
+#[tauri::command]
+async fn update_history_latex(id: i64, edited_latex: String) -> Result<(), String> {
+    history::update_latex(id, &edited_latex).map_err(|e| e.to_string())
+}
+
+/// Attach a prose note to a history record; `None` or blank clears it.
+#[tauri::command]
+async fn set_history_note(id: i64, note: Option<String>) -> Result<(), String> {
+    history::set_note(id, note.as_deref()).map_err(|e| e.to_string())
+}
+
+/// Replace a history record's tags; an empty list clears them.
+#[tauri::command]
+async fn set_history_tags(id: i64, tags: Vec<String>) -> Result<(), String> {
+    history::set_tags(id, &tags).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_history_tags(ids: Vec<i64>) -> Result<std::collections::HashMap<i64, Vec<String>>, String> {
+    history::get_tags(&ids).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -258,16 +664,133 @@ async fn toggle_favorite(id: i64) -> Result<(), String> {
     history::toggle_favorite(id).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn delete_history_many(ids: Vec<i64>) -> Result<usize, String> {
+    history::delete_many(&ids).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn export_tex(ids: Vec<i64>, options: TexExportOptions) -> Result<Vec<u8>, String> {
     let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
     export::export_tex(&records, &options).map_err(|e| e.to_string())
 }
 
+/// Export one history record as LaTeX / MathML / OMML / SVG / PNG bytes.
+#[tauri::command]
+async fn export_one(
+    id: i64,
+    format: SingleExportFormat,
+    color_scheme: Option<ColorScheme>,
+) -> Result<Vec<u8>, String> {
+    let record = history::get_by_id(id).map_err(|e| e.to_string())?;
+    export::export_one_with_scheme(&record, format, color_scheme.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+/// Export the selected records stacked into one PNG image.
+#[tauri::command]
+async fn export_png_sheet(ids: Vec<i64>, options: Option<PngSheetOptions>) -> Result<Vec<u8>, String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    export::export_png_sheet(&records, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_markdown(ids: Vec<i64>, options: Option<MarkdownExportOptions>) -> Result<Vec<u8>, String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    export::export_markdown(&records, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_html(ids: Vec<i64>, options: Option<HtmlExportOptions>) -> Result<Vec<u8>, String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    export::export_html(&records, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
-async fn export_docx(ids: Vec<i64>) -> Result<Vec<u8>, String> {
+async fn export_ipynb(ids: Vec<i64>) -> Result<Vec<u8>, String> {
     let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
-    export::export_docx(&records).map_err(|e| e.to_string())
+    export::export_ipynb(&records).map_err(|e| e.to_string())
+}
+
+/// Export the selected records as a ZIP with one file per tag.
+#[tauri::command]
+async fn export_by_tag_zip(ids: Vec<i64>, filter: Option<TagExportFilter>) -> Result<Vec<u8>, String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    let tags = history::get_tags(&ids).map_err(|e| e.to_string())?;
+    export::export_by_tag_zip(&records, &tags, &filter.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_docx(ids: Vec<i64>, options: Option<DocxExportOptions>) -> Result<Vec<u8>, String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    export::export_docx_with_options(&records, &options.unwrap_or_default())
+        .map(|(docx, _)| docx)
+        .map_err(|e| e.to_string())
+}
+
+/// Export .docx and report which records failed to convert.
+#[tauri::command]
+async fn export_docx_with_report(
+    ids: Vec<i64>,
+    options: Option<DocxExportOptions>,
+) -> Result<(Vec<u8>, Vec<ExportRecordStatus>), String> {
+    let records = history::get_by_ids(&ids).map_err(|e| e.to_string())?;
+    export::export_docx_with_options(&records, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+/// Reserve a token for a progress-reporting export; pass it to the export
+/// command and to `cancel_export`.
+#[tauri::command]
+async fn start_export(registry: tauri::State<'_, ExportRegistry>) -> Result<u64, String> {
+    Ok(registry.start())
+}
+
+/// Export .tex, emitting `export-progress` ({done, total}) after each record.
+/// Stops early when `cancel_export` is called with the same `token`.
+#[tauri::command]
+async fn export_tex_with_progress(
+    ids: Vec<i64>,
+    options: Option<TexExportOptions>,
+    token: u64,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, ExportRegistry>,
+) -> Result<StreamedExport<Vec<u8>>, String> {
+    let cancel = registry.flag(token).ok_or_else(|| format!("未知的导出令牌: {}", token))?;
+    let result = history::get_by_ids(&ids).map_err(|e| e.to_string()).and_then(|records| {
+        export::export_tex_streamed(&records, &options.unwrap_or_default(), &cancel, |progress| {
+            let _ = app_handle.emit("export-progress", progress);
+        })
+        .map_err(|e| e.to_string())
+    });
+    registry.finish(token);
+    result
+}
+
+/// Export .docx with per-record statuses, emitting `export-progress` after each record.
+/// Stops early when `cancel_export` is called with the same `token`.
+#[tauri::command]
+async fn export_docx_with_progress(
+    ids: Vec<i64>,
+    options: Option<DocxExportOptions>,
+    token: u64,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, ExportRegistry>,
+) -> Result<StreamedExport<export::DocxWithReport>, String> {
+    let cancel = registry.flag(token).ok_or_else(|| format!("未知的导出令牌: {}", token))?;
+    let result = history::get_by_ids(&ids).map_err(|e| e.to_string()).and_then(|records| {
+        export::export_docx_streamed(&records, &options.unwrap_or_default(), &cancel, |progress| {
+            let _ = app_handle.emit("export-progress", progress);
+        })
+        .map_err(|e| e.to_string())
+    });
+    registry.finish(token);
+    result
+}
+
+/// Ask the export started with `token` to stop before its next record.
+/// Returns `false` when that export has already finished.
+#[tauri::command]
+async fn cancel_export(token: u64, registry: tauri::State<'_, ExportRegistry>) -> Result<bool, String> {
+    Ok(registry.cancel(token))
 }
 
 // ============================================================
@@ -280,20 +803,70 @@ pub fn run() {
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_clipboard_manager::init())
+        .manage(capture::CaptureService::new())
+        .manage(ExportRegistry::default())
+        .manage(DbRecoveryNotice::default())
+        .manage(ocr::RecognitionCache::default())
         .invoke_handler(tauri::generate_handler![
             capture_screenshot,
             capture_screen_region,
+            capture_preview,
             cancel_capture,
             recognize_formula,
+            recognize_data_url,
+            recognize_region,
+            recognize_clipboard_image,
+            recognize_and_convert,
+            recognize_batch,
+            test_shortcut,
+            default_symbol_rules,
             convert_to_omml,
             convert_to_mathml,
+            validate_formula,
             copy_formula_to_clipboard,
             copy_latex_to_clipboard,
+            copy_selection_as_docx,
+            assess_image,
+            preprocess_image,
+            preview_preprocessed,
+            preprocess_many,
+            preprocess_trace,
             save_history,
+            list_needs_review,
+            set_needs_review,
             search_history,
+            search_history_paged,
+            count_history,
+            query_history,
+            suggest_history,
+            confidence_histogram,
+            check_history_db,
+            take_db_recovery_notice,
+            export_backup,
+            cleanup_temp_files,
+            import_backup,
+            dedup_history,
             toggle_favorite,
+            delete_history_many,
+            update_history_latex,
+            set_history_note,
+            set_history_tags,
+            get_history_tags,
+            reencode_thumbnail,
+            reprocess_record,
             export_tex,
+            export_markdown,
+            export_html,
+            export_ipynb,
+            export_by_tag_zip,
+            export_one,
+            export_png_sheet,
             export_docx,
+            export_docx_with_report,
+            export_tex_with_progress,
+            export_docx_with_progress,
+            start_export,
+            cancel_export,
         ])
         .setup(|app| {
             // Initialize the SQLite database for history records.
@@ -313,8 +886,18 @@ pub fn run() {
                 .to_str()
                 .expect("app data directory path is not valid UTF-8");
 
-            history::init_db(db_path_str)
-                .expect("failed to initialize history database");
+            // A corrupt file is backed up and replaced by an empty database;
+            // the UI is told through `take_db_recovery_notice`. Only failures
+            // that leave no usable database abort startup.
+            match history::init_db(db_path_str) {
+                Ok(()) => {}
+                Err(e @ history::HistoryError::Recovered(_)) => {
+                    if let Ok(mut notice) = app.state::<DbRecoveryNotice>().0.lock() {
+                        *notice = Some(e.to_string());
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
 
             // Note: OCR engine initialization is deferred to the first
             // recognize_formula call because the model file may not be
@@ -4,8 +4,10 @@
 use ort::session::builder::GraphOptimizationLevel;
 use ort::session::Session;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 /// OCR 识别结果
@@ -17,6 +19,21 @@ pub struct OcrResult {
     pub confidence: f64,
 }
 
+impl OcrResult {
+    /// 按引擎版本校准置信度（见 `calibrate_confidence`）
+    ///
+    /// Applied once where an engine's result enters the app, so everything
+    /// downstream — the UI, `save_history`, `reprocess_record` — sees the
+    /// calibrated value and never calibrates again.
+    pub fn calibrated(mut self, engine_version: &str) -> Self {
+        self.confidence = calibrate_confidence(engine_version, self.confidence);
+        self
+    }
+}
+
+/// 当前识别引擎的版本，保存记录时写入 `engine_version`，也是置信度校准的依据
+pub const ENGINE_VERSION: &str = "pix2tex-onnx-1.0";
+
 /// OCR 错误类型
 #[derive(Debug, thiserror::Error)]
 pub enum OcrError {
@@ -28,6 +45,16 @@ pub enum OcrError {
     Timeout,
     #[error("识别结果为空")]
     EmptyResult,
+    #[error("临时图片写入失败: {0}")]
+    TempImage(String),
+    #[error("无效的图片 data URL: {0}")]
+    InvalidDataUrl(String),
+    #[error("截图失败: {0}")]
+    Capture(String),
+    #[error("读取剪贴板图片失败: {0}")]
+    Clipboard(String),
+    #[error("预处理失败: {0}")]
+    Preprocess(String),
 }
 
 impl Serialize for OcrError {
@@ -46,7 +73,7 @@ impl From<ort::Error> for OcrError {
 }
 
 /// 推理超时时间（10 秒）
-const INFERENCE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const INFERENCE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// pix2tex 模型默认输入高度
 const MODEL_INPUT_HEIGHT: u32 = 64;
@@ -295,7 +322,7 @@ fn run_inference(session: &mut Session, image_bytes: &[u8]) -> Result<OcrResult,
         return Err(OcrError::EmptyResult);
     }
 
-    Ok(result)
+    Ok(result.calibrated(ENGINE_VERSION))
 }
 
 /// 识别图片中的公式（同步版本）
@@ -353,6 +380,650 @@ pub async fn recognize_async(engine: &OcrEngine, image: Vec<u8>) -> Result<OcrRe
     }
 }
 
+/// OCR 临时图片格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TempImageFormat {
+    /// 无损 PNG（默认，所有引擎都能读取）
+    #[default]
+    Png,
+    /// JPEG，体积更小、写入更快
+    Jpeg,
+}
+
+impl TempImageFormat {
+    /// 临时文件扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            TempImageFormat::Png => "png",
+            TempImageFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// OCR 临时图片写入选项
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TempImageOptions {
+    /// 临时图片格式
+    pub format: TempImageFormat,
+    /// JPEG 质量 1–100（仅对 JPEG 生效）
+    pub jpeg_quality: u8,
+}
+
+impl Default for TempImageOptions {
+    fn default() -> Self {
+        Self {
+            format: TempImageFormat::Png,
+            jpeg_quality: 90,
+        }
+    }
+}
+
+/// 外部 OCR 引擎可读取的临时图片格式
+///
+/// 打包的 ocr_engine 与 `scripts/ocr_server.py` 都通过 PIL 打开图片，PNG 和 JPEG 均可读取。
+pub const ENGINE_SUPPORTED_FORMATS: &[TempImageFormat] = &[TempImageFormat::Png, TempImageFormat::Jpeg];
+
+/// 临时图片序号，保证同一进程内的临时文件名唯一
+static TEMP_IMAGE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 将截图写入 OCR 临时文件
+///
+/// 文件名为 `formulasnap_ocr_input_<pid>_<n>.<ext>`，每次调用都不同，
+/// 并发识别不会互相覆盖。如果请求的格式不在 `supported` 中
+/// （引擎无法读取），回退为 PNG。PNG 直接写入原始字节；JPEG 会先把透明像素
+/// 合成到白色背景上再按 `jpeg_quality` 编码。
+///
+/// # Returns
+/// * `Ok(PathBuf)` - 写入的临时文件路径
+/// * `Err(OcrError::TempImage)` - 解码、编码或写入失败
+pub fn write_temp_image(
+    image: &[u8],
+    dir: &Path,
+    options: &TempImageOptions,
+    supported: &[TempImageFormat],
+) -> Result<PathBuf, OcrError> {
+    let format = if supported.contains(&options.format) {
+        options.format
+    } else {
+        TempImageFormat::Png
+    };
+    let seq = TEMP_IMAGE_SEQ.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!(
+        "{}input_{}_{}.{}",
+        TEMP_FILE_PREFIX,
+        std::process::id(),
+        seq,
+        format.extension()
+    ));
+
+    let bytes = match format {
+        TempImageFormat::Png => image.to_vec(),
+        TempImageFormat::Jpeg => crate::preprocess::reencode(
+            image,
+            crate::preprocess::OutputFormat::Jpeg {
+                quality: options.jpeg_quality,
+            },
+        )
+        .map_err(|e| OcrError::TempImage(e.to_string()))?,
+    };
+
+    std::fs::write(&path, bytes).map_err(|e| OcrError::TempImage(format!("{}: {}", path.display(), e)))?;
+    Ok(path)
+}
+
+/// OCR 临时文件名前缀（见 `write_temp_image`）
+pub const TEMP_FILE_PREFIX: &str = "formulasnap_ocr_";
+
+/// 清理临时文件时默认的过期阈值：超过此时间未修改的临时文件视为残留
+pub const DEFAULT_STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// 删除残留的 OCR 临时文件，返回删除的数量
+///
+/// Only regular files directly in `dir` whose name starts with
+/// `TEMP_FILE_PREFIX` and that were last modified more than `max_age` ago are
+/// removed. Younger files may belong to a recognition still in progress and
+/// are kept; files that cannot be removed (e.g. held open by a running engine
+/// on Windows) are skipped rather than failing the cleanup.
+///
+/// # Returns
+/// * `Ok(usize)` - 删除的文件数
+/// * `Err(OcrError::TempImage)` - 无法读取目录
+pub fn cleanup_temp_files(dir: &Path, max_age: Duration) -> Result<usize, OcrError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| OcrError::TempImage(format!("{}: {}", dir.display(), e)))?;
+    let now = std::time::SystemTime::now();
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with(TEMP_FILE_PREFIX) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else { continue };
+        if !metadata.is_file() {
+            continue;
+        }
+        // A modification time in the future (clock skew) counts as fresh
+        let stale = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > max_age);
+        if stale && std::fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// data URL 中可接受的图片 MIME 类型（外部引擎可读取的格式）
+pub const DATA_URL_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/jpg"];
+
+/// 解码 `data:image/png;base64,...` 形式的 data URL，返回图片字节
+///
+/// The MIME type is matched case-insensitively against `DATA_URL_MIME_TYPES`
+/// and the payload must be base64-encoded; ASCII whitespace inside the
+/// payload (e.g. line wrapping) is ignored.
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - 解码后的图片字节
+/// * `Err(OcrError::InvalidDataUrl)` - 格式错误、MIME 类型不受支持或 base64 无效
+pub fn decode_data_url(data_url: &str) -> Result<Vec<u8>, OcrError> {
+    use base64::Engine as _;
+
+    let rest = data_url
+        .trim()
+        .strip_prefix("data:")
+        .ok_or_else(|| OcrError::InvalidDataUrl("缺少 data: 前缀".to_string()))?;
+    let (header, payload) = rest
+        .split_once(',')
+        .ok_or_else(|| OcrError::InvalidDataUrl("缺少逗号分隔的数据部分".to_string()))?;
+
+    let mut params = header.split(';');
+    let mime = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+    if !DATA_URL_MIME_TYPES.contains(&mime.as_str()) {
+        let shown = if mime.is_empty() { "(空)" } else { mime.as_str() };
+        return Err(OcrError::InvalidDataUrl(format!("不支持的 MIME 类型: {}", shown)));
+    }
+    if !params.any(|p| p.trim().eq_ignore_ascii_case("base64")) {
+        return Err(OcrError::InvalidDataUrl("仅支持 base64 编码".to_string()));
+    }
+
+    let payload: String = payload.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|e| OcrError::InvalidDataUrl(format!("base64 解码失败: {}", e)))?;
+    if bytes.is_empty() {
+        return Err(OcrError::InvalidDataUrl("图片数据为空".to_string()));
+    }
+    Ok(bytes)
+}
+
+/// 置信度校准表：引擎版本 → 分段线性映射节点 `(原始值, 校准值)`，按原始值升序
+///
+/// 各引擎报告的置信度刻度不同，校准后统一为可比较的 0–1 概率。
+const CONFIDENCE_CALIBRATION: &[(&str, &[(f64, f64)])] = &[
+    // pix2tex 的平均 token 概率普遍偏高：0.8 以下的结果大多需要人工修改
+    ("pix2tex-onnx-1.0", &[(0.0, 0.0), (0.5, 0.2), (0.8, 0.55), (0.95, 0.9), (1.0, 1.0)]),
+];
+
+/// 按引擎版本把原始置信度映射为校准后的 0–1 置信度
+///
+/// 在 `CONFIDENCE_CALIBRATION` 的节点之间线性插值（超出 0–1 的输入先钳制）。
+/// 未知引擎版本或 NaN 原样返回。
+pub fn calibrate_confidence(engine_version: &str, raw: f64) -> f64 {
+    let Some((_, points)) = CONFIDENCE_CALIBRATION.iter().find(|(version, _)| *version == engine_version) else {
+        return raw;
+    };
+    if raw.is_nan() {
+        return raw;
+    }
+    let raw = raw.clamp(0.0, 1.0);
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if raw <= x1 {
+            return y0 + (raw - x0) * (y1 - y0) / (x1 - x0);
+        }
+    }
+    points.last().map_or(raw, |&(_, y)| y)
+}
+
+/// 符号混淆规则的生效上下文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolContext {
+    /// 两侧以空格分隔且都是操作数（字母、数字或括号），如 `a x b`
+    BetweenOperands,
+    /// 不与字母相连的独立记号，如 `l_1`（`ln` 中的 `l` 不算）
+    Standalone,
+    /// 任意位置
+    Anywhere,
+}
+
+/// 符号字典中的一条规则：OCR 输出的记号 `from` 在 `context` 下改写为 `to`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolRule {
+    /// 单个记号：一个字符或一条 `\command`
+    pub from: String,
+    pub to: String,
+    pub context: SymbolContext,
+}
+
+/// 默认符号字典：`x` → `\times`、`.` → `\cdot`（均需位于两个操作数之间）、
+/// 独立的 `l` → `\ell`
+pub fn default_symbol_rules() -> Vec<SymbolRule> {
+    let rule = |from: &str, to: &str, context| SymbolRule { from: from.to_string(), to: to.to_string(), context };
+    vec![
+        rule("x", r"\times", SymbolContext::BetweenOperands),
+        rule(".", r"\cdot", SymbolContext::BetweenOperands),
+        rule("l", r"\ell", SymbolContext::Standalone),
+    ]
+}
+
+/// One LaTeX token with its byte range and whether whitespace surrounds it.
+struct SymbolToken<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+    spaced_before: bool,
+    spaced_after: bool,
+}
+
+/// Split LaTeX into commands (`\name`, `\,`) and single characters, skipping whitespace.
+fn symbol_tokens(latex: &str) -> Vec<SymbolToken<'_>> {
+    let mut tokens: Vec<SymbolToken> = Vec::new();
+    let mut spaced = false;
+    let mut chars = latex.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            spaced = true;
+            if let Some(last) = tokens.last_mut() {
+                last.spaced_after = true;
+            }
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        if c == '\\' {
+            match chars.peek() {
+                Some(&(_, next)) if next.is_ascii_alphabetic() => {
+                    while let Some(&(i, next)) = chars.peek() {
+                        if !next.is_ascii_alphabetic() {
+                            break;
+                        }
+                        end = i + 1;
+                        chars.next();
+                    }
+                }
+                Some(&(i, next)) => {
+                    end = i + next.len_utf8();
+                    chars.next();
+                }
+                None => {}
+            }
+        }
+        tokens.push(SymbolToken { text: &latex[start..end], start, end, spaced_before: spaced, spaced_after: false });
+        spaced = false;
+    }
+    tokens
+}
+
+/// 按符号字典修正 OCR 常见的符号混淆（可选的识别后处理）
+///
+/// 每个记号按顺序匹配 `rules`，取第一条 `from` 相同且上下文满足的规则；
+/// 没有规则匹配的记号原样保留。改写为命令且后面紧跟字母时会补一个空格。
+pub fn correct_symbols(latex: &str, rules: &[SymbolRule]) -> String {
+    let tokens = symbol_tokens(latex);
+    let is_letter = |t: Option<&SymbolToken>| {
+        t.is_some_and(|t| t.text.chars().next().is_some_and(|c| c.is_alphabetic()))
+    };
+    let is_operand = |t: Option<&SymbolToken>, closing: bool| {
+        t.is_some_and(|t| {
+            let brackets: &[&str] = if closing { &[")", "]", "}"] } else { &["(", "[", "{"] };
+            t.text.chars().all(|c| c.is_alphanumeric()) || brackets.contains(&t.text)
+        })
+    };
+
+    let mut result = String::with_capacity(latex.len());
+    let mut copied = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).and_then(|j| tokens.get(j));
+        let next = tokens.get(i + 1);
+        let matches = |rule: &&SymbolRule| {
+            rule.from == token.text
+                && match rule.context {
+                    SymbolContext::BetweenOperands => {
+                        token.spaced_before
+                            && token.spaced_after
+                            && is_operand(prev, true)
+                            && is_operand(next, false)
+                    }
+                    SymbolContext::Standalone => {
+                        (token.spaced_before || !is_letter(prev))
+                            && (token.spaced_after || !is_letter(next))
+                    }
+                    SymbolContext::Anywhere => true,
+                }
+        };
+        let Some(rule) = rules.iter().find(matches) else {
+            continue;
+        };
+        result.push_str(&latex[copied..token.start]);
+        result.push_str(&rule.to);
+        let glues = rule.to.ends_with(|c: char| c.is_ascii_alphabetic()) && rule.to.starts_with('\\');
+        if glues && !token.spaced_after && is_letter(next) {
+            result.push(' ');
+        }
+        copied = token.end;
+    }
+    result.push_str(&latex[copied..]);
+    result
+}
+
+/// 识别模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecognitionMode {
+    /// 整张图片作为一个公式识别（默认）
+    #[default]
+    Single,
+    /// 按行切分后逐行识别，结果以 `\\` 换行合并为一个 `aligned` 公式
+    MultiLine,
+}
+
+/// 区域识别结果：截图缩略图与 OCR 结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionRecognition {
+    /// 截取区域的原始 PNG（未预处理，可直接作为历史缩略图）
+    pub thumbnail: Vec<u8>,
+    /// 识别结果
+    pub result: OcrResult,
+}
+
+/// 截图 → 预处理 → 识别，一次完成
+///
+/// `capture` 与 `recognize` 由调用方注入：命令层传入屏幕截图与外部引擎调用，
+/// 测试中可替换为桩。截图或预处理失败时直接返回，不会调用 `recognize`。
+///
+/// `RecognitionMode::MultiLine` 先用 `segment_lines` 切分截图，每行单独预处理
+/// 和识别，置信度取各行最小值；只切出一行时与 `Single` 相同。
+///
+/// # Returns
+/// * `Ok(RegionRecognition)` - 原始截图 PNG 与识别结果
+/// * `Err(OcrError::Capture)` / `Err(OcrError::Preprocess)` - 截图或预处理失败
+/// * `Err(_)` - `recognize` 返回的错误
+pub fn recognize_region_with<C, R>(
+    capture: C,
+    preprocess_options: &crate::preprocess::PreprocessOptions,
+    mode: RecognitionMode,
+    recognize: R,
+) -> Result<RegionRecognition, OcrError>
+where
+    C: FnOnce() -> Result<Vec<u8>, crate::capture::CaptureError>,
+    R: FnMut(&[u8]) -> Result<OcrResult, OcrError>,
+{
+    let thumbnail = capture().map_err(|e| OcrError::Capture(e.to_string()))?;
+    let result = recognize_image_with(&thumbnail, preprocess_options, mode, recognize)?;
+    Ok(RegionRecognition { thumbnail, result })
+}
+
+/// 单个格式的转换结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum FormatConversion {
+    Succeeded { output: String },
+    Failed { error: String },
+}
+
+impl FormatConversion {
+    fn from_result(result: Result<String, crate::convert::ConvertError>) -> Self {
+        match result {
+            Ok(output) => FormatConversion::Succeeded { output },
+            Err(e) => FormatConversion::Failed { error: e.to_string() },
+        }
+    }
+}
+
+/// 识别并转换的结果：LaTeX、置信度以及各格式的转换结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvertedRecognition {
+    pub latex: String,
+    /// 置信度 0.0 ~ 1.0
+    pub confidence: f64,
+    pub mathml: FormatConversion,
+    pub omml: FormatConversion,
+}
+
+/// 预处理 → 识别 → 转换为 MathML 与 OMML，一次完成
+///
+/// 识别步骤与 `recognize_image_with` 相同，识别失败时直接返回错误。
+/// 转换失败不会中断：每种格式的结果（或错误信息）分别记录在返回值中。
+pub fn recognize_and_convert_with<R>(
+    image: &[u8],
+    preprocess_options: &crate::preprocess::PreprocessOptions,
+    display: crate::convert::ConvertDisplay,
+    recognize: R,
+) -> Result<ConvertedRecognition, OcrError>
+where
+    R: FnMut(&[u8]) -> Result<OcrResult, OcrError>,
+{
+    let result = recognize_image_with(image, preprocess_options, RecognitionMode::Single, recognize)?;
+    Ok(ConvertedRecognition {
+        mathml: FormatConversion::from_result(crate::convert::latex_to_mathml(&result.latex, display)),
+        omml: FormatConversion::from_result(crate::convert::latex_to_omml(&result.latex, display)),
+        latex: result.latex,
+        confidence: result.confidence,
+    })
+}
+
+/// 读取剪贴板图片 → 预处理 → 识别，一次完成
+///
+/// 与 `recognize_region_with` 相同，只是图片来自 `read_clipboard`
+/// （命令层传入 `clipboard::read_image`，测试中可替换为桩）。
+/// 剪贴板中没有图片或读取失败时返回 `OcrError::Clipboard`，不会调用 `recognize`。
+pub fn recognize_clipboard_with<C, R>(
+    read_clipboard: C,
+    preprocess_options: &crate::preprocess::PreprocessOptions,
+    mode: RecognitionMode,
+    recognize: R,
+) -> Result<RegionRecognition, OcrError>
+where
+    C: FnOnce() -> Result<Vec<u8>, crate::clipboard::ClipboardError>,
+    R: FnMut(&[u8]) -> Result<OcrResult, OcrError>,
+{
+    let thumbnail = read_clipboard().map_err(|e| OcrError::Clipboard(e.to_string()))?;
+    let result = recognize_image_with(&thumbnail, preprocess_options, mode, recognize)?;
+    Ok(RegionRecognition { thumbnail, result })
+}
+
+/// 批量识别的最大并发数
+pub const MAX_OCR_WORKERS: usize = 8;
+
+/// 识别结果缓存最多保留的条数，满了之后清空重来
+const RECOGNITION_CACHE_CAPACITY: usize = 256;
+
+/// 识别结果缓存：预处理后图片的 BLAKE3 哈希 → 识别结果
+///
+/// Re-running a batch (e.g. after one image failed) skips the engine for
+/// images it has already recognized. Keyed on the preprocessed image, so
+/// different preprocessing options never share an entry. Clones share the
+/// same entries.
+#[derive(Debug, Clone, Default)]
+pub struct RecognitionCache {
+    entries: Arc<Mutex<HashMap<[u8; 32], OcrResult>>>,
+}
+
+impl RecognitionCache {
+    /// 查找这张（预处理后的）图片的识别结果
+    pub fn get(&self, image: &[u8]) -> Option<OcrResult> {
+        let entries = self.entries.lock().ok()?;
+        entries.get(blake3::hash(image).as_bytes()).cloned()
+    }
+
+    /// 记录这张（预处理后的）图片的识别结果
+    pub fn insert(&self, image: &[u8], result: &OcrResult) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= RECOGNITION_CACHE_CAPACITY {
+                entries.clear();
+            }
+            entries.insert(*blake3::hash(image).as_bytes(), result.clone());
+        }
+    }
+}
+
+/// 等待引擎进程退出时轮询的间隔
+const ENGINE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// 运行外部识别引擎进程并收集输出，超过 `timeout` 时终止进程
+///
+/// stdout/stderr 在后台线程中读取，避免输出填满管道时进程卡住。超时后
+/// 进程被 kill 并回收，返回 `OcrError::Timeout`，因此超时的识别不会在
+/// 后台继续占用一个引擎进程。
+pub fn run_engine_process(
+    command: &mut std::process::Command,
+    timeout: Duration,
+) -> Result<std::process::Output, OcrError> {
+    use std::process::Stdio;
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| OcrError::InferenceFailed(format!("无法启动 OCR 引擎: {}", e)))?;
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let deadline = std::time::Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if std::time::Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(OcrError::Timeout);
+            }
+            Ok(None) => std::thread::sleep(ENGINE_POLL_INTERVAL),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(OcrError::InferenceFailed(format!("等待 OCR 引擎失败: {}", e)));
+            }
+        }
+    };
+
+    Ok(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// 在后台线程中读完一个子进程管道
+fn read_pipe<P>(pipe: Option<P>) -> std::thread::JoinHandle<Vec<u8>>
+where
+    P: std::io::Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// 批量预处理 → 识别，最多 `workers` 个引擎调用同时进行
+///
+/// 每张图片按 `RecognitionMode::Single` 走 `recognize_image_with` 的流程；
+/// 结果与输入按顺序一一对应，单张失败只记录在对应位置。`workers` 被限制在
+/// 1..=`MAX_OCR_WORKERS`，为 1 时逐张识别（与逐次调用相同）。
+///
+/// `recognize` receives each image with `timeout` and is expected to give up
+/// by then (`run_engine_process` kills the engine process); an image whose
+/// call runs over fails with `OcrError::Timeout` and the rest of the batch
+/// carries on. A worker only moves on once its engine call has returned, so
+/// at most `workers` engine calls are ever running. Results are looked up in
+/// / added to `cache` so an image recognized before does not reach the
+/// engine again. Blocks until the whole batch is done; async callers should
+/// run it on a blocking thread.
+pub fn recognize_batch_with<R>(
+    images: &[Vec<u8>],
+    preprocess_options: &crate::preprocess::PreprocessOptions,
+    workers: usize,
+    timeout: Duration,
+    cache: &RecognitionCache,
+    recognize: R,
+) -> Vec<Result<OcrResult, OcrError>>
+where
+    R: Fn(&[u8], Duration) -> Result<OcrResult, OcrError> + Send + Sync,
+{
+    use rayon::prelude::*;
+
+    let recognize_one = |image: &Vec<u8>| {
+        recognize_image_with(image, preprocess_options, RecognitionMode::Single, |prepared| {
+            if let Some(hit) = cache.get(prepared) {
+                return Ok(hit);
+            }
+            let started = std::time::Instant::now();
+            let result = recognize(prepared, timeout);
+            if started.elapsed() >= timeout {
+                return Err(OcrError::Timeout);
+            }
+            let result = result?;
+            cache.insert(prepared, &result);
+            Ok(result)
+        })
+    };
+    let workers = workers.clamp(1, MAX_OCR_WORKERS);
+    if workers == 1 {
+        return images.iter().map(recognize_one).collect();
+    }
+    match rayon::ThreadPoolBuilder::new().num_threads(workers).build() {
+        Ok(pool) => pool.install(|| images.par_iter().map(recognize_one).collect()),
+        // No threads available: fall back to one at a time
+        Err(_) => images.iter().map(recognize_one).collect(),
+    }
+}
+
+/// 预处理 → 识别一张已有图片（如历史记录的缩略图）
+///
+/// 与 `recognize_region_with` 相同，只是省去截图步骤。
+pub fn recognize_image_with<R>(
+    image: &[u8],
+    preprocess_options: &crate::preprocess::PreprocessOptions,
+    mode: RecognitionMode,
+    mut recognize: R,
+) -> Result<OcrResult, OcrError>
+where
+    R: FnMut(&[u8]) -> Result<OcrResult, OcrError>,
+{
+    let lines = match mode {
+        RecognitionMode::Single => vec![image.to_vec()],
+        RecognitionMode::MultiLine => crate::preprocess::segment_lines(image)
+            .map_err(|e| OcrError::Preprocess(e.to_string()))?,
+    };
+
+    let mut results = Vec::with_capacity(lines.len());
+    for line in &lines {
+        let prepared = crate::preprocess::preprocess(line, preprocess_options)
+            .map_err(|e| OcrError::Preprocess(e.to_string()))?;
+        results.push(recognize(&prepared)?);
+    }
+
+    if results.len() == 1 {
+        Ok(results.remove(0))
+    } else {
+        let latex = results
+            .iter()
+            .map(|r| r.latex.trim())
+            .collect::<Vec<_>>()
+            .join(r" \\ ");
+        Ok(OcrResult {
+            latex: format!(r"\begin{{aligned}} {} \end{{aligned}}", latex),
+            confidence: results.iter().map(|r| r.confidence).fold(f64::INFINITY, f64::min),
+        })
+    }
+}
+
 /// 获取引擎的模型路径
 impl OcrEngine {
     /// 返回加载的模型文件路径
@@ -586,6 +1257,7 @@ mod tests {
             OcrError::InferenceFailed("test".to_string()),
             OcrError::Timeout,
             OcrError::EmptyResult,
+            OcrError::TempImage("test".to_string()),
         ];
         for err in &errors {
             let json = serde_json::to_string(err).unwrap();
@@ -617,10 +1289,552 @@ mod tests {
         assert!((deserialized.confidence - result.confidence).abs() < f64::EPSILON);
     }
 
+    // ================================================================
+    // Temp image tests
+    // ================================================================
+
+    /// Create a unique scratch directory under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("formulasnap_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_temp_image_default_is_png() {
+        let dir = scratch_dir("temp_png");
+        let png = create_test_image(20, 10);
+        let path = write_temp_image(&png, &dir, &TempImageOptions::default(), ENGINE_SUPPORTED_FORMATS).unwrap();
+
+        assert_eq!(path.extension().unwrap(), "png");
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(written, png, "PNG should be written unchanged");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_write_temp_image_in_configured_jpeg_format() {
+        let dir = scratch_dir("temp_jpeg");
+        let png = create_test_image(20, 10);
+        let options = TempImageOptions { format: TempImageFormat::Jpeg, jpeg_quality: 75 };
+        let path = write_temp_image(&png, &dir, &options, ENGINE_SUPPORTED_FORMATS).unwrap();
+
+        assert_eq!(path.extension().unwrap(), "jpg");
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(image::guess_format(&written).unwrap(), image::ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(&written).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (20, 10));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_write_temp_image_falls_back_to_png_when_unsupported() {
+        let dir = scratch_dir("temp_fallback");
+        let png = create_test_image(20, 10);
+        let options = TempImageOptions { format: TempImageFormat::Jpeg, jpeg_quality: 75 };
+        let path = write_temp_image(&png, &dir, &options, &[TempImageFormat::Png]).unwrap();
+
+        assert_eq!(path.extension().unwrap(), "png");
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(image::guess_format(&written).unwrap(), image::ImageFormat::Png);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cleanup_temp_files_removes_only_stale_ocr_files() {
+        let dir = scratch_dir("temp_cleanup");
+        let two_hours_ago = std::time::SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        let write_aged = |name: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, b"x").unwrap();
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(two_hours_ago).unwrap();
+            path
+        };
+        let stale = write_aged("formulasnap_ocr_input.png");
+        let unrelated = write_aged("other_input.png");
+        // Written just now: may belong to a recognition still in progress
+        let fresh = dir.join("formulasnap_ocr_input.jpg");
+        std::fs::write(&fresh, b"x").unwrap();
+
+        let removed = cleanup_temp_files(&dir, DEFAULT_STALE_TEMP_AGE).unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(!stale.exists());
+        assert!(unrelated.exists());
+        assert!(fresh.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_temp_image_options_deserialize_partial() {
+        let options: TempImageOptions = serde_json::from_str(r#"{"format":"jpeg"}"#).unwrap();
+        assert_eq!(options.format, TempImageFormat::Jpeg);
+        assert_eq!(options.jpeg_quality, 90);
+    }
+
+    // ================================================================
+    // Data URL decoding tests
+    // ================================================================
+
+    #[test]
+    fn test_decode_data_url_valid_png() {
+        use base64::Engine as _;
+
+        let png = create_test_image(4, 2);
+        let data_url = format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(&png)
+        );
+
+        let decoded = decode_data_url(&data_url).expect("valid data URL should decode");
+        assert_eq!(decoded, png);
+        let img = image::load_from_memory(&decoded).expect("decoded bytes should be an image");
+        assert_eq!((img.width(), img.height()), (4, 2));
+    }
+
+    #[test]
+    fn test_decode_data_url_rejects_malformed_base64() {
+        let result = decode_data_url("data:image/png;base64,iVBOR!!not*base64");
+        assert!(matches!(result, Err(OcrError::InvalidDataUrl(ref msg)) if msg.contains("base64")));
+    }
+
+    #[test]
+    fn test_decode_data_url_rejects_unsupported_mime() {
+        let result = decode_data_url("data:image/svg+xml;base64,PHN2Zy8+");
+        assert!(matches!(result, Err(OcrError::InvalidDataUrl(ref msg)) if msg.contains("image/svg+xml")));
+    }
+
+    #[test]
+    fn test_decode_data_url_rejects_missing_prefix_and_encoding() {
+        assert!(matches!(
+            decode_data_url("iVBORw0KGgo="),
+            Err(OcrError::InvalidDataUrl(_))
+        ));
+        assert!(matches!(
+            decode_data_url("data:image/png,rawbytes"),
+            Err(OcrError::InvalidDataUrl(_))
+        ));
+        assert!(matches!(
+            decode_data_url("data:image/png;base64,"),
+            Err(OcrError::InvalidDataUrl(_))
+        ));
+    }
+
     // ================================================================
     // Async timeout tests
     // ================================================================
 
+    #[test]
+    fn test_calibrate_confidence_known_engine() {
+        assert!((calibrate_confidence("pix2tex-onnx-1.0", 0.8) - 0.55).abs() < 1e-9);
+        assert!((calibrate_confidence("pix2tex-onnx-1.0", 0.65) - 0.375).abs() < 1e-9);
+        assert_eq!(calibrate_confidence("pix2tex-onnx-1.0", 0.0), 0.0);
+        assert_eq!(calibrate_confidence("pix2tex-onnx-1.0", 1.0), 1.0);
+        assert_eq!(calibrate_confidence("pix2tex-onnx-1.0", 1.5), 1.0);
+    }
+
+    #[test]
+    fn test_ocr_result_calibrated_maps_confidence_once() {
+        let raw = OcrResult { latex: "x".to_string(), confidence: 0.8 };
+        let calibrated = raw.calibrated(ENGINE_VERSION);
+        assert!((calibrated.confidence - calibrate_confidence(ENGINE_VERSION, 0.8)).abs() < 1e-9);
+        assert_eq!(calibrated.latex, "x");
+        assert_eq!(calibrated.calibrated("some-future-engine").confidence, 0.55);
+    }
+
+    #[test]
+    fn test_calibrate_confidence_unknown_engine_passes_through() {
+        assert_eq!(calibrate_confidence("some-future-engine", 0.73), 0.73);
+        assert_eq!(calibrate_confidence("", 1.2), 1.2);
+    }
+
+    #[test]
+    fn test_calibration_table_is_monotone_and_spans_unit_range() {
+        for (version, points) in CONFIDENCE_CALIBRATION {
+            assert_eq!(points.first(), Some(&(0.0, 0.0)), "{}", version);
+            assert_eq!(points.last(), Some(&(1.0, 1.0)), "{}", version);
+            for pair in points.windows(2) {
+                assert!(pair[0].0 < pair[1].0 && pair[0].1 <= pair[1].1, "{}: {:?}", version, pair);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recognize_region_returns_thumbnail_and_result() {
+        let captured = create_test_image(120, 40);
+        let mut seen = None;
+        let output = recognize_region_with(
+            || Ok(captured.clone()),
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::Single,
+            |image| {
+                seen = Some(image.to_vec());
+                Ok(OcrResult { latex: "x^2".to_string(), confidence: 0.8 })
+            },
+        )
+        .expect("recognize_region should succeed");
+
+        assert_eq!(output.thumbnail, captured);
+        assert_eq!(output.result.latex, "x^2");
+        assert_eq!(output.result.confidence, 0.8);
+        // The OCR stub receives the preprocessed image, not the raw capture
+        let seen = image::load_from_memory(&seen.unwrap()).unwrap();
+        assert_eq!(seen.height(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_recognize_image_feeds_engine_at_target_height() {
+        let image = create_test_image(300, 60);
+        let options = crate::preprocess::PreprocessOptions { target_height: Some(96), ..Default::default() };
+        let mut seen = None;
+
+        recognize_image_with(&image, &options, RecognitionMode::Single, |prepared| {
+            seen = Some(image::load_from_memory(prepared).unwrap());
+            Ok(OcrResult { latex: "x".to_string(), confidence: 1.0 })
+        })
+        .expect("recognition should succeed");
+
+        let seen = seen.expect("engine should be called");
+        assert_eq!(seen.height(), 96);
+        // The engine sees what the preprocess_image command returns for these options
+        let direct = crate::preprocess_image(image.clone(), options).await.unwrap();
+        assert_eq!(image::load_from_memory(&direct).unwrap().to_rgba8(), seen.to_rgba8());
+    }
+
+    #[test]
+    fn test_recognize_image_auto_inverts_dark_background() {
+        // Light formula on a dark background, as captured from a dark-themed editor
+        let img = image::ImageBuffer::from_fn(120, 40, |x, y| {
+            if (40..80).contains(&x) && (15..25).contains(&y) {
+                image::Rgba([240u8, 240, 240, 255])
+            } else {
+                image::Rgba([20u8, 20, 20, 255])
+            }
+        });
+        let mut dark = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(img).write_to(&mut dark, image::ImageFormat::Png).unwrap();
+        let dark = dark.into_inner();
+
+        let engine_input = |auto_invert: bool| {
+            let options = crate::preprocess::PreprocessOptions { auto_invert, ..Default::default() };
+            let mut seen = None;
+            recognize_image_with(&dark, &options, RecognitionMode::Single, |image| {
+                seen = Some(image::load_from_memory(image).unwrap().to_luma8());
+                Ok(OcrResult { latex: "x".to_string(), confidence: 1.0 })
+            })
+            .unwrap();
+            seen.unwrap()
+        };
+
+        // The engine receives dark-on-light: a light corner after inversion
+        assert!(engine_input(true).get_pixel(0, 0)[0] > 200);
+        // Without the setting the image is passed through unchanged
+        assert!(engine_input(false).get_pixel(0, 0)[0] < 50);
+    }
+
+    #[test]
+    fn test_recognize_batch_bounds_concurrency_and_keeps_order() {
+        use std::sync::atomic::AtomicUsize;
+
+        let images: Vec<Vec<u8>> = (1..=8).map(|i| create_test_image(10 * i, 20)).collect();
+        let options = crate::preprocess::PreprocessOptions {
+            auto_crop: false,
+            target_height: None,
+            ..Default::default()
+        };
+        let run = |workers: usize| {
+            let (in_flight, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+            let (engine_in_flight, engine_peak) = (Arc::clone(&in_flight), Arc::clone(&peak));
+            let cache = RecognitionCache::default();
+            let results = recognize_batch_with(&images, &options, workers, INFERENCE_TIMEOUT, &cache, move |image, _| {
+                let now = engine_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                engine_peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(30));
+                engine_in_flight.fetch_sub(1, Ordering::SeqCst);
+                let width = image::load_from_memory(image).unwrap().width();
+                Ok(OcrResult { latex: width.to_string(), confidence: 1.0 })
+            });
+            let latex: Vec<String> = results.into_iter().map(|r| r.unwrap().latex).collect();
+            (latex, peak.load(Ordering::SeqCst))
+        };
+        let expected: Vec<String> = (1..=8).map(|i| (10 * i).to_string()).collect();
+
+        let (latex, peak) = run(4);
+        assert_eq!(latex, expected);
+        assert!((2..=4).contains(&peak), "peak concurrency {}", peak);
+
+        let (latex, peak) = run(1);
+        assert_eq!(latex, expected);
+        assert_eq!(peak, 1);
+    }
+
+    #[test]
+    fn test_recognize_batch_times_out_slow_images_only() {
+        let images = vec![create_test_image(40, 20), create_test_image(80, 20)];
+        let options = crate::preprocess::PreprocessOptions {
+            auto_crop: false,
+            target_height: None,
+            ..Default::default()
+        };
+        let results = recognize_batch_with(&images, &options, 2, Duration::from_millis(100), &RecognitionCache::default(), |image, timeout| {
+            assert_eq!(timeout, Duration::from_millis(100));
+            let width = image::load_from_memory(image).unwrap().width();
+            if width == 80 {
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            Ok(OcrResult { latex: width.to_string(), confidence: 1.0 })
+        });
+        assert_eq!(results[0].as_ref().unwrap().latex, "40");
+        assert!(matches!(results[1], Err(OcrError::Timeout)), "got: {:?}", results[1]);
+    }
+
+    #[test]
+    fn test_recognize_batch_timeouts_do_not_exceed_worker_bound() {
+        use std::sync::atomic::AtomicUsize;
+
+        let images: Vec<Vec<u8>> = (1..=6).map(|i| create_test_image(10 * i, 20)).collect();
+        let options = crate::preprocess::PreprocessOptions {
+            auto_crop: false,
+            target_height: None,
+            ..Default::default()
+        };
+        let (in_flight, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (engine_in_flight, engine_peak) = (Arc::clone(&in_flight), Arc::clone(&peak));
+        let results = recognize_batch_with(&images, &options, 2, Duration::from_millis(20), &RecognitionCache::default(), move |_, _| {
+            let now = engine_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            engine_peak.fetch_max(now, Ordering::SeqCst);
+            // Every call runs past the timeout
+            std::thread::sleep(Duration::from_millis(60));
+            engine_in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(OcrResult { latex: "x".to_string(), confidence: 1.0 })
+        });
+        assert!(results.iter().all(|r| matches!(r, Err(OcrError::Timeout))), "got: {:?}", results);
+        assert!(peak.load(Ordering::SeqCst) <= 2, "peak concurrency {}", peak.load(Ordering::SeqCst));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_engine_process_kills_engine_on_timeout() {
+        let started = std::time::Instant::now();
+        let mut command = std::process::Command::new("sleep");
+        command.arg("5");
+        let result = run_engine_process(&mut command, Duration::from_millis(100));
+        assert!(matches!(result, Err(OcrError::Timeout)), "got: {:?}", result);
+        assert!(started.elapsed() < Duration::from_secs(2), "the engine should be killed, not awaited");
+
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "echo '{\"latex\": \"x\"}'; echo warn >&2"]);
+        let output = run_engine_process(&mut command, Duration::from_secs(5)).unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), r#"{"latex": "x"}"#);
+        assert_eq!(String::from_utf8_lossy(&output.stderr).trim(), "warn");
+    }
+
+    #[test]
+    fn test_recognize_batch_uses_cache_for_repeated_images() {
+        use std::sync::atomic::AtomicUsize;
+
+        let images = vec![create_test_image(40, 20), create_test_image(60, 20), create_test_image(40, 20)];
+        let options = crate::preprocess::PreprocessOptions::default();
+        let cache = RecognitionCache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let batch = || {
+            let calls = Arc::clone(&calls);
+            recognize_batch_with(&images, &options, 1, INFERENCE_TIMEOUT, &cache, move |_, _| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                Ok(OcrResult { latex: format!("call{}", n), confidence: 1.0 })
+            })
+        };
+
+        let first: Vec<String> = batch().into_iter().map(|r| r.unwrap().latex).collect();
+        assert_eq!(first, vec!["call0", "call1", "call0"], "the repeat comes from the cache");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let second: Vec<String> = batch().into_iter().map(|r| r.unwrap().latex).collect();
+        assert_eq!(second, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "a re-run does not reach the engine");
+    }
+
+    #[test]
+    fn test_recognize_region_capture_failure_skips_ocr() {
+        let mut called = false;
+        let result = recognize_region_with(
+            || Err(crate::capture::CaptureError::CaptureFailed("no screen".to_string())),
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::Single,
+            |_| {
+                called = true;
+                Ok(OcrResult { latex: "x".to_string(), confidence: 1.0 })
+            },
+        );
+        assert!(matches!(result, Err(OcrError::Capture(msg)) if msg.contains("no screen")));
+        assert!(!called, "OCR must not run when capture fails");
+    }
+
+    #[test]
+    fn test_recognize_clipboard_without_image_skips_ocr() {
+        let mut called = false;
+        let result = recognize_clipboard_with(
+            || Err(crate::clipboard::ClipboardError::NoImage),
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::Single,
+            |_| {
+                called = true;
+                Ok(OcrResult { latex: "x".to_string(), confidence: 1.0 })
+            },
+        );
+        assert!(matches!(result, Err(OcrError::Clipboard(msg)) if msg.contains("没有图片")));
+        assert!(!called, "OCR must not run without a clipboard image");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    #[ignore = "Requires desktop session - clipboard access may fail in parallel tests"]
+    fn test_recognize_clipboard_reads_placed_image() {
+        let img = image::load_from_memory(&create_test_image(120, 40)).unwrap();
+        let mut bmp = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut bmp, image::ImageFormat::Bmp).unwrap();
+        clipboard_win::set_clipboard(clipboard_win::formats::Bitmap, bmp.into_inner())
+            .expect("place image on clipboard");
+
+        let output = recognize_clipboard_with(
+            crate::clipboard::read_image,
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::Single,
+            |_| Ok(OcrResult { latex: "y^2".to_string(), confidence: 0.9 }),
+        )
+        .expect("recognize_clipboard should succeed");
+
+        assert_eq!(output.result.latex, "y^2");
+        let thumbnail = image::load_from_memory_with_format(&output.thumbnail, image::ImageFormat::Png)
+            .expect("thumbnail is a PNG");
+        assert_eq!((thumbnail.width(), thumbnail.height()), (120, 40));
+    }
+
+    #[test]
+    fn test_recognize_and_convert_fills_every_format() {
+        let output = recognize_and_convert_with(
+            &create_test_image(120, 40),
+            &crate::preprocess::PreprocessOptions::default(),
+            crate::convert::ConvertDisplay::Inline,
+            |_| Ok(OcrResult { latex: r"\frac{a}{b}".to_string(), confidence: 0.85 }),
+        )
+        .expect("recognize_and_convert should succeed");
+
+        assert_eq!(output.latex, r"\frac{a}{b}");
+        assert_eq!(output.confidence, 0.85);
+        assert!(matches!(&output.mathml, FormatConversion::Succeeded { output } if output.contains("<mfrac>")));
+        assert!(matches!(&output.omml, FormatConversion::Succeeded { output } if output.contains("<m:f>")));
+    }
+
+    #[test]
+    fn test_recognize_and_convert_reports_conversion_failure_per_format() {
+        let output = recognize_and_convert_with(
+            &create_test_image(120, 40),
+            &crate::preprocess::PreprocessOptions::default(),
+            crate::convert::ConvertDisplay::Inline,
+            |_| Ok(OcrResult { latex: r"\begin{unknownenv} x \end{unknownenv}".to_string(), confidence: 0.3 }),
+        )
+        .expect("conversion failures must not abort recognition");
+
+        assert_eq!(output.latex, r"\begin{unknownenv} x \end{unknownenv}");
+        assert!(matches!(output.mathml, FormatConversion::Failed { .. }), "got: {:?}", output.mathml);
+        assert!(matches!(output.omml, FormatConversion::Failed { .. }), "got: {:?}", output.omml);
+    }
+
+    /// White image with a black band over each `(top, height)` row range
+    fn banded_image(width: u32, height: u32, bands: &[(u32, u32)]) -> Vec<u8> {
+        let img = image::RgbaImage::from_fn(width, height, |_, y| {
+            if bands.iter().any(|&(top, h)| y >= top && y < top + h) {
+                image::Rgba([0, 0, 0, 255])
+            } else {
+                image::Rgba([255, 255, 255, 255])
+            }
+        });
+        let mut buf = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_recognize_region_multi_line_joins_bands() {
+        let captured = banded_image(100, 80, &[(10, 15), (50, 15)]);
+        let mut calls = 0;
+        let output = recognize_region_with(
+            || Ok(captured.clone()),
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::MultiLine,
+            |_| {
+                calls += 1;
+                let latex = if calls == 1 { "a = b" } else { "c = d" };
+                Ok(OcrResult { latex: latex.to_string(), confidence: 0.5 + 0.1 * calls as f64 })
+            },
+        )
+        .expect("multi-line recognition should succeed");
+
+        assert_eq!(calls, 2);
+        assert_eq!(output.result.latex, r"\begin{aligned} a = b \\ c = d \end{aligned}");
+        assert!((output.result.confidence - 0.6).abs() < 1e-9);
+        assert_eq!(output.thumbnail, captured);
+    }
+
+    #[test]
+    fn test_recognize_region_multi_line_single_band_is_unchanged() {
+        let captured = banded_image(100, 80, &[(30, 20)]);
+        let mut calls = 0;
+        let output = recognize_region_with(
+            || Ok(captured.clone()),
+            &crate::preprocess::PreprocessOptions::default(),
+            RecognitionMode::MultiLine,
+            |_| {
+                calls += 1;
+                Ok(OcrResult { latex: "x^2".to_string(), confidence: 0.9 })
+            },
+        )
+        .unwrap();
+        assert_eq!(calls, 1);
+        assert_eq!(output.result.latex, "x^2");
+    }
+
+    #[test]
+    fn test_correct_symbols_times_between_operands() {
+        let rules = default_symbol_rules();
+        assert_eq!(correct_symbols("a x b", &rules), r"a \times b");
+        assert_eq!(correct_symbols("( a + 1 ) x ( b )", &rules), r"( a + 1 ) \times ( b )");
+        // No operand on one side, or not spaced apart: x stays a variable
+        assert_eq!(correct_symbols("2 x", &rules), "2 x");
+        assert_eq!(correct_symbols("a x + b", &rules), "a x + b");
+        assert_eq!(correct_symbols("axb", &rules), "axb");
+    }
+
+    #[test]
+    fn test_correct_symbols_only_applies_matching_rules() {
+        assert_eq!(correct_symbols("a x b", &[]), "a x b");
+        let cdot_only = vec![SymbolRule {
+            from: ".".to_string(),
+            to: r"\cdot".to_string(),
+            context: SymbolContext::BetweenOperands,
+        }];
+        assert_eq!(correct_symbols("a x b . c", &cdot_only), r"a x b \cdot c");
+        assert_eq!(correct_symbols("3.14", &cdot_only), "3.14");
+    }
+
+    #[test]
+    fn test_correct_symbols_standalone_ell() {
+        let rules = default_symbol_rules();
+        assert_eq!(correct_symbols("l_1 + ln x", &rules), r"\ell_1 + ln x");
+        assert_eq!(correct_symbols(r"\lambda l", &rules), r"\lambda \ell");
+        let anywhere = vec![SymbolRule { from: "l".to_string(), to: r"\ell".to_string(), context: SymbolContext::Anywhere }];
+        assert_eq!(correct_symbols("ln", &anywhere), r"\ell n");
+    }
+
+    #[test]
+    fn test_symbol_rule_deserializes_snake_case_context() {
+        let rule: SymbolRule =
+            serde_json::from_str(r#"{"from":"x","to":"\\times","context":"between_operands"}"#).unwrap();
+        assert_eq!(rule, default_symbol_rules()[0]);
+    }
+
     #[tokio::test]
     async fn test_recognize_async_without_model() {
         // Without a real model, init_engine should fail
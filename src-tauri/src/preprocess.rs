@@ -3,25 +3,58 @@
 
 use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView, ImageFormat, Pixel};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
+/// 自动裁边判断"背景"像素的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CropMode {
+    /// 裁掉接近白色或完全透明的边缘
+    #[default]
+    White,
+    /// 只裁掉完全透明的边缘，不论颜色
+    ///
+    /// Useful for screenshots of dark-themed editors or formulas rendered on
+    /// a transparent canvas, where the opaque background is not white.
+    Alpha,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PreprocessOptions {
     /// 自动裁边
     pub auto_crop: bool,
+    /// 裁边方式（默认按白色背景裁剪）
+    #[serde(default)]
+    pub crop_mode: CropMode,
+    /// 检测到深色背景时先反色（模型只认浅底深字）
+    #[serde(default)]
+    pub auto_invert: bool,
     /// 对比度增强
     pub enhance_contrast: bool,
-    /// 模型推荐高度
-    pub target_height: u32,
+    /// Otsu 二值化为纯黑/纯白（在对比度增强之后、缩放之前，缩放插值会在边缘带回灰阶）
+    #[serde(default)]
+    pub binarize: bool,
+    /// 模型推荐高度（`None` 表示不缩放；有值时须在 1 到 `MAX_TARGET_HEIGHT` 之间）
+    pub target_height: Option<u32>,
 }
 
+/// `target_height` 的上限；超过此值的缩放请求视为无效
+pub const MAX_TARGET_HEIGHT: u32 = 1024;
+
+/// 缩放后宽高比上限：极宽的公式宽度被钳制为 `target_height * MAX_ASPECT_RATIO`
+pub const MAX_ASPECT_RATIO: u32 = 32;
+
 impl Default for PreprocessOptions {
     fn default() -> Self {
         Self {
             auto_crop: true,
+            crop_mode: CropMode::White,
+            auto_invert: false,
             enhance_contrast: false,
-            target_height: 64,
+            binarize: false,
+            target_height: Some(64),
         }
     }
 }
@@ -58,9 +91,41 @@ fn is_white_pixel(pixel: &image::Rgba<u8>) -> bool {
         && channels[2] >= WHITE_THRESHOLD
 }
 
-/// 自动裁边：检测非白色像素边界并裁剪
+/// 平均亮度（0–255，只统计不透明像素）低于此值视为深色背景
+const DARK_BACKGROUND_LUMA: f64 = 128.0;
+
+/// 判断图片是否为深色背景（如暗色主题编辑器中的浅色公式）
+///
+/// Formula strokes cover a small part of a screenshot, so the mean luminance
+/// of the opaque pixels is dominated by the background.
+fn has_dark_background(img: &DynamicImage) -> bool {
+    let rgba = img.to_rgba8();
+    let (sum, count) = rgba
+        .pixels()
+        .filter(|p| p[3] > 0)
+        .fold((0.0, 0usize), |(sum, count), p| (sum + p.to_luma()[0] as f64, count + 1));
+    count > 0 && sum / (count as f64) < DARK_BACKGROUND_LUMA
+}
+
+/// 深色背景时反色（保留 alpha），否则原样返回
+fn invert_dark_background(mut img: DynamicImage) -> DynamicImage {
+    if has_dark_background(&img) {
+        img.invert();
+    }
+    img
+}
+
+/// 判断一个像素在给定裁边方式下是否为背景
+fn is_background_pixel(pixel: &image::Rgba<u8>, mode: CropMode) -> bool {
+    match mode {
+        CropMode::White => is_white_pixel(pixel),
+        CropMode::Alpha => pixel.channels()[3] == 0,
+    }
+}
+
+/// 自动裁边：检测非背景像素边界并裁剪
 /// 在内容边界周围保留一定的 padding
-fn auto_crop(img: &DynamicImage) -> DynamicImage {
+fn auto_crop(img: &DynamicImage, mode: CropMode) -> DynamicImage {
     let (width, height) = img.dimensions();
     if width == 0 || height == 0 {
         return img.clone();
@@ -73,11 +138,11 @@ fn auto_crop(img: &DynamicImage) -> DynamicImage {
     let mut max_x: u32 = 0;
     let mut max_y: u32 = 0;
 
-    // 扫描所有像素，找到非白色像素的边界
+    // 扫描所有像素，找到非背景像素的边界
     for y in 0..height {
         for x in 0..width {
             let pixel = rgba.get_pixel(x, y);
-            if !is_white_pixel(pixel) {
+            if !is_background_pixel(pixel, mode) {
                 if x < min_x {
                     min_x = x;
                 }
@@ -116,6 +181,9 @@ fn auto_crop(img: &DynamicImage) -> DynamicImage {
 }
 
 /// 缩放图片到目标高度，保持宽高比
+///
+/// The width is clamped to `target_height * MAX_ASPECT_RATIO`, so extremely
+/// wide formulas are squeezed horizontally instead of producing huge images.
 fn scale_to_height(img: &DynamicImage, target_height: u32) -> DynamicImage {
     let (width, height) = img.dimensions();
     if height == 0 || width == 0 {
@@ -130,8 +198,8 @@ fn scale_to_height(img: &DynamicImage, target_height: u32) -> DynamicImage {
     // 计算保持宽高比的新宽度
     let scale = target_height as f64 / height as f64;
     let new_width = (width as f64 * scale).round() as u32;
-    // 确保宽度至少为 1
-    let new_width = new_width.max(1);
+    // 确保宽度至少为 1，且不超过宽高比上限
+    let new_width = new_width.clamp(1, target_height.saturating_mul(MAX_ASPECT_RATIO));
 
     img.resize_exact(new_width, target_height, FilterType::Lanczos3)
 }
@@ -185,36 +253,117 @@ fn enhance_contrast(img: &DynamicImage) -> DynamicImage {
     DynamicImage::ImageRgba8(rgba)
 }
 
+/// Otsu 二值化：按亮度直方图选取类间方差最大的阈值，映射为纯黑/纯白
+///
+/// Only opaque pixels count towards the histogram; alpha is kept as is.
+/// Pixels brighter than the threshold become white, the rest black, so a
+/// gray or tinted background with slightly darker strokes still separates.
+fn binarize_otsu(img: &DynamicImage) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    let mut histogram = [0u64; 256];
+    for pixel in rgba.pixels().filter(|p| p[3] > 0) {
+        histogram[pixel.to_luma()[0] as usize] += 1;
+    }
+    let threshold = otsu_threshold(&histogram);
+
+    for pixel in rgba.pixels_mut() {
+        let value = if pixel.to_luma()[0] > threshold { 255 } else { 0 };
+        let channels = pixel.channels_mut();
+        channels[0] = value;
+        channels[1] = value;
+        channels[2] = value;
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// 最大化类间方差的阈值（阈值本身归入暗类）
+fn otsu_threshold(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let luma_sum: f64 = histogram.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+
+    let (mut dark_count, mut dark_sum) = (0u64, 0.0);
+    let (mut best_threshold, mut best_variance) = (0u8, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        dark_count += count;
+        dark_sum += value as f64 * count as f64;
+        let light_count = total - dark_count;
+        if dark_count == 0 {
+            continue;
+        }
+        if light_count == 0 {
+            break;
+        }
+        let dark_mean = dark_sum / dark_count as f64;
+        let light_mean = (luma_sum - dark_sum) / light_count as f64;
+        let variance = dark_count as f64 * light_count as f64 * (dark_mean - light_mean).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = value as u8;
+        }
+    }
+    best_threshold
+}
+
+/// 校验 `target_height`：不缩放用 `None` 表示，`Some(0)` 与超过上限一样视为无效
+fn validate_target_height(options: &PreprocessOptions) -> Result<(), PreprocessError> {
+    match options.target_height {
+        Some(height) if height == 0 || height > MAX_TARGET_HEIGHT => {
+            Err(PreprocessError::ProcessingFailed(format!(
+                "目标高度超出范围: {} (应为 1 到 {})",
+                height, MAX_TARGET_HEIGHT
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
 /// 预处理图片，返回处理后的图片 PNG 字节
 ///
 /// 处理流程：
 /// 1. 从字节加载图片
-/// 2. 可选：自动裁边（检测非白色像素边界）
-/// 3. 可选：对比度增强
-/// 4. 缩放到目标高度（保持宽高比）
-/// 5. 编码为 PNG 字节返回
+/// 2. 可选：深色背景反色（`auto_invert`，浅底深字的图片不变）
+/// 3. 可选：自动裁边（按 `crop_mode` 检测非白色或非透明像素边界）
+/// 4. 可选：对比度增强
+/// 5. 可选：Otsu 二值化（`binarize`）
+/// 6. 缩放到目标高度（保持宽高比，宽度受 `MAX_ASPECT_RATIO` 限制）
+/// 7. 编码为 PNG 字节返回
+///
+/// `target_height` 为 `None` 时跳过缩放；为 0 或超过 `MAX_TARGET_HEIGHT` 时返回
+/// `ProcessingFailed`。
 pub fn preprocess(image_bytes: &[u8], options: &PreprocessOptions) -> Result<Vec<u8>, PreprocessError> {
+    validate_target_height(options)?;
+
     // 1. 从字节加载图片
     let mut img = image::load_from_memory(image_bytes).map_err(|e| {
         PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
     })?;
 
-    // 2. 自动裁边
+    // 2. 深色背景反色（在裁边之前，白色裁边才能找到内容）
+    if options.auto_invert {
+        img = invert_dark_background(img);
+    }
+
+    // 3. 自动裁边
     if options.auto_crop {
-        img = auto_crop(&img);
+        img = auto_crop(&img, options.crop_mode);
     }
 
-    // 3. 对比度增强
+    // 4. 对比度增强
     if options.enhance_contrast {
         img = enhance_contrast(&img);
     }
 
-    // 4. 缩放到目标高度
-    if options.target_height > 0 {
-        img = scale_to_height(&img, options.target_height);
+    // 5. Otsu 二值化
+    if options.binarize {
+        img = binarize_otsu(&img);
+    }
+
+    // 6. 缩放到目标高度
+    if let Some(target_height) = options.target_height {
+        img = scale_to_height(&img, target_height);
     }
 
-    // 5. 编码为 PNG 字节
+    // 7. 编码为 PNG 字节
     let mut output = Cursor::new(Vec::new());
     img.write_to(&mut output, ImageFormat::Png).map_err(|e| {
         PreprocessError::ProcessingFailed(format!("PNG 编码失败: {}", e))
@@ -223,6 +372,505 @@ pub fn preprocess(image_bytes: &[u8], options: &PreprocessOptions) -> Result<Vec
     Ok(output.into_inner())
 }
 
+/// 单个预处理步骤的效果
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StepTrace {
+    /// 选项中是否开启该步骤
+    pub enabled: bool,
+    /// 是否实际修改了图片（关闭的步骤总是 `false`）
+    pub changed: bool,
+    /// 步骤前的尺寸 (宽, 高)
+    pub size_before: (u32, u32),
+    /// 步骤后的尺寸 (宽, 高)
+    pub size_after: (u32, u32),
+}
+
+/// 预处理各步骤的效果，供用户调整选项时参考
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreprocessTrace {
+    /// 深色背景反色；`changed` 即是否检测到深色背景并反色
+    pub invert: StepTrace,
+    pub crop: StepTrace,
+    /// 裁边减少的面积百分比（0–100）
+    pub crop_area_reduction: f64,
+    pub contrast: StepTrace,
+    /// 对比度增强改变的像素百分比（0–100）
+    pub contrast_pixels_changed: f64,
+    pub binarize: StepTrace,
+    /// 二值化改变的像素百分比（0–100）
+    pub binarize_pixels_changed: f64,
+    pub scale: StepTrace,
+    /// 缩放倍数（新高度 / 原高度）；未缩放时为 1.0
+    pub scale_factor: f64,
+}
+
+/// 按与 `preprocess` 相同的流程处理图片，报告反色、裁边、对比度增强、二值化
+/// 与缩放各步骤是否、以及在多大程度上改变了图片
+///
+/// 关闭的步骤报告为未开启且未改变；开启但没有效果的步骤（如无白边可裁、
+/// 已是目标高度）报告为未改变。选项校验与 `preprocess` 一致。
+pub fn preprocess_trace(image_bytes: &[u8], options: &PreprocessOptions) -> Result<PreprocessTrace, PreprocessError> {
+    validate_target_height(options)?;
+    let mut img = image::load_from_memory(image_bytes).map_err(|e| {
+        PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
+    })?;
+    let area = |(w, h): (u32, u32)| w as f64 * h as f64;
+
+    let before = img.dimensions();
+    let inverted = options.auto_invert && has_dark_background(&img);
+    if inverted {
+        img.invert();
+    }
+    let invert = StepTrace {
+        enabled: options.auto_invert,
+        changed: inverted,
+        size_before: before,
+        size_after: img.dimensions(),
+    };
+
+    let before = img.dimensions();
+    if options.auto_crop {
+        img = auto_crop(&img, options.crop_mode);
+    }
+    let crop = StepTrace {
+        enabled: options.auto_crop,
+        changed: img.dimensions() != before,
+        size_before: before,
+        size_after: img.dimensions(),
+    };
+    let crop_area_reduction = if area(before) > 0.0 {
+        (1.0 - area(img.dimensions()) / area(before)) * 100.0
+    } else {
+        0.0
+    };
+
+    let pixels_changed = |old: &DynamicImage, new: &DynamicImage| {
+        let (old, new) = (old.to_rgba8(), new.to_rgba8());
+        let changed = old.pixels().zip(new.pixels()).filter(|(a, b)| a != b).count();
+        changed as f64 / area(old.dimensions()).max(1.0) * 100.0
+    };
+
+    let before = img.dimensions();
+    let mut contrast_pixels_changed = 0.0;
+    if options.enhance_contrast {
+        let enhanced = enhance_contrast(&img);
+        contrast_pixels_changed = pixels_changed(&img, &enhanced);
+        img = enhanced;
+    }
+    let contrast = StepTrace {
+        enabled: options.enhance_contrast,
+        changed: contrast_pixels_changed > 0.0,
+        size_before: before,
+        size_after: img.dimensions(),
+    };
+
+    let before = img.dimensions();
+    let mut binarize_pixels_changed = 0.0;
+    if options.binarize {
+        let binarized = binarize_otsu(&img);
+        binarize_pixels_changed = pixels_changed(&img, &binarized);
+        img = binarized;
+    }
+    let binarize = StepTrace {
+        enabled: options.binarize,
+        changed: binarize_pixels_changed > 0.0,
+        size_before: before,
+        size_after: img.dimensions(),
+    };
+
+    let before = img.dimensions();
+    if let Some(target_height) = options.target_height {
+        img = scale_to_height(&img, target_height);
+    }
+    let scale = StepTrace {
+        enabled: options.target_height.is_some(),
+        changed: img.dimensions() != before,
+        size_before: before,
+        size_after: img.dimensions(),
+    };
+    let scale_factor = if before.1 > 0 {
+        img.height() as f64 / before.1 as f64
+    } else {
+        1.0
+    };
+
+    Ok(PreprocessTrace {
+        invert,
+        crop,
+        crop_area_reduction,
+        contrast,
+        contrast_pixels_changed,
+        binarize,
+        binarize_pixels_changed,
+        scale,
+        scale_factor,
+    })
+}
+
+/// 低于此高度的截图建议放大后再识别（模型输入高度的一半）
+pub const MIN_RECOMMENDED_HEIGHT: u32 = 32;
+
+/// 亮度范围（0–1）低于此值视为低对比度
+pub const MIN_RECOMMENDED_CONTRAST: f64 = 0.5;
+
+/// 识别前的图片质量建议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityAdvice {
+    /// 质量良好，可直接识别
+    Good,
+    /// 图片太小，建议放大截图区域或缩放后再识别
+    TooSmall,
+    /// 对比度低，建议开启对比度增强
+    LowContrast,
+    /// 没有检测到内容（全白或全透明）
+    NoContent,
+}
+
+/// 图片质量评估结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageQuality {
+    pub width: u32,
+    pub height: u32,
+    /// 非白色（内容）像素占比 0.0 ~ 1.0
+    pub content_density: f64,
+    /// 灰度范围 (max - min) / 255，0.0 ~ 1.0
+    pub contrast: f64,
+    /// 深色背景（浅色公式），开启 `auto_invert` 后识别前会反色
+    pub dark_background: bool,
+    /// 建议列表；没有问题时只有 `Good`
+    pub recommendations: Vec<QualityAdvice>,
+}
+
+/// 评估 OCR 输入质量，供前端在识别前提示用户
+///
+/// 报告分辨率、内容密度、对比度以及是否为深色背景，并给出建议：高度低于
+/// `MIN_RECOMMENDED_HEIGHT` 为 `TooSmall`，对比度低于
+/// `MIN_RECOMMENDED_CONTRAST` 为 `LowContrast`，没有内容像素为 `NoContent`。
+pub fn assess_image(image_bytes: &[u8]) -> Result<ImageQuality, PreprocessError> {
+    let img = image::load_from_memory(image_bytes).map_err(|e| {
+        PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
+    })?;
+    let (width, height) = img.dimensions();
+
+    let rgba = img.to_rgba8();
+    let content_pixels = rgba.pixels().filter(|p| !is_white_pixel(p)).count();
+    let total_pixels = (width as usize * height as usize).max(1);
+    let content_density = content_pixels as f64 / total_pixels as f64;
+
+    let gray = img.to_luma8();
+    let (min_val, max_val) = gray
+        .pixels()
+        .fold((255u8, 0u8), |(lo, hi), p| (lo.min(p[0]), hi.max(p[0])));
+    let contrast = max_val.saturating_sub(min_val) as f64 / 255.0;
+
+    let mut recommendations = Vec::new();
+    if height < MIN_RECOMMENDED_HEIGHT {
+        recommendations.push(QualityAdvice::TooSmall);
+    }
+    if content_pixels == 0 {
+        recommendations.push(QualityAdvice::NoContent);
+    } else if contrast < MIN_RECOMMENDED_CONTRAST {
+        recommendations.push(QualityAdvice::LowContrast);
+    }
+    if recommendations.is_empty() {
+        recommendations.push(QualityAdvice::Good);
+    }
+
+    Ok(ImageQuality {
+        width,
+        height,
+        content_density,
+        contrast,
+        dark_background: has_dark_background(&img),
+        recommendations,
+    })
+}
+
+/// 批量预处理多张图片（并行）
+///
+/// 结果与输入按顺序一一对应；单张图片失败只记录在对应位置的 `Err`
+/// 中，不会中断整个批次。
+pub fn preprocess_many(images: Vec<Vec<u8>>, options: &PreprocessOptions) -> Vec<Result<Vec<u8>, String>> {
+    images
+        .par_iter()
+        .map(|bytes| preprocess(bytes, options).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// 分行时行间空白的最小高度（像素）；更窄的空隙（如分数线上下）不拆分
+pub const MIN_LINE_GAP: u32 = 8;
+
+/// 将多行公式截图按水平空白带切分为逐行图片（PNG）
+///
+/// 连续至少 `MIN_LINE_GAP` 行全白的像素行视为行间隔。每一行保留整幅宽度，
+/// 裁边交给后续的 `preprocess`。没有检测到内容或只有一行时返回原图，
+/// 与单行识别一致。
+pub fn segment_lines(image_bytes: &[u8]) -> Result<Vec<Vec<u8>>, PreprocessError> {
+    let img = image::load_from_memory(image_bytes).map_err(|e| {
+        PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
+    })?;
+    let (width, height) = img.dimensions();
+    let rgba = img.to_rgba8();
+    let row_has_content =
+        |y: u32| (0..width).any(|x| !is_white_pixel(rgba.get_pixel(x, y)));
+
+    // 收集内容行区间 [start, end)，间隔不足 MIN_LINE_GAP 的合并
+    let mut bands: Vec<(u32, u32)> = Vec::new();
+    for y in (0..height).filter(|&y| row_has_content(y)) {
+        match bands.last_mut() {
+            Some((_, end)) if y - *end < MIN_LINE_GAP => *end = y + 1,
+            _ => bands.push((y, y + 1)),
+        }
+    }
+
+    if bands.len() <= 1 {
+        return Ok(vec![image_bytes.to_vec()]);
+    }
+
+    bands
+        .into_iter()
+        .map(|(start, end)| {
+            let mut output = Cursor::new(Vec::new());
+            img.crop_imm(0, start, width, end - start)
+                .write_to(&mut output, ImageFormat::Png)
+                .map_err(|e| PreprocessError::ProcessingFailed(format!("PNG 编码失败: {}", e)))?;
+            Ok(output.into_inner())
+        })
+        .collect()
+}
+
+/// 图片输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// 无损 PNG
+    Png,
+    /// JPEG，`quality` 取值 1–100
+    Jpeg { quality: u8 },
+}
+
+/// 将图片重新编码为指定格式
+///
+/// JPEG 没有透明通道，透明像素会先与白色背景合成（与 `is_white_pixel`
+/// 把透明视为背景的约定一致）。
+pub fn reencode(image_bytes: &[u8], format: OutputFormat) -> Result<Vec<u8>, PreprocessError> {
+    let img = image::load_from_memory(image_bytes).map_err(|e| {
+        PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
+    })?;
+
+    let mut output = Vec::new();
+    match format {
+        OutputFormat::Png => {
+            img.write_to(&mut Cursor::new(&mut output), ImageFormat::Png).map_err(|e| {
+                PreprocessError::ProcessingFailed(format!("PNG 编码失败: {}", e))
+            })?;
+        }
+        OutputFormat::Jpeg { quality } => {
+            let rgba = img.to_rgba8();
+            let rgb = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+                image::Rgb([blend(r), blend(g), blend(b)])
+            });
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality.clamp(1, 100))
+                .encode_image(&rgb)
+                .map_err(|e| PreprocessError::ProcessingFailed(format!("JPEG 编码失败: {}", e)))?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// 按墨迹深浅重新着色（用于深色模式等预览配色），返回 PNG
+///
+/// 像素先与白色背景合成，灰度越深视为墨迹越浓：输出颜色在 `background` 与
+/// `ink` 之间按墨迹浓度插值；`background` 为 `None` 时输出 `ink` 颜色，
+/// 墨迹浓度作为透明度。
+pub fn recolor(image_bytes: &[u8], ink: [u8; 3], background: Option<[u8; 3]>) -> Result<Vec<u8>, PreprocessError> {
+    let img = image::load_from_memory(image_bytes).map_err(|e| {
+        PreprocessError::InvalidFormat(format!("无法解码图片: {}", e))
+    })?;
+
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let blend = |c: u8| (c as u32 * a as u32 + 255 * (255 - a as u32)) / 255;
+        let luma = (299 * blend(r) + 587 * blend(g) + 114 * blend(b)) / 1000;
+        let amount = 255 - luma.min(255);
+        pixel.0 = match background {
+            Some(bg) => {
+                let mix = |i: usize| ((bg[i] as u32 * (255 - amount) + ink[i] as u32 * amount) / 255) as u8;
+                [mix(0), mix(1), mix(2), 255]
+            }
+            None => [ink[0], ink[1], ink[2], amount as u8],
+        };
+    }
+
+    let mut output = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(rgba).write_to(&mut output, ImageFormat::Png).map_err(|e| {
+        PreprocessError::ProcessingFailed(format!("PNG 编码失败: {}", e))
+    })?;
+    Ok(output.into_inner())
+}
+
+/// PNG 文件签名
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// 识别引擎版本使用的 tEXt 关键字
+pub const ENGINE_VERSION_KEYWORD: &str = "engine_version";
+
+/// 计算 PNG 块使用的 CRC-32（IEEE 802.3 多项式）
+fn png_crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// 编码一个完整的 PNG 块（长度 + 类型 + 数据 + CRC）
+fn encode_png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    let crc = png_crc32(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+/// 将字符串编码为 Latin-1（tEXt 块要求），含 NUL 或超出 Latin-1 时返回 None
+fn to_latin1(s: &str) -> Option<Vec<u8>> {
+    s.chars()
+        .map(|c| match c as u32 {
+            0 => None,
+            code @ 1..=0xFF => Some(code as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 向 PNG 字节中写入一个 tEXt 文本块
+///
+/// 新块插入在 IHDR 之后；若已存在相同关键字的 tEXt 块则先移除，
+/// 因此重复写入不会产生重复的元数据。其余块原样保留，输出仍是合法 PNG。
+///
+/// 关键字须为 1–79 个 Latin-1 字符，文本须为 Latin-1 且不含 NUL。
+pub fn insert_png_text_chunk(
+    png_bytes: &[u8],
+    keyword: &str,
+    text: &str,
+) -> Result<Vec<u8>, PreprocessError> {
+    let keyword_bytes = png_keyword(keyword)?;
+    let text_bytes = to_latin1(text).ok_or_else(|| {
+        PreprocessError::ProcessingFailed(format!("tEXt 文本必须为 Latin-1 字符: '{}'", text))
+    })?;
+
+    let mut data = keyword_bytes.clone();
+    data.push(0);
+    data.extend_from_slice(&text_bytes);
+    replace_png_keyword_chunk(png_bytes, b"tEXt", &keyword_bytes, &data)
+}
+
+/// 向 PNG 字节中写入一个 iTXt 文本块（未压缩的 UTF-8 文本）
+///
+/// 插入和替换规则与 `insert_png_text_chunk` 相同，但文本可以含任意
+/// Unicode 字符（如 LaTeX 中的中文）。关键字仍须为 1–79 个 Latin-1 字符。
+pub fn insert_png_itxt_chunk(
+    png_bytes: &[u8],
+    keyword: &str,
+    text: &str,
+) -> Result<Vec<u8>, PreprocessError> {
+    let keyword_bytes = png_keyword(keyword)?;
+
+    // keyword \0, no compression (flag + method), empty language tag and translated keyword
+    let mut data = keyword_bytes.clone();
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(text.as_bytes());
+    replace_png_keyword_chunk(png_bytes, b"iTXt", &keyword_bytes, &data)
+}
+
+/// 校验并编码 tEXt / iTXt 关键字（1–79 个 Latin-1 字符）
+fn png_keyword(keyword: &str) -> Result<Vec<u8>, PreprocessError> {
+    to_latin1(keyword)
+        .filter(|k| !k.is_empty() && k.len() <= 79)
+        .ok_or_else(|| {
+            PreprocessError::ProcessingFailed(format!("无效的 tEXt 关键字: '{}'", keyword))
+        })
+}
+
+/// 在 IHDR 之后插入一个 `chunk_type` 块，并移除同类型、同关键字的旧块
+fn replace_png_keyword_chunk(
+    png_bytes: &[u8],
+    chunk_type: &[u8; 4],
+    keyword_bytes: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, PreprocessError> {
+    if png_bytes.len() < PNG_SIGNATURE.len() || png_bytes[..8] != PNG_SIGNATURE {
+        return Err(PreprocessError::InvalidFormat("不是 PNG 数据".to_string()));
+    }
+    let new_chunk = encode_png_chunk(chunk_type, data);
+
+    let mut output = Vec::with_capacity(png_bytes.len() + new_chunk.len());
+    output.extend_from_slice(&PNG_SIGNATURE);
+
+    let mut pos = PNG_SIGNATURE.len();
+    let mut inserted = false;
+    while pos < png_bytes.len() {
+        if pos + 12 > png_bytes.len() {
+            return Err(PreprocessError::InvalidFormat("PNG 块被截断".to_string()));
+        }
+        let length = u32::from_be_bytes([
+            png_bytes[pos],
+            png_bytes[pos + 1],
+            png_bytes[pos + 2],
+            png_bytes[pos + 3],
+        ]) as usize;
+        let end = pos + 12 + length;
+        if end > png_bytes.len() {
+            return Err(PreprocessError::InvalidFormat("PNG 块被截断".to_string()));
+        }
+        let existing_type = &png_bytes[pos + 4..pos + 8];
+        let chunk_data = &png_bytes[pos + 8..pos + 8 + length];
+
+        // 跳过同关键字的旧块
+        let is_same_text = existing_type == chunk_type
+            && chunk_data.len() > keyword_bytes.len()
+            && chunk_data[..keyword_bytes.len()] == keyword_bytes[..]
+            && chunk_data[keyword_bytes.len()] == 0;
+        if !is_same_text {
+            output.extend_from_slice(&png_bytes[pos..end]);
+        }
+
+        if existing_type == b"IHDR" && !inserted {
+            output.extend_from_slice(&new_chunk);
+            inserted = true;
+        }
+        pos = end;
+    }
+
+    if !inserted {
+        return Err(PreprocessError::InvalidFormat("PNG 缺少 IHDR 块".to_string()));
+    }
+
+    Ok(output)
+}
+
+/// 在 PNG 缩略图中写入识别引擎版本（`tEXt` "engine_version"）
+///
+/// `export::export_one` 导出 PNG 时调用，使导出的图片携带识别来源信息。
+pub fn stamp_engine_version(
+    png_bytes: &[u8],
+    engine_version: &str,
+) -> Result<Vec<u8>, PreprocessError> {
+    insert_png_text_chunk(png_bytes, ENGINE_VERSION_KEYWORD, engine_version)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -278,8 +926,7 @@ mod tests {
             // Use options without auto_crop to test pure scaling behavior
             let options = PreprocessOptions {
                 auto_crop: false,
-                enhance_contrast: false,
-                target_height: 64,
+                ..Default::default()
             };
             
             // Preprocess the image
@@ -332,11 +979,7 @@ mod tests {
             // Create test image with content
             let image_bytes = create_proptest_image(width, height);
             
-            let options = PreprocessOptions {
-                auto_crop: true,
-                enhance_contrast: false,
-                target_height: 64,
-            };
+            let options = PreprocessOptions::default();
             
             let result = preprocess(&image_bytes, &options);
             prop_assert!(result.is_ok(), "Preprocessing should succeed");
@@ -364,6 +1007,199 @@ mod tests {
     // Unit tests
     // ============================================================
 
+    #[test]
+    fn test_reencode_png_to_jpeg() {
+        let png = create_proptest_image(32, 16);
+        let jpeg = reencode(&png, OutputFormat::Jpeg { quality: 80 }).expect("reencode should succeed");
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 16));
+    }
+
+    #[test]
+    fn test_reencode_jpeg_flattens_transparency_to_white() {
+        let img = ImageBuffer::from_fn(8, 8, |_, _| Rgba([0u8, 0, 0, 0]));
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(img).write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let jpeg = reencode(&png.into_inner(), OutputFormat::Jpeg { quality: 90 }).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        assert!(decoded.pixels().all(|p| p.0.iter().all(|&c| c >= 250)));
+    }
+
+    #[test]
+    fn test_reencode_invalid_bytes() {
+        let result = reencode(b"not an image", OutputFormat::Png);
+        assert!(matches!(result, Err(PreprocessError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_assess_tiny_low_contrast_image() {
+        let quality = assess_image(&create_low_contrast_image(40, 12)).unwrap();
+        assert_eq!((quality.width, quality.height), (40, 12));
+        assert!(quality.contrast < MIN_RECOMMENDED_CONTRAST);
+        assert_eq!(
+            quality.recommendations,
+            vec![QualityAdvice::TooSmall, QualityAdvice::LowContrast]
+        );
+    }
+
+    #[test]
+    fn test_assess_clean_image_is_good() {
+        let quality = assess_image(&create_image_with_content(200, 80, 40, 20, 120, 40)).unwrap();
+        assert_eq!(quality.recommendations, vec![QualityAdvice::Good]);
+        assert_eq!(quality.contrast, 1.0);
+        assert!((quality.content_density - 0.3).abs() < 1e-9);
+        assert!(!quality.dark_background);
+    }
+
+    #[test]
+    fn test_auto_invert_only_flips_dark_backgrounds() {
+        // White 20x10 formula on a black 100x40 background
+        let img = ImageBuffer::from_fn(100, 40, |x, y| {
+            if (40..60).contains(&x) && (15..25).contains(&y) {
+                Rgba([255u8, 255, 255, 255])
+            } else {
+                Rgba([0u8, 0, 0, 255])
+            }
+        });
+        let mut buf = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(img).write_to(&mut buf, ImageFormat::Png).unwrap();
+        let dark = buf.into_inner();
+        assert!(assess_image(&dark).unwrap().dark_background);
+
+        let options = PreprocessOptions { auto_invert: true, target_height: None, ..PreprocessOptions::default() };
+        let output = image::load_from_memory(&preprocess(&dark, &options).unwrap()).unwrap();
+        // Inverted to dark-on-light, so white cropping trims to the formula
+        assert_eq!(output.dimensions(), (28, 18));
+        assert_eq!(output.to_rgba8().get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(output.to_rgba8().get_pixel(14, 9).0, [0, 0, 0, 255]);
+        let trace = preprocess_trace(&dark, &options).unwrap();
+        assert!(trace.invert.enabled && trace.invert.changed);
+        assert_eq!(trace.crop.size_after, (28, 18));
+
+        // Light backgrounds are left alone
+        let light = create_image_with_content(100, 40, 40, 15, 20, 10);
+        let output = image::load_from_memory(&preprocess(&light, &options).unwrap()).unwrap();
+        assert_eq!(output.to_rgba8().get_pixel(14, 9).0, [0, 0, 0, 255]);
+        let trace = preprocess_trace(&light, &options).unwrap();
+        assert!(trace.invert.enabled && !trace.invert.changed);
+    }
+
+    #[test]
+    fn test_binarize_low_contrast_gray_image() {
+        // Slightly darker strokes on a noisy mid-gray background
+        let img = ImageBuffer::from_fn(120, 40, |x, y| {
+            let noise = ((x * 7 + y * 3) % 9) as u8;
+            if (30..90).contains(&x) && (15..25).contains(&y) {
+                Rgba([100 + noise, 100 + noise, 100 + noise, 255])
+            } else {
+                Rgba([135 + noise, 135 + noise, 135 + noise, 255])
+            }
+        });
+        let mut buf = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(img).write_to(&mut buf, ImageFormat::Png).unwrap();
+
+        let options = PreprocessOptions {
+            auto_crop: false,
+            binarize: true,
+            target_height: None,
+            ..PreprocessOptions::default()
+        };
+        let output = image::load_from_memory(&preprocess(&buf.into_inner(), &options).unwrap())
+            .unwrap()
+            .to_luma8();
+
+        assert!(output.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        assert_eq!(output.get_pixel(0, 0)[0], 255, "background turns white");
+        assert_eq!(output.get_pixel(60, 20)[0], 0, "strokes turn black");
+        assert_eq!(output.pixels().filter(|p| p[0] == 0).count(), 60 * 10);
+    }
+
+    #[test]
+    fn test_assess_blank_image_has_no_content() {
+        let quality = assess_image(&create_white_image(100, 100)).unwrap();
+        assert_eq!(quality.content_density, 0.0);
+        assert_eq!(quality.recommendations, vec![QualityAdvice::NoContent]);
+        assert!(matches!(assess_image(b"not an image"), Err(PreprocessError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_segment_lines_splits_on_blank_gap() {
+        // Two content rows 20px apart; a 3px gap (fraction bar spacing) stays joined
+        let img = ImageBuffer::from_fn(60, 70, |_, y| {
+            if (5..15).contains(&y) || (18..20).contains(&y) || (40..55).contains(&y) {
+                Rgba([0u8, 0, 0, 255])
+            } else {
+                Rgba([255u8, 255, 255, 255])
+            }
+        });
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(img).write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let lines = segment_lines(&png.into_inner()).unwrap();
+        let heights: Vec<u32> = lines
+            .iter()
+            .map(|l| image::load_from_memory(l).unwrap().height())
+            .collect();
+        assert_eq!(heights, vec![15, 15]);
+    }
+
+    #[test]
+    fn test_segment_lines_single_line_returns_original() {
+        let image_bytes = create_image_with_content(200, 80, 40, 20, 120, 40);
+        assert_eq!(segment_lines(&image_bytes).unwrap(), vec![image_bytes.clone()]);
+        let blank = create_white_image(50, 50);
+        assert_eq!(segment_lines(&blank).unwrap(), vec![blank.clone()]);
+    }
+
+    #[test]
+    fn test_preview_options_change_output_dimensions() {
+        let image_bytes = create_image_with_content(200, 100, 50, 25, 100, 50);
+        let dimensions = |options: PreprocessOptions| {
+            let output = preprocess(&image_bytes, &options).unwrap();
+            image::load_from_memory(&output).unwrap().dimensions()
+        };
+        let raw = dimensions(PreprocessOptions { auto_crop: false, target_height: None, ..Default::default() });
+        let cropped = dimensions(PreprocessOptions { target_height: None, ..Default::default() });
+        let scaled = dimensions(PreprocessOptions { target_height: Some(32), ..Default::default() });
+        assert_eq!(raw, (200, 100));
+        assert!(cropped.0 < raw.0 && cropped.1 < raw.1, "crop should shrink: {:?}", cropped);
+        assert_eq!(scaled.1, 32);
+        assert_ne!(cropped, scaled);
+    }
+
+    #[test]
+    fn test_preprocess_many_preserves_order() {
+        let images: Vec<Vec<u8>> = (1..=8).map(|i| create_white_image(i * 10, 20)).collect();
+        let options = PreprocessOptions {
+            auto_crop: false,
+            target_height: None,
+            ..Default::default()
+        };
+        let results = preprocess_many(images, &options);
+        assert_eq!(results.len(), 8);
+        for (i, result) in results.iter().enumerate() {
+            let decoded = image::load_from_memory(result.as_ref().unwrap()).unwrap();
+            assert_eq!(decoded.dimensions(), ((i as u32 + 1) * 10, 20));
+        }
+    }
+
+    #[test]
+    fn test_preprocess_many_keeps_going_after_invalid_image() {
+        let images = vec![
+            create_white_image(40, 20),
+            b"not an image".to_vec(),
+            create_image_with_content(100, 50, 10, 10, 30, 20),
+        ];
+        let results = preprocess_many(images, &PreprocessOptions::default());
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].as_ref().unwrap_err().contains("图片格式无效"));
+        let third = image::load_from_memory(results[2].as_ref().unwrap()).unwrap();
+        assert_eq!(third.height(), 64);
+    }
+
     /// 辅助函数：创建一个纯白色的 PNG 图片字节
     fn create_white_image(width: u32, height: u32) -> Vec<u8> {
         let img = ImageBuffer::from_fn(width, height, |_, _| Rgba([255u8, 255, 255, 255]));
@@ -413,6 +1249,74 @@ mod tests {
         buf.into_inner()
     }
 
+    #[test]
+    fn test_preprocess_trace_disabled_steps_show_no_change() {
+        let image = create_image_with_content(200, 100, 50, 30, 100, 40);
+        let options = PreprocessOptions {
+            auto_crop: false,
+            target_height: None,
+            ..Default::default()
+        };
+        let trace = preprocess_trace(&image, &options).expect("trace should succeed");
+
+        for step in [trace.invert, trace.crop, trace.contrast, trace.binarize, trace.scale] {
+            assert!(!step.enabled && !step.changed, "{:?}", step);
+            assert_eq!(step.size_before, (200, 100));
+            assert_eq!(step.size_after, (200, 100));
+        }
+        assert_eq!(trace.crop_area_reduction, 0.0);
+        assert_eq!(trace.contrast_pixels_changed, 0.0);
+        assert_eq!(trace.binarize_pixels_changed, 0.0);
+        assert_eq!(trace.scale_factor, 1.0);
+    }
+
+    #[test]
+    fn test_preprocess_trace_crop_reports_area_reduction() {
+        // 100x40 content + 4px padding on each side → 108x48 out of 200x100
+        let image = create_image_with_content(200, 100, 50, 30, 100, 40);
+        let trace = preprocess_trace(&image, &PreprocessOptions::default()).expect("trace should succeed");
+
+        assert!(trace.crop.enabled && trace.crop.changed);
+        assert_eq!(trace.crop.size_after, (108, 48));
+        let expected = (1.0 - (108.0 * 48.0) / (200.0 * 100.0)) * 100.0;
+        assert!((trace.crop_area_reduction - expected).abs() < 1e-9, "{}", trace.crop_area_reduction);
+
+        // Default scaling takes the cropped 48px height to 64px
+        assert!(trace.scale.changed);
+        assert_eq!(trace.scale.size_before, (108, 48));
+        assert!((trace.scale_factor - 64.0 / 48.0).abs() < 1e-9);
+        // Contrast is off by default; black on white would be unchanged anyway
+        assert!(!trace.contrast.enabled && !trace.contrast.changed);
+    }
+
+    #[test]
+    fn test_preprocess_trace_contrast_and_validation() {
+        let options = PreprocessOptions {
+            auto_crop: false,
+            enhance_contrast: true,
+            target_height: None,
+            ..Default::default()
+        };
+        let trace = preprocess_trace(&create_low_contrast_image(100, 20), &options).unwrap();
+        assert!(trace.contrast.enabled && trace.contrast.changed);
+        assert!(trace.contrast_pixels_changed > 0.0);
+
+        // Already full contrast: enabled but no change
+        let trace = preprocess_trace(&create_image_with_content(50, 50, 10, 10, 10, 10), &options).unwrap();
+        assert!(trace.contrast.enabled && !trace.contrast.changed);
+
+        // Every gray level snaps to black or white
+        let binarize = PreprocessOptions { enhance_contrast: false, binarize: true, ..options.clone() };
+        let trace = preprocess_trace(&create_low_contrast_image(100, 20), &binarize).unwrap();
+        assert!(trace.binarize.enabled && trace.binarize.changed);
+        assert!(trace.binarize_pixels_changed > 0.0);
+        assert_eq!(trace.binarize.size_after, (100, 20));
+
+        let too_tall = PreprocessOptions { target_height: Some(MAX_TARGET_HEIGHT + 1), ..PreprocessOptions::default() };
+        assert!(matches!(preprocess_trace(&create_white_image(10, 10), &too_tall), Err(PreprocessError::ProcessingFailed(_))));
+        assert!(matches!(preprocess_trace(b"nope", &PreprocessOptions::default()), Err(PreprocessError::InvalidFormat(_))));
+    }
+
     #[test]
     fn test_preprocess_invalid_bytes() {
         let options = PreprocessOptions::default();
@@ -429,8 +1333,7 @@ mod tests {
         let image_bytes = create_white_image(100, 100);
         let options = PreprocessOptions {
             auto_crop: false,
-            enhance_contrast: false,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options);
         assert!(result.is_ok());
@@ -446,8 +1349,7 @@ mod tests {
         let image_bytes = create_white_image(200, 100);
         let options = PreprocessOptions {
             auto_crop: false,
-            enhance_contrast: false,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -463,8 +1365,7 @@ mod tests {
         let image_bytes = create_white_image(300, 150);
         let options = PreprocessOptions {
             auto_crop: false,
-            enhance_contrast: false,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -474,14 +1375,54 @@ mod tests {
         assert_eq!(w, 128);
     }
 
+    #[test]
+    fn test_huge_target_height_is_rejected() {
+        let image_bytes = create_white_image(20, 10);
+        let options = PreprocessOptions {
+            auto_crop: false,
+            target_height: Some(1_000_000),
+            ..Default::default()
+        };
+        match preprocess(&image_bytes, &options) {
+            Err(PreprocessError::ProcessingFailed(msg)) => assert!(msg.contains("目标高度")),
+            other => panic!("Expected ProcessingFailed, got: {:?}", other.map(|b| b.len())),
+        }
+    }
+
+    #[test]
+    fn test_zero_target_height_is_rejected_not_skipped() {
+        let image_bytes = create_white_image(20, 10);
+        let options = PreprocessOptions { auto_crop: false, target_height: Some(0), ..Default::default() };
+        assert!(matches!(preprocess(&image_bytes, &options), Err(PreprocessError::ProcessingFailed(_))));
+        assert!(matches!(preprocess_trace(&image_bytes, &options), Err(PreprocessError::ProcessingFailed(_))));
+
+        let options = PreprocessOptions { auto_crop: false, target_height: None, ..Default::default() };
+        let result = preprocess(&image_bytes, &options).unwrap();
+        assert_eq!(image::load_from_memory(&result).unwrap().dimensions(), (20, 10));
+    }
+
+    #[test]
+    fn test_scale_clamps_extreme_aspect_ratio() {
+        // 4000x10 is 400:1, far wider than MAX_ASPECT_RATIO
+        let image_bytes = create_white_image(4000, 10);
+        let options = PreprocessOptions {
+            auto_crop: false,
+            target_height: Some(20),
+            ..Default::default()
+        };
+        let result = preprocess(&image_bytes, &options).unwrap();
+        let (w, h) = image::load_from_memory(&result).unwrap().dimensions();
+        assert_eq!(h, 20);
+        assert_eq!(w, 20 * MAX_ASPECT_RATIO);
+    }
+
     #[test]
     fn test_auto_crop_removes_whitespace() {
         // Create a 200x200 image with a 20x20 black square at (90, 90)
         let image_bytes = create_image_with_content(200, 200, 90, 90, 20, 20);
         let options = PreprocessOptions {
-            auto_crop: true,
-            enhance_contrast: false,
-            target_height: 0, // disable scaling for this test
+            target_height: None, // disable scaling for this test
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -498,9 +1439,8 @@ mod tests {
         // All-white image should not be cropped
         let image_bytes = create_white_image(100, 80);
         let options = PreprocessOptions {
-            auto_crop: true,
-            enhance_contrast: false,
-            target_height: 0, // disable scaling
+            target_height: None, // disable scaling
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -513,11 +1453,7 @@ mod tests {
     fn test_auto_crop_with_scaling() {
         // Create a 200x200 image with a 50x30 black rectangle at (75, 85)
         let image_bytes = create_image_with_content(200, 200, 75, 85, 50, 30);
-        let options = PreprocessOptions {
-            auto_crop: true,
-            enhance_contrast: false,
-            target_height: 64,
-        };
+        let options = PreprocessOptions::default();
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
         let (w, h) = output_img.dimensions();
@@ -525,13 +1461,49 @@ mod tests {
         assert!(w > 0, "Width should be positive");
     }
 
+    #[test]
+    fn test_alpha_crop_trims_to_opaque_square() {
+        // 200x200 transparent canvas with an opaque white 10x10 square at (95, 95):
+        // white-mode cropping cannot see it, alpha mode crops to it (plus padding)
+        let img = ImageBuffer::from_fn(200, 200, |x, y| {
+            if (95..105).contains(&x) && (95..105).contains(&y) {
+                Rgba([255u8, 255, 255, 255])
+            } else {
+                Rgba([0u8, 0, 0, 0])
+            }
+        });
+        let mut buf = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(img).write_to(&mut buf, ImageFormat::Png).unwrap();
+        let image_bytes = buf.into_inner();
+
+        let crop = |crop_mode: CropMode| {
+            let options = PreprocessOptions {
+                crop_mode,
+                target_height: None,
+                ..Default::default()
+            };
+            let output = preprocess(&image_bytes, &options).unwrap();
+            image::load_from_memory(&output).unwrap()
+        };
+
+        assert_eq!(crop(CropMode::White).dimensions(), (200, 200));
+        let cropped = crop(CropMode::Alpha);
+        assert_eq!(cropped.dimensions(), (18, 18));
+        let rgba = cropped.to_rgba8();
+        assert_eq!(rgba.get_pixel(4, 4)[3], 255);
+        assert_eq!(rgba.get_pixel(13, 13)[3], 255);
+        assert_eq!(rgba.get_pixel(3, 3)[3], 0);
+        assert_eq!(rgba.get_pixel(14, 14)[3], 0);
+    }
+
     #[test]
     fn test_contrast_enhancement() {
         let image_bytes = create_low_contrast_image(100, 100);
         let options = PreprocessOptions {
             auto_crop: false,
             enhance_contrast: true,
-            target_height: 0, // disable scaling
+            target_height: None, // disable scaling
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -564,9 +1536,8 @@ mod tests {
         // Test the full pipeline: crop + enhance + scale
         let image_bytes = create_image_with_content(300, 300, 100, 100, 60, 40);
         let options = PreprocessOptions {
-            auto_crop: true,
             enhance_contrast: true,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -594,8 +1565,7 @@ mod tests {
         let image_bytes = create_white_image(10, 5);
         let options = PreprocessOptions {
             auto_crop: false,
-            enhance_contrast: false,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -611,8 +1581,7 @@ mod tests {
         let image_bytes = create_white_image(100, 64);
         let options = PreprocessOptions {
             auto_crop: false,
-            enhance_contrast: false,
-            target_height: 64,
+            ..Default::default()
         };
         let result = preprocess(&image_bytes, &options).unwrap();
         let output_img = image::load_from_memory(&result).unwrap();
@@ -620,6 +1589,98 @@ mod tests {
         assert_eq!(h, 64);
         assert_eq!(w, 100);
     }
+
+    /// 辅助函数：按顺序列出 PNG 中所有块的 (块类型, 原始数据)
+    fn png_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos + 12 <= png.len() {
+            let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]])
+                as usize;
+            let kind = [png[pos + 4], png[pos + 5], png[pos + 6], png[pos + 7]];
+            chunks.push((kind, &png[pos + 8..pos + 8 + len]));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    /// 辅助函数：列出 PNG 中所有 tEXt 块的 (关键字, 文本)
+    fn read_text_chunks(png: &[u8]) -> Vec<(String, String)> {
+        png_chunks(png)
+            .into_iter()
+            .filter(|(kind, _)| kind == b"tEXt")
+            .map(|(_, data)| {
+                let sep = data.iter().position(|&b| b == 0).unwrap();
+                (
+                    String::from_utf8_lossy(&data[..sep]).to_string(),
+                    String::from_utf8_lossy(&data[sep + 1..]).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_png_crc32_known_value() {
+        // CRC of the IEND chunk type is a fixed constant in every PNG
+        assert_eq!(png_crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_stamp_engine_version_adds_text_chunk() {
+        let png = create_image_with_content(40, 20, 5, 5, 10, 10);
+        let stamped = stamp_engine_version(&png, "texify-0.2").unwrap();
+
+        let chunks = read_text_chunks(&stamped);
+        assert_eq!(
+            chunks,
+            vec![(ENGINE_VERSION_KEYWORD.to_string(), "texify-0.2".to_string())]
+        );
+
+        // The stamped PNG must still decode to the same image
+        let decoded = image::load_from_memory(&stamped).expect("stamped PNG should decode");
+        assert_eq!(decoded.dimensions(), (40, 20));
+    }
+
+    #[test]
+    fn test_stamp_engine_version_replaces_existing_stamp() {
+        let png = create_white_image(8, 8);
+        let once = stamp_engine_version(&png, "v1").unwrap();
+        let twice = stamp_engine_version(&once, "v2").unwrap();
+
+        let chunks = read_text_chunks(&twice);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].1, "v2");
+        assert!(image::load_from_memory(&twice).is_ok());
+    }
+
+    #[test]
+    fn test_insert_png_text_chunk_rejects_invalid_input() {
+        let png = create_white_image(4, 4);
+        assert!(matches!(
+            insert_png_text_chunk(b"not a png", "k", "v"),
+            Err(PreprocessError::InvalidFormat(_))
+        ));
+        assert!(insert_png_text_chunk(&png, "", "v").is_err());
+        assert!(insert_png_text_chunk(&png, "k", "公式").is_err());
+        assert!(insert_png_text_chunk(&png[..20], "k", "v").is_err());
+    }
+
+    #[test]
+    fn test_insert_png_itxt_chunk_keeps_utf8_text() {
+        let png = create_white_image(4, 4);
+        let once = insert_png_itxt_chunk(&png, "latex", r"\text{公式}").unwrap();
+        let twice = insert_png_itxt_chunk(&once, "latex", r"x^2").unwrap();
+
+        let itxt: Vec<&[u8]> = png_chunks(&twice)
+            .into_iter()
+            .filter(|(kind, _)| kind == b"iTXt")
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(itxt, vec![&b"latex\0\0\0\0\0x^2"[..]]);
+        let once_text = png_chunks(&once).into_iter().find(|(kind, _)| kind == b"iTXt").unwrap().1;
+        assert!(once_text.ends_with(r"\text{公式}".as_bytes()));
+        assert!(image::load_from_memory(&twice).is_ok());
+    }
 }
 
 // Property-based tests using proptest
@@ -674,8 +1735,7 @@ mod property_tests {
             // Use options without auto_crop to test pure scaling behavior
             let options = PreprocessOptions {
                 auto_crop: false,
-                enhance_contrast: false,
-                target_height: 64,
+                ..Default::default()
             };
 
             // Preprocess the image
@@ -728,11 +1788,7 @@ mod property_tests {
             // Create test image with content
             let image_bytes = create_test_image(width, height);
 
-            let options = PreprocessOptions {
-                auto_crop: true,
-                enhance_contrast: false,
-                target_height: 64,
-            };
+            let options = PreprocessOptions::default();
 
             let result = preprocess(&image_bytes, &options);
             prop_assert!(result.is_ok(), "Preprocessing should succeed");
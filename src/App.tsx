@@ -50,6 +50,7 @@ export default function App() {
   const copyLatex = useFormulaStore((s) => s.copyLatex);
   const saveToHistory = useFormulaStore((s) => s.saveToHistory);
   const searchHistory = useFormulaStore((s) => s.searchHistory);
+  const checkDbRecovery = useFormulaStore((s) => s.checkDbRecovery);
   const exportTex = useFormulaStore((s) => s.exportTex);
   const exportDocx = useFormulaStore((s) => s.exportDocx);
 
@@ -73,11 +74,11 @@ export default function App() {
   }, [error, setError]);
 
   // ============================================================
-  // Load history on mount
+  // Load history on mount, then report a database recovered at startup
   // ============================================================
   useEffect(() => {
-    searchHistory("");
-  }, [searchHistory]);
+    searchHistory("").then(checkDbRecovery);
+  }, [searchHistory, checkDbRecovery]);
 
   // ============================================================
   // Capture flow - Req 1.2
@@ -91,7 +91,9 @@ export function HistoryRecordItem({
   const thumbnailUrl = useMemo(() => {
     if (record.thumbnail && record.thumbnail.length > 0) {
       const bytes = new Uint8Array(record.thumbnail);
-      const blob = new Blob([bytes], { type: "image/png" });
+      // reencode_thumbnail may have stored the thumbnail as JPEG
+      const type = bytes[0] === 0xff && bytes[1] === 0xd8 ? "image/jpeg" : "image/png";
+      const blob = new Blob([bytes], { type });
       return URL.createObjectURL(blob);
     }
     return null;
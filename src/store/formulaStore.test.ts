@@ -481,6 +481,29 @@ describe("FormulaStore - Tauri command wrappers", () => {
     });
   });
 
+  // ----------------------------------------------------------
+  // checkDbRecovery
+  // ----------------------------------------------------------
+
+  describe("checkDbRecovery", () => {
+    it("shows the recovery notice as an error", async () => {
+      mockInvoke.mockResolvedValue("数据库文件已损坏，已备份为 history.db.corrupt-1 并重新创建");
+
+      await useFormulaStore.getState().checkDbRecovery();
+
+      expect(mockInvoke).toHaveBeenCalledWith("take_db_recovery_notice");
+      expect(useFormulaStore.getState().error).toContain("history.db.corrupt-1");
+    });
+
+    it("leaves error unset without a notice", async () => {
+      mockInvoke.mockResolvedValue(null);
+
+      await useFormulaStore.getState().checkDbRecovery();
+
+      expect(useFormulaStore.getState().error).toBeNull();
+    });
+  });
+
   // ----------------------------------------------------------
   // toggleFavorite
   // ----------------------------------------------------------
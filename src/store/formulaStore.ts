@@ -81,6 +81,7 @@ export interface FormulaState {
   copyLatex: () => Promise<void>;
   saveToHistory: () => Promise<number>;
   searchHistory: (query: string) => Promise<void>;
+  checkDbRecovery: () => Promise<void>;
   toggleFavorite: (id: number) => Promise<void>;
   exportTex: (ids: number[], options: TexExportOptions) => Promise<Uint8Array>;
   exportDocx: (ids: number[]) => Promise<Uint8Array>;
@@ -310,6 +311,23 @@ export const useFormulaStore = create<FormulaState>((set, get) => ({
     }
   },
 
+  /**
+   * Show the startup history database recovery notice (the corrupt file was
+   * backed up and replaced) - invokes take_db_recovery_notice.
+   */
+  checkDbRecovery: async () => {
+    try {
+      const notice = await invoke<string | null>("take_db_recovery_notice");
+      if (notice) {
+        set({ error: notice });
+      }
+    } catch (err) {
+      set({
+        error: err instanceof Error ? err.message : String(err),
+      });
+    }
+  },
+
   /**
    * Toggle favorite status of a history record - invokes toggle_favorite.
    * Updates the local historyRecords state to reflect the change.
@@ -19,20 +19,41 @@ export interface HistoryRecord {
   edited_latex?: string;
   confidence: number; // 0.0 ~ 1.0
   engine_version: string;
-  thumbnail?: number[]; // PNG 缩略图（Rust Vec<u8> 序列化为 number[]）
+  thumbnail?: number[]; // 缩略图，PNG 或 JPEG（Rust Vec<u8> 序列化为 number[]）
   is_favorite: boolean;
+  needs_review?: boolean; // 置信度低于复核阈值时由后端标记
+  note?: string | null; // 用户备注（导出时作为公式前的段落）
+}
+
+/** 历史记录筛选条件（对应 Rust HistoryFilter） */
+export interface HistoryFilter {
+  favorite_only?: boolean;
+  from?: string; // ISO 8601，含
+  to?: string; // ISO 8601，含
+  keyword?: string;
 }
 
 /** .tex 导出选项（对应 Rust TexExportOptions） */
 export interface TexExportOptions {
   add_time_comments: boolean;
+  smart_environments?: boolean; // 多行公式自动使用 align*/gather*
+  delimiter?: "double_dollar" | "bracket_display" | "equation_env"; // 默认 $$...$$
+}
+
+/** 导出进度事件 `export-progress` 的负载（对应 Rust ExportProgress） */
+export interface ExportProgress {
+  done: number;
+  total: number;
 }
 
 /** 图像预处理选项（对应 Rust PreprocessOptions） */
 export interface PreprocessOptions {
   auto_crop: boolean;
+  crop_mode?: "white" | "alpha"; // alpha: 只裁掉透明边缘
+  auto_invert?: boolean; // 深色背景自动反色后再识别
   enhance_contrast: boolean;
-  target_height: number;
+  binarize?: boolean; // Otsu 二值化为纯黑白
+  target_height: number | null; // null 表示不缩放，否则须在 1 到 1024 之间
 }
 
 /** 截图配置（对应 Rust CaptureConfig） */